@@ -1,16 +1,25 @@
-use anyhow::{anyhow, bail, ensure, Context, Result};
-use image::{codecs::tiff::TiffDecoder, ColorType, DynamicImage, GenericImageView, ImageBuffer, ImageDecoder};
+use memmap2::Mmap;
+use image::{codecs::tiff::TiffDecoder, DynamicImage, GenericImageView, ImageBuffer, ImageDecoder};
 use rayon::prelude::*;
-use std::collections::HashMap;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs::{remove_file, File, OpenOptions};
-use std::io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::io::{BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use std::sync::OnceLock;
+use std::sync::{Arc, Mutex, OnceLock};
 use tiff::decoder::{ChunkType as TiffChunkType, Decoder as RawTiffDecoder, DecodingResult as RawTiffDecodingResult, Limits as TiffLimits};
+use tiff::encoder::{colortype as tiff_colortype, DirectoryEncoder, ImageEncoder, TiffEncoder, TiffKindStandard, TiffValue};
 use tiff::tags::Tag as TiffTag;
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
 
 pub const CTI_MAGIC: &[u8; 4] = b"CTI1";
 pub const CTI_HEADER_SIZE: usize = 64;
+/// Newest header layout this crate reads; v2 adds rectangular tiles.
+pub const CTI_MAX_VERSION: u16 = 2;
 pub const TILE_INDEX_ONDISK_SIZE: usize = 8 + 4 + 4 + 4;
 pub const DEFAULT_TILE_SIZE: u32 = 256;
 
@@ -22,31 +31,255 @@ pub const SEC_TYPE_RES: u32 = 0x2053_4552; // 'RES '
 pub const SEC_TYPE_ICC: u32 = 0x2043_4349; // 'ICC '
 pub const SEC_TYPE_PYLV: u32 = 0x564C_5950; // 'PYLV'
 pub const SEC_TYPE_TMOD: u32 = 0x444F_4D54; // 'TMOD'
+pub const SEC_TYPE_NLER: u32 = 0x5245_4C4E; // 'NLER'
+pub const SEC_TYPE_ZDIC: u32 = 0x4349_445A; // 'ZDIC'
+pub const SEC_TYPE_TSOL: u32 = 0x4C4F_5354; // 'TSOL'
+pub const SEC_TYPE_SCRC: u32 = 0x4352_4353; // 'SCRC'
+pub const SEC_TYPE_FXTY: u32 = 0x5954_5846; // 'FXTY'
+pub const SEC_TYPE_BAND: u32 = 0x444E_4142; // 'BAND'
+pub const SEC_TYPE_XMP: u32 = 0x2050_4D58; // 'XMP '
+pub const SEC_TYPE_EXIF: u32 = 0x4649_5845; // 'EXIF'
+pub const SEC_TYPE_IPTC: u32 = 0x4354_5049; // 'IPTC'
+
+const TIFF_TAG_INK_SET: u16 = 332;
+const TIFF_TAG_INK_NAMES: u16 = 333;
+const TIFF_INK_NAMES_MAX_BYTES: u64 = 64 * 1024;
+
+const TIFF_TAG_XMP: u16 = 700;
+const TIFF_TAG_IPTC: u16 = 33723;
+const TIFF_TAG_EXIF_IFD: u16 = 34665;
+const TIFF_TAG_GPS_IFD: u16 = 34853;
+const EXIF_TAG_INTEROP_IFD: u16 = 40965;
+/// ImageDescription, Make, Model, Orientation, Software, DateTime, Artist,
+/// HostComputer and Copyright: the IFD0 tags an `EXIF` section keeps.
+const EXIF_IFD0_TAGS: [u16; 9] = [270, 271, 272, 274, 305, 306, 315, 316, 33432];
+const TIFF_TYPE_BYTE: u16 = 1;
+const TIFF_TYPE_LONG: u16 = 4;
+const TIFF_TYPE_UNDEFINED: u16 = 7;
+const DESCRIPTIVE_METADATA_MAX_BYTES: u64 = 16 * 1024 * 1024;
+const BILEVEL_TIFF_STRIP_BYTES: usize = 64 * 1024;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const PNG_XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+const PNG_IPTC_KEYWORD: &str = "Raw profile type iptc";
+const JPEG_EXIF_SIGNATURE: &[u8] = b"Exif\0\0";
+const JPEG_XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const JPEG_PHOTOSHOP_SIGNATURE: &[u8] = b"Photoshop 3.0\0";
+
+const FIXITY_ALGORITHM_SHA256: u8 = 1;
+const FIXITY_HEADER_SIZE: usize = 4;
 
 const FLAG_COLOR_RCT: u16 = 1 << 0;
 const FLAG_COLOR_RGB16_DELTA_G: u16 = 1 << 1;
+const FLAG_CHECKSUMS: u16 = 1 << 2;
+
+// With FLAG_CHECKSUMS, the last 12 reserved header bytes hold CRC32s of the
+// section TOC, the tile index table and the header itself.
+const RESERVED_TOC_CRC: usize = 21;
+const RESERVED_INDEX_CRC: usize = 25;
+const RESERVED_HEADER_CRC: usize = 29;
+
+const ZSTD_DICT_MAX_SAMPLES: usize = 512;
+const ZSTD_DICT_SAMPLE_BYTES: usize = 64 * 1024;
+
+pub type Result<T, E = CtiError> = std::result::Result<T, E>;
+
+/// Error type of the CTI library. Malformed files surface as the specific
+/// variants below where callers may want to react, and as `Corrupt` with a
+/// description otherwise.
+#[derive(Debug)]
+pub enum CtiError {
+    Io(std::io::Error),
+    Image(image::ImageError),
+    Tiff(tiff::TiffError),
+    BadMagic,
+    UnsupportedVersion(u16),
+    UnknownCompression(u8),
+    UnknownColorType(u8),
+    /// Valid input that uses a pixel layout or feature this crate lacks.
+    Unsupported(String),
+    CrcMismatch { tile: usize },
+    /// Header, tile index or section TOC checksum (`FLAG_CHECKSUMS`) failed.
+    ChecksumMismatch(&'static str),
+    SectionCrcMismatch { section: &'static str, offset: u64 },
+    MissingTmod,
+    /// A section required by the header flags or compression is absent.
+    MissingSection(&'static str),
+    PyramidLevelMissing(u32),
+    /// Tile, region or section request outside the image.
+    OutOfRange(String),
+    /// The file asks for more than the configured [`DecodeLimits`].
+    LimitExceeded(String),
+    Corrupt(String),
+    /// Bad arguments or configuration passed by the caller.
+    InvalidInput(String),
+    Context { context: String, source: Box<CtiError> },
+}
+
+impl fmt::Display for CtiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CtiError::Io(err) => write!(f, "{err}"),
+            CtiError::Image(err) => write!(f, "{err}"),
+            CtiError::Tiff(err) => write!(f, "{err}"),
+            CtiError::BadMagic => write!(f, "Bad magic"),
+            CtiError::UnsupportedVersion(version) => write!(f, "Unsupported CTI version {version}"),
+            CtiError::UnknownCompression(id) => write!(f, "Unknown compression id {id}"),
+            CtiError::UnknownColorType(id) => write!(f, "Unsupported color type id {id}"),
+            CtiError::CrcMismatch { tile } => write!(f, "CRC mismatch at tile {tile}"),
+            CtiError::ChecksumMismatch(what) => write!(f, "{what} CRC mismatch"),
+            CtiError::SectionCrcMismatch { section, offset } => {
+                write!(f, "CRC mismatch in section '{section}' at offset {offset}")
+            }
+            CtiError::MissingTmod => write!(f, "Adaptive CTI is missing TMOD section"),
+            CtiError::MissingSection(name) => write!(f, "CTI is missing {name} section"),
+            CtiError::PyramidLevelMissing(level) => write!(f, "Pyramid level {level} not present"),
+            CtiError::Unsupported(msg)
+            | CtiError::OutOfRange(msg)
+            | CtiError::LimitExceeded(msg)
+            | CtiError::Corrupt(msg)
+            | CtiError::InvalidInput(msg) => f.write_str(msg),
+            CtiError::Context { context, .. } => f.write_str(context),
+        }
+    }
+}
+
+impl std::error::Error for CtiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        // Wrapped errors print their own message, so report their cause.
+        match self {
+            CtiError::Io(err) => err.source(),
+            CtiError::Image(err) => err.source(),
+            CtiError::Tiff(err) => err.source(),
+            CtiError::Context { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CtiError {
+    fn from(err: std::io::Error) -> Self {
+        CtiError::Io(err)
+    }
+}
+
+impl From<image::ImageError> for CtiError {
+    fn from(err: image::ImageError) -> Self {
+        CtiError::Image(err)
+    }
+}
+
+impl From<tiff::TiffError> for CtiError {
+    fn from(err: tiff::TiffError) -> Self {
+        CtiError::Tiff(err)
+    }
+}
+
+impl From<rayon::ThreadPoolBuildError> for CtiError {
+    fn from(err: rayon::ThreadPoolBuildError) -> Self {
+        CtiError::InvalidInput(format!("thread pool: {err}"))
+    }
+}
+
+/// `bail!("...")` returns `CtiError::Corrupt`; `bail!(err)` returns `err`.
+macro_rules! bail {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {
+        return Err(CtiError::Corrupt(format!($fmt $(, $arg)*)))
+    };
+    ($err:expr $(,)?) => {
+        return Err($err)
+    };
+}
+
+macro_rules! ensure {
+    ($cond:expr, $fmt:literal $(, $arg:expr)* $(,)?) => {
+        if !$cond {
+            bail!($fmt $(, $arg)*);
+        }
+    };
+    ($cond:expr, $err:expr $(,)?) => {
+        if !$cond {
+            bail!($err);
+        }
+    };
+}
+
+/// Wraps an error (or a missing value) with a description of what failed.
+trait Context<T> {
+    fn context(self, context: impl Into<String>) -> Result<T>;
+    fn with_context<C: Into<String>>(self, context: impl FnOnce() -> C) -> Result<T>;
+}
+
+impl<T, E: Into<CtiError>> Context<T> for std::result::Result<T, E> {
+    fn context(self, context: impl Into<String>) -> Result<T> {
+        self.with_context(|| context)
+    }
+
+    fn with_context<C: Into<String>>(self, context: impl FnOnce() -> C) -> Result<T> {
+        self.map_err(|err| CtiError::Context {
+            context: context().into(),
+            source: Box::new(err.into()),
+        })
+    }
+}
+
+impl<T> Context<T> for Option<T> {
+    fn context(self, context: impl Into<String>) -> Result<T> {
+        self.with_context(|| context)
+    }
+
+    fn with_context<C: Into<String>>(self, context: impl FnOnce() -> C) -> Result<T> {
+        self.ok_or_else(|| CtiError::Corrupt(context().into()))
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct CTIConfig {
     pub tile_size: u32,
+    /// Tile height when it differs from `tile_size` (the tile width); written
+    /// as a format v2 header.
+    pub tile_height: Option<u32>,
     pub compression: CompressionType,
     pub quality_level: u8,
     pub color_transform: bool,
     pub zstd_level: i32,
     pub pyramid_levels: u32,
     pub downcast_16_to_8: bool,
+    /// Maximum absolute per-sample error for [`CompressionType::NearLossless`].
+    pub max_error: u16,
+    /// Size limit of the Zstd dictionary trained from sample tiles, 0 disables it.
+    pub zstd_dict_size: u32,
+    /// Store constant-color tiles as a single pixel and let byte-identical
+    /// payloads share one offset. Off by default: readers that predate the
+    /// `TSOL` section would decode solid tiles as garbage.
+    pub dedup_tiles: bool,
+    /// SHA-256 fixity digests of the decoded pixels stored in the `FXTY` section.
+    pub fixity: FixityScope,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixityScope {
+    None,
+    /// One digest over the whole raster.
+    Image,
+    /// The whole-raster digest plus one digest per tile.
+    Tiles,
 }
 
 impl Default for CTIConfig {
     fn default() -> Self {
         Self {
             tile_size: DEFAULT_TILE_SIZE,
+            tile_height: None,
             compression: CompressionType::Zstd,
             quality_level: 100,
             color_transform: false,
             zstd_level: 6,
             pyramid_levels: 0,
             downcast_16_to_8: false,
+            max_error: 0,
+            zstd_dict_size: 0,
+            dedup_tiles: false,
+            fixity: FixityScope::None,
         }
     }
 }
@@ -61,6 +294,9 @@ pub enum CompressionType {
     Predictive = 4,
     Zstd = 10,
     Lz4 = 11,
+    Dct = 20,
+    NearLossless = 21,
+    Bilevel = 30,
     Adaptive = 250,
 }
 
@@ -74,8 +310,11 @@ impl CompressionType {
             4 => Self::Predictive,
             10 => Self::Zstd,
             11 => Self::Lz4,
+            20 => Self::Dct,
+            21 => Self::NearLossless,
+            30 => Self::Bilevel,
             250 => Self::Adaptive,
-            _ => bail!("Unknown compression id {}", id),
+            _ => bail!(CtiError::UnknownCompression(id)),
         })
     }
 
@@ -88,9 +327,23 @@ impl CompressionType {
             Self::Predictive => "predictive",
             Self::Zstd => "zstd",
             Self::Lz4 => "lz4",
+            Self::Dct => "dct",
+            Self::NearLossless => "near-lossless",
+            Self::Bilevel => "bilevel",
             Self::Adaptive => "adaptive",
         }
     }
+
+    /// Lossy modes store the CRC of the reconstructed tile and do not use the
+    /// reversible color transforms.
+    pub fn is_lossy(self) -> bool {
+        matches!(self, Self::Dct | Self::NearLossless)
+    }
+
+    /// Modes whose Zstd payloads are compressed against the `ZDIC` dictionary.
+    pub fn uses_zstd_dictionary(self) -> bool {
+        matches!(self, Self::Zstd | Self::Adaptive)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -102,6 +355,13 @@ enum AdaptiveTileMode {
     ZstdShuffle16 = 3,
     ZstdGradient16 = 4,
     Lz4Raw = 5,
+    ZstdWavelet53 = 6,
+    ZstdDelta8 = 7,
+    ZstdGradient8 = 8,
+    ZstdPaeth8 = 9,
+    ZstdShuffle32 = 10,
+    ZstdXorDelta32 = 11,
+    Bilevel = 12,
 }
 
 impl AdaptiveTileMode {
@@ -113,9 +373,39 @@ impl AdaptiveTileMode {
             3 => Self::ZstdShuffle16,
             4 => Self::ZstdGradient16,
             5 => Self::Lz4Raw,
+            6 => Self::ZstdWavelet53,
+            7 => Self::ZstdDelta8,
+            8 => Self::ZstdGradient8,
+            9 => Self::ZstdPaeth8,
+            10 => Self::ZstdShuffle32,
+            11 => Self::ZstdXorDelta32,
+            12 => Self::Bilevel,
             _ => bail!("Unknown adaptive tile mode {}", value),
         })
     }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::ZstdRaw => "zstd-raw",
+            Self::ZstdDelta16 => "zstd-delta16",
+            Self::ZstdPredict16 => "zstd-predict16",
+            Self::ZstdShuffle16 => "zstd-shuffle16",
+            Self::ZstdGradient16 => "zstd-gradient16",
+            Self::Lz4Raw => "lz4-raw",
+            Self::ZstdWavelet53 => "zstd-wavelet53",
+            Self::ZstdDelta8 => "zstd-delta8",
+            Self::ZstdGradient8 => "zstd-gradient8",
+            Self::ZstdPaeth8 => "zstd-paeth8",
+            Self::ZstdShuffle32 => "zstd-shuffle32",
+            Self::ZstdXorDelta32 => "zstd-xordelta32",
+            Self::Bilevel => "bilevel",
+        }
+    }
+}
+
+/// Human-readable name of a `TMOD` adaptive tile mode byte.
+pub fn adaptive_tile_mode_name(value: u8) -> Option<&'static str> {
+    AdaptiveTileMode::from_byte(value).ok().map(AdaptiveTileMode::label)
 }
 
 #[derive(Debug, Clone)]
@@ -130,6 +420,106 @@ struct CompTile {
     orig_len: u32,
     crc: u32,
     adaptive_mode: Option<AdaptiveTileMode>,
+    solid: bool,
+}
+
+/// Appends compressed tiles to the data area and collects their index
+/// entries plus the per-tile `TMOD` / `TSOL` sections. Payloads are written
+/// in arrival order; the index slot is chosen by tile number.
+struct TileDataWriter {
+    cursor: u64,
+    indices: Vec<TileIndex>,
+    adaptive_modes: Vec<u8>,
+    solid_bitmap: Vec<u8>,
+    seen_payloads: Option<HashMap<[u8; 32], u64>>,
+    next_tile: usize,
+}
+
+impl TileDataWriter {
+    fn new(data_offset: u64, total_tiles: usize, dedup: bool) -> Self {
+        let empty = TileIndex {
+            offset: 0,
+            compressed_size: 0,
+            original_size: 0,
+            crc32: 0,
+        };
+        Self {
+            cursor: data_offset,
+            indices: vec![empty; total_tiles],
+            adaptive_modes: Vec::new(),
+            solid_bitmap: vec![0u8; total_tiles.div_ceil(8)],
+            seen_payloads: dedup.then(HashMap::new),
+            next_tile: 0,
+        }
+    }
+
+    /// Writes the next tile in index order.
+    fn write_tile<W: Write>(&mut self, w: &mut W, comp: &CompTile) -> Result<()> {
+        self.write_tile_at(w, self.next_tile, comp)?;
+        self.next_tile += 1;
+        Ok(())
+    }
+
+    fn write_tile_at<W: Write>(&mut self, w: &mut W, tile_number: usize, comp: &CompTile) -> Result<()> {
+        if let Some(mode) = comp.adaptive_mode {
+            // Adaptive compression assigns a mode to every tile.
+            if self.adaptive_modes.is_empty() {
+                self.adaptive_modes = vec![0; self.indices.len()];
+            }
+            self.adaptive_modes[tile_number] = mode as u8;
+        }
+        if comp.solid {
+            self.solid_bitmap[tile_number / 8] |= 1 << (tile_number % 8);
+        }
+
+        // Identical payloads decode identically, so later tiles can point at
+        // the first copy; the index entry keeps each tile's own size and CRC.
+        let shared = match &mut self.seen_payloads {
+            Some(seen) => match seen.entry(Sha256::digest(&comp.comp).into()) {
+                Entry::Occupied(entry) => Some(*entry.get()),
+                Entry::Vacant(entry) => {
+                    entry.insert(self.cursor);
+                    None
+                }
+            },
+            None => None,
+        };
+        let offset = match shared {
+            Some(offset) => offset,
+            None => {
+                w.write_all(&comp.comp)?;
+                let offset = self.cursor;
+                self.cursor += comp.comp.len() as u64;
+                offset
+            }
+        };
+        self.indices[tile_number] = TileIndex {
+            offset,
+            compressed_size: comp.comp.len() as u32,
+            original_size: comp.orig_len,
+            crc32: comp.crc,
+        };
+        Ok(())
+    }
+
+    fn write_indices<W: Write + Seek>(&self, w: &mut W) -> Result<()> {
+        w.seek(SeekFrom::Start(CTI_HEADER_SIZE as u64))?;
+        for idx in &self.indices {
+            write_tile_index(w, idx)?;
+        }
+        Ok(())
+    }
+
+    fn tile_sections(&self) -> Vec<(u32, Vec<u8>)> {
+        let mut out = Vec::new();
+        if !self.adaptive_modes.is_empty() {
+            out.push((SEC_TYPE_TMOD, self.adaptive_modes.clone()));
+        }
+        if self.solid_bitmap.iter().any(|&bits| bits != 0) {
+            out.push((SEC_TYPE_TSOL, self.solid_bitmap.clone()));
+        }
+        out
+    }
 }
 
 struct PreparedTile {
@@ -137,6 +527,71 @@ struct PreparedTile {
     tile_extent: (u32, u32),
 }
 
+/// Output of a streamed level before its sections and final header.
+struct StreamedTiles {
+    header: CTIHeader,
+    tile_data: TileDataWriter,
+    zstd_dict: Option<ZstdDictionary>,
+    fixity: Option<FixityDigests>,
+}
+
+/// Per-level settings shared by every tile codec call.
+#[derive(Debug, Clone, Copy)]
+struct TileCodecParams<'a> {
+    zstd_level: i32,
+    quality: u8,
+    max_error: u16,
+    /// Near-lossless tiles start with a predictor id (`NLER` version 1).
+    near_lossless_tagged: bool,
+    zstd_dict: Option<&'a ZstdDictionary>,
+}
+
+impl TileCodecParams<'_> {
+    fn zstd_compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self.zstd_dict {
+            Some(dict) => Ok(zstd::bulk::Compressor::with_prepared_dictionary(dict.encoder())?.compress(data)?),
+            None => Ok(zstd::bulk::compress(data, self.zstd_level)?),
+        }
+    }
+
+    fn zstd_decompress(&self, comp: &[u8], original_size: usize) -> Result<Vec<u8>> {
+        let out = match self.zstd_dict {
+            Some(dict) => zstd::bulk::Decompressor::with_prepared_dictionary(dict.decoder())?.decompress(comp, original_size),
+            None => zstd::bulk::decompress(comp, original_size),
+        };
+        out.map_err(|e| CtiError::Corrupt(format!("zstd decompress failed: {e}")))
+    }
+}
+
+/// A level's `ZDIC` dictionary. Zstd digests it on first use for compression
+/// or decompression and every tile of the level reuses the result.
+struct ZstdDictionary {
+    bytes: Vec<u8>,
+    level: i32,
+    encoder: OnceLock<EncoderDictionary<'static>>,
+    decoder: OnceLock<DecoderDictionary<'static>>,
+}
+
+impl ZstdDictionary {
+    fn new(bytes: Vec<u8>, level: i32) -> Self {
+        Self { bytes, level, encoder: OnceLock::new(), decoder: OnceLock::new() }
+    }
+
+    fn encoder(&self) -> &EncoderDictionary<'static> {
+        self.encoder.get_or_init(|| EncoderDictionary::copy(&self.bytes, self.level))
+    }
+
+    fn decoder(&self) -> &DecoderDictionary<'static> {
+        self.decoder.get_or_init(|| DecoderDictionary::copy(&self.bytes))
+    }
+}
+
+impl fmt::Debug for ZstdDictionary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ZstdDictionary").field("len", &self.bytes.len()).field("level", &self.level).finish()
+    }
+}
+
 #[derive(Debug, Clone)]
 struct StagedSectionFile {
     ty: u32,
@@ -169,6 +624,7 @@ pub struct CTIHeader {
 }
 
 impl CTIHeader {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         width: u32,
         height: u32,
@@ -195,6 +651,101 @@ impl CTIHeader {
             reserved: [0u8; 33],
         }
     }
+
+    /// Sets the tile height. Non-square tiles need the v2 layout, which keeps
+    /// the width in `tile_size` and stores the height in `reserved[0..4]`.
+    pub fn with_tile_height(mut self, tile_height: u32) -> Self {
+        if tile_height == self.tile_size {
+            self.version = 1;
+            self.reserved[0..4].fill(0);
+        } else {
+            self.version = 2;
+            self.reserved[0..4].copy_from_slice(&tile_height.to_le_bytes());
+        }
+        self
+    }
+
+    pub fn tile_width(&self) -> u32 {
+        self.tile_size
+    }
+
+    pub fn tile_height(&self) -> u32 {
+        if self.version >= 2 {
+            u32::from_le_bytes(self.reserved[0..4].try_into().unwrap())
+        } else {
+            self.tile_size
+        }
+    }
+
+    pub fn tile_dims(&self) -> (u32, u32) {
+        (self.tile_width(), self.tile_height())
+    }
+
+    pub fn bytes_per_pixel(&self) -> Result<u32> {
+        bytes_per_pixel_from_id(self.color_type)
+    }
+
+    /// Whether the header carries header, tile index and section TOC CRCs.
+    pub fn has_checksums(&self) -> bool {
+        self.flags & FLAG_CHECKSUMS != 0
+    }
+
+    fn stamp_checksums(&mut self, indices: &[TileIndex], toc_crc: u32) {
+        self.flags |= FLAG_CHECKSUMS;
+        self.reserved[RESERVED_TOC_CRC..RESERVED_TOC_CRC + 4].copy_from_slice(&toc_crc.to_le_bytes());
+        self.reserved[RESERVED_INDEX_CRC..RESERVED_INDEX_CRC + 4]
+            .copy_from_slice(&tile_index_crc(indices).to_le_bytes());
+        let header_crc = header_crc(self);
+        self.reserved[RESERVED_HEADER_CRC..RESERVED_HEADER_CRC + 4].copy_from_slice(&header_crc.to_le_bytes());
+    }
+
+    fn stored_crc(&self, at: usize) -> u32 {
+        u32::from_le_bytes(self.reserved[at..at + 4].try_into().unwrap())
+    }
+}
+
+/// CRC32 of the on-disk header with its own CRC field zeroed.
+fn header_crc(header: &CTIHeader) -> u32 {
+    let mut unstamped = *header;
+    unstamped.reserved[RESERVED_HEADER_CRC..RESERVED_HEADER_CRC + 4].fill(0);
+    let mut bytes = Vec::with_capacity(CTI_HEADER_SIZE);
+    write_header(&mut bytes, &unstamped).expect("writing to a Vec cannot fail");
+    crc32(&bytes)
+}
+
+fn tile_index_crc(indices: &[TileIndex]) -> u32 {
+    let mut bytes = Vec::with_capacity(indices.len() * TILE_INDEX_ONDISK_SIZE);
+    for idx in indices {
+        write_tile_index(&mut bytes, idx).expect("writing to a Vec cannot fail");
+    }
+    crc32(&bytes)
+}
+
+fn section_toc_bytes(descs: &[SectionDesc]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(4 + descs.len() * 20);
+    bytes.extend_from_slice(&(descs.len() as u32).to_le_bytes());
+    for desc in descs {
+        bytes.extend_from_slice(&desc.ty.to_le_bytes());
+        bytes.extend_from_slice(&desc.offset.to_le_bytes());
+        bytes.extend_from_slice(&desc.size.to_le_bytes());
+    }
+    bytes
+}
+
+/// Rewrites the header at offset 0 with fresh checksums.
+fn write_checksummed_header<W: Write + Seek>(
+    w: &mut W,
+    header: &CTIHeader,
+    indices: &[TileIndex],
+    toc_crc: u32,
+) -> Result<()> {
+    let mut header = *header;
+    header.stamp_checksums(indices, toc_crc);
+    let pos = w.stream_position()?;
+    w.seek(SeekFrom::Start(0))?;
+    write_header(w, &header)?;
+    w.seek(SeekFrom::Start(pos))?;
+    Ok(())
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -205,7 +756,109 @@ pub struct TileIndex {
     pub crc32: u32,
 }
 
+/// Pixel layouts a CTI file can hold. Samples are interleaved; 16-bit and
+/// float samples are stored little endian.
+///
+/// This replaces `image::ColorType` in the public API because that enum has
+/// no CMYK, N-band, bilevel or single-channel float layouts. Both convert
+/// with `TryFrom` where the other side has a matching layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorType {
+    L8,
+    L16,
+    Rgb8,
+    Rgba8,
+    Rgb16,
+    La8,
+    La16,
+    Rgba16,
+    L32F,
+    Rgb32F,
+    Cmyk8,
+    Cmyk16,
+    /// `n` generic bands (1..=64) of 8-bit samples, described by a `BAND` section.
+    Bands8(u8),
+    /// `n` generic bands (1..=64) of 16-bit samples, described by a `BAND` section.
+    Bands16(u8),
+    /// Bilevel: one byte per pixel in rasters (0 = black, 255 = white), stored
+    /// bit-packed by [`CompressionType::Bilevel`].
+    L1,
+}
+
+pub const MAX_BANDS: u8 = 64;
+const COLOR_ID_BANDS8: u8 = 0x40;
+const COLOR_ID_BANDS16: u8 = 0x80;
+
+/// Meaning of the bands of a CMYK or N-band image, stored as `BAND`. Values
+/// follow the TIFF tags they are carried over from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BandInfo {
+    /// PhotometricInterpretation (1 = gray, 2 = RGB, 5 = separated/CMYK).
+    pub photometric: u16,
+    /// InkSet (1 = CMYK, 2 = other inks); 0 when the source had none.
+    pub ink_set: u16,
+    /// ExtraSamples for the bands that follow the photometric ones.
+    pub extra_samples: Vec<u16>,
+    /// InkNames or other band labels, possibly empty.
+    pub names: Vec<String>,
+}
+
+impl BandInfo {
+    fn to_section(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&self.photometric.to_le_bytes());
+        out.extend_from_slice(&self.ink_set.to_le_bytes());
+        out.extend_from_slice(&(self.extra_samples.len() as u16).to_le_bytes());
+        for value in &self.extra_samples {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.extend_from_slice(&(self.names.len() as u16).to_le_bytes());
+        for name in &self.names {
+            out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            out.extend_from_slice(name.as_bytes());
+        }
+        out
+    }
+
+    fn from_section(payload: &[u8]) -> Result<Self> {
+        let mut pos = 0;
+        let photometric = take_u16_le(payload, &mut pos)?;
+        let ink_set = take_u16_le(payload, &mut pos)?;
+        let extra_count = take_u16_le(payload, &mut pos)?;
+        let extra_samples = (0..extra_count)
+            .map(|_| take_u16_le(payload, &mut pos))
+            .collect::<Result<Vec<_>>>()?;
+        let name_count = take_u16_le(payload, &mut pos)?;
+        let mut names = Vec::new();
+        for _ in 0..name_count {
+            let len = take_u16_le(payload, &mut pos)? as usize;
+            ensure!(payload.len() - pos >= len, "BAND section truncated");
+            let name = std::str::from_utf8(&payload[pos..pos + len])
+                .map_err(|_| CtiError::Corrupt("BAND band name is not UTF-8".into()))?;
+            names.push(name.to_string());
+            pos += len;
+        }
+        ensure!(pos == payload.len(), "BAND section has {} trailing bytes", payload.len() - pos);
+        Ok(Self {
+            photometric,
+            ink_set,
+            extra_samples,
+            names,
+        })
+    }
+}
+
+fn take_u16_le(payload: &[u8], pos: &mut usize) -> Result<u16> {
+    ensure!(payload.len() - *pos >= 2, "BAND section truncated");
+    let value = u16::from_le_bytes([payload[*pos], payload[*pos + 1]]);
+    *pos += 2;
+    Ok(value)
+}
+
+/// Decoded raster plus the metadata carried into CTI sections. Construct it
+/// with [`TiffImage::new`] and set the optional fields afterwards.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct TiffImage {
     pub width: u32,
     pub height: u32,
@@ -214,6 +867,25 @@ pub struct TiffImage {
     pub xdpi: Option<f32>,
     pub ydpi: Option<f32>,
     pub icc: Option<Vec<u8>>,
+    pub bands: Option<BandInfo>,
+    pub descriptive: DescriptiveMetadata,
+}
+
+impl TiffImage {
+    /// Raster without resolution, ICC, band or descriptive metadata.
+    pub fn new(width: u32, height: u32, color_type: ColorType, data: Vec<u8>) -> Self {
+        Self {
+            width,
+            height,
+            color_type,
+            data,
+            xdpi: None,
+            ydpi: None,
+            icc: None,
+            bands: None,
+            descriptive: DescriptiveMetadata::default(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -237,6 +909,8 @@ pub struct SectionDesc {
     pub ty: u32,
     pub offset: u64,
     pub size: u64,
+    /// Payload CRC32 from the `SCRC` section, when the file has one.
+    pub crc32: Option<u32>,
 }
 
 #[derive(Debug, Clone)]
@@ -245,6 +919,7 @@ pub struct PyramidLevelInfo {
     pub width: u32,
     pub height: u32,
     pub tile_size: u32,
+    pub tile_height: u32,
     pub color_type: u8,
     pub compression: u8,
     pub payload_size: usize,
@@ -258,88 +933,446 @@ pub struct CTIFileInfo {
     pub xdpi: Option<f32>,
     pub ydpi: Option<f32>,
     pub icc_size: Option<usize>,
+    pub bands: Option<BandInfo>,
+    pub max_error: Option<u16>,
+    /// Tiles stored as a single repeated pixel (`TSOL`).
+    pub solid_tiles: usize,
+    pub fixity: Option<FixityDigests>,
     pub pyramid_levels: Vec<PyramidLevelInfo>,
 }
 
-#[derive(Debug, Clone)]
-pub struct DecodedImage {
-    pub header: CTIHeader,
-    pub data: Vec<u8>,
-    pub sections: Vec<CTISection>,
+pub type Sha256Digest = [u8; 32];
+
+/// SHA-256 digests of the decoded base-level pixels: the raster as returned by
+/// `decode` (rows top to bottom, tightly packed, little-endian samples) and,
+/// optionally, every tile as returned by `decode_tile`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixityDigests {
+    pub image: Sha256Digest,
+    /// Empty unless the file was written with [`FixityScope::Tiles`].
+    pub tiles: Vec<Sha256Digest>,
 }
 
-#[derive(Debug, Clone)]
-pub struct RasterRegion {
-    pub width: u32,
-    pub height: u32,
-    pub color_type: u8,
-    pub data: Vec<u8>,
+impl FixityDigests {
+    fn to_section(&self) -> Vec<u8> {
+        let scope = if self.tiles.is_empty() { FixityScope::Image } else { FixityScope::Tiles };
+        let mut out = Vec::with_capacity(FIXITY_HEADER_SIZE + 32 * (1 + self.tiles.len()));
+        out.extend_from_slice(&[FIXITY_ALGORITHM_SHA256, scope as u8, 0, 0]);
+        out.extend_from_slice(&self.image);
+        for digest in &self.tiles {
+            out.extend_from_slice(digest);
+        }
+        out
+    }
+
+    fn from_section(payload: &[u8], total_tiles: usize) -> Result<Self> {
+        ensure!(payload.len() >= FIXITY_HEADER_SIZE + 32, "FXTY section too short");
+        ensure!(
+            payload[0] == FIXITY_ALGORITHM_SHA256,
+            "Unsupported fixity algorithm {}",
+            payload[0]
+        );
+        let digests = &payload[FIXITY_HEADER_SIZE..];
+        let tile_count = match payload[1] {
+            s if s == FixityScope::Image as u8 => 0,
+            s if s == FixityScope::Tiles as u8 => total_tiles,
+            other => bail!("Unsupported fixity scope {}", other),
+        };
+        ensure!(
+            digests.len() == 32 * (1 + tile_count),
+            "FXTY size mismatch: expected {} digests, got {} bytes",
+            1 + tile_count,
+            digests.len()
+        );
+        let mut chunks = digests.chunks_exact(32).map(|chunk| <Sha256Digest>::try_from(chunk).unwrap());
+        Ok(Self {
+            image: chunks.next().unwrap(),
+            tiles: chunks.collect(),
+        })
+    }
 }
 
-pub struct CTIEncoder {
-    config: CTIConfig,
+/// Stored fixity digests next to the ones recomputed from the decoded pixels.
+#[derive(Debug, Clone)]
+pub struct FixityReport {
+    pub stored: FixityDigests,
+    pub computed: FixityDigests,
+    /// Tiles whose recomputed digest differs, as `(tx, ty)`.
+    pub mismatched_tiles: Vec<(u32, u32)>,
 }
 
-impl CTIEncoder {
-    pub fn new(config: CTIConfig) -> Self {
-        Self { config }
+impl FixityReport {
+    pub fn image_matches(&self) -> bool {
+        self.stored.image == self.computed.image
     }
 
-    fn pyramid_tile_size(&self) -> u32 {
-        self.config.tile_size.min(1024)
+    pub fn is_ok(&self) -> bool {
+        self.image_matches() && self.mismatched_tiles.is_empty()
     }
+}
 
-    fn prepare_image_for_storage(&self, img: &TiffImage) -> Result<TiffImage> {
-        let stored_color_type = storage_color_type(img.color_type, self.config.downcast_16_to_8);
-        if stored_color_type == img.color_type {
-            return Ok(img.clone());
-        }
+/// One problem found by [`verify_cti`]. `level` is 0 for the base image and
+/// counts embedded pyramid levels depth-first from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyIssue {
+    pub level: u32,
+    pub tile: Option<(u32, u32)>,
+    /// Index into the section TOC of that level.
+    pub section: Option<usize>,
+    pub message: String,
+}
 
-        Ok(TiffImage {
-            width: img.width,
-            height: img.height,
-            color_type: stored_color_type,
-            data: convert_tile_for_storage(&img.data, img.color_type, stored_color_type)?,
-            xdpi: img.xdpi,
-            ydpi: img.ydpi,
-            icc: img.icc.clone(),
-        })
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    pub file_size: u64,
+    pub levels_checked: u32,
+    pub tiles_checked: usize,
+    pub sections_checked: usize,
+    pub issues: Vec<VerifyIssue>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
     }
 
-    pub fn load_image<P: AsRef<Path>>(&self, path: P) -> Result<TiffImage> {
-        let p = path.as_ref();
-        let file = File::open(p).with_context(|| format!("open {:?}", p))?;
-        let mut br = BufReader::new(file);
+    pub fn to_json(&self) -> String {
+        let issues = self
+            .issues
+            .iter()
+            .map(|issue| {
+                serde_json::json!({
+                    "level": issue.level,
+                    "tile": issue.tile,
+                    "section": issue.section,
+                    "message": issue.message,
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::json!({
+            "ok": self.is_ok(),
+            "file_size": self.file_size,
+            "levels_checked": self.levels_checked,
+            "tiles_checked": self.tiles_checked,
+            "sections_checked": self.sections_checked,
+            "issues": issues,
+        })
+        .to_string()
+    }
+}
 
-        let (width, height, color_type, data, icc_from_decoder) = match TiffDecoder::new(&mut br) {
-            Ok(mut d) => {
-                let (w, h) = d.dimensions();
-                let ct = d.color_type();
-                let icc = d.icc_profile().ok().flatten();
-                let mut buf = vec![0u8; d.total_bytes() as usize];
-                d.read_image(&mut buf)?;
-                (w, h, ct, buf, icc)
-            }
-            Err(_) => {
-                let dynimg =
-                    image::open(p).with_context(|| format!("image::open fallback for {:?}", p))?;
-                let (w, h) = dynimg.dimensions();
-                let (ct, buf) = dynamic_image_into_raw(dynimg);
-                (w, h, ct, buf, None)
-            }
-        };
+pub fn sha256_hex(digest: &Sha256Digest) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
 
-        let (xdpi, ydpi, icc_from_tags) =
-            read_tiff_metadata_for_sections(p).unwrap_or((None, None, None));
+/// Hashes a raster in row order from tiles pushed in tile index order, keeping
+/// at most one row of tiles buffered.
+struct RasterHasher {
+    width: u32,
+    height: u32,
+    tile_dims: (u32, u32),
+    bpp: usize,
+    tiles_x: u32,
+    image: Sha256,
+    tile_row: Vec<Vec<u8>>,
+    tile_digests: Option<Vec<Sha256Digest>>,
+    next_ty: u32,
+}
 
-        Ok(TiffImage {
+impl RasterHasher {
+    fn new(width: u32, height: u32, tile_dims: (u32, u32), bpp: usize, per_tile: bool) -> Self {
+        Self {
+            width,
+            height,
+            tile_dims,
+            bpp,
+            tiles_x: width.div_ceil(tile_dims.0),
+            image: Sha256::new(),
+            tile_row: Vec::new(),
+            tile_digests: per_tile.then(Vec::new),
+            next_ty: 0,
+        }
+    }
+
+    fn push_tile(&mut self, tile: Vec<u8>) {
+        if let Some(digests) = self.tile_digests.as_mut() {
+            digests.push(Sha256::digest(&tile).into());
+        }
+        self.tile_row.push(tile);
+        if self.tile_row.len() < self.tiles_x as usize {
+            return;
+        }
+
+        let (_, row_h) = tile_extent(self.width, self.height, self.tile_dims, 0, self.next_ty);
+        for local_y in 0..row_h as usize {
+            for (tx, tile) in self.tile_row.iter().enumerate() {
+                let (tile_w, _) = tile_extent(self.width, self.height, self.tile_dims, tx as u32, self.next_ty);
+                let row_bytes = tile_w as usize * self.bpp;
+                self.image.update(&tile[local_y * row_bytes..(local_y + 1) * row_bytes]);
+            }
+        }
+        self.tile_row.clear();
+        self.next_ty += 1;
+    }
+
+    fn finish(self) -> FixityDigests {
+        FixityDigests {
+            image: self.image.finalize().into(),
+            tiles: self.tile_digests.unwrap_or_default(),
+        }
+    }
+}
+
+/// Result of a recovery decode together with what had to be left out.
+#[derive(Debug, Clone)]
+pub struct Recovered<T> {
+    pub value: T,
+    /// Tiles painted with the fill value, as `(tx, ty)` in raster order.
+    pub failed_tiles: Vec<(u32, u32)>,
+    /// TOC indices of sections dropped because their payload was unreadable.
+    pub failed_sections: Vec<usize>,
+}
+
+/// Upper bounds applied while parsing and decoding untrusted files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Largest `width * height` accepted for any level.
+    pub max_pixels: u64,
+    /// Largest single buffer (tile index, section payload, decoded raster).
+    pub max_alloc: u64,
+    /// Largest number of entries accepted in a section TOC.
+    pub max_sections: u32,
+    /// How many levels of nested `PYLV` CTIs may be opened.
+    pub max_pyramid_depth: u32,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_pixels: 1 << 34,
+            max_alloc: 1 << 36,
+            max_sections: 1024,
+            max_pyramid_depth: 1,
+        }
+    }
+}
+
+impl DecodeLimits {
+    pub fn unlimited() -> Self {
+        Self {
+            max_pixels: u64::MAX,
+            max_alloc: u64::MAX,
+            max_sections: u32::MAX,
+            max_pyramid_depth: u32::MAX,
+        }
+    }
+
+    fn check_pixels(&self, width: u32, height: u32) -> Result<()> {
+        ensure!(
+            width as u64 * height as u64 <= self.max_pixels,
+            CtiError::LimitExceeded(format!(
+                "Image {}x{} exceeds the limit of {} pixels",
+                width,
+                height,
+                self.max_pixels
+            ))
+        );
+        Ok(())
+    }
+
+    fn check_alloc(&self, bytes: u64, what: &str) -> Result<()> {
+        ensure!(
+            bytes <= self.max_alloc,
+            CtiError::LimitExceeded(format!(
+                "{} of {} bytes exceeds the allocation limit of {}",
+                what,
+                bytes,
+                self.max_alloc
+            ))
+        );
+        Ok(())
+    }
+
+    /// Size of a `width x height` raster with `bpp` bytes per pixel, checked
+    /// against `max_alloc` and the address space.
+    fn raster_bytes(&self, width: u32, height: u32, bpp: u32) -> Result<usize> {
+        let bytes = width as u64 * height as u64 * bpp as u64;
+        self.check_alloc(bytes, "raster")?;
+        usize::try_from(bytes)
+            .map_err(|_| CtiError::LimitExceeded(format!("raster of {} bytes does not fit in memory", bytes)))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DecodedImage {
+    pub header: CTIHeader,
+    pub data: Vec<u8>,
+    pub sections: Vec<CTISection>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RasterRegion {
+    pub width: u32,
+    pub height: u32,
+    pub color_type: u8,
+    pub data: Vec<u8>,
+}
+
+pub struct CTIEncoder {
+    config: CTIConfig,
+}
+
+impl CTIEncoder {
+    pub fn new(config: CTIConfig) -> Self {
+        Self { config }
+    }
+
+    /// Sections the tile codec itself needs at decode time.
+    fn codec_sections(&self, zstd_dict: Option<&ZstdDictionary>) -> Vec<(u32, Vec<u8>)> {
+        let mut out = Vec::new();
+        if self.config.compression == CompressionType::NearLossless {
+            let mut nler = self.config.max_error.to_le_bytes().to_vec();
+            nler.push(NLER_VERSION_TAGGED_PREDICTOR);
+            out.push((SEC_TYPE_NLER, nler));
+        }
+        if let Some(dict) = zstd_dict {
+            out.push((SEC_TYPE_ZDIC, dict.bytes.clone()));
+        }
+        out
+    }
+
+    /// Digests describe the decoded pixels, so they are only offered for
+    /// lossless modes.
+    fn fixity_scope(&self) -> Result<FixityScope> {
+        ensure!(
+            self.config.fixity == FixityScope::None || !self.config.compression.is_lossy(),
+            CtiError::InvalidInput("Fixity digests require a lossless compression mode".into())
+        );
+        Ok(self.config.fixity)
+    }
+
+    fn codec_params<'a>(&self, zstd_dict: Option<&'a ZstdDictionary>) -> TileCodecParams<'a> {
+        TileCodecParams {
+            zstd_level: self.config.zstd_level,
+            quality: self.config.quality_level,
+            max_error: self.config.max_error,
+            near_lossless_tagged: true,
+            zstd_dict,
+        }
+    }
+
+    /// Trains the `ZDIC` dictionary of one level from evenly spaced prepared
+    /// tiles. Returns `None` when disabled or when Zstd cannot build one from
+    /// the samples (e.g. too little data), in which case tiles are
+    /// compressed without a dictionary.
+    fn train_zstd_dictionary(
+        &self,
+        total_tiles: usize,
+        mut sample_tile: impl FnMut(usize) -> Result<Vec<u8>>,
+    ) -> Result<Option<ZstdDictionary>> {
+        if self.config.zstd_dict_size == 0 || !self.config.compression.uses_zstd_dictionary() || total_tiles < 2 {
+            return Ok(None);
+        }
+
+        let step = total_tiles.div_ceil(ZSTD_DICT_MAX_SAMPLES).max(1);
+        let mut samples = Vec::with_capacity(total_tiles.div_ceil(step));
+        for idx in (0..total_tiles).step_by(step) {
+            let mut tile = sample_tile(idx)?;
+            tile.truncate(ZSTD_DICT_SAMPLE_BYTES);
+            samples.push(tile);
+        }
+        Ok(zstd::dict::from_samples(&samples, self.config.zstd_dict_size as usize)
+            .ok()
+            .map(|dict| ZstdDictionary::new(dict, self.config.zstd_level)))
+    }
+
+    fn tile_dims(&self) -> (u32, u32) {
+        (self.config.tile_size, self.config.tile_height.unwrap_or(self.config.tile_size))
+    }
+
+    /// Pyramid levels always use square tiles; the base geometry usually
+    /// mirrors the source layout, which does not apply to downsampled levels.
+    fn pyramid_tile_size(&self) -> u32 {
+        self.config.tile_size.min(1024)
+    }
+
+    /// SHA-256 of the pixels this encoder would store for `img`, comparable
+    /// with [`FixityDigests::image`].
+    pub fn image_fixity(&self, img: &TiffImage) -> Result<Sha256Digest> {
+        Ok(Sha256::digest(&self.prepare_image_for_storage(img)?.data).into())
+    }
+
+    fn prepare_image_for_storage(&self, img: &TiffImage) -> Result<TiffImage> {
+        let stored_color_type = storage_color_type(img.color_type, self.config.downcast_16_to_8);
+        if stored_color_type == img.color_type {
+            return Ok(img.clone());
+        }
+
+        Ok(TiffImage {
+            width: img.width,
+            height: img.height,
+            color_type: stored_color_type,
+            data: convert_tile_for_storage(&img.data, img.color_type, stored_color_type)?,
+            xdpi: img.xdpi,
+            ydpi: img.ydpi,
+            icc: img.icc.clone(),
+            bands: img.bands.clone(),
+            descriptive: img.descriptive.clone(),
+        })
+    }
+
+    pub fn load_image<P: AsRef<Path>>(&self, path: P) -> Result<TiffImage> {
+        let p = path.as_ref();
+        let file = File::open(p).with_context(|| format!("open {:?}", p))?;
+        let mut br = BufReader::new(file);
+
+        let (width, height, color_type, data, icc_from_source) = match TiffDecoder::new(&mut br) {
+            // `image` converts CMYK to RGB and bilevel to L8; keep both via the
+            // raw TIFF path below.
+            Ok(mut d)
+                if !matches!(
+                    d.original_color_type(),
+                    image::ExtendedColorType::Cmyk8 | image::ExtendedColorType::Cmyk16 | image::ExtendedColorType::L1
+                ) =>
+            {
+                let (w, h) = d.dimensions();
+                let ct = color_type_from_image(d.color_type())?;
+                let icc = d.icc_profile().ok().flatten();
+                let mut buf = vec![0u8; d.total_bytes() as usize];
+                d.read_image(&mut buf)?;
+                (w, h, ct, buf, icc)
+            }
+            _ if is_tiff_path(p) => {
+                let mut source = TiffTileSource::open(p)?;
+                let (w, h) = source.dimensions();
+                let ct = source.color_type();
+                let buf = source.read_tile(0, 0, (w, h))?;
+                (w, h, ct, buf, source.metadata().icc)
+            }
+            _ => {
+                let dynimg =
+                    image::open(p).with_context(|| format!("image::open fallback for {:?}", p))?;
+                let (w, h) = dynimg.dimensions();
+                let (ct, buf) = dynamic_image_into_raw(dynimg);
+                (w, h, ct, buf, None)
+            }
+        };
+
+        let (xdpi, ydpi, icc_from_tags) =
+            read_tiff_metadata_for_sections(p).unwrap_or((None, None, None));
+        let bands = read_tiff_band_info(p, color_type).unwrap_or(None);
+        let descriptive = read_descriptive_metadata(p).unwrap_or_default();
+
+        Ok(TiffImage {
             width,
             height,
             color_type,
             data,
             xdpi,
             ydpi,
-            icc: icc_from_decoder.or(icc_from_tags),
+            icc: icc_from_source.or(icc_from_tags),
+            bands,
+            descriptive,
         })
     }
 
@@ -352,6 +1385,18 @@ impl CTIEncoder {
         if is_tiff_path(p) {
             return inspect_tiff_input(p);
         }
+        if is_png_path(p) && let Ok(source) = PngRowSource::open(p) {
+            let (width, height) = source.dimensions();
+            let meta = source.metadata();
+            return Ok(InputImageInfo {
+                width,
+                height,
+                color_type: source.color_type(),
+                xdpi: meta.xdpi,
+                ydpi: meta.ydpi,
+                icc_size: meta.icc.as_ref().map(Vec::len),
+            });
+        }
 
         let dynimg = image::open(p).with_context(|| format!("image::open for {:?}", p))?;
         let (w, h) = dynimg.dimensions();
@@ -368,8 +1413,10 @@ impl CTIEncoder {
 
     pub fn encode_path_to_cti<P: AsRef<Path>, Q: AsRef<Path>>(&self, input_path: P, out_path: Q) -> Result<()> {
         if is_tiff_path(input_path.as_ref()) {
-            match self.encode_tiff_streaming(input_path.as_ref(), out_path.as_ref()) {
-                Ok(()) => return Ok(()),
+            let streamed = TiffTileSource::open(input_path.as_ref())
+                .and_then(|mut source| self.encode_source_to_cti(&mut source, out_path.as_ref()));
+            match streamed {
+                Ok(()) => Ok(()),
                 Err(err) => {
                     if self.config.pyramid_levels > 0
                         || self.config.tile_size >= 1024
@@ -388,6 +1435,13 @@ impl CTIEncoder {
                 }
             }
         } else {
+            // Interlaced PNGs and layouts CTI cannot store as-is go through
+            // the full decoder, which converts them.
+            if is_png_path(input_path.as_ref())
+                && let Ok(mut source) = PngRowSource::open(input_path.as_ref())
+            {
+                return self.encode_source_to_cti(&mut source, out_path.as_ref());
+            }
             let img = self.load_image(input_path.as_ref())?;
             self.encode_to_cti(&img, out_path.as_ref())
         }
@@ -421,7 +1475,9 @@ impl CTIEncoder {
             current = downsample_half(&current)?;
             let cfg = CTIConfig {
                 tile_size: self.pyramid_tile_size(),
+                tile_height: None,
                 pyramid_levels: 0,
+                fixity: FixityScope::None,
                 ..self.config.clone()
             };
             let enc = CTIEncoder::new(cfg);
@@ -432,70 +1488,124 @@ impl CTIEncoder {
         Ok(out)
     }
 
-    fn encode_tiff_streaming(&self, input_path: &Path, out_path: &Path) -> Result<()> {
-        let file = File::open(input_path).with_context(|| format!("open {:?}", input_path))?;
-        let reader = BufReader::new(file);
-        let mut dec = RawTiffDecoder::new(reader)?.with_limits(TiffLimits::unlimited());
-
-        let (width, height) = dec.dimensions()?;
-        let color_type = tiff_color_type_to_image_color_type(dec.colortype()?)?;
-        let stored_color_type = storage_color_type(color_type, self.config.downcast_16_to_8);
-        let bpp = bytes_per_pixel(&color_type)?;
-        let chunky = dec
-            .find_tag_unsigned::<u16>(TiffTag::PlanarConfiguration)?
-            .unwrap_or(1);
-        ensure!(chunky == 1, "Only chunky TIFF input is supported for streaming encode");
-
-        let chunk_type = dec.get_chunk_type();
-        let (chunk_w_default, chunk_h_default) = dec.chunk_dimensions();
-        ensure!(chunk_w_default > 0 && chunk_h_default > 0, "TIFF chunk dimensions must be non-zero");
+    /// Streams `source` into a CTI at `out_path` without holding the whole
+    /// raster; pyramid levels are built from the written file afterwards.
+    pub fn encode_source_to_cti<S: TileSource + ?Sized, P: AsRef<Path>>(&self, source: &mut S, out_path: P) -> Result<()> {
+        let out_path = out_path.as_ref();
+        let fixity = self.fixity_scope()?;
+        let metadata = source.metadata();
+        let mut bw = BufWriter::new(File::create(out_path)?);
+        let streamed = self.encode_tiles_from_source(source, &mut bw, self.tile_dims(), fixity)?;
+        let cursor = streamed.tile_data.cursor;
+        bw.flush()?;
+        drop(bw);
 
-        let (xdpi, ydpi, icc) =
-            read_tiff_metadata_for_sections(input_path).unwrap_or((None, None, None));
+        let (width, height) = source.dimensions();
         let meta = TiffImage {
             width,
             height,
-            color_type: stored_color_type,
+            color_type: storage_color_type(source.color_type(), self.config.downcast_16_to_8),
             data: Vec::new(),
-            xdpi,
-            ydpi,
-            icc,
+            xdpi: metadata.xdpi,
+            ydpi: metadata.ydpi,
+            icc: metadata.icc,
+            bands: metadata.bands,
+            descriptive: metadata.descriptive,
         };
+        let mut base_sections = build_metadata_sections(&meta);
+        base_sections.extend(streamed.tile_data.tile_sections());
+        base_sections.extend(self.codec_sections(streamed.zstd_dict.as_ref()));
+        if let Some(digests) = &streamed.fixity {
+            base_sections.push((SEC_TYPE_FXTY, digests.to_section()));
+        }
+        let header = streamed.header;
+        let indices = &streamed.tile_data.indices;
 
-        let mut bw = BufWriter::new(File::create(out_path)?);
+        {
+            let mut section_writer = OpenOptions::new().read(true).write(true).open(out_path)?;
+            section_writer.seek(SeekFrom::Start(cursor))?;
+            let (end, toc_crc) = write_sections_with_staged(&mut section_writer, &base_sections, &[], true)?;
+            section_writer.set_len(end)?;
+            write_checksummed_header(&mut section_writer, &header, indices, toc_crc)?;
+        }
 
-        let tiles_x = width.div_ceil(self.config.tile_size);
-        let tiles_y = height.div_ceil(self.config.tile_size);
-        let total_tiles = (tiles_x * tiles_y) as usize;
-        let color_type_id = color_type_to_id(stored_color_type)?;
+        let pyramid_sections = self.build_pyramid_sections_streaming_to_files(out_path)?;
+        let rewrite_result = {
+            let mut section_writer = OpenOptions::new().read(true).write(true).open(out_path)?;
+            section_writer.seek(SeekFrom::Start(cursor))?;
+            let (end, toc_crc) =
+                write_sections_with_staged(&mut section_writer, &base_sections, &pyramid_sections, true)?;
+            section_writer.set_len(end)?;
+            write_checksummed_header(&mut section_writer, &header, indices, toc_crc)?;
+            Ok::<(), CtiError>(())
+        };
+        for section in &pyramid_sections {
+            let _ = remove_file(&section.path);
+        }
+        rewrite_result?;
+        Ok(())
+    }
 
-        let color_transform = color_transform_mode_for_encode(self.config.color_transform, stored_color_type);
-        let flags = color_transform_flags(color_transform);
+    /// Writes the header placeholder, then reads, transforms and compresses
+    /// the tiles of `source` in parallel batches. The index, sections and
+    /// final header are left to the caller.
+    fn encode_tiles_from_source<S: TileSource + ?Sized, W: Write + Seek>(
+        &self,
+        source: &mut S,
+        w: &mut W,
+        tile_dims: (u32, u32),
+        fixity: FixityScope,
+    ) -> Result<StreamedTiles> {
+        let (width, height) = source.dimensions();
+        let color_type = source.color_type();
+        let stored_color_type = storage_color_type(color_type, self.config.downcast_16_to_8);
+        let tiles_x = width.div_ceil(tile_dims.0);
+        let tiles_y = height.div_ceil(tile_dims.1);
+        let total_tiles = tiles_x as usize * tiles_y as usize;
 
+        let color_transform = color_transform_mode_for_encode(&self.config, stored_color_type);
         let header = CTIHeader::new(
             width,
             height,
-            self.config.tile_size,
+            tile_dims.0,
             tiles_x,
             tiles_y,
-            color_type_id,
+            color_type_to_id(stored_color_type)?,
             self.config.compression as u8,
             self.config.quality_level,
-            flags,
-        );
-        write_header(&mut bw, &header)?;
+            color_transform_flags(color_transform),
+        )
+        .with_tile_height(tile_dims.1);
+        write_header(w, &header)?;
 
-        let index_offset = CTI_HEADER_SIZE as u64;
-        let index_size = total_tiles * TILE_INDEX_ONDISK_SIZE;
-        let data_offset = index_offset + index_size as u64;
-        bw.seek(SeekFrom::Start(data_offset))?;
+        let data_offset = (CTI_HEADER_SIZE + total_tiles * TILE_INDEX_ONDISK_SIZE) as u64;
+        w.seek(SeekFrom::Start(data_offset))?;
 
-        let mut indices = Vec::with_capacity(total_tiles);
-        let mut cursor = data_offset;
-        let mut adaptive_modes = Vec::with_capacity(total_tiles);
-        let batch_tiles = streaming_batch_tiles(self.config.tile_size, bpp);
+        let mut tile_data = TileDataWriter::new(data_offset, total_tiles, self.config.dedup_tiles);
+        let batch_tiles = streaming_batch_tiles(tile_dims, bytes_per_pixel(&color_type)?);
         let mut next_idx = 0usize;
 
+        let mut hasher = match fixity {
+            FixityScope::None => None,
+            scope => Some(RasterHasher::new(
+                width,
+                height,
+                tile_dims,
+                bytes_per_pixel(&stored_color_type)? as usize,
+                scope == FixityScope::Tiles,
+            )),
+        };
+        let mut read_tile = |idx: usize| -> Result<Vec<u8>> {
+            let tile = source.read_tile(idx as u32 % tiles_x, idx as u32 / tiles_x, tile_dims)?;
+            convert_tile_for_storage(&tile, color_type, stored_color_type)
+        };
+        let zstd_dict = self.train_zstd_dictionary(total_tiles, |idx| {
+            let mut tile = read_tile(idx)?;
+            apply_color_transform_forward(color_transform, stored_color_type, &mut tile);
+            Ok(tile)
+        })?;
+        let params = self.codec_params(zstd_dict.as_ref());
+
         while next_idx < total_tiles {
             let batch_end = (next_idx + batch_tiles).min(total_tiles);
             let mut prepared_tiles = Vec::with_capacity(batch_end - next_idx);
@@ -503,24 +1613,14 @@ impl CTIEncoder {
             for idx in next_idx..batch_end {
                 let tx = (idx as u32) % tiles_x;
                 let ty = (idx as u32) / tiles_x;
-                let mut tile = extract_tiff_tile(
-                    &mut dec,
-                    width,
-                    height,
-                    color_type,
-                    bpp,
-                    self.config.tile_size,
-                    tx,
-                    ty,
-                    chunk_type,
-                    chunk_w_default,
-                    chunk_h_default,
-                )?;
-                tile = convert_tile_for_storage(&tile, color_type, stored_color_type)?;
+                let mut tile = read_tile(idx)?;
+                if let Some(hasher) = hasher.as_mut() {
+                    hasher.push_tile(tile.clone());
+                }
                 apply_color_transform_forward(color_transform, stored_color_type, &mut tile);
                 prepared_tiles.push(PreparedTile {
                     tile,
-                    tile_extent: tile_extent(width, height, self.config.tile_size, tx, ty),
+                    tile_extent: tile_extent(width, height, tile_dims, tx, ty),
                 });
             }
 
@@ -532,61 +1632,27 @@ impl CTIEncoder {
                         stored_color_type,
                         prepared.tile,
                         prepared.tile_extent,
-                        self.config.zstd_level,
+                        &params,
+                        self.config.dedup_tiles,
                     )
                 })
                 .collect::<Result<Vec<_>>>()?;
 
-            for comp in comp_tiles {
-                if let Some(mode) = comp.adaptive_mode {
-                    adaptive_modes.push(mode as u8);
-                }
-                bw.write_all(&comp.comp)?;
-                indices.push(TileIndex {
-                    offset: cursor,
-                    compressed_size: comp.comp.len() as u32,
-                    original_size: comp.orig_len,
-                    crc32: comp.crc,
-                });
-                cursor += comp.comp.len() as u64;
+            for comp in &comp_tiles {
+                tile_data.write_tile(w, comp)?;
             }
 
             next_idx = batch_end;
         }
 
-        bw.seek(SeekFrom::Start(index_offset))?;
-        for idx in &indices {
-            write_tile_index(&mut bw, idx)?;
-        }
-
-        bw.flush()?;
-        drop(bw);
-
-        let mut base_sections = build_metadata_sections(&meta);
-        if !adaptive_modes.is_empty() {
-            base_sections.push((SEC_TYPE_TMOD, adaptive_modes));
-        }
-
-        {
-            let mut section_writer = OpenOptions::new().read(true).write(true).open(out_path)?;
-            section_writer.seek(SeekFrom::Start(cursor))?;
-            let end = write_sections_with_staged(&mut section_writer, &base_sections, &[])?;
-            section_writer.set_len(end)?;
-        }
-
-        let pyramid_sections = self.build_pyramid_sections_streaming_to_files(out_path)?;
-        let rewrite_result = {
-            let mut section_writer = OpenOptions::new().read(true).write(true).open(out_path)?;
-            section_writer.seek(SeekFrom::Start(cursor))?;
-            let end = write_sections_with_staged(&mut section_writer, &base_sections, &pyramid_sections)?;
-            section_writer.set_len(end)?;
-            Ok::<(), anyhow::Error>(())
-        };
-        for section in &pyramid_sections {
-            let _ = remove_file(&section.path);
-        }
-        rewrite_result?;
-        Ok(())
+        tile_data.write_indices(w)?;
+        w.seek(SeekFrom::Start(tile_data.cursor))?;
+        Ok(StreamedTiles {
+            header,
+            tile_data,
+            zstd_dict,
+            fixity: hasher.map(RasterHasher::finish),
+        })
     }
 
     fn build_pyramid_sections_streaming_to_files(&self, base_cti_path: &Path) -> Result<Vec<StagedSectionFile>> {
@@ -632,215 +1698,302 @@ impl CTIEncoder {
     fn encode_downsampled_level_from_cti_reader<R: Read + Seek, W: Write + Seek>(
         &self,
         source: &mut R,
-        mut out: &mut W,
+        out: &mut W,
     ) -> Result<u64> {
-        let layout = scan_cti_layout(source)?;
+        // The encoder reads back a file it has just written itself.
+        let layout = scan_cti_layout(source, &DecodeLimits::unlimited(), 0)?;
         if layout.header.width <= 1 && layout.header.height <= 1 {
             return Ok(0);
         }
 
-        let src_header = layout.header;
-        let color_type = color_type_from_id(src_header.color_type)?;
-        let dst_width = src_header.width.div_ceil(2);
-        let dst_height = src_header.height.div_ceil(2);
         let level_tile_size = self.pyramid_tile_size();
-        let tiles_x = dst_width.div_ceil(level_tile_size);
-        let tiles_y = dst_height.div_ceil(level_tile_size);
-        let total_tiles = (tiles_x * tiles_y) as usize;
+        let mut half = DownsampledCtiSource::new(source, layout)?;
+        let (dst_width, dst_height) = half.dimensions();
         trace_pyramid(format!(
             "encode pyramid level: {}x{} tile={} tiles={}x{}",
-            dst_width, dst_height, level_tile_size, tiles_x, tiles_y
+            dst_width,
+            dst_height,
+            level_tile_size,
+            dst_width.div_ceil(level_tile_size),
+            dst_height.div_ceil(level_tile_size)
         ));
 
-        let color_transform = color_transform_mode_for_encode(self.config.color_transform, color_type);
+        let streamed =
+            self.encode_tiles_from_source(&mut half, out, (level_tile_size, level_tile_size), FixityScope::None)?;
+        let mut sections = streamed.tile_data.tile_sections();
+        sections.extend(self.codec_sections(streamed.zstd_dict.as_ref()));
+        let (end, toc_crc) = write_sections_with_staged(out, &sections, &[], true)?;
+        write_checksummed_header(out, &streamed.header, &streamed.tile_data.indices, toc_crc)?;
+        Ok(end)
+    }
+
+    fn encode_image_with_sections<W: Write + Seek>(
+        &self,
+        img: &TiffImage,
+        w: &mut W,
+        extra_sections: &[(u32, Vec<u8>)],
+    ) -> Result<()> {
+        let tile_dims = self.tile_dims();
+        let tiles_x = img.width.div_ceil(tile_dims.0);
+        let tiles_y = img.height.div_ceil(tile_dims.1);
+        let total_tiles = (tiles_x * tiles_y) as usize;
+
+        let color_type_id = color_type_to_id(img.color_type)?;
+        let fixity = self.fixity_scope()?;
+        let color_transform = color_transform_mode_for_encode(&self.config, img.color_type);
         let flags = color_transform_flags(color_transform);
 
         let header = CTIHeader::new(
-            dst_width,
-            dst_height,
-            level_tile_size,
+            img.width,
+            img.height,
+            self.config.tile_size,
             tiles_x,
             tiles_y,
-            src_header.color_type,
+            color_type_id,
             self.config.compression as u8,
             self.config.quality_level,
             flags,
-        );
+        )
+        .with_tile_height(tile_dims.1);
+        write_header(w, &header)?;
 
-        write_header(&mut out, &header)?;
         let index_offset = CTI_HEADER_SIZE as u64;
         let index_size = total_tiles * TILE_INDEX_ONDISK_SIZE;
         let data_offset = index_offset + index_size as u64;
-        out.seek(SeekFrom::Start(data_offset))?;
-
-        let mut indices = Vec::with_capacity(total_tiles);
-        let mut cursor = data_offset;
-        let mut adaptive_modes = Vec::with_capacity(total_tiles);
-        let batch_tiles =
-            streaming_batch_tiles(level_tile_size, bytes_per_pixel(&color_type)?);
-        let mut next_idx = 0usize;
-        let mut source_tile_cache: HashMap<usize, Vec<u8>> = HashMap::new();
+        w.seek(SeekFrom::Start(data_offset))?;
 
-        while next_idx < total_tiles {
-            let batch_end = (next_idx + batch_tiles).min(total_tiles);
-            let mut prepared_tiles = Vec::with_capacity(batch_end - next_idx);
-            let batch_ty = (next_idx as u32) / tiles_x;
-            let min_keep_source_row = (batch_ty * level_tile_size * 2) / src_header.tile_size;
-            source_tile_cache.retain(|idx, _| {
-                let source_row = (*idx as u32) / src_header.tiles_x;
-                source_row >= min_keep_source_row
-            });
+        let prepare_tile = |idx: usize| -> Result<Vec<u8>> {
+            let tx = (idx as u32) % tiles_x;
+            let ty = (idx as u32) / tiles_x;
+            let mut tile = extract_tile(img, tx, ty, tile_dims)?;
+            apply_color_transform_forward(color_transform, img.color_type, &mut tile);
+            Ok(tile)
+        };
+        let zstd_dict = self.train_zstd_dictionary(total_tiles, prepare_tile)?;
+        let params = self.codec_params(zstd_dict.as_ref());
 
-            for idx in next_idx..batch_end {
+        let comp_tiles: Vec<CompTile> = (0..total_tiles)
+            .into_par_iter()
+            .map(|idx| -> Result<CompTile> {
                 let tx = (idx as u32) % tiles_x;
                 let ty = (idx as u32) / tiles_x;
-                if idx == 0 {
-                    trace_pyramid(format!("encode pyramid first tile ({}, {})", tx, ty));
-                }
-                let mut tile = downsample_cti_tile_from_layout(
-                    source,
-                    &layout,
-                    color_type,
-                    level_tile_size,
-                    tx,
-                    ty,
-                    &mut source_tile_cache,
-                )?;
-
-                apply_color_transform_forward(color_transform, color_type, &mut tile);
-                prepared_tiles.push(PreparedTile {
-                    tile,
-                    tile_extent: tile_extent(dst_width, dst_height, level_tile_size, tx, ty),
-                });
-            }
-
-            let comp_tiles: Vec<CompTile> = prepared_tiles
-                .into_par_iter()
-                .map(|prepared| {
-                    compress_prepared_tile(
-                        self.config.compression,
-                        color_type,
-                        prepared.tile,
-                        prepared.tile_extent,
-                        self.config.zstd_level,
-                    )
-                })
-                .collect::<Result<Vec<_>>>()?;
-
-            for comp in comp_tiles {
-                if let Some(mode) = comp.adaptive_mode {
-                    adaptive_modes.push(mode as u8);
-                }
-                out.write_all(&comp.comp)?;
-                indices.push(TileIndex {
-                    offset: cursor,
-                    compressed_size: comp.comp.len() as u32,
-                    original_size: comp.orig_len,
-                    crc32: comp.crc,
-                });
-                cursor += comp.comp.len() as u64;
-            }
-
-            next_idx = batch_end;
-        }
+                compress_prepared_tile(
+                    self.config.compression,
+                    img.color_type,
+                    prepare_tile(idx)?,
+                    tile_extent(img.width, img.height, tile_dims, tx, ty),
+                    &params,
+                    self.config.dedup_tiles,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
 
-        out.seek(SeekFrom::Start(index_offset))?;
-        for idx in &indices {
-            write_tile_index(&mut out, idx)?;
+        let mut tile_data = TileDataWriter::new(data_offset, total_tiles, self.config.dedup_tiles);
+        for ct in &comp_tiles {
+            tile_data.write_tile(w, ct)?;
         }
-        out.seek(SeekFrom::Start(cursor))?;
-        let mut sections = Vec::new();
-        if !adaptive_modes.is_empty() {
-            sections.push((SEC_TYPE_TMOD, adaptive_modes));
+        tile_data.write_indices(w)?;
+
+        w.seek(SeekFrom::Start(tile_data.cursor))?;
+        let mut sections = tile_data.tile_sections();
+        sections.extend(self.codec_sections(zstd_dict.as_ref()));
+        if fixity != FixityScope::None {
+            let tiles = if fixity == FixityScope::Tiles {
+                (0..total_tiles)
+                    .into_par_iter()
+                    .map(|idx| {
+                        let tile = extract_tile(img, idx as u32 % tiles_x, idx as u32 / tiles_x, tile_dims)?;
+                        Ok(Sha256::digest(&tile).into())
+                    })
+                    .collect::<Result<Vec<_>>>()?
+            } else {
+                Vec::new()
+            };
+            let digests = FixityDigests {
+                image: Sha256::digest(&img.data).into(),
+                tiles,
+            };
+            sections.push((SEC_TYPE_FXTY, digests.to_section()));
         }
-        write_sections(&mut out, &sections)?;
-        Ok(out.stream_position()?)
+        sections.extend_from_slice(extra_sections);
+        let (_, toc_crc) = write_sections_with_staged(w, &sections, &[], true)?;
+        write_checksummed_header(w, &header, &tile_data.indices, toc_crc)?;
+        Ok(())
     }
+}
 
-    fn encode_image_with_sections<W: Write + Seek>(
-        &self,
-        img: &TiffImage,
-        w: &mut W,
-        extra_sections: &[(u32, Vec<u8>)],
-    ) -> Result<()> {
-        let tiles_x = img.width.div_ceil(self.config.tile_size);
-        let tiles_y = img.height.div_ceil(self.config.tile_size);
-        let total_tiles = (tiles_x * tiles_y) as usize;
-
-        let color_type_id = color_type_to_id(img.color_type)?;
-        let color_transform = color_transform_mode_for_encode(self.config.color_transform, img.color_type);
-        let flags = color_transform_flags(color_transform);
-
+/// Incremental encoder for images that arrive tile by tile, in any order.
+/// Tile payloads are appended to `w` as they come in and the index, codec
+/// sections and checksummed header are written by [`finish`](Self::finish).
+///
+/// A Zstd dictionary must be trained before the first tile and pyramid
+/// levels need the whole image, so `zstd_dict_size` and `pyramid_levels`
+/// must be zero; levels encoded separately can be passed to `finish` as
+/// `PYLV` sections. With fixity enabled, tiles that arrive ahead of raster
+/// order are kept in memory until the gap before them is filled.
+pub struct CTIWriter<W: Write + Seek> {
+    w: W,
+    encoder: CTIEncoder,
+    header: CTIHeader,
+    color_type: ColorType,
+    stored_color_type: ColorType,
+    color_transform: ColorTransformMode,
+    tile_dims: (u32, u32),
+    tile_data: TileDataWriter,
+    written: Vec<bool>,
+    remaining: usize,
+    fixity: Option<RasterHasher>,
+    fixity_pending: BTreeMap<usize, Vec<u8>>,
+    fixity_next: usize,
+}
+
+impl<W: Write + Seek> CTIWriter<W> {
+    /// Starts a CTI of `width x height` pixels whose tiles will be supplied
+    /// as `color_type` samples; `config.downcast_16_to_8` still applies.
+    pub fn new(mut w: W, width: u32, height: u32, color_type: ColorType, config: CTIConfig) -> Result<Self> {
+        ensure!(
+            config.pyramid_levels == 0,
+            CtiError::InvalidInput("CTIWriter cannot build pyramid levels; pass PYLV sections to finish".into())
+        );
+        ensure!(
+            config.zstd_dict_size == 0,
+            CtiError::InvalidInput("CTIWriter cannot train a Zstd dictionary".into())
+        );
+        let encoder = CTIEncoder::new(config);
+        let tile_dims = encoder.tile_dims();
+        ensure!(
+            tile_dims.0 > 0 && tile_dims.1 > 0,
+            CtiError::InvalidInput(format!("Invalid tile dimensions {}x{}", tile_dims.0, tile_dims.1))
+        );
+        let fixity_scope = encoder.fixity_scope()?;
+        let stored_color_type = storage_color_type(color_type, encoder.config.downcast_16_to_8);
+        bytes_per_pixel(&color_type)?;
+        let color_transform = color_transform_mode_for_encode(&encoder.config, stored_color_type);
+
+        let tiles_x = width.div_ceil(tile_dims.0);
+        let tiles_y = height.div_ceil(tile_dims.1);
+        let total_tiles = tiles_x as usize * tiles_y as usize;
         let header = CTIHeader::new(
-            img.width,
-            img.height,
-            self.config.tile_size,
+            width,
+            height,
+            tile_dims.0,
             tiles_x,
             tiles_y,
-            color_type_id,
-            self.config.compression as u8,
-            self.config.quality_level,
-            flags,
-        );
-        write_header(w, &header)?;
-
-        let index_offset = CTI_HEADER_SIZE as u64;
-        let index_size = total_tiles * TILE_INDEX_ONDISK_SIZE;
-        let data_offset = index_offset + index_size as u64;
+            color_type_to_id(stored_color_type)?,
+            encoder.config.compression as u8,
+            encoder.config.quality_level,
+            color_transform_flags(color_transform),
+        )
+        .with_tile_height(tile_dims.1);
+        w.seek(SeekFrom::Start(0))?;
+        write_header(&mut w, &header)?;
+        let data_offset = (CTI_HEADER_SIZE + total_tiles * TILE_INDEX_ONDISK_SIZE) as u64;
         w.seek(SeekFrom::Start(data_offset))?;
 
-        let zstd_level = self.config.zstd_level;
-
-        let comp_tiles: Vec<CompTile> = (0..total_tiles)
-            .into_par_iter()
-            .map(|idx| -> Result<CompTile> {
-                let tx = (idx as u32) % tiles_x;
-                let ty = (idx as u32) / tiles_x;
+        let fixity = match fixity_scope {
+            FixityScope::None => None,
+            scope => Some(RasterHasher::new(
+                width,
+                height,
+                tile_dims,
+                bytes_per_pixel(&stored_color_type)? as usize,
+                scope == FixityScope::Tiles,
+            )),
+        };
+        Ok(Self {
+            w,
+            tile_data: TileDataWriter::new(data_offset, total_tiles, encoder.config.dedup_tiles),
+            encoder,
+            header,
+            color_type,
+            stored_color_type,
+            color_transform,
+            tile_dims,
+            written: vec![false; total_tiles],
+            remaining: total_tiles,
+            fixity,
+            fixity_pending: BTreeMap::new(),
+            fixity_next: 0,
+        })
+    }
 
-                let mut tile = extract_tile(img, tx, ty, self.config.tile_size)?;
-                apply_color_transform_forward(color_transform, img.color_type, &mut tile);
-                compress_prepared_tile(
-                    self.config.compression,
-                    img.color_type,
-                    tile,
-                    tile_extent(img.width, img.height, self.config.tile_size, tx, ty),
-                    zstd_level,
-                )
-            })
-            .collect::<Result<Vec<_>>>()?;
+    pub fn header(&self) -> &CTIHeader {
+        &self.header
+    }
 
-        let mut indices: Vec<TileIndex> = Vec::with_capacity(total_tiles);
-        let mut cursor = data_offset;
-        for ct in &comp_tiles {
-            w.write_all(&ct.comp)?;
-            indices.push(TileIndex {
-                offset: cursor,
-                compressed_size: ct.comp.len() as u32,
-                original_size: ct.orig_len,
-                crc32: ct.crc,
-            });
-            cursor += ct.comp.len() as u64;
-        }
+    /// Compresses and appends tile `(tx, ty)`. `data` holds the tile's rows
+    /// tightly packed; edge tiles are cropped to the image like everywhere
+    /// else in the format.
+    pub fn write_tile(&mut self, tx: u32, ty: u32, data: &[u8]) -> Result<()> {
+        let hdr = &self.header;
+        ensure!(
+            tx < hdr.tiles_x && ty < hdr.tiles_y,
+            CtiError::OutOfRange(format!("Tile {},{} out of range", tx, ty))
+        );
+        let idx = ty as usize * hdr.tiles_x as usize + tx as usize;
+        ensure!(
+            !self.written[idx],
+            CtiError::InvalidInput(format!("Tile {},{} was already written", tx, ty))
+        );
+        let extent = tile_extent(hdr.width, hdr.height, self.tile_dims, tx, ty);
+        let expected = extent.0 as usize * extent.1 as usize * bytes_per_pixel(&self.color_type)? as usize;
+        ensure!(
+            data.len() == expected,
+            CtiError::InvalidInput(format!(
+                "Tile {},{} has {} bytes, expected {} for {}x{} pixels",
+                tx,
+                ty,
+                data.len(),
+                expected,
+                extent.0,
+                extent.1
+            ))
+        );
 
-        w.seek(SeekFrom::Start(index_offset))?;
-        for idx in &indices {
-            write_tile_index(w, idx)?;
+        let mut tile = convert_tile_for_storage(data, self.color_type, self.stored_color_type)?;
+        if let Some(hasher) = self.fixity.as_mut() {
+            self.fixity_pending.insert(idx, tile.clone());
+            while let Some(next) = self.fixity_pending.remove(&self.fixity_next) {
+                hasher.push_tile(next);
+                self.fixity_next += 1;
+            }
         }
+        apply_color_transform_forward(self.color_transform, self.stored_color_type, &mut tile);
+        let config = &self.encoder.config;
+        let comp = compress_prepared_tile(
+            config.compression,
+            self.stored_color_type,
+            tile,
+            extent,
+            &self.encoder.codec_params(None),
+            config.dedup_tiles,
+        )?;
+        self.tile_data.write_tile_at(&mut self.w, idx, &comp)?;
+        self.written[idx] = true;
+        self.remaining -= 1;
+        Ok(())
+    }
 
-        w.seek(SeekFrom::Start(cursor))?;
-        let mut sections = Vec::with_capacity(extra_sections.len() + 1);
-        if comp_tiles.iter().any(|tile| tile.adaptive_mode.is_some()) {
-            sections.push((
-                SEC_TYPE_TMOD,
-                comp_tiles
-                    .iter()
-                    .map(|tile| tile.adaptive_mode.expect("adaptive mode missing") as u8)
-                    .collect(),
-            ));
+    /// Writes the tile index, codec and fixity sections followed by
+    /// `sections`, stamps the header checksums and returns the writer.
+    pub fn finish(mut self, sections: &[(u32, Vec<u8>)]) -> Result<W> {
+        ensure!(
+            self.remaining == 0,
+            CtiError::InvalidInput(format!("{} tiles were never written", self.remaining))
+        );
+        let w = &mut self.w;
+        self.tile_data.write_indices(w)?;
+        w.seek(SeekFrom::Start(self.tile_data.cursor))?;
+        let mut all_sections = self.tile_data.tile_sections();
+        all_sections.extend(self.encoder.codec_sections(None));
+        if let Some(hasher) = self.fixity {
+            all_sections.push((SEC_TYPE_FXTY, hasher.finish().to_section()));
         }
-        sections.extend_from_slice(extra_sections);
-        write_sections(w, &sections)?;
-        Ok(())
+        all_sections.extend_from_slice(sections);
+        let (_, toc_crc) = write_sections_with_staged(w, &all_sections, &[], true)?;
+        write_checksummed_header(w, &self.header, &self.tile_data.indices, toc_crc)?;
+        w.flush()?;
+        Ok(self.w)
     }
 }
 
@@ -853,45 +2006,47 @@ fn trace_pyramid(message: impl AsRef<str>) {
 pub struct CTIDecoder;
 
 impl CTIDecoder {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<CTIReader<BufReader<File>>> {
+        CTIReader::open(path)
+    }
+
+    pub fn open_with_limits<P: AsRef<Path>>(path: P, limits: DecodeLimits) -> Result<CTIReader<BufReader<File>>> {
+        CTIReader::open_with_limits(path, limits)
+    }
+
+    /// Memory-mapped reader for serving tiles concurrently from many threads.
+    pub fn open_mmap<P: AsRef<Path>>(path: P) -> Result<CTIMmapReader> {
+        CTIMmapReader::open(path)
+    }
+
     pub fn info<P: AsRef<Path>>(path: P) -> Result<CTIFileInfo> {
-        let mut br = BufReader::new(File::open(path)?);
-        Self::info_from_reader(&mut br)
+        CTIReader::open(path)?.info()
     }
 
-    pub fn info_from_reader<R: Read + Seek>(r: &mut R) -> Result<CTIFileInfo> {
-        let layout = scan_cti_layout(r)?;
-        let sections = read_sections_from_descs(r, &layout.sections)?;
-        let (xdpi, ydpi) = sections
-            .iter()
-            .find(|sec| sec.ty == SEC_TYPE_RES)
-            .and_then(|sec| parse_resolution_section(&sec.payload))
-            .map(|(x, y)| (Some(x), Some(y)))
-            .unwrap_or((None, None));
-        let icc_size = sections
-            .iter()
-            .find(|sec| sec.ty == SEC_TYPE_ICC)
-            .map(|sec| sec.payload.len());
-        let pyramid_levels = parse_pyramid_levels(&sections)?;
+    pub fn verify<P: AsRef<Path>>(path: P) -> Result<VerifyReport> {
+        Self::verify_with_limits(path, &DecodeLimits::default())
+    }
 
-        Ok(CTIFileInfo {
-            header: layout.header,
-            indices: layout.indices,
-            sections: layout.sections,
-            xdpi,
-            ydpi,
-            icc_size,
-            pyramid_levels,
-        })
+    pub fn verify_with_limits<P: AsRef<Path>>(path: P, limits: &DecodeLimits) -> Result<VerifyReport> {
+        let p = path.as_ref();
+        let file = File::open(p).with_context(|| format!("open {:?}", p))?;
+        verify_cti_with_limits(&mut BufReader::new(file), limits)
+    }
+
+    pub fn verify_fixity<P: AsRef<Path>>(path: P) -> Result<Option<FixityReport>> {
+        CTIReader::open(path)?.verify_fixity()
+    }
+
+    pub fn info_from_reader<R: Read + Seek>(r: &mut R) -> Result<CTIFileInfo> {
+        CTIReader::new(r)?.info()
     }
 
     pub fn sections<P: AsRef<Path>>(path: P) -> Result<Vec<CTISection>> {
-        let mut br = BufReader::new(File::open(path)?);
-        Self::sections_from_reader(&mut br)
+        CTIReader::open(path)?.sections()
     }
 
     pub fn sections_from_reader<R: Read + Seek>(r: &mut R) -> Result<Vec<CTISection>> {
-        let layout = scan_cti_layout(r)?;
-        read_sections_from_descs(r, &layout.sections)
+        CTIReader::new(r)?.sections()
     }
 
     pub fn decode<P: AsRef<Path>>(path: P) -> Result<(CTIHeader, Vec<u8>)> {
@@ -905,30 +2060,15 @@ impl CTIDecoder {
     }
 
     pub fn decode_detailed<P: AsRef<Path>>(path: P, level: u32) -> Result<DecodedImage> {
-        let mut br = BufReader::new(File::open(path)?);
-        Self::decode_from_reader(&mut br, level)
+        CTIReader::open(path)?.decode(level)
     }
 
     pub fn decode_from_reader<R: Read + Seek>(r: &mut R, level: u32) -> Result<DecodedImage> {
-        if level > 0 {
-            let bytes = read_pyramid_level_bytes(r, level)?;
-            let mut cur = Cursor::new(bytes);
-            return Self::decode_from_reader(&mut cur, 0);
-        }
-
-        let layout = scan_cti_layout(r)?;
-        let data = decode_all_tiles(r, &layout)?;
-        let sections = read_sections_from_descs(r, &layout.sections)?;
-        Ok(DecodedImage {
-            header: layout.header,
-            data,
-            sections,
-        })
+        CTIReader::new(r)?.decode(level)
     }
 
     pub fn decode_tile<P: AsRef<Path>>(path: P, tx: u32, ty: u32, level: u32) -> Result<RasterRegion> {
-        let mut br = BufReader::new(File::open(path)?);
-        Self::decode_tile_from_reader(&mut br, tx, ty, level)
+        CTIReader::open(path)?.decode_tile(tx, ty, level)
     }
 
     pub fn decode_tile_from_reader<R: Read + Seek>(
@@ -937,27 +2077,7 @@ impl CTIDecoder {
         ty: u32,
         level: u32,
     ) -> Result<RasterRegion> {
-        if level > 0 {
-            let bytes = read_pyramid_level_bytes(r, level)?;
-            let mut cur = Cursor::new(bytes);
-            return Self::decode_tile_from_reader(&mut cur, tx, ty, 0);
-        }
-
-        let layout = scan_cti_layout(r)?;
-        ensure!(tx < layout.header.tiles_x, "Tile x {} out of range", tx);
-        ensure!(ty < layout.header.tiles_y, "Tile y {} out of range", ty);
-
-        let tile_index = (ty * layout.header.tiles_x + tx) as usize;
-        let tile = read_decoded_tile(r, &layout, &layout.indices[tile_index], tile_index)?;
-        let (tile_w, tile_h) =
-            tile_extent(layout.header.width, layout.header.height, layout.header.tile_size, tx, ty);
-
-        Ok(RasterRegion {
-            width: tile_w,
-            height: tile_h,
-            color_type: layout.header.color_type,
-            data: tile,
-        })
+        CTIReader::new(r)?.decode_tile(tx, ty, level)
     }
 
     pub fn extract_region<P: AsRef<Path>>(
@@ -968,8 +2088,7 @@ impl CTIDecoder {
         height: u32,
         level: u32,
     ) -> Result<RasterRegion> {
-        let mut br = BufReader::new(File::open(path)?);
-        Self::extract_region_from_reader(&mut br, x, y, width, height, level)
+        CTIReader::open(path)?.extract_region(x, y, width, height, level)
     }
 
     pub fn extract_region_from_reader<R: Read + Seek>(
@@ -980,2258 +2099,6208 @@ impl CTIDecoder {
         height: u32,
         level: u32,
     ) -> Result<RasterRegion> {
-        if level > 0 {
-            let bytes = read_pyramid_level_bytes(r, level)?;
-            let mut cur = Cursor::new(bytes);
-            return Self::extract_region_from_reader(&mut cur, x, y, width, height, 0);
-        }
-
-        let layout = scan_cti_layout(r)?;
-        ensure!(x < layout.header.width, "Region x {} out of range", x);
-        ensure!(y < layout.header.height, "Region y {} out of range", y);
-        ensure!(width > 0 && height > 0, "Region size must be positive");
-        ensure!(x + width <= layout.header.width, "Region exceeds image width");
-        ensure!(y + height <= layout.header.height, "Region exceeds image height");
-
-        let bpp = bytes_per_pixel_from_id(layout.header.color_type)?;
-        let mut out = vec![0u8; (width * height * bpp) as usize];
-        let ts = layout.header.tile_size;
-        let tile_x0 = x / ts;
-        let tile_y0 = y / ts;
-        let tile_x1 = (x + width - 1) / ts;
-        let tile_y1 = (y + height - 1) / ts;
-
-        for ty in tile_y0..=tile_y1 {
-            for tx in tile_x0..=tile_x1 {
-                let idx = (ty * layout.header.tiles_x + tx) as usize;
-                let tile = read_decoded_tile(r, &layout, &layout.indices[idx], idx)?;
-                let (tile_w, tile_h) = tile_extent(
-                    layout.header.width,
-                    layout.header.height,
-                    layout.header.tile_size,
-                    tx,
-                    ty,
-                );
-                blit_tile_region(
-                    &mut out,
-                    &tile,
-                    width,
-                    height,
-                    x,
-                    y,
-                    layout.header.tile_size,
-                    tile_w,
-                    tile_h,
-                    bpp,
-                    tx,
-                    ty,
-                )?;
-            }
-        }
-
-        Ok(RasterRegion {
-            width,
-            height,
-            color_type: layout.header.color_type,
-            data: out,
-        })
-    }
-}
-
-pub fn section_type_name(ty: u32) -> &'static str {
-    match ty {
-        SEC_TYPE_RES => "RES ",
-        SEC_TYPE_ICC => "ICC ",
-        SEC_TYPE_PYLV => "PYLV",
-        SEC_TYPE_TMOD => "TMOD",
-        _ => "????",
-    }
-}
-
-pub fn save_raster<P: AsRef<Path>>(
-    path: P,
-    width: u32,
-    height: u32,
-    color_type: u8,
-    data: &[u8],
-) -> Result<()> {
-    match color_type {
-        1 => {
-            let img: ImageBuffer<image::Luma<u8>, _> =
-                ImageBuffer::from_raw(width, height, data.to_vec()).context("raw->L8")?;
-            img.save(path)?;
-        }
-        2 => {
-            let img: ImageBuffer<image::Luma<u16>, _> =
-                ImageBuffer::from_raw(width, height, bytes_to_u16_vec(data)?).context("raw->L16")?;
-            img.save(path)?;
-        }
-        3 => {
-            let img: ImageBuffer<image::Rgb<u8>, _> =
-                ImageBuffer::from_raw(width, height, data.to_vec()).context("raw->RGB8")?;
-            img.save(path)?;
-        }
-        4 => {
-            let img: ImageBuffer<image::Rgba<u8>, _> =
-                ImageBuffer::from_raw(width, height, data.to_vec()).context("raw->RGBA8")?;
-            img.save(path)?;
-        }
-        5 => {
-            let img: ImageBuffer<image::Rgb<u16>, _> =
-                ImageBuffer::from_raw(width, height, bytes_to_u16_vec(data)?).context("raw->RGB16")?;
-            img.save(path)?;
-        }
-        _ => bail!("Unsupported ColorType ID {} for image output", color_type),
+        CTIReader::new(r)?.extract_region(x, y, width, height, level)
     }
-    Ok(())
 }
 
-pub fn write_header<W: Write>(w: &mut W, h: &CTIHeader) -> Result<()> {
-    w.write_all(&h.magic)?;
-    w.write_all(&h.version.to_le_bytes())?;
-    w.write_all(&h.flags.to_le_bytes())?;
-    w.write_all(&h.width.to_le_bytes())?;
-    w.write_all(&h.height.to_le_bytes())?;
-    w.write_all(&h.tile_size.to_le_bytes())?;
-    w.write_all(&h.tiles_x.to_le_bytes())?;
-    w.write_all(&h.tiles_y.to_le_bytes())?;
-    w.write_all(&[h.color_type])?;
-    w.write_all(&[h.compression])?;
-    w.write_all(&[h.quality])?;
-    w.write_all(&h.reserved)?;
-    Ok(())
+/// Stateful CTI reader that parses the header, tile index, section TOC and
+/// TMOD once and then serves any number of tile, region, section and pyramid
+/// level requests from the cached layout.
+pub struct CTIReader<R: Read + Seek> {
+    inner: R,
+    layout: LayoutInfo,
+    levels: Vec<PyramidLevelSlot>,
+    pool: Option<Arc<ThreadPool>>,
 }
 
-pub fn read_header<R: Read>(r: &mut R) -> Result<CTIHeader> {
-    let mut magic = [0u8; 4];
-    r.read_exact(&mut magic)?;
-    let version = read_u16_le(r)?;
-    let flags = read_u16_le(r)?;
-    let width = read_u32_le(r)?;
-    let height = read_u32_le(r)?;
-    let tile_size = read_u32_le(r)?;
-    let tiles_x = read_u32_le(r)?;
-    let tiles_y = read_u32_le(r)?;
-    let color_type = read_u8(r)?;
-    let compression = read_u8(r)?;
-    let quality = read_u8(r)?;
-    let mut reserved = [0u8; 33];
-    r.read_exact(&mut reserved)?;
-    Ok(CTIHeader {
-        magic,
-        version,
-        flags,
-        width,
-        height,
-        tile_size,
-        tiles_x,
-        tiles_y,
-        color_type,
-        compression,
-        quality,
-        reserved,
-    })
+/// Pyramid levels are nested CTI files inside PYLV sections. Their layout is
+/// parsed on first use and read in place through a [`SubRangeReader`].
+struct PyramidLevelSlot {
+    desc: SectionDesc,
+    layout: Option<LayoutInfo>,
 }
 
-fn write_tile_index<W: Write>(w: &mut W, t: &TileIndex) -> Result<()> {
-    w.write_all(&t.offset.to_le_bytes())?;
-    w.write_all(&t.compressed_size.to_le_bytes())?;
-    w.write_all(&t.original_size.to_le_bytes())?;
-    w.write_all(&t.crc32.to_le_bytes())?;
-    Ok(())
-}
+impl CTIReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with_limits(path, DecodeLimits::default())
+    }
 
-pub fn read_indices<R: Read>(r: &mut R, n: usize) -> Result<Vec<TileIndex>> {
-    let mut v = Vec::with_capacity(n);
-    for _ in 0..n {
-        let offset = read_u64_le(r)?;
-        let compressed_size = read_u32_le(r)?;
-        let original_size = read_u32_le(r)?;
-        let crc32 = read_u32_le(r)?;
-        v.push(TileIndex {
-            offset,
-            compressed_size,
-            original_size,
-            crc32,
-        });
+    pub fn open_with_limits<P: AsRef<Path>>(path: P, limits: DecodeLimits) -> Result<Self> {
+        let p = path.as_ref();
+        let file = File::open(p).with_context(|| format!("open {:?}", p))?;
+        Self::with_limits(BufReader::new(file), limits)
     }
-    Ok(v)
 }
 
-pub fn write_sections<W: Write + Seek>(w: &mut W, sections: &[(u32, Vec<u8>)]) -> Result<()> {
-    let count = sections.len() as u32;
-    w.write_all(&count.to_le_bytes())?;
-    if sections.is_empty() {
-        return Ok(());
+impl<R: Read + Seek> CTIReader<R> {
+    pub fn new(inner: R) -> Result<Self> {
+        Self::with_limits(inner, DecodeLimits::default())
     }
 
-    let toc_pos = w.stream_position()?;
-    let rec_size = 4 + 8 + 8;
-    w.seek(SeekFrom::Current((count as i64) * (rec_size as i64)))?;
-    let mut descs: Vec<SectionDesc> = Vec::with_capacity(sections.len());
-    for (ty, payload) in sections {
-        let off = w.stream_position()?;
-        w.write_all(payload)?;
-        descs.push(SectionDesc {
-            ty: *ty,
-            offset: off,
-            size: payload.len() as u64,
-        });
+    /// Opens `inner` rejecting anything beyond `limits`; pyramid levels
+    /// opened later are held to the same limits.
+    pub fn with_limits(mut inner: R, limits: DecodeLimits) -> Result<Self> {
+        let layout = scan_cti_layout(&mut inner, &limits, 0)?;
+        let levels = layout
+            .sections
+            .iter()
+            .filter(|desc| desc.ty == SEC_TYPE_PYLV)
+            .map(|desc| PyramidLevelSlot {
+                desc: *desc,
+                layout: None,
+            })
+            .collect();
+        Ok(Self {
+            inner,
+            layout,
+            levels,
+            pool: None,
+        })
     }
 
-    let end = w.stream_position()?;
-    w.seek(SeekFrom::Start(toc_pos))?;
-    for d in &descs {
-        w.write_all(&d.ty.to_le_bytes())?;
-        w.write_all(&d.offset.to_le_bytes())?;
-        w.write_all(&d.size.to_le_bytes())?;
+    /// Caps the number of worker threads used for full-image and region
+    /// decoding. `0` uses the global rayon pool.
+    pub fn set_threads(&mut self, threads: usize) -> Result<()> {
+        self.pool = if threads == 0 {
+            None
+        } else {
+            Some(Arc::new(ThreadPoolBuilder::new().num_threads(threads).build()?))
+        };
+        Ok(())
     }
-    w.seek(SeekFrom::Start(end))?;
-    Ok(())
-}
 
-fn write_sections_with_staged<W: Write + Seek>(
-    w: &mut W,
-    memory_sections: &[(u32, Vec<u8>)],
-    staged_sections: &[StagedSectionFile],
-) -> Result<u64> {
-    let total_count = (memory_sections.len() + staged_sections.len()) as u32;
-    w.write_all(&total_count.to_le_bytes())?;
-    if total_count == 0 {
-        return Ok(w.stream_position()?);
+    pub fn with_threads(mut self, threads: usize) -> Result<Self> {
+        self.set_threads(threads)?;
+        Ok(self)
     }
 
-    let toc_pos = w.stream_position()?;
-    let rec_size = 4 + 8 + 8;
-    w.seek(SeekFrom::Current((total_count as i64) * (rec_size as i64)))?;
-    let mut descs = Vec::with_capacity(total_count as usize);
-
-    for (ty, payload) in memory_sections {
-        let offset = w.stream_position()?;
-        w.write_all(payload)?;
-        descs.push(SectionDesc {
-            ty: *ty,
-            offset,
-            size: payload.len() as u64,
-        });
+    /// Decodes on `pool` instead of building one, so callers that open many
+    /// readers can share a single pool.
+    pub fn with_thread_pool(mut self, pool: Option<Arc<ThreadPool>>) -> Self {
+        self.pool = pool;
+        self
     }
 
-    for section in staged_sections {
-        let offset = w.stream_position()?;
-        let mut staged = BufReader::new(File::open(&section.path)?);
-        std::io::copy(&mut staged, w)?;
-        descs.push(SectionDesc {
-            ty: section.ty,
-            offset,
-            size: section.size,
-        });
+    pub fn header(&self) -> &CTIHeader {
+        &self.layout.header
     }
 
-    let end = w.stream_position()?;
-    w.seek(SeekFrom::Start(toc_pos))?;
-    for desc in &descs {
-        w.write_all(&desc.ty.to_le_bytes())?;
-        w.write_all(&desc.offset.to_le_bytes())?;
-        w.write_all(&desc.size.to_le_bytes())?;
+    pub fn indices(&self) -> &[TileIndex] {
+        &self.layout.indices
     }
-    w.seek(SeekFrom::Start(end))?;
-    Ok(end)
-}
 
-fn staged_pyramid_path(base_cti_path: &Path, level: u32) -> PathBuf {
-    let mut path = base_cti_path.to_path_buf();
-    let ext = base_cti_path
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .map(|ext| format!("{ext}.pylv{level}.tmp"))
-        .unwrap_or_else(|| format!("pylv{level}.tmp"));
-    path.set_extension(ext);
-    path
-}
+    pub fn section_descs(&self) -> &[SectionDesc] {
+        &self.layout.sections
+    }
 
-fn build_metadata_sections(img: &TiffImage) -> Vec<(u32, Vec<u8>)> {
-    let mut sections = Vec::new();
-    if let (Some(x), Some(y)) = (img.xdpi, img.ydpi) {
-        let mut res = Vec::with_capacity(8);
-        res.extend_from_slice(&x.to_le_bytes());
-        res.extend_from_slice(&y.to_le_bytes());
-        sections.push((SEC_TYPE_RES, res));
+    pub fn pyramid_level_count(&self) -> u32 {
+        self.levels.len() as u32
     }
-    if let Some(icc) = &img.icc {
-        sections.push((SEC_TYPE_ICC, icc.clone()));
+
+    pub fn into_inner(self) -> R {
+        self.inner
     }
-    sections
-}
 
-fn inspect_tiff_input(path: &Path) -> Result<InputImageInfo> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let mut dec = RawTiffDecoder::new(reader)?;
-    let (width, height) = dec.dimensions()?;
-    let color_type = tiff_color_type_to_image_color_type(dec.colortype()?)?;
-    let (xdpi, ydpi, icc) = read_tiff_metadata_for_sections(path).unwrap_or((None, None, None));
-    Ok(InputImageInfo {
-        width,
-        height,
-        color_type,
-        xdpi,
-        ydpi,
-        icc_size: icc.as_ref().map(Vec::len),
-    })
-}
+    pub fn info(&mut self) -> Result<CTIFileInfo> {
+        self.layout.check_checksums()?;
+        let metadata_descs = self
+            .layout
+            .sections
+            .iter()
+            .filter(|desc| desc.ty != SEC_TYPE_PYLV)
+            .copied()
+            .collect::<Vec<_>>();
+        let sections = read_sections_from_descs(&mut self.inner, &metadata_descs)?;
+        let metadata = SourceMetadata::from_sections(&sections)?;
+        let fixity = sections
+            .iter()
+            .find(|sec| sec.ty == SEC_TYPE_FXTY)
+            .map(|sec| FixityDigests::from_section(&sec.payload, self.layout.indices.len()))
+            .transpose()?;
+        let pyramid_levels = self.pyramid_levels()?;
 
-fn is_tiff_path(path: &Path) -> bool {
-    path.extension()
-        .and_then(|ext| ext.to_str())
-        .map(|ext| {
-            let ext = ext.to_ascii_lowercase();
-            ext == "tif" || ext == "tiff"
+        Ok(CTIFileInfo {
+            header: self.layout.header,
+            indices: self.layout.indices.clone(),
+            sections: self.layout.sections.clone(),
+            xdpi: metadata.xdpi,
+            ydpi: metadata.ydpi,
+            icc_size: metadata.icc.as_ref().map(Vec::len),
+            bands: metadata.bands,
+            max_error: self.layout.max_error,
+            solid_tiles: self.layout.solid_tile_count(),
+            fixity,
+            pyramid_levels,
         })
-        .unwrap_or(false)
-}
+    }
 
-fn tiff_color_type_to_image_color_type(ct: tiff::ColorType) -> Result<ColorType> {
-    Ok(match ct {
-        tiff::ColorType::Gray(8) => ColorType::L8,
-        tiff::ColorType::Gray(16) => ColorType::L16,
-        tiff::ColorType::RGB(8) => ColorType::Rgb8,
-        tiff::ColorType::RGBA(8) => ColorType::Rgba8,
-        tiff::ColorType::RGB(16) => ColorType::Rgb16,
-        other => bail!("Unsupported TIFF color type for streaming: {:?}", other),
-    })
-}
+    /// Describes every embedded pyramid level by reading only its nested header.
+    pub fn pyramid_levels(&mut self) -> Result<Vec<PyramidLevelInfo>> {
+        let mut out = Vec::with_capacity(self.levels.len());
+        for (idx, slot) in self.levels.iter().enumerate() {
+            let mut sub = SubRangeReader::new(&mut self.inner, slot.desc.offset, slot.desc.size);
+            let hdr = read_header(&mut sub)?;
+            ensure!(&hdr.magic == CTI_MAGIC, CtiError::BadMagic);
+            out.push(PyramidLevelInfo {
+                level: (idx + 1) as u32,
+                width: hdr.width,
+                height: hdr.height,
+                tile_size: hdr.tile_size,
+                tile_height: hdr.tile_height(),
+                color_type: hdr.color_type,
+                compression: hdr.compression,
+                payload_size: slot.desc.size as usize,
+            });
+        }
+        Ok(out)
+    }
 
-fn color_type_from_id(id: u8) -> Result<ColorType> {
-    Ok(match id {
-        1 => ColorType::L8,
-        2 => ColorType::L16,
-        3 => ColorType::Rgb8,
-        4 => ColorType::Rgba8,
-        5 => ColorType::Rgb16,
-        _ => bail!("Unsupported color type id {}", id),
-    })
-}
+    pub fn sections(&mut self) -> Result<Vec<CTISection>> {
+        self.layout.check_checksums()?;
+        read_sections_from_descs(&mut self.inner, &self.layout.sections)
+    }
 
-fn extract_tiff_tile<R: Read + Seek>(
-    dec: &mut RawTiffDecoder<R>,
-    width: u32,
-    height: u32,
-    color_type: ColorType,
-    bpp: u32,
-    tile_size: u32,
-    tx: u32,
-    ty: u32,
-    chunk_type: TiffChunkType,
-    chunk_w_default: u32,
-    chunk_h_default: u32,
-) -> Result<Vec<u8>> {
-    let start_x = tx * tile_size;
-    let start_y = ty * tile_size;
-    let end_x = (start_x + tile_size).min(width);
-    let end_y = (start_y + tile_size).min(height);
-    let tile_w = end_x - start_x;
-    let tile_h = end_y - start_y;
-    let mut out = vec![0u8; (tile_w * tile_h * bpp) as usize];
+    /// Resolution, ICC profile, band description and XMP / EXIF / IPTC of the
+    /// base level, as needed to export decoded pixels with
+    /// [`save_raster_with_metadata`].
+    pub fn metadata(&mut self) -> Result<SourceMetadata> {
+        self.layout.check_checksums()?;
+        let descs = self
+            .layout
+            .sections
+            .iter()
+            .filter(|desc| {
+                matches!(
+                    desc.ty,
+                    SEC_TYPE_RES | SEC_TYPE_ICC | SEC_TYPE_BAND | SEC_TYPE_XMP | SEC_TYPE_EXIF | SEC_TYPE_IPTC
+                )
+            })
+            .copied()
+            .collect::<Vec<_>>();
+        SourceMetadata::from_sections(&read_sections_from_descs(&mut self.inner, &descs)?)
+    }
 
-    let chunks_across = match chunk_type {
-        TiffChunkType::Strip => 1,
-        TiffChunkType::Tile => width.div_ceil(chunk_w_default),
-    };
-    let chunks_down = match chunk_type {
-        TiffChunkType::Strip => height.div_ceil(chunk_h_default),
-        TiffChunkType::Tile => height.div_ceil(chunk_h_default),
-    };
+    /// Fixity digests from the `FXTY` section, if the file has one.
+    pub fn fixity(&mut self) -> Result<Option<FixityDigests>> {
+        self.layout.check_checksums()?;
+        let Some(desc) = self.layout.sections.iter().find(|desc| desc.ty == SEC_TYPE_FXTY).copied() else {
+            return Ok(None);
+        };
+        let payload = read_section_payload(&mut self.inner, &desc)?;
+        FixityDigests::from_section(&payload, self.layout.indices.len()).map(Some)
+    }
 
-    let chunk_x0 = match chunk_type {
-        TiffChunkType::Strip => 0,
-        TiffChunkType::Tile => start_x / chunk_w_default,
-    };
-    let chunk_x1 = match chunk_type {
-        TiffChunkType::Strip => 0,
-        TiffChunkType::Tile => (end_x - 1) / chunk_w_default,
-    };
-    let chunk_y0 = start_y / chunk_h_default;
-    let chunk_y1 = (end_y - 1) / chunk_h_default;
+    /// Decodes the base level tile by tile and recomputes its SHA-256 digests.
+    /// Returns `None` when the file carries no `FXTY` section.
+    pub fn verify_fixity(&mut self) -> Result<Option<FixityReport>> {
+        let Some(stored) = self.fixity()? else {
+            return Ok(None);
+        };
+        let header = self.layout.header;
+        let mut hasher = RasterHasher::new(
+            header.width,
+            header.height,
+            header.tile_dims(),
+            header.bytes_per_pixel()? as usize,
+            !stored.tiles.is_empty(),
+        );
+        for ty in 0..header.tiles_y {
+            for tx in 0..header.tiles_x {
+                hasher.push_tile(self.decode_tile(tx, ty, 0)?.data);
+            }
+        }
+        let computed = hasher.finish();
+        let mismatched_tiles = stored
+            .tiles
+            .iter()
+            .zip(&computed.tiles)
+            .enumerate()
+            .filter(|(_, (stored, computed))| stored != computed)
+            .map(|(idx, _)| (idx as u32 % header.tiles_x, idx as u32 / header.tiles_x))
+            .collect();
+        Ok(Some(FixityReport {
+            stored,
+            computed,
+            mismatched_tiles,
+        }))
+    }
+
+    pub fn read_section(&mut self, index: usize) -> Result<CTISection> {
+        self.layout.check_checksums()?;
+        let desc = *self
+            .layout
+            .sections
+            .get(index)
+            .ok_or_else(|| CtiError::OutOfRange(format!("Section {} out of range", index)))?;
+        let mut sections = read_sections_from_descs(&mut self.inner, &[desc])?;
+        Ok(sections.remove(0))
+    }
+
+    /// Header of the base image (`level == 0`) or of an embedded pyramid level.
+    pub fn level_header(&mut self, level: u32) -> Result<CTIHeader> {
+        self.with_level(level, |_, layout| Ok(layout.header))
+    }
+
+    pub fn decode(&mut self, level: u32) -> Result<DecodedImage> {
+        let pool = self.pool.clone();
+        self.with_level(level, |source, layout| {
+            let data = decode_all_tiles(&mut stream_payloads(source), layout, pool.as_deref(), None)?;
+            let sections = read_sections_from_descs(source, &layout.sections)?;
+            Ok(DecodedImage {
+                header: layout.header,
+                data,
+                sections,
+            })
+        })
+    }
 
-    for cy in chunk_y0..=chunk_y1 {
-        for cx in chunk_x0..=chunk_x1 {
-            ensure!(cy < chunks_down, "Source TIFF chunk row out of range");
-            ensure!(cx < chunks_across, "Source TIFF chunk column out of range");
-            let chunk_index = match chunk_type {
-                TiffChunkType::Strip => cy,
-                TiffChunkType::Tile => cy * chunks_across + cx,
-            };
+    /// Like [`decode`](Self::decode), but tiles that cannot be read,
+    /// decompressed or verified are filled with `fill` (every sample, clamped
+    /// to 255 for 8-bit images, as `fill as f32` for float images) and listed
+    /// instead of aborting the decode.
+    /// A tile index or section TOC checksum failure does not stop it either;
+    /// every tile and section is then judged by its own checks.
+    pub fn decode_recover(&mut self, level: u32, fill: u16) -> Result<Recovered<DecodedImage>> {
+        let pool = self.pool.clone();
+        self.with_level(level, |source, layout| {
+            let recovery = TileRecovery::new(layout.header.color_type, fill)?;
+            let data = decode_all_tiles(&mut stream_payloads(source), layout, pool.as_deref(), Some(&recovery))?;
+            let (sections, failed_sections) =
+                recover_sections(&layout.sections, |desc| read_section_payload(source, desc));
+            Ok(Recovered {
+                value: DecodedImage {
+                    header: layout.header,
+                    data,
+                    sections,
+                },
+                failed_tiles: recovery.into_failed_tiles(),
+                failed_sections,
+            })
+        })
+    }
 
-            let chunk_x = match chunk_type {
-                TiffChunkType::Strip => 0,
-                TiffChunkType::Tile => cx * chunk_w_default,
-            };
-            let chunk_y = cy * chunk_h_default;
-            let actual_chunk_w = match chunk_type {
-                TiffChunkType::Strip => width,
-                TiffChunkType::Tile => (chunk_x + chunk_w_default).min(width) - chunk_x,
-            };
-            let actual_chunk_h = (chunk_y + chunk_h_default).min(height) - chunk_y;
+    /// Decodes a whole level into `out`, whose rows start `stride` bytes
+    /// apart. Returns the level header.
+    pub fn decode_into(&mut self, level: u32, out: &mut [u8], stride: usize) -> Result<CTIHeader> {
+        let pool = self.pool.clone();
+        self.with_level(level, |source, layout| {
+            decode_all_tiles_into(&mut stream_payloads(source), layout, out, stride, pool.as_deref(), None)?;
+            Ok(layout.header)
+        })
+    }
 
-            let mut chunk = dec.read_chunk(chunk_index)?;
-            let chunk_bytes = chunk_to_le_bytes(&mut chunk, color_type)?;
-            blit_chunk_overlap(
-                &mut out,
-                tile_w,
-                tile_h,
-                start_x,
-                start_y,
-                &chunk_bytes,
-                actual_chunk_w,
-                actual_chunk_h,
-                chunk_x,
-                chunk_y,
-                bpp,
+    pub fn decode_tile(&mut self, tx: u32, ty: u32, level: u32) -> Result<RasterRegion> {
+        self.with_level(level, |source, layout| {
+            decode_tile_from_layout(&mut stream_payloads(source), layout, tx, ty)
+        })
+    }
+
+    /// Decodes one tile into `out` with the given row stride and returns its
+    /// width and height.
+    pub fn decode_tile_into(
+        &mut self,
+        tx: u32,
+        ty: u32,
+        level: u32,
+        out: &mut [u8],
+        stride: usize,
+    ) -> Result<(u32, u32)> {
+        self.with_level(level, |source, layout| {
+            decode_tile_into_buffer(&mut stream_payloads(source), layout, tx, ty, out, stride)
+        })
+    }
+
+    pub fn extract_region(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        level: u32,
+    ) -> Result<RasterRegion> {
+        let pool = self.pool.clone();
+        self.with_level(level, |source, layout| {
+            extract_region_from_layout(&mut stream_payloads(source), layout, x, y, width, height, pool.as_deref(), None)
+        })
+    }
+
+    /// Region counterpart of [`decode_recover`](Self::decode_recover).
+    pub fn extract_region_recover(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        level: u32,
+        fill: u16,
+    ) -> Result<Recovered<RasterRegion>> {
+        let pool = self.pool.clone();
+        self.with_level(level, |source, layout| {
+            let recovery = TileRecovery::new(layout.header.color_type, fill)?;
+            let region = extract_region_from_layout(
+                &mut stream_payloads(source),
+                layout,
+                x,
+                y,
+                width,
+                height,
+                pool.as_deref(),
+                Some(&recovery),
             )?;
+            Ok(Recovered {
+                value: region,
+                failed_tiles: recovery.into_failed_tiles(),
+                failed_sections: Vec::new(),
+            })
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn extract_region_into(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        level: u32,
+        out: &mut [u8],
+        stride: usize,
+    ) -> Result<()> {
+        let pool = self.pool.clone();
+        self.with_level(level, |source, layout| {
+            extract_region_into_buffer(
+                &mut stream_payloads(source),
+                layout,
+                x,
+                y,
+                width,
+                height,
+                out,
+                stride,
+                pool.as_deref(),
+                None,
+            )
+        })
+    }
+
+    fn with_level<T>(
+        &mut self,
+        level: u32,
+        op: impl FnOnce(&mut SubRangeReader<&mut R>, &LayoutInfo) -> Result<T>,
+    ) -> Result<T> {
+        if level == 0 {
+            let mut source = SubRangeReader::whole(&mut self.inner);
+            return op(&mut source, &self.layout);
+        }
+
+        let slot = self
+            .levels
+            .get_mut((level - 1) as usize)
+            .ok_or(CtiError::PyramidLevelMissing(level))?;
+        let mut source = SubRangeReader::new(&mut self.inner, slot.desc.offset, slot.desc.size);
+        if slot.layout.is_none() {
+            slot.layout = Some(scan_cti_layout(&mut source, &self.layout.limits, self.layout.depth + 1)?);
         }
+        let layout = slot.layout.as_ref().expect("pyramid level layout initialized");
+        op(&mut source, layout)
     }
+}
 
-    Ok(out)
+/// `Read + Seek` view of the byte range `start..start + len` of another
+/// reader. Positions are relative to `start`, which lets nested CTI payloads
+/// such as PYLV sections be parsed and decoded in place.
+pub struct SubRangeReader<R> {
+    inner: R,
+    start: u64,
+    len: u64,
+    pos: u64,
+    synced: bool,
 }
 
-fn chunk_to_le_bytes(chunk: &mut RawTiffDecodingResult, color_type: ColorType) -> Result<Vec<u8>> {
-    Ok(match color_type {
-        ColorType::L8 | ColorType::Rgb8 | ColorType::Rgba8 => chunk.as_buffer(0).as_bytes().to_vec(),
-        ColorType::L16 | ColorType::Rgb16 => {
-            if cfg!(target_endian = "little") {
-                chunk.as_buffer(0).as_bytes().to_vec()
-            } else {
-                match chunk {
-                    RawTiffDecodingResult::U16(values) => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
-                    _ => bail!("Unexpected TIFF chunk type for 16-bit image"),
-                }
-            }
+impl<R: Read + Seek> SubRangeReader<R> {
+    pub fn new(inner: R, start: u64, len: u64) -> Self {
+        Self {
+            inner,
+            start,
+            len,
+            pos: 0,
+            synced: false,
         }
-        _ => bail!("Unsupported color type for chunk conversion: {:?}", color_type),
-    })
+    }
+
+    fn whole(inner: R) -> Self {
+        Self::new(inner, 0, u64::MAX)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
 }
 
-fn blit_chunk_overlap(
-    out: &mut [u8],
-    tile_w: u32,
-    tile_h: u32,
-    tile_x: u32,
-    tile_y: u32,
-    chunk: &[u8],
-    chunk_w: u32,
-    chunk_h: u32,
-    chunk_x: u32,
-    chunk_y: u32,
-    bpp: u32,
-) -> Result<()> {
-    let copy_x0 = tile_x.max(chunk_x);
-    let copy_y0 = tile_y.max(chunk_y);
-    let copy_x1 = (tile_x + tile_w).min(chunk_x + chunk_w);
-    let copy_y1 = (tile_y + tile_h).min(chunk_y + chunk_h);
-    if copy_x0 >= copy_x1 || copy_y0 >= copy_y1 {
-        return Ok(());
+impl<R: Read + Seek> Read for SubRangeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.pos);
+        if remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        if !self.synced {
+            // Only reposition the inner reader after our own seeks so that
+            // sequential reads keep the inner buffer warm.
+            self.inner.seek(SeekFrom::Start(self.start + self.pos))?;
+            self.synced = true;
+        }
+        let max = remaining.min(buf.len() as u64) as usize;
+        let n = self.inner.read(&mut buf[..max])?;
+        self.pos += n as u64;
+        Ok(n)
     }
+}
 
-    let copy_w = copy_x1 - copy_x0;
-    let copy_h = copy_y1 - copy_y0;
-    for row in 0..copy_h {
-        let src_x = copy_x0 - chunk_x;
-        let src_y = (copy_y0 - chunk_y) + row;
-        let dst_x = copy_x0 - tile_x;
-        let dst_y = (copy_y0 - tile_y) + row;
-        let src_off = ((src_y * chunk_w + src_x) * bpp) as usize;
-        let dst_off = ((dst_y * tile_w + dst_x) * bpp) as usize;
-        let len = (copy_w * bpp) as usize;
-        out[dst_off..dst_off + len].copy_from_slice(&chunk[src_off..src_off + len]);
+impl<R: Read + Seek> Seek for SubRangeReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+        };
+        let target = target.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before start of sub-range")
+        })?;
+        if target != self.pos {
+            self.pos = target;
+            self.synced = false;
+        }
+        Ok(self.pos)
     }
-    Ok(())
 }
 
-fn downsample_cti_tile_from_layout<R: Read + Seek>(
-    source: &mut R,
-    layout: &LayoutInfo,
-    color_type: ColorType,
-    tile_size: u32,
-    tx: u32,
-    ty: u32,
-    tile_cache: &mut HashMap<usize, Vec<u8>>,
-) -> Result<Vec<u8>> {
-    let src_header = layout.header;
-    let dst_width = src_header.width.div_ceil(2);
-    let dst_height = src_header.height.div_ceil(2);
-    let start_x = tx * tile_size;
-    let start_y = ty * tile_size;
-    let end_x = (start_x + tile_size).min(dst_width);
-    let end_y = (start_y + tile_size).min(dst_height);
-    let dst_tile_w = end_x - start_x;
-    let dst_tile_h = end_y - start_y;
-    let (channels, sample_bytes) = sample_layout(color_type)?;
-    let dst_pixels = (dst_tile_w * dst_tile_h) as usize;
-    let mut sums = vec![0u32; dst_pixels * channels];
-    let mut counts = vec![0u8; dst_pixels];
+/// CTI reader over a memory-mapped file (or any in-memory byte buffer).
+/// Compressed tiles are decoded straight from slices of the mapping, nested
+/// pyramid levels are sub-slices of it, and every method takes `&self`, so a
+/// single reader can be shared across threads without locking.
+pub struct CTIMmapReader<B: AsRef<[u8]> = Mmap> {
+    data: B,
+    layout: LayoutInfo,
+    levels: Vec<MmapLevelSlot>,
+    pool: Option<Arc<ThreadPool>>,
+}
 
-    let src_start_x = start_x * 2;
-    let src_start_y = start_y * 2;
-    let src_end_x = (end_x * 2).min(src_header.width);
-    let src_end_y = (end_y * 2).min(src_header.height);
-    let src_tile_x0 = src_start_x / src_header.tile_size;
-    let src_tile_y0 = src_start_y / src_header.tile_size;
-    let src_tile_x1 = (src_end_x.saturating_sub(1)) / src_header.tile_size;
-    let src_tile_y1 = (src_end_y.saturating_sub(1)) / src_header.tile_size;
+struct MmapLevelSlot {
+    desc: SectionDesc,
+    layout: OnceLock<LayoutInfo>,
+}
 
-    for sty in src_tile_y0..=src_tile_y1 {
-        for stx in src_tile_x0..=src_tile_x1 {
-            let idx = (sty * src_header.tiles_x + stx) as usize;
-            if !tile_cache.contains_key(&idx) {
-                let tile = read_decoded_tile(source, layout, &layout.indices[idx], idx)?;
-                tile_cache.insert(idx, tile);
-            }
-            let tile = tile_cache
-                .get(&idx)
-                .expect("source tile cache missing freshly inserted tile");
-            let (tile_w, tile_h) = tile_extent(
-                src_header.width,
-                src_header.height,
-                src_header.tile_size,
-                stx,
-                sty,
-            );
-            let tile_origin_x = stx * src_header.tile_size;
-            let tile_origin_y = sty * src_header.tile_size;
+impl CTIMmapReader<Mmap> {
+    /// Maps `path` read-only. The file must not be truncated or rewritten
+    /// while the reader is alive.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with_limits(path, DecodeLimits::default())
+    }
 
-            for local_y in 0..tile_h as usize {
-                let src_y = tile_origin_y + local_y as u32;
-                if src_y < src_start_y || src_y >= src_end_y {
-                    continue;
-                }
-                let dst_y = src_y / 2;
-                if dst_y < start_y || dst_y >= end_y {
-                    continue;
-                }
+    pub fn open_with_limits<P: AsRef<Path>>(path: P, limits: DecodeLimits) -> Result<Self> {
+        let p = path.as_ref();
+        let file = File::open(p).with_context(|| format!("open {:?}", p))?;
+        // SAFETY: the mapping is read-only and CTI files are not modified in
+        // place; concurrent truncation by another process is not supported.
+        let map = unsafe { Mmap::map(&file) }.with_context(|| format!("mmap {:?}", p))?;
+        Self::from_bytes_with_limits(map, limits)
+    }
+}
 
-                for local_x in 0..tile_w as usize {
-                    let src_x = tile_origin_x + local_x as u32;
-                    if src_x < src_start_x || src_x >= src_end_x {
-                        continue;
-                    }
-                    let dst_x = src_x / 2;
-                    if dst_x < start_x || dst_x >= end_x {
-                        continue;
-                    }
+impl<B: AsRef<[u8]>> CTIMmapReader<B> {
+    pub fn from_bytes(data: B) -> Result<Self> {
+        Self::from_bytes_with_limits(data, DecodeLimits::default())
+    }
 
-                    let dst_index = ((dst_y - start_y) * dst_tile_w + (dst_x - start_x)) as usize;
-                    counts[dst_index] = counts[dst_index].saturating_add(1);
-                    let src_base = (local_y * tile_w as usize + local_x) * channels * sample_bytes;
-                    let dst_base = dst_index * channels;
-                    if sample_bytes == 1 {
-                        for ch in 0..channels {
-                            sums[dst_base + ch] += tile[src_base + ch] as u32;
-                        }
-                    } else {
-                        for ch in 0..channels {
-                            let off = src_base + ch * 2;
-                            sums[dst_base + ch] +=
-                                u16::from_le_bytes([tile[off], tile[off + 1]]) as u32;
-                        }
-                    }
-                }
-            }
-        }
+    pub fn from_bytes_with_limits(data: B, limits: DecodeLimits) -> Result<Self> {
+        let layout = scan_cti_layout(&mut Cursor::new(data.as_ref()), &limits, 0)?;
+        let levels = layout
+            .sections
+            .iter()
+            .filter(|desc| desc.ty == SEC_TYPE_PYLV)
+            .map(|desc| MmapLevelSlot {
+                desc: *desc,
+                layout: OnceLock::new(),
+            })
+            .collect();
+        Ok(Self {
+            data,
+            layout,
+            levels,
+            pool: None,
+        })
     }
 
-    let mut out = Vec::with_capacity((dst_pixels * channels * sample_bytes) as usize);
-    for pixel in 0..dst_pixels {
-        let count = counts[pixel].max(1) as u32;
-        let base = pixel * channels;
-        if sample_bytes == 1 {
-            for ch in 0..channels {
-                out.push((sums[base + ch] / count) as u8);
-            }
+    /// Caps the number of worker threads used for full-image and region
+    /// decoding. `0` uses the global rayon pool.
+    pub fn set_threads(&mut self, threads: usize) -> Result<()> {
+        self.pool = if threads == 0 {
+            None
         } else {
-            for ch in 0..channels {
-                out.extend_from_slice(&((sums[base + ch] / count) as u16).to_le_bytes());
-            }
-        }
+            Some(Arc::new(ThreadPoolBuilder::new().num_threads(threads).build()?))
+        };
+        Ok(())
     }
 
-    Ok(out)
-}
+    pub fn with_threads(mut self, threads: usize) -> Result<Self> {
+        self.set_threads(threads)?;
+        Ok(self)
+    }
 
-#[allow(dead_code)]
-fn downsample_packed_image(
-    src: &[u8],
-    src_w: u32,
-    src_h: u32,
-    color_type: ColorType,
-) -> Result<Vec<u8>> {
-    let dst_w = src_w.div_ceil(2);
-    let dst_h = src_h.div_ceil(2);
-    let bpp = bytes_per_pixel(&color_type)?;
-    let mut out = vec![0u8; (dst_w * dst_h * bpp) as usize];
-    let (channels, sample_bytes) = sample_layout(color_type)?;
-    for dy in 0..dst_h as usize {
-        let sy0 = dy * 2;
-        let sy1 = (sy0 + 1).min(src_h.saturating_sub(1) as usize);
-        for dx in 0..dst_w as usize {
-            let sx0 = dx * 2;
-            let sx1 = (sx0 + 1).min(src_w.saturating_sub(1) as usize);
-            for ch in 0..channels {
-                if sample_bytes == 1 {
-                    let mut sum = 0u32;
-                    let mut count = 0u32;
-                    for sy in [sy0, sy1] {
-                        for sx in [sx0, sx1] {
-                            let base = (sy * src_w as usize + sx) * channels + ch;
-                            sum += src[base] as u32;
-                            count += 1;
-                        }
-                    }
-                    out[(dy * dst_w as usize + dx) * channels + ch] = (sum / count) as u8;
-                } else {
-                    let mut sum = 0u32;
-                    let mut count = 0u32;
-                    for sy in [sy0, sy1] {
-                        for sx in [sx0, sx1] {
-                            let base = ((sy * src_w as usize + sx) * channels + ch) * 2;
-                            sum += u16::from_le_bytes([src[base], src[base + 1]]) as u32;
-                            count += 1;
-                        }
-                    }
-                    let value = (sum / count) as u16;
-                    let dst_off = ((dy * dst_w as usize + dx) * channels + ch) * 2;
-                    out[dst_off..dst_off + 2].copy_from_slice(&value.to_le_bytes());
-                }
-            }
-        }
+    /// Decodes on `pool` instead of building one, so callers that open many
+    /// readers can share a single pool.
+    pub fn with_thread_pool(mut self, pool: Option<Arc<ThreadPool>>) -> Self {
+        self.pool = pool;
+        self
     }
-    Ok(out)
-}
 
-#[derive(Debug)]
-struct LayoutInfo {
-    header: CTIHeader,
-    indices: Vec<TileIndex>,
-    sections: Vec<SectionDesc>,
-    adaptive_tile_modes: Option<Vec<u8>>,
-}
+    pub fn header(&self) -> &CTIHeader {
+        &self.layout.header
+    }
 
-fn scan_cti_layout<R: Read + Seek>(r: &mut R) -> Result<LayoutInfo> {
-    r.seek(SeekFrom::Start(0))?;
-    let header = read_header(r)?;
-    ensure!(&header.magic == CTI_MAGIC, "Bad magic");
-    let total_tiles = (header.tiles_x * header.tiles_y) as usize;
-    let indices = read_indices(r, total_tiles)?;
+    pub fn indices(&self) -> &[TileIndex] {
+        &self.layout.indices
+    }
 
-    let data_start = CTI_HEADER_SIZE as u64 + (total_tiles * TILE_INDEX_ONDISK_SIZE) as u64;
-    let data_end = indices
-        .iter()
-        .map(|idx| idx.offset + idx.compressed_size as u64)
-        .max()
-        .unwrap_or(data_start);
-    let sections = read_section_descs(r, data_end)?;
-    let adaptive_tile_modes = read_adaptive_tile_modes(r, header.compression, total_tiles, &sections)?;
-    Ok(LayoutInfo {
-        header,
-        indices,
-        sections,
-        adaptive_tile_modes,
-    })
-}
+    pub fn section_descs(&self) -> &[SectionDesc] {
+        &self.layout.sections
+    }
 
-fn read_adaptive_tile_modes<R: Read + Seek>(
-    r: &mut R,
-    compression: u8,
-    total_tiles: usize,
-    descs: &[SectionDesc],
-) -> Result<Option<Vec<u8>>> {
-    if compression != CompressionType::Adaptive as u8 {
-        return Ok(None);
+    pub fn pyramid_level_count(&self) -> u32 {
+        self.levels.len() as u32
     }
 
-    let desc = descs
-        .iter()
-        .find(|desc| desc.ty == SEC_TYPE_TMOD)
-        .ok_or_else(|| anyhow!("Adaptive CTI is missing TMOD section"))?;
-    ensure!(
-        desc.size as usize == total_tiles,
-        "TMOD size mismatch: expected {} bytes, got {}",
-        total_tiles,
-        desc.size
-    );
+    pub fn sections(&self) -> Result<Vec<CTISection>> {
+        self.layout.check_checksums()?;
+        sections_from_bytes(self.data.as_ref(), &self.layout.sections)
+    }
 
-    r.seek(SeekFrom::Start(desc.offset))?;
-    let mut payload = vec![0u8; desc.size as usize];
-    r.read_exact(&mut payload)?;
-    Ok(Some(payload))
-}
+    /// Borrowed payload of section `index`, without copying.
+    pub fn section_bytes(&self, index: usize) -> Result<&[u8]> {
+        self.layout.check_checksums()?;
+        let desc = self
+            .layout
+            .sections
+            .get(index)
+            .ok_or_else(|| CtiError::OutOfRange(format!("Section {} out of range", index)))?;
+        let payload = byte_range(self.data.as_ref(), desc.offset, desc.size)?;
+        check_section_crc(desc, payload)?;
+        Ok(payload)
+    }
 
-fn read_section_descs<R: Read + Seek>(r: &mut R, start: u64) -> Result<Vec<SectionDesc>> {
-    r.seek(SeekFrom::Start(start))?;
-    let mut count_buf = [0u8; 4];
-    match r.read_exact(&mut count_buf) {
-        Ok(()) => {}
-        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(Vec::new()),
-        Err(err) => return Err(err.into()),
+    pub fn level_header(&self, level: u32) -> Result<CTIHeader> {
+        Ok(self.level(level)?.1.header)
     }
 
-    let count = u32::from_le_bytes(count_buf);
-    let mut descs = Vec::with_capacity(count as usize);
-    for _ in 0..count {
-        descs.push(SectionDesc {
-            ty: read_u32_le(r)?,
-            offset: read_u64_le(r)?,
-            size: read_u64_le(r)?,
-        });
+    pub fn decode(&self, level: u32) -> Result<DecodedImage> {
+        let (bytes, layout) = self.level(level)?;
+        let data = decode_all_tiles(&mut mapped_payloads(bytes), layout, self.pool.as_deref(), None)?;
+        Ok(DecodedImage {
+            header: layout.header,
+            data,
+            sections: sections_from_bytes(bytes, &layout.sections)?,
+        })
     }
-    Ok(descs)
-}
 
-fn read_sections_from_descs<R: Read + Seek>(r: &mut R, descs: &[SectionDesc]) -> Result<Vec<CTISection>> {
-    let mut out = Vec::with_capacity(descs.len());
-    for desc in descs {
-        r.seek(SeekFrom::Start(desc.offset))?;
-        let mut payload = vec![0u8; desc.size as usize];
-        r.read_exact(&mut payload)?;
-        out.push(CTISection {
-            ty: desc.ty,
-            payload,
+    /// See [`CTIReader::decode_recover`].
+    pub fn decode_recover(&self, level: u32, fill: u16) -> Result<Recovered<DecodedImage>> {
+        let (bytes, layout) = self.level(level)?;
+        let recovery = TileRecovery::new(layout.header.color_type, fill)?;
+        let data = decode_all_tiles(&mut mapped_payloads(bytes), layout, self.pool.as_deref(), Some(&recovery))?;
+        let (sections, failed_sections) = recover_sections(&layout.sections, |desc| {
+            let payload = byte_range(bytes, desc.offset, desc.size)?;
+            check_section_crc(desc, payload)?;
+            Ok(payload.to_vec())
         });
+        Ok(Recovered {
+            value: DecodedImage {
+                header: layout.header,
+                data,
+                sections,
+            },
+            failed_tiles: recovery.into_failed_tiles(),
+            failed_sections,
+        })
     }
-    Ok(out)
-}
 
-fn parse_pyramid_levels(sections: &[CTISection]) -> Result<Vec<PyramidLevelInfo>> {
-    let mut out = Vec::new();
-    for (idx, sec) in sections.iter().filter(|sec| sec.ty == SEC_TYPE_PYLV).enumerate() {
-        let mut cur = Cursor::new(sec.payload.as_slice());
-        let hdr = read_header(&mut cur)?;
-        ensure!(&hdr.magic == CTI_MAGIC, "Bad pyramid CTI magic");
-        out.push(PyramidLevelInfo {
-            level: (idx + 1) as u32,
-            width: hdr.width,
-            height: hdr.height,
-            tile_size: hdr.tile_size,
-            color_type: hdr.color_type,
-            compression: hdr.compression,
-            payload_size: sec.payload.len(),
-        });
+    pub fn decode_into(&self, level: u32, out: &mut [u8], stride: usize) -> Result<CTIHeader> {
+        let (bytes, layout) = self.level(level)?;
+        decode_all_tiles_into(&mut mapped_payloads(bytes), layout, out, stride, self.pool.as_deref(), None)?;
+        Ok(layout.header)
     }
-    Ok(out)
-}
 
-fn read_pyramid_level_bytes<R: Read + Seek>(r: &mut R, level: u32) -> Result<Vec<u8>> {
-    ensure!(level > 0, "Level must be >= 1");
-    let sections = CTIDecoder::sections_from_reader(r)?;
-    let mut iter = sections.into_iter().filter(|sec| sec.ty == SEC_TYPE_PYLV);
-    let bytes = iter
-        .nth((level - 1) as usize)
-        .map(|sec| sec.payload)
-        .ok_or_else(|| anyhow!("Pyramid level {} not present", level))?;
-    Ok(bytes)
-}
+    pub fn decode_tile(&self, tx: u32, ty: u32, level: u32) -> Result<RasterRegion> {
+        let (bytes, layout) = self.level(level)?;
+        decode_tile_from_layout(&mut mapped_payloads(bytes), layout, tx, ty)
+    }
 
-fn decode_all_tiles<R: Read + Seek>(r: &mut R, layout: &LayoutInfo) -> Result<Vec<u8>> {
-    let hdr = &layout.header;
-    let bpp = bytes_per_pixel_from_id(hdr.color_type)?;
-    let mut out = vec![0u8; (hdr.width * hdr.height * bpp) as usize];
-    for (i, t) in layout.indices.iter().enumerate() {
-        let tile_bytes = read_decoded_tile(r, layout, t, i)?;
-        let tx = (i as u32) % hdr.tiles_x;
-        let ty = (i as u32) / hdr.tiles_x;
-        blit_tile(
-            &mut out,
-            &tile_bytes,
-            hdr.width,
-            hdr.height,
-            hdr.tile_size,
-            bpp,
-            tx,
-            ty,
-        )?;
+    pub fn decode_tile_into(&self, tx: u32, ty: u32, level: u32, out: &mut [u8], stride: usize) -> Result<(u32, u32)> {
+        let (bytes, layout) = self.level(level)?;
+        decode_tile_into_buffer(&mut mapped_payloads(bytes), layout, tx, ty, out, stride)
     }
-    Ok(out)
-}
 
-fn read_decoded_tile<R: Read + Seek>(
-    r: &mut R,
-    layout: &LayoutInfo,
-    index: &TileIndex,
-    tile_number: usize,
-) -> Result<Vec<u8>> {
-    let hdr = &layout.header;
-    r.seek(SeekFrom::Start(index.offset))?;
-    let mut comp = vec![0u8; index.compressed_size as usize];
-    r.read_exact(&mut comp)?;
+    pub fn extract_region(&self, x: u32, y: u32, width: u32, height: u32, level: u32) -> Result<RasterRegion> {
+        let (bytes, layout) = self.level(level)?;
+        extract_region_from_layout(
+            &mut mapped_payloads(bytes),
+            layout,
+            x,
+            y,
+            width,
+            height,
+            self.pool.as_deref(),
+            None,
+        )
+    }
 
-    let adaptive_mode = layout
-        .adaptive_tile_modes
-        .as_ref()
-        .map(|modes| AdaptiveTileMode::from_byte(modes[tile_number]))
-        .transpose()?;
-    let tile_x = tile_number as u32 % hdr.tiles_x;
-    let tile_y = tile_number as u32 / hdr.tiles_x;
-    let mut tile_bytes = decompress_tile_with_size(
-        hdr.compression,
-        &comp,
-        index.original_size as usize,
-        hdr.color_type,
-        tile_extent(hdr.width, hdr.height, hdr.tile_size, tile_x, tile_y),
-        adaptive_mode,
-    )?;
-    ensure!(crc32(&tile_bytes) == index.crc32, "CRC mismatch at tile {}", tile_number);
+    /// See [`CTIReader::extract_region_recover`].
+    pub fn extract_region_recover(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        level: u32,
+        fill: u16,
+    ) -> Result<Recovered<RasterRegion>> {
+        let (bytes, layout) = self.level(level)?;
+        let recovery = TileRecovery::new(layout.header.color_type, fill)?;
+        let region = extract_region_from_layout(
+            &mut mapped_payloads(bytes),
+            layout,
+            x,
+            y,
+            width,
+            height,
+            self.pool.as_deref(),
+            Some(&recovery),
+        )?;
+        Ok(Recovered {
+            value: region,
+            failed_tiles: recovery.into_failed_tiles(),
+            failed_sections: Vec::new(),
+        })
+    }
 
-    let color_transform = color_transform_mode_from_header(hdr.flags, hdr.color_type);
-    apply_color_transform_inverse(color_transform, hdr.color_type, &mut tile_bytes);
-    Ok(tile_bytes)
-}
+    #[allow(clippy::too_many_arguments)]
+    pub fn extract_region_into(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        level: u32,
+        out: &mut [u8],
+        stride: usize,
+    ) -> Result<()> {
+        let (bytes, layout) = self.level(level)?;
+        extract_region_into_buffer(
+            &mut mapped_payloads(bytes),
+            layout,
+            x,
+            y,
+            width,
+            height,
+            out,
+            stride,
+            self.pool.as_deref(),
+            None,
+        )
+    }
 
-fn dynamic_image_into_raw(img: DynamicImage) -> (ColorType, Vec<u8>) {
-    match img {
-        DynamicImage::ImageLuma8(buf) => (ColorType::L8, buf.into_raw()),
-        DynamicImage::ImageLuma16(buf) => (
-            ColorType::L16,
-            buf.into_raw()
-                .into_iter()
-                .flat_map(|v| v.to_le_bytes())
-                .collect(),
-        ),
-        DynamicImage::ImageRgb8(buf) => (ColorType::Rgb8, buf.into_raw()),
-        DynamicImage::ImageRgba8(buf) => (ColorType::Rgba8, buf.into_raw()),
-        DynamicImage::ImageRgb16(buf) => (
-            ColorType::Rgb16,
-            buf.into_raw()
-                .into_iter()
-                .flat_map(|v| v.to_le_bytes())
-                .collect(),
-        ),
-        other => (ColorType::Rgba8, other.into_rgba8().into_raw()),
+    /// Bytes and parsed layout of the base image or of a pyramid level.
+    fn level(&self, level: u32) -> Result<(&[u8], &LayoutInfo)> {
+        if level == 0 {
+            return Ok((self.data.as_ref(), &self.layout));
+        }
+
+        let slot = self
+            .levels
+            .get((level - 1) as usize)
+            .ok_or(CtiError::PyramidLevelMissing(level))?;
+        let bytes = byte_range(self.data.as_ref(), slot.desc.offset, slot.desc.size)?;
+        if slot.layout.get().is_none() {
+            // Racing threads may both parse the level; the first result wins.
+            let layout = scan_cti_layout(&mut Cursor::new(bytes), &self.layout.limits, self.layout.depth + 1)?;
+            let _ = slot.layout.set(layout);
+        }
+        let layout = slot.layout.get().expect("pyramid level layout initialized");
+        Ok((bytes, layout))
     }
 }
 
-fn color_type_to_id(ct: ColorType) -> Result<u8> {
-    Ok(match ct {
-        ColorType::L8 => 1,
-        ColorType::L16 => 2,
-        ColorType::Rgb8 => 3,
-        ColorType::Rgba8 => 4,
-        ColorType::Rgb16 => 5,
-        _ => bail!("Unsupported color type: {:?}", ct),
-    })
+fn mapped_payloads<'a>(bytes: &'a [u8]) -> impl FnMut(&TileIndex) -> Result<Cow<'a, [u8]>> + 'a {
+    move |index| byte_range(bytes, index.offset, index.compressed_size as u64).map(Cow::Borrowed)
 }
 
-fn storage_color_type(color_type: ColorType, downcast_16_to_8: bool) -> ColorType {
-    if !downcast_16_to_8 {
-        return color_type;
-    }
-
-    match color_type {
-        ColorType::L16 => ColorType::L8,
-        ColorType::Rgb16 => ColorType::Rgb8,
-        other => other,
-    }
+fn byte_range(bytes: &[u8], offset: u64, size: u64) -> Result<&[u8]> {
+    offset
+        .checked_add(size)
+        .filter(|end| *end <= bytes.len() as u64)
+        .map(|end| &bytes[offset as usize..end as usize])
+        .ok_or_else(|| CtiError::Corrupt(format!("Range {}+{} exceeds file size {}", offset, size, bytes.len())))
 }
 
-fn bytes_per_pixel(ct: &ColorType) -> Result<u32> {
-    Ok(match ct {
-        ColorType::L8 => 1,
-        ColorType::L16 => 2,
-        ColorType::Rgb8 => 3,
-        ColorType::Rgba8 => 4,
-        ColorType::Rgb16 => 6,
-        _ => bail!("Unsupported color type {:?}", ct),
-    })
+fn sections_from_bytes(bytes: &[u8], descs: &[SectionDesc]) -> Result<Vec<CTISection>> {
+    descs
+        .iter()
+        .map(|desc| {
+            let payload = byte_range(bytes, desc.offset, desc.size)?;
+            check_section_crc(desc, payload)?;
+            Ok(CTISection {
+                ty: desc.ty,
+                payload: payload.to_vec(),
+            })
+        })
+        .collect()
 }
 
-fn bytes_per_pixel_from_id(id: u8) -> Result<u32> {
-    Ok(match id {
-        1 => 1,
-        2 => 2,
-        3 => 3,
-        4 => 4,
-        5 => 6,
-        _ => bail!("Unsupported color type id {}", id),
-    })
+pub fn section_type_name(ty: u32) -> &'static str {
+    match ty {
+        SEC_TYPE_RES => "RES ",
+        SEC_TYPE_ICC => "ICC ",
+        SEC_TYPE_PYLV => "PYLV",
+        SEC_TYPE_TMOD => "TMOD",
+        SEC_TYPE_NLER => "NLER",
+        SEC_TYPE_ZDIC => "ZDIC",
+        SEC_TYPE_TSOL => "TSOL",
+        SEC_TYPE_SCRC => "SCRC",
+        SEC_TYPE_FXTY => "FXTY",
+        SEC_TYPE_BAND => "BAND",
+        SEC_TYPE_XMP => "XMP ",
+        SEC_TYPE_EXIF => "EXIF",
+        SEC_TYPE_IPTC => "IPTC",
+        _ => "????",
+    }
 }
 
-fn sample_layout(color_type: ColorType) -> Result<(usize, usize)> {
-    Ok(match color_type {
-        ColorType::L8 => (1, 1),
-        ColorType::L16 => (1, 2),
-        ColorType::Rgb8 => (3, 1),
-        ColorType::Rgba8 => (4, 1),
-        ColorType::Rgb16 => (3, 2),
-        _ => bail!("Unsupported color type {:?}", color_type),
-    })
+pub fn save_raster<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    color_type: u8,
+    data: &[u8],
+) -> Result<()> {
+    save_raster_with_metadata(path, width, height, color_type, data, &SourceMetadata::default())
 }
 
-fn convert_tile_for_storage(data: &[u8], input_color_type: ColorType, output_color_type: ColorType) -> Result<Vec<u8>> {
-    if input_color_type == output_color_type {
-        return Ok(data.to_vec());
+/// Like [`save_raster`], but CMYK and N-band TIFFs get their photometric
+/// interpretation, extra samples and ink tags back from `metadata.bands`, and
+/// TIFF and PNG files carry `metadata.descriptive` (XMP, EXIF, IPTC).
+pub fn save_raster_with_metadata<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    color_type: u8,
+    data: &[u8],
+    metadata: &SourceMetadata,
+) -> Result<()> {
+    let path = path.as_ref();
+    let descriptive = &metadata.descriptive;
+    match color_type {
+        1..=5 | 8 if is_tiff_path(path) => save_plain_tiff(path, width, height, color_type, data, descriptive)?,
+        1 => {
+            let img: ImageBuffer<image::Luma<u8>, _> =
+                ImageBuffer::from_raw(width, height, data.to_vec()).context("raw->L8")?;
+            img.save(path)?;
+        }
+        2 => {
+            let img: ImageBuffer<image::Luma<u16>, _> =
+                ImageBuffer::from_raw(width, height, bytes_to_u16_vec(data)?).context("raw->L16")?;
+            img.save(path)?;
+        }
+        3 => {
+            let img: ImageBuffer<image::Rgb<u8>, _> =
+                ImageBuffer::from_raw(width, height, data.to_vec()).context("raw->RGB8")?;
+            img.save(path)?;
+        }
+        4 => {
+            let img: ImageBuffer<image::Rgba<u8>, _> =
+                ImageBuffer::from_raw(width, height, data.to_vec()).context("raw->RGBA8")?;
+            img.save(path)?;
+        }
+        5 => {
+            let img: ImageBuffer<image::Rgb<u16>, _> =
+                ImageBuffer::from_raw(width, height, bytes_to_u16_vec(data)?).context("raw->RGB16")?;
+            img.save(path)?;
+        }
+        6 | 7 if is_tiff_path(path) => save_luma_alpha_tiff(path, width, height, color_type, data, descriptive)?,
+        9 | 10 => {
+            ensure!(
+                is_tiff_path(path),
+                CtiError::Unsupported("Floating-point rasters can only be exported as TIFF".into())
+            );
+            save_float_tiff(path, width, height, color_type, data, descriptive)?;
+        }
+        13 if is_tiff_path(path) => save_bilevel_tiff(path, width, height, data, descriptive)?,
+        13 => {
+            let img: ImageBuffer<image::Luma<u8>, _> =
+                ImageBuffer::from_raw(width, height, data.to_vec()).context("raw->L1")?;
+            img.save(path)?;
+        }
+        11 | 12 | COLOR_ID_BANDS8..0xC0 => {
+            ensure!(
+                is_tiff_path(path),
+                CtiError::Unsupported("CMYK and multi-band rasters can only be exported as TIFF".into())
+            );
+            let color_type = color_type_from_id(color_type)?;
+            save_banded_tiff(path, width, height, color_type, data, metadata.bands.as_ref(), descriptive)?;
+        }
+        6 => {
+            let img: ImageBuffer<image::LumaA<u8>, _> =
+                ImageBuffer::from_raw(width, height, data.to_vec()).context("raw->LA8")?;
+            img.save(path)?;
+        }
+        7 => {
+            let img: ImageBuffer<image::LumaA<u16>, _> =
+                ImageBuffer::from_raw(width, height, bytes_to_u16_vec(data)?).context("raw->LA16")?;
+            img.save(path)?;
+        }
+        8 => {
+            let img: ImageBuffer<image::Rgba<u16>, _> =
+                ImageBuffer::from_raw(width, height, bytes_to_u16_vec(data)?).context("raw->RGBA16")?;
+            img.save(path)?;
+        }
+        _ => bail!(CtiError::UnknownColorType(color_type)),
     }
-
-    match (input_color_type, output_color_type) {
-        (ColorType::L16, ColorType::L8) | (ColorType::Rgb16, ColorType::Rgb8) => downcast_u16_bytes_to_u8(data),
-        _ => bail!(
-            "Unsupported storage conversion from {:?} to {:?}",
-            input_color_type,
-            output_color_type
-        ),
+    if is_png_path(path) {
+        insert_png_descriptive_metadata(path, descriptive)?;
     }
+    Ok(())
 }
 
-fn downcast_u16_bytes_to_u8(data: &[u8]) -> Result<Vec<u8>> {
-    Ok(bytes_to_u16_vec(data)?
-        .into_iter()
-        .map(|value| ((value as u32 + 128) / 257) as u8)
-        .collect())
-}
+/// TIFF being exported: the encoder plus the descriptive IFD0 entries, whose
+/// Exif and GPS IFDs are already in the file.
+struct TiffExport {
+    encoder: TiffEncoder<BufWriter<File>>,
+    descriptive: Vec<RawIfdEntry>,
+}
+
+impl TiffExport {
+    /// The EXIF blob is parsed before `path` is created, so one that cannot
+    /// be rebased into the file fails the export without leaving a file.
+    fn create(path: &Path, descriptive: &DescriptiveMetadata) -> Result<Self> {
+        let exif = descriptive
+            .exif
+            .as_deref()
+            .map(ExifDirectories::from_blob)
+            .transpose()
+            .context("EXIF section cannot be written as TIFF tags")?;
+        // The tiff encoder writes in native byte order.
+        let big_endian = cfg!(target_endian = "big");
+        let mut encoder = TiffEncoder::new(BufWriter::new(File::create(path)?))?;
+        let mut entries = Vec::new();
+        if let Some(xmp) = &descriptive.xmp {
+            entries.push(RawIfdEntry::bytes(TIFF_TAG_XMP, TIFF_TYPE_BYTE, xmp));
+        }
+        if let Some(iptc) = &descriptive.iptc {
+            entries.push(RawIfdEntry::bytes(TIFF_TAG_IPTC, TIFF_TYPE_UNDEFINED, iptc));
+        }
+        if let Some(exif) = &exif {
+            entries.extend(exif.ifd0_entries(big_endian));
+            for (tag, ifd) in [(TIFF_TAG_EXIF_IFD, &exif.exif), (TIFF_TAG_GPS_IFD, &exif.gps)] {
+                if let Some(ifd) = ifd {
+                    let mut dir = encoder.extra_directory()?;
+                    for entry in exif.in_byte_order(ifd, big_endian) {
+                        write_raw_tiff_entry(&mut dir, &entry)?;
+                    }
+                    let offset = dir.finish_with_offsets()?;
+                    entries.push(RawIfdEntry::long(tag, offset.offset, big_endian));
+                }
+            }
+        }
+        Ok(Self { encoder, descriptive: entries })
+    }
 
-fn extract_tile(img: &TiffImage, tx: u32, ty: u32, ts: u32) -> Result<Vec<u8>> {
-    let bpp = bytes_per_pixel(&img.color_type)?;
-    let start_x = tx * ts;
-    let start_y = ty * ts;
-    let (tile_w, tile_h) = tile_extent(img.width, img.height, ts, tx, ty);
-    let end_y = start_y + tile_h;
+    /// Image IFD for callers that write the strips themselves, with the
+    /// descriptive tags already in it.
+    fn image_directory(&mut self) -> Result<DirectoryEncoder<'_, BufWriter<File>, TiffKindStandard>> {
+        let mut dir = self.encoder.image_directory()?;
+        for entry in &self.descriptive {
+            write_raw_tiff_entry(&mut dir, entry)?;
+        }
+        Ok(dir)
+    }
 
-    let mut out = Vec::with_capacity((tile_w * tile_h * bpp) as usize);
-    for y in start_y..end_y {
-        let row_start = ((y * img.width + start_x) * bpp) as usize;
-        let row_end = row_start + (tile_w * bpp) as usize;
-        out.extend_from_slice(&img.data[row_start..row_end]);
+    /// Starts the image IFD; the descriptive tags go in ahead of the pixel data.
+    fn new_image<C: tiff_colortype::ColorType>(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<ImageEncoder<'_, BufWriter<File>, C, TiffKindStandard>> {
+        let mut image = self.encoder.new_image::<C>(width, height)?;
+        for entry in &self.descriptive {
+            write_raw_tiff_entry(image.encoder(), entry)?;
+        }
+        Ok(image)
     }
-    Ok(out)
 }
 
-fn blit_tile(
-    out: &mut [u8],
-    tile: &[u8],
-    w: u32,
-    h: u32,
-    ts: u32,
-    bpp: u32,
-    tx: u32,
-    ty: u32,
+fn write_raw_tiff_entry<W: Write + Seek>(
+    dir: &mut DirectoryEncoder<'_, W, TiffKindStandard>,
+    entry: &RawIfdEntry,
 ) -> Result<()> {
-    let start_x = tx * ts;
-    let start_y = ty * ts;
-    let end_x = (start_x + ts).min(w);
-    let end_y = (start_y + ts).min(h);
-    let tile_w = end_x - start_x;
-    let tile_h = end_y - start_y;
-
-    for row in 0..tile_h {
-        let dst_off = (((start_y + row) * w + start_x) * bpp) as usize;
-        let src_off = (row * tile_w * bpp) as usize;
-        let len = (tile_w * bpp) as usize;
-        out[dst_off..dst_off + len].copy_from_slice(&tile[src_off..src_off + len]);
-    }
+    let field_type = tiff::tags::Type::from_u16(entry.field_type).context("TIFF entry of unknown field type")?;
+    let value = dir.write_entry_bytes(field_type, &entry.value)?;
+    dir.extend_from(&tiff::Directory::from_iter([(TiffTag::from_u16_exhaustive(entry.tag), value)]));
     Ok(())
 }
 
-fn blit_tile_region(
-    out: &mut [u8],
-    tile: &[u8],
-    region_w: u32,
-    region_h: u32,
-    region_x: u32,
-    region_y: u32,
-    tile_size: u32,
-    tile_w: u32,
-    tile_h: u32,
-    bpp: u32,
-    tx: u32,
-    ty: u32,
+/// Gray, RGB and RGBA rasters the `image` crate could write, but through the
+/// tiff encoder so the descriptive tags can be added.
+fn save_plain_tiff(
+    path: &Path,
+    width: u32,
+    height: u32,
+    color_type: u8,
+    data: &[u8],
+    descriptive: &DescriptiveMetadata,
 ) -> Result<()> {
-    let tile_x = tx * tile_size;
-    let tile_y = ty * tile_size;
-    let copy_x0 = tile_x.max(region_x);
-    let copy_y0 = tile_y.max(region_y);
-    let copy_x1 = (tile_x + tile_w).min(region_x + region_w);
-    let copy_y1 = (tile_y + tile_h).min(region_y + region_h);
-
-    if copy_x0 >= copy_x1 || copy_y0 >= copy_y1 {
-        return Ok(());
-    }
-
-    let copy_w = copy_x1 - copy_x0;
-    let copy_h = copy_y1 - copy_y0;
-    for row in 0..copy_h {
-        let src_x = copy_x0 - tile_x;
-        let src_y = (copy_y0 - tile_y) + row;
-        let dst_x = copy_x0 - region_x;
-        let dst_y = (copy_y0 - region_y) + row;
-        let src_off = ((src_y * tile_w + src_x) * bpp) as usize;
-        let dst_off = ((dst_y * region_w + dst_x) * bpp) as usize;
-        let len = (copy_w * bpp) as usize;
-        out[dst_off..dst_off + len].copy_from_slice(&tile[src_off..src_off + len]);
+    let expected = width as usize * height as usize * bytes_per_pixel_from_id(color_type)? as usize;
+    ensure!(data.len() == expected, "raw->TIFF: {} bytes, expected {}", data.len(), expected);
+    let mut export = TiffExport::create(path, descriptive)?;
+    match color_type {
+        1 => export.new_image::<tiff_colortype::Gray8>(width, height)?.write_data(data)?,
+        2 => export.new_image::<tiff_colortype::Gray16>(width, height)?.write_data(&bytes_to_u16_vec(data)?)?,
+        3 => export.new_image::<tiff_colortype::RGB8>(width, height)?.write_data(data)?,
+        4 => export.new_image::<tiff_colortype::RGBA8>(width, height)?.write_data(data)?,
+        5 => export.new_image::<tiff_colortype::RGB16>(width, height)?.write_data(&bytes_to_u16_vec(data)?)?,
+        _ => export.new_image::<tiff_colortype::RGBA16>(width, height)?.write_data(&bytes_to_u16_vec(data)?)?,
     }
     Ok(())
 }
 
-fn tile_extent(width: u32, height: u32, tile_size: u32, tx: u32, ty: u32) -> (u32, u32) {
-    let start_x = tx * tile_size;
-    let start_y = ty * tile_size;
-    (
-        (start_x + tile_size).min(width) - start_x,
-        (start_y + tile_size).min(height) - start_y,
-    )
-}
-
-fn color_transform_mode_for_encode(enabled: bool, color_type: ColorType) -> ColorTransformMode {
-    if !enabled {
-        return ColorTransformMode::None;
-    }
-
-    match color_type {
-        ColorType::Rgb8 => ColorTransformMode::Rct,
-        ColorType::Rgb16 => ColorTransformMode::Rgb16DeltaG,
-        _ => ColorTransformMode::None,
-    }
-}
-
-fn color_transform_mode_from_header(flags: u16, color_type: u8) -> ColorTransformMode {
-    if (flags & FLAG_COLOR_RGB16_DELTA_G) != 0 && color_type == 5 {
-        ColorTransformMode::Rgb16DeltaG
-    } else if (flags & FLAG_COLOR_RCT) != 0 && matches!(color_type, 3 | 5) {
-        ColorTransformMode::Rct
+/// The `image` TIFF encoder has no gray+alpha layout, so LA rasters are
+/// written as gray with an unassociated alpha extra sample.
+fn save_luma_alpha_tiff(
+    path: &Path,
+    width: u32,
+    height: u32,
+    color_type: u8,
+    data: &[u8],
+    descriptive: &DescriptiveMetadata,
+) -> Result<()> {
+    let expected = width as usize * height as usize * bytes_per_pixel_from_id(color_type)? as usize;
+    ensure!(data.len() == expected, "raw->LA: {} bytes, expected {}", data.len(), expected);
+    let mut export = TiffExport::create(path, descriptive)?;
+    let alpha = [tiff::tags::ExtraSamples::UnassociatedAlpha];
+    if color_type == 6 {
+        let mut image = export.new_image::<tiff_colortype::Gray8>(width, height)?;
+        image.extra_samples(&alpha)?;
+        image.write_data(data)?;
     } else {
-        ColorTransformMode::None
+        let mut image = export.new_image::<tiff_colortype::Gray16>(width, height)?;
+        image.extra_samples(&alpha)?;
+        image.write_data(&bytes_to_u16_vec(data)?)?;
     }
+    Ok(())
 }
 
-fn color_transform_flags(mode: ColorTransformMode) -> u16 {
-    match mode {
-        ColorTransformMode::None => 0,
-        ColorTransformMode::Rct => FLAG_COLOR_RCT,
-        ColorTransformMode::Rgb16DeltaG => FLAG_COLOR_RGB16_DELTA_G,
-    }
+/// 1-bit BlackIsZero TIFF. The tiff `ImageEncoder` only writes whole-byte
+/// samples, so the directory and its strips of packed rows are written here.
+fn save_bilevel_tiff(path: &Path, width: u32, height: u32, data: &[u8], descriptive: &DescriptiveMetadata) -> Result<()> {
+    let expected = width as usize * height as usize;
+    ensure!(data.len() == expected, "raw->L1: {} bytes, expected {}", data.len(), expected);
+    ensure!(width > 0 && height > 0, CtiError::InvalidInput("raw->L1: empty image".into()));
+    let packed = pack_bilevel_rows(data, width);
+    let row_bytes = width.div_ceil(8) as usize;
+    let rows_per_strip = BILEVEL_TIFF_STRIP_BYTES.div_ceil(row_bytes).min(height as usize);
+    let too_large = || CtiError::LimitExceeded("1-bit TIFF exceeds 4 GiB".into());
+
+    let mut export = TiffExport::create(path, descriptive)?;
+    let mut dir = export.image_directory()?;
+    let mut offsets = Vec::new();
+    let mut byte_counts = Vec::new();
+    for strip in packed.chunks(rows_per_strip * row_bytes) {
+        offsets.push(u32::try_from(dir.write_data(strip)?).map_err(|_| too_large())?);
+        byte_counts.push(strip.len() as u32);
+    }
+    dir.write_tag(TiffTag::ImageWidth, width)?;
+    dir.write_tag(TiffTag::ImageLength, height)?;
+    dir.write_tag(TiffTag::BitsPerSample, 1u16)?;
+    dir.write_tag(TiffTag::Compression, tiff::tags::CompressionMethod::None.to_u16())?;
+    dir.write_tag(
+        TiffTag::PhotometricInterpretation,
+        tiff::tags::PhotometricInterpretation::BlackIsZero.to_u16(),
+    )?;
+    dir.write_tag(TiffTag::StripOffsets, &offsets[..])?;
+    dir.write_tag(TiffTag::SamplesPerPixel, 1u16)?;
+    dir.write_tag(TiffTag::RowsPerStrip, rows_per_strip as u32)?;
+    dir.write_tag(TiffTag::StripByteCounts, &byte_counts[..])?;
+    dir.write_tag(TiffTag::XResolution, tiff::encoder::Rational { n: 1, d: 1 })?;
+    dir.write_tag(TiffTag::YResolution, tiff::encoder::Rational { n: 1, d: 1 })?;
+    dir.write_tag(TiffTag::ResolutionUnit, tiff::tags::ResolutionUnit::None.to_u16())?;
+    dir.finish()?;
+    Ok(())
 }
 
-fn apply_color_transform_forward(mode: ColorTransformMode, color_type: ColorType, tile: &mut [u8]) {
-    match mode {
-        ColorTransformMode::None => {}
-        ColorTransformMode::Rct => match color_type {
-            ColorType::Rgb8 => rct_forward_rgb8(tile),
-            ColorType::Rgb16 => rct_forward_rgb16(tile),
-            _ => {}
-        },
-        ColorTransformMode::Rgb16DeltaG => rgb16_delta_g_forward(tile),
+/// Writes CMYK / N-band pixels as a gray, RGB or CMYK TIFF (following the
+/// stored photometric interpretation) with the remaining bands as extra samples.
+/// RGB and CMYK only take a single alpha extra sample, as that is all the tiff
+/// decoder reads back; other layouts are written as gray plus extra samples.
+fn save_banded_tiff(
+    path: &Path,
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+    data: &[u8],
+    bands: Option<&BandInfo>,
+    descriptive: &DescriptiveMetadata,
+) -> Result<()> {
+    let (channels, sample_bytes) = sample_layout(color_type)?;
+    let expected = width as usize * height as usize * channels * sample_bytes;
+    ensure!(data.len() == expected, "raw->bands: {} bytes, expected {}", data.len(), expected);
+    let cmyk = matches!(color_type, ColorType::Cmyk8 | ColorType::Cmyk16);
+    let photometric = bands.map_or(if cmyk { 5 } else { 1 }, |b| b.photometric);
+    let first_extra_is_alpha = bands
+        .and_then(|b| b.extra_samples.first())
+        .is_some_and(|&v| v == 1 || v == 2);
+    let base_channels = match (photometric, channels) {
+        (5, 4) | (2, 3) => channels,
+        (5, 5) | (2, 4) if first_extra_is_alpha => channels - 1,
+        _ => 1,
+    };
+    let extra = (0..channels - base_channels)
+        .map(|i| {
+            bands
+                .and_then(|b| b.extra_samples.get(i))
+                .and_then(|&v| tiff::tags::ExtraSamples::from_u16(v))
+                .unwrap_or(tiff::tags::ExtraSamples::Unspecified)
+        })
+        .collect::<Vec<_>>();
+
+    let mut export = TiffExport::create(path, descriptive)?;
+    match (base_channels, sample_bytes) {
+        (4, 1) => write_banded_tiff::<tiff_colortype::CMYK8>(&mut export, width, height, &extra, bands, data),
+        (3, 1) => write_banded_tiff::<tiff_colortype::RGB8>(&mut export, width, height, &extra, bands, data),
+        (_, 1) => write_banded_tiff::<tiff_colortype::Gray8>(&mut export, width, height, &extra, bands, data),
+        (4, _) => write_banded_tiff::<tiff_colortype::CMYK16>(
+            &mut export,
+            width,
+            height,
+            &extra,
+            bands,
+            &bytes_to_u16_vec(data)?,
+        ),
+        (3, _) => write_banded_tiff::<tiff_colortype::RGB16>(
+            &mut export,
+            width,
+            height,
+            &extra,
+            bands,
+            &bytes_to_u16_vec(data)?,
+        ),
+        (_, _) => write_banded_tiff::<tiff_colortype::Gray16>(
+            &mut export,
+            width,
+            height,
+            &extra,
+            bands,
+            &bytes_to_u16_vec(data)?,
+        ),
     }
 }
 
-fn apply_color_transform_inverse(mode: ColorTransformMode, color_type: u8, tile: &mut [u8]) {
-    match mode {
-        ColorTransformMode::None => {}
-        ColorTransformMode::Rct => match color_type {
-            3 => rct_inverse_rgb8(tile),
-            5 => rct_inverse_rgb16(tile),
-            _ => {}
-        },
-        ColorTransformMode::Rgb16DeltaG => rgb16_delta_g_inverse(tile),
+fn write_banded_tiff<C: tiff_colortype::ColorType>(
+    export: &mut TiffExport,
+    width: u32,
+    height: u32,
+    extra: &[tiff::tags::ExtraSamples],
+    bands: Option<&BandInfo>,
+    samples: &[C::Inner],
+) -> Result<()>
+where
+    [C::Inner]: TiffValue,
+{
+    let mut image = export.new_image::<C>(width, height)?;
+    if !extra.is_empty() {
+        image.extra_samples(extra)?;
+    }
+    if let Some(bands) = bands {
+        if bands.ink_set != 0 {
+            image
+                .encoder()
+                .write_tag(TiffTag::Unknown(TIFF_TAG_INK_SET), bands.ink_set)?;
+        }
+        // InkNames is ASCII only; other labels stay in the CTI `BAND` section.
+        if !bands.names.is_empty() && bands.names.iter().all(|name| name.is_ascii() && !name.contains('\0')) {
+            image
+                .encoder()
+                .write_tag(TiffTag::Unknown(TIFF_TAG_INK_NAMES), TiffInkNames(&bands.names))?;
+        }
     }
+    image.write_data(samples)?;
+    Ok(())
 }
 
-fn downsample_half(img: &TiffImage) -> Result<TiffImage> {
-    let new_w = img.width.div_ceil(2);
-    let new_h = img.height.div_ceil(2);
-    let (channels, sample_bytes) = sample_layout(img.color_type)?;
-    let mut out = Vec::with_capacity((new_w * new_h * bytes_per_pixel(&img.color_type)?) as usize);
+/// NUL-separated ASCII list as InkNames expects; the `str` impl rejects
+/// embedded NULs.
+struct TiffInkNames<'a>(&'a [String]);
 
-    for y in 0..new_h {
-        for x in 0..new_w {
-            for ch in 0..channels {
-                if sample_bytes == 1 {
-                    let mut sum = 0u32;
-                    let mut count = 0u32;
-                    for sy in 0..2 {
-                        for sx in 0..2 {
-                            let src_x = x * 2 + sx;
-                            let src_y = y * 2 + sy;
-                            if src_x < img.width && src_y < img.height {
-                                let idx = ((src_y * img.width + src_x) as usize * channels) + ch;
-                                sum += img.data[idx] as u32;
-                                count += 1;
-                            }
-                        }
-                    }
-                    out.push((sum / count) as u8);
-                } else {
-                    let mut sum = 0u32;
-                    let mut count = 0u32;
-                    for sy in 0..2 {
-                        for sx in 0..2 {
-                            let src_x = x * 2 + sx;
-                            let src_y = y * 2 + sy;
-                            if src_x < img.width && src_y < img.height {
-                                let pixel = (src_y * img.width + src_x) as usize;
-                                let off = pixel * channels * 2 + ch * 2;
-                                let val = u16::from_le_bytes([img.data[off], img.data[off + 1]]) as u32;
-                                sum += val;
-                                count += 1;
-                            }
-                        }
-                    }
-                    out.extend_from_slice(&((sum / count) as u16).to_le_bytes());
-                }
-            }
-        }
+impl TiffValue for TiffInkNames<'_> {
+    const BYTE_LEN: u8 = 1;
+    const FIELD_TYPE: tiff::tags::Type = tiff::tags::Type::ASCII;
+
+    fn count(&self) -> usize {
+        self.0.iter().map(|name| name.len() + 1).sum()
     }
 
-    Ok(TiffImage {
-        width: new_w,
-        height: new_h,
-        color_type: img.color_type,
-        data: out,
-        xdpi: img.xdpi.map(|v| v / 2.0),
-        ydpi: img.ydpi.map(|v| v / 2.0),
-        icc: img.icc.clone(),
-    })
+    fn data(&self) -> Cow<'_, [u8]> {
+        let mut out = Vec::with_capacity(self.count());
+        for name in self.0 {
+            out.extend_from_slice(name.as_bytes());
+            out.push(0);
+        }
+        Cow::Owned(out)
+    }
 }
 
-fn compress_tile(
-    kind: CompressionType,
-    color_type: ColorType,
+fn save_float_tiff(
+    path: &Path,
+    width: u32,
+    height: u32,
+    color_type: u8,
     data: &[u8],
-    tile_extent: (u32, u32),
-    zstd_level: i32,
-) -> Result<TileCompressionResult> {
-    let comp = match kind {
-        CompressionType::None => TileCompressionResult {
-            comp: data.to_vec(),
-            adaptive_mode: None,
-        },
-        CompressionType::RLE => TileCompressionResult {
-            comp: rle_compress(data)?,
-            adaptive_mode: None,
-        },
-        CompressionType::Delta => TileCompressionResult {
-            comp: rle_compress(&delta_forward_for_color(data, color_type)?)?,
-            adaptive_mode: None,
-        },
-        CompressionType::Predictive => TileCompressionResult {
-            comp: rle_compress(&predictive_forward_for_color(data, color_type)?)?,
-            adaptive_mode: None,
-        },
-        CompressionType::LZ77 => TileCompressionResult {
-            comp: lz77_compress(data)?,
-            adaptive_mode: None,
-        },
-        CompressionType::Zstd => TileCompressionResult {
-            comp: zstd::bulk::compress(data, zstd_level)?,
-            adaptive_mode: None,
-        },
-        CompressionType::Lz4 => TileCompressionResult {
-            comp: lz4_flex::block::compress_prepend_size(data),
-            adaptive_mode: None,
-        },
-        CompressionType::Adaptive => compress_tile_adaptive(color_type, data, tile_extent, zstd_level)?,
-    };
-    Ok(comp)
+    descriptive: &DescriptiveMetadata,
+) -> Result<()> {
+    let expected = width as usize * height as usize * bytes_per_pixel_from_id(color_type)? as usize;
+    ensure!(data.len() == expected, "raw->float: {} bytes, expected {}", data.len(), expected);
+    let samples: Vec<f32> = data
+        .chunks_exact(4)
+        .map(f32_from_le_bytes)
+        .collect();
+    let mut export = TiffExport::create(path, descriptive)?;
+    if color_type == 9 {
+        export.new_image::<tiff_colortype::Gray32Float>(width, height)?.write_data(&samples)?;
+    } else {
+        export.new_image::<tiff_colortype::RGB32Float>(width, height)?.write_data(&samples)?;
+    }
+    Ok(())
 }
 
-fn compress_prepared_tile(
-    kind: CompressionType,
-    color_type: ColorType,
-    tile: Vec<u8>,
-    tile_extent: (u32, u32),
-    zstd_level: i32,
-) -> Result<CompTile> {
-    let comp = compress_tile(kind, color_type, &tile, tile_extent, zstd_level)?;
-    Ok(CompTile {
-        comp: comp.comp,
-        orig_len: tile.len() as u32,
-        crc: crc32(&tile),
-        adaptive_mode: comp.adaptive_mode,
+pub fn write_header<W: Write>(w: &mut W, h: &CTIHeader) -> Result<()> {
+    w.write_all(&h.magic)?;
+    w.write_all(&h.version.to_le_bytes())?;
+    w.write_all(&h.flags.to_le_bytes())?;
+    w.write_all(&h.width.to_le_bytes())?;
+    w.write_all(&h.height.to_le_bytes())?;
+    w.write_all(&h.tile_size.to_le_bytes())?;
+    w.write_all(&h.tiles_x.to_le_bytes())?;
+    w.write_all(&h.tiles_y.to_le_bytes())?;
+    w.write_all(&[h.color_type])?;
+    w.write_all(&[h.compression])?;
+    w.write_all(&[h.quality])?;
+    w.write_all(&h.reserved)?;
+    Ok(())
+}
+
+pub fn read_header<R: Read>(r: &mut R) -> Result<CTIHeader> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    let version = read_u16_le(r)?;
+    let flags = read_u16_le(r)?;
+    let width = read_u32_le(r)?;
+    let height = read_u32_le(r)?;
+    let tile_size = read_u32_le(r)?;
+    let tiles_x = read_u32_le(r)?;
+    let tiles_y = read_u32_le(r)?;
+    let color_type = read_u8(r)?;
+    let compression = read_u8(r)?;
+    let quality = read_u8(r)?;
+    let mut reserved = [0u8; 33];
+    r.read_exact(&mut reserved)?;
+    Ok(CTIHeader {
+        magic,
+        version,
+        flags,
+        width,
+        height,
+        tile_size,
+        tiles_x,
+        tiles_y,
+        color_type,
+        compression,
+        quality,
+        reserved,
     })
 }
 
-fn streaming_batch_tiles(tile_size: u32, bytes_per_pixel: u32) -> usize {
-    if let Some(override_tiles) = std::env::var_os("CTI_BATCH_TILES")
-        .and_then(|value| value.to_str().map(str::trim).map(str::to_owned))
-        .and_then(|value| value.parse::<usize>().ok())
-        .filter(|&value| value > 0)
-    {
-        return override_tiles;
+fn write_tile_index<W: Write>(w: &mut W, t: &TileIndex) -> Result<()> {
+    w.write_all(&t.offset.to_le_bytes())?;
+    w.write_all(&t.compressed_size.to_le_bytes())?;
+    w.write_all(&t.original_size.to_le_bytes())?;
+    w.write_all(&t.crc32.to_le_bytes())?;
+    Ok(())
+}
+
+pub fn read_indices<R: Read>(r: &mut R, n: usize) -> Result<Vec<TileIndex>> {
+    let mut v = Vec::with_capacity(n);
+    for _ in 0..n {
+        let offset = read_u64_le(r)?;
+        let compressed_size = read_u32_le(r)?;
+        let original_size = read_u32_le(r)?;
+        let crc32 = read_u32_le(r)?;
+        v.push(TileIndex {
+            offset,
+            compressed_size,
+            original_size,
+            crc32,
+        });
     }
+    Ok(v)
+}
 
-    let threads = rayon::current_num_threads().max(1);
-    let max_tile_bytes = (tile_size as usize)
-        .saturating_mul(tile_size as usize)
-        .saturating_mul(bytes_per_pixel as usize)
-        .max(1);
-    let target_batch_bytes = 64usize * 1024 * 1024;
-    let memory_limited = (target_batch_bytes / max_tile_bytes).max(1);
-    memory_limited.min(threads * 2).max(1)
+/// Writes the section TOC and payloads without an `SCRC` section. Section
+/// CRCs are only checked in files whose header has `FLAG_CHECKSUMS`, which
+/// [`CTIEncoder`] and [`CTIWriter`] stamp together with the `SCRC` they write.
+pub fn write_sections<W: Write + Seek>(w: &mut W, sections: &[(u32, Vec<u8>)]) -> Result<()> {
+    write_sections_with_staged(w, sections, &[], false)?;
+    Ok(())
 }
 
-fn compress_tile_adaptive(
-    color_type: ColorType,
-    data: &[u8],
-    tile_extent: (u32, u32),
-    zstd_level: i32,
-) -> Result<TileCompressionResult> {
-    let mut best_mode = AdaptiveTileMode::ZstdRaw;
-    let mut best_comp = zstd::bulk::compress(data, zstd_level)?;
-
-    if matches!(color_type, ColorType::L16 | ColorType::Rgb16) {
-        let channels = sample_layout(color_type)?.0;
-
-        let shuffle = shuffle_u16_bytes(data)?;
-        let shuffle_comp = zstd::bulk::compress(&shuffle, zstd_level)?;
-        if shuffle_comp.len() < best_comp.len() {
-            best_mode = AdaptiveTileMode::ZstdShuffle16;
-            best_comp = shuffle_comp;
-        }
+/// Writes the section TOC and payloads, closed by an `SCRC` section holding
+/// the CRC32 of every other payload when `section_crcs` is set; the caller
+/// then has to stamp `FLAG_CHECKSUMS`. Returns the end offset and the CRC32 of
+/// the TOC for the header.
+fn write_sections_with_staged<W: Write + Seek>(
+    w: &mut W,
+    memory_sections: &[(u32, Vec<u8>)],
+    staged_sections: &[StagedSectionFile],
+    section_crcs: bool,
+) -> Result<(u64, u32)> {
+    let payload_count = memory_sections.len() + staged_sections.len();
+    if payload_count == 0 {
+        w.write_all(&0u32.to_le_bytes())?;
+        return Ok((w.stream_position()?, crc32(&section_toc_bytes(&[]))));
+    }
 
-        let delta = delta_forward_u16(data, channels)?;
-        let delta_score = residual_score_u16(&delta, channels)?;
-        let delta_comp = zstd::bulk::compress(&delta, zstd_level)?;
-        if delta_comp.len() < best_comp.len() {
-            best_mode = AdaptiveTileMode::ZstdDelta16;
-            best_comp = delta_comp;
-        }
+    let total_count = payload_count + section_crcs as usize;
+    let toc_pos = w.stream_position()?;
+    w.seek(SeekFrom::Current(4 + (total_count as i64) * 20))?;
+    let mut descs = Vec::with_capacity(total_count);
+    let mut crcs = Vec::with_capacity(total_count * 4);
 
-        let predict = predictive_forward_u16(data, channels)?;
-        let predict_score = residual_score_u16(&predict, channels * 2)?;
-        if predict_score <= delta_score {
-            let predict_comp = zstd::bulk::compress(&predict, zstd_level)?;
-            if predict_comp.len() < best_comp.len() {
-                best_mode = AdaptiveTileMode::ZstdPredict16;
-                best_comp = predict_comp;
-            }
-        }
+    for (ty, payload) in memory_sections {
+        let offset = w.stream_position()?;
+        w.write_all(payload)?;
+        descs.push(SectionDesc {
+            ty: *ty,
+            offset,
+            size: payload.len() as u64,
+            crc32: None,
+        });
+        crcs.extend_from_slice(&crc32(payload).to_le_bytes());
+    }
 
-        let gradient = gradient_forward_u16(data, tile_extent.0 as usize, channels)?;
-        let gradient_score =
-            residual_score_u16(&gradient, tile_extent.0 as usize * channels + channels)?;
-        let best_linear_score = predict_score.min(delta_score);
-        if gradient_score * 100 <= best_linear_score * 95 {
-            let gradient_comp = zstd::bulk::compress(&gradient, zstd_level)?;
-            if gradient_comp.len() * 100 <= best_comp.len() * 97 {
-                best_mode = AdaptiveTileMode::ZstdGradient16;
-                best_comp = gradient_comp;
+    for section in staged_sections {
+        let offset = w.stream_position()?;
+        let mut staged = BufReader::new(File::open(&section.path)?);
+        let mut hasher = crc32fast::Hasher::new();
+        let mut buf = vec![0u8; 1 << 20];
+        loop {
+            let n = staged.read(&mut buf)?;
+            if n == 0 {
+                break;
             }
+            hasher.update(&buf[..n]);
+            w.write_all(&buf[..n])?;
         }
-
+        descs.push(SectionDesc {
+            ty: section.ty,
+            offset,
+            size: section.size,
+            crc32: None,
+        });
+        crcs.extend_from_slice(&hasher.finalize().to_le_bytes());
     }
 
-    let lz4_raw = lz4_flex::block::compress_prepend_size(data);
-    if lz4_raw.len() * 100 <= best_comp.len() * 105 {
-        best_mode = AdaptiveTileMode::Lz4Raw;
-        best_comp = lz4_raw;
+    if section_crcs {
+        // The SCRC entry itself is not covered; its slot stays zero.
+        crcs.extend_from_slice(&0u32.to_le_bytes());
+        descs.push(SectionDesc {
+            ty: SEC_TYPE_SCRC,
+            offset: w.stream_position()?,
+            size: crcs.len() as u64,
+            crc32: None,
+        });
+        w.write_all(&crcs)?;
     }
 
-    Ok(TileCompressionResult {
-        comp: best_comp,
-        adaptive_mode: Some(best_mode),
-    })
+    let end = w.stream_position()?;
+    let toc = section_toc_bytes(&descs);
+    w.seek(SeekFrom::Start(toc_pos))?;
+    w.write_all(&toc)?;
+    w.seek(SeekFrom::Start(end))?;
+    Ok((end, crc32(&toc)))
 }
 
-fn decompress_tile_with_size(
-    kind: u8,
-    comp: &[u8],
-    original_size: usize,
-    color_type: u8,
-    tile_extent: (u32, u32),
-    adaptive_mode: Option<AdaptiveTileMode>,
-) -> Result<Vec<u8>> {
-    match kind {
-        0 => Ok(comp.to_vec()),
-        1 => rle_decompress(comp),
-        2 => lz77_decompress(comp),
-        3 => {
-            let d = rle_decompress(comp)?;
-            delta_inverse_for_color(&d, color_type)
-        }
-        4 => {
-            let d = rle_decompress(comp)?;
-            predictive_inverse_for_color(&d, color_type)
-        }
-        10 => zstd::bulk::decompress(comp, original_size)
-            .map_err(|e| anyhow!("zstd decompress failed: {e}")),
-        11 => lz4_flex::block::decompress_size_prepended(comp).map_err(|e| anyhow!(e)),
-        250 => {
-            let mode = adaptive_mode.context("Adaptive tile is missing mode metadata")?;
-            match mode {
-                AdaptiveTileMode::ZstdRaw => zstd::bulk::decompress(comp, original_size)
-                    .map_err(|e| anyhow!("zstd decompress failed: {e}")),
-                AdaptiveTileMode::ZstdDelta16 => {
-                    let d = zstd::bulk::decompress(comp, original_size)
-                        .map_err(|e| anyhow!("zstd decompress failed: {e}"))?;
-                    delta_inverse_for_color(&d, color_type)
-                }
-                AdaptiveTileMode::ZstdPredict16 => {
-                    let d = zstd::bulk::decompress(comp, original_size)
-                        .map_err(|e| anyhow!("zstd decompress failed: {e}"))?;
-                    predictive_inverse_for_color(&d, color_type)
-                }
-                AdaptiveTileMode::ZstdShuffle16 => {
-                    let d = zstd::bulk::decompress(comp, original_size)
-                        .map_err(|e| anyhow!("zstd decompress failed: {e}"))?;
-                    unshuffle_u16_bytes(&d)
-                }
-                AdaptiveTileMode::ZstdGradient16 => {
-                    let d = zstd::bulk::decompress(comp, original_size)
-                        .map_err(|e| anyhow!("zstd decompress failed: {e}"))?;
-                    gradient_inverse_u16(
-                        &d,
-                        tile_extent.0 as usize,
-                        bytes_per_pixel_from_id(color_type)? as usize / 2,
-                    )
-                }
-                AdaptiveTileMode::Lz4Raw => {
-                    lz4_flex::block::decompress_size_prepended(comp).map_err(|e| anyhow!(e))
-                }
-            }
-        }
-        _ => bail!("Unknown compression id {}", kind),
-    }
+fn staged_pyramid_path(base_cti_path: &Path, level: u32) -> PathBuf {
+    let mut path = base_cti_path.to_path_buf();
+    let ext = base_cti_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| format!("{ext}.pylv{level}.tmp"))
+        .unwrap_or_else(|| format!("pylv{level}.tmp"));
+    path.set_extension(ext);
+    path
 }
 
-fn rle_compress(data: &[u8]) -> Result<Vec<u8>> {
-    let mut out = Vec::with_capacity(data.len());
-    let mut i = 0usize;
-    while i < data.len() {
-        let val = data[i];
-        let mut cnt = 1usize;
-        while i + cnt < data.len() && data[i + cnt] == val && cnt < 255 {
-            cnt += 1;
-        }
-        if cnt >= 4 {
-            out.push(0xFF);
-            out.push(TAG_RLE);
-            out.push(cnt as u8);
-            out.push(val);
-            i += cnt;
-        } else {
-            for _ in 0..cnt {
-                if val == 0xFF {
-                    out.push(0xFF);
-                    out.push(TAG_ESCAPE_FF);
-                } else {
-                    out.push(val);
-                }
-            }
-            i += cnt;
+fn build_metadata_sections(img: &TiffImage) -> Vec<(u32, Vec<u8>)> {
+    let mut sections = Vec::new();
+    if let (Some(x), Some(y)) = (img.xdpi, img.ydpi) {
+        let mut res = Vec::with_capacity(8);
+        res.extend_from_slice(&x.to_le_bytes());
+        res.extend_from_slice(&y.to_le_bytes());
+        sections.push((SEC_TYPE_RES, res));
+    }
+    if let Some(icc) = &img.icc {
+        sections.push((SEC_TYPE_ICC, icc.clone()));
+    }
+    if let Some(bands) = &img.bands {
+        sections.push((SEC_TYPE_BAND, bands.to_section()));
+    }
+    let descriptive = &img.descriptive;
+    for (ty, payload) in [
+        (SEC_TYPE_XMP, &descriptive.xmp),
+        (SEC_TYPE_EXIF, &descriptive.exif),
+        (SEC_TYPE_IPTC, &descriptive.iptc),
+    ] {
+        if let Some(payload) = payload {
+            sections.push((ty, payload.clone()));
         }
     }
-    Ok(out)
+    sections
 }
 
-fn rle_decompress(data: &[u8]) -> Result<Vec<u8>> {
-    let mut out = Vec::with_capacity(data.len());
-    let mut i = 0;
-    while i < data.len() {
-        let b = data[i];
-        i += 1;
-        if b != 0xFF {
-            out.push(b);
-            continue;
-        }
-        ensure!(i < data.len(), "RLE: truncated after 0xFF");
-        let tag = data[i];
-        i += 1;
-        match tag {
-            TAG_ESCAPE_FF => out.push(0xFF),
-            TAG_RLE => {
-                ensure!(i + 1 < data.len(), "RLE: truncated run");
-                let count = data[i] as usize;
-                let val = data[i + 1];
-                i += 2;
-                out.extend(std::iter::repeat_n(val, count));
-            }
-            TAG_LZ77 => bail!("RLE stream contains LZ77 tag"),
-            _ => bail!("RLE unknown tag {}", tag),
-        }
-    }
-    Ok(out)
+fn inspect_tiff_input(path: &Path) -> Result<InputImageInfo> {
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+    let mut dec = RawTiffDecoder::new(reader)?;
+    let (width, height) = dec.dimensions()?;
+    let color_type = tiff_decoder_color_type(&mut dec)?;
+    let (xdpi, ydpi, icc) = read_tiff_metadata_for_sections(path).unwrap_or((None, None, None));
+    Ok(InputImageInfo {
+        width,
+        height,
+        color_type,
+        xdpi,
+        ydpi,
+        icc_size: icc.as_ref().map(Vec::len),
+    })
 }
 
-fn delta_forward(data: &[u8]) -> Vec<u8> {
-    if data.is_empty() {
-        return vec![];
-    }
-    let mut out = Vec::with_capacity(data.len());
-    out.push(data[0]);
-    for i in 1..data.len() {
-        out.push(data[i].wrapping_sub(data[i - 1]));
-    }
-    out
+fn is_tiff_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| {
+            let ext = ext.to_ascii_lowercase();
+            ext == "tif" || ext == "tiff"
+        })
+        .unwrap_or(false)
 }
 
-fn delta_forward_for_color(data: &[u8], color_type: ColorType) -> Result<Vec<u8>> {
-    match color_type {
-        ColorType::L16 | ColorType::Rgb16 => delta_forward_u16(data, sample_layout(color_type)?.0),
-        _ => Ok(delta_forward(data)),
-    }
+fn is_png_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
 }
 
-fn delta_inverse(data: &[u8]) -> Vec<u8> {
-    if data.is_empty() {
-        return vec![];
-    }
-    let mut out = Vec::with_capacity(data.len());
-    let mut prev = data[0];
-    out.push(prev);
-    for &value in data.iter().skip(1) {
-        let v = prev.wrapping_add(value);
-        out.push(v);
-        prev = v;
-    }
-    out
+/// Resolution, ICC profile, band description and descriptive metadata of a
+/// tile source, stored as `RES` / `ICC` / `BAND` / `XMP ` `EXIF` `IPTC`.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct SourceMetadata {
+    pub xdpi: Option<f32>,
+    pub ydpi: Option<f32>,
+    pub icc: Option<Vec<u8>>,
+    pub bands: Option<BandInfo>,
+    pub descriptive: DescriptiveMetadata,
 }
 
-fn delta_inverse_for_color(data: &[u8], color_type: u8) -> Result<Vec<u8>> {
-    match color_type {
-        2 | 5 => delta_inverse_u16(data, bytes_per_pixel_from_id(color_type)? as usize / 2),
-        _ => Ok(delta_inverse(data)),
-    }
+/// XMP packet, EXIF blob and IPTC-NAA record copied from the source file.
+/// The EXIF blob is TIFF-structured, as in a PNG `eXIf` chunk.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DescriptiveMetadata {
+    pub xmp: Option<Vec<u8>>,
+    pub exif: Option<Vec<u8>>,
+    pub iptc: Option<Vec<u8>>,
 }
 
-fn predictive_forward(data: &[u8]) -> Vec<u8> {
-    if data.len() < 3 {
-        return data.to_vec();
+impl DescriptiveMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.xmp.is_none() && self.exif.is_none() && self.iptc.is_none()
     }
-    let mut out = Vec::with_capacity(data.len());
-    out.push(data[0]);
-    out.push(data[1]);
-    for i in 2..data.len() {
-        let p = data[i - 1].wrapping_add(data[i - 1].wrapping_sub(data[i - 2]));
-        out.push(data[i].wrapping_sub(p));
-    }
-    out
 }
 
-fn predictive_forward_for_color(data: &[u8], color_type: ColorType) -> Result<Vec<u8>> {
-    match color_type {
-        ColorType::L16 | ColorType::Rgb16 => predictive_forward_u16(data, sample_layout(color_type)?.0),
-        _ => Ok(predictive_forward(data)),
+impl SourceMetadata {
+    fn from_sections(sections: &[CTISection]) -> Result<Self> {
+        let payload = |ty| sections.iter().find(|sec| sec.ty == ty).map(|sec| sec.payload.clone());
+        let (xdpi, ydpi) = sections
+            .iter()
+            .find(|sec| sec.ty == SEC_TYPE_RES)
+            .and_then(|sec| parse_resolution_section(&sec.payload))
+            .map(|(x, y)| (Some(x), Some(y)))
+            .unwrap_or((None, None));
+        Ok(Self {
+            xdpi,
+            ydpi,
+            icc: payload(SEC_TYPE_ICC),
+            bands: sections
+                .iter()
+                .find(|sec| sec.ty == SEC_TYPE_BAND)
+                .map(|sec| BandInfo::from_section(&sec.payload))
+                .transpose()?,
+            descriptive: DescriptiveMetadata {
+                xmp: payload(SEC_TYPE_XMP),
+                exif: payload(SEC_TYPE_EXIF),
+                iptc: payload(SEC_TYPE_IPTC),
+            },
+        })
     }
 }
 
-fn predictive_inverse(data: &[u8]) -> Vec<u8> {
-    if data.len() < 3 {
-        return data.to_vec();
-    }
-    let mut out = Vec::with_capacity(data.len());
-    let (mut a0, mut a1) = (data[0], data[1]);
-    out.push(a0);
-    out.push(a1);
-    for &value in data.iter().skip(2) {
-        let p = a1.wrapping_add(a1.wrapping_sub(a0));
-        let v = p.wrapping_add(value);
-        out.push(v);
-        a0 = a1;
-        a1 = v;
+/// Supplies an image tile by tile to the streaming encoder.
+///
+/// Tiles are requested in tile index order. Zstd dictionary training first
+/// samples an increasing subset of tiles, so the order restarts once; sources
+/// that can only move forward have to rewind at that point.
+pub trait TileSource {
+    fn dimensions(&self) -> (u32, u32);
+
+    fn color_type(&self) -> ColorType;
+
+    /// Tile `(tx, ty)` of a grid of `tile_dims` tiles as tightly packed rows,
+    /// cropped at the right and bottom image edges.
+    fn read_tile(&mut self, tx: u32, ty: u32, tile_dims: (u32, u32)) -> Result<Vec<u8>>;
+
+    fn metadata(&self) -> SourceMetadata {
+        SourceMetadata::default()
     }
-    out
 }
 
-fn predictive_inverse_for_color(data: &[u8], color_type: u8) -> Result<Vec<u8>> {
-    match color_type {
-        2 | 5 => predictive_inverse_u16(data, bytes_per_pixel_from_id(color_type)? as usize / 2),
-        _ => Ok(predictive_inverse(data)),
+/// Pixel rectangle covered by tile `(tx, ty)`: `(x, y, width, height)`.
+fn tile_rect(width: u32, height: u32, tile_dims: (u32, u32), tx: u32, ty: u32) -> Result<(u32, u32, u32, u32)> {
+    ensure!(
+        tx < width.div_ceil(tile_dims.0) && ty < height.div_ceil(tile_dims.1),
+        CtiError::OutOfRange(format!("Tile {},{} out of range", tx, ty))
+    );
+    let (tile_w, tile_h) = tile_extent(width, height, tile_dims, tx, ty);
+    Ok((tx * tile_dims.0, ty * tile_dims.1, tile_w, tile_h))
+}
+
+/// Streams strips or tiles of a chunky TIFF through the `tiff` decoder.
+pub struct TiffTileSource {
+    dec: RawTiffDecoder<BufReader<File>>,
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+    chunk_type: TiffChunkType,
+    chunk_dims: (u32, u32),
+    metadata: SourceMetadata,
+}
+
+impl TiffTileSource {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let p = path.as_ref();
+        let file = File::open(p).with_context(|| format!("open {:?}", p))?;
+        let mut dec = RawTiffDecoder::new(BufReader::new(file))?.with_limits(TiffLimits::unlimited());
+        let (width, height) = dec.dimensions()?;
+        let color_type = tiff_decoder_color_type(&mut dec)?;
+        let chunky = dec
+            .find_tag_unsigned::<u16>(TiffTag::PlanarConfiguration)?
+            .unwrap_or(1);
+        ensure!(
+            chunky == 1,
+            CtiError::Unsupported("Only chunky TIFF input is supported for streaming encode".into())
+        );
+        let chunk_type = dec.get_chunk_type();
+        let chunk_dims = dec.chunk_dimensions();
+        ensure!(chunk_dims.0 > 0 && chunk_dims.1 > 0, "TIFF chunk dimensions must be non-zero");
+        let (xdpi, ydpi, icc) = read_tiff_metadata_for_sections(p).unwrap_or((None, None, None));
+        let bands = read_tiff_band_info(p, color_type).unwrap_or(None);
+        Ok(Self {
+            dec,
+            width,
+            height,
+            color_type,
+            chunk_type,
+            chunk_dims,
+            metadata: SourceMetadata {
+                xdpi,
+                ydpi,
+                icc,
+                bands,
+                descriptive: read_descriptive_metadata(p).unwrap_or_default(),
+            },
+        })
     }
 }
 
-fn delta_forward_u16(data: &[u8], channels: usize) -> Result<Vec<u8>> {
-    let samples = bytes_to_u16_vec(data)?;
-    let mut out = vec![0u16; samples.len()];
+impl TileSource for TiffTileSource {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
 
-    let head = channels.min(samples.len());
-    out[..head].copy_from_slice(&samples[..head]);
+    fn color_type(&self) -> ColorType {
+        self.color_type
+    }
 
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    {
-        if std::is_x86_feature_detected!("avx2") && channels > 0 && samples.len() > channels {
-            unsafe {
-                delta_forward_u16_avx2(&samples, channels, &mut out);
-            }
-            return Ok(u16_vec_to_bytes(&out));
-        }
+    fn read_tile(&mut self, tx: u32, ty: u32, tile_dims: (u32, u32)) -> Result<Vec<u8>> {
+        tile_rect(self.width, self.height, tile_dims, tx, ty)?;
+        extract_tiff_tile(
+            &mut self.dec,
+            self.width,
+            self.height,
+            self.color_type,
+            bytes_per_pixel(&self.color_type)?,
+            tile_dims,
+            tx,
+            ty,
+            self.chunk_type,
+            self.chunk_dims.0,
+            self.chunk_dims.1,
+        )
     }
 
-    for i in channels..samples.len() {
-        out[i] = samples[i].wrapping_sub(samples[i - channels]);
+    fn metadata(&self) -> SourceMetadata {
+        self.metadata.clone()
     }
-    Ok(u16_vec_to_bytes(&out))
 }
 
-fn delta_inverse_u16(data: &[u8], channels: usize) -> Result<Vec<u8>> {
-    let residuals = bytes_to_u16_vec(data)?;
-    let mut out = vec![0u16; residuals.len()];
-    for i in 0..residuals.len() {
-        out[i] = if i < channels {
-            residuals[i]
-        } else {
-            out[i - channels].wrapping_add(residuals[i])
+/// Decodes a non-interlaced PNG row by row, keeping one band of tile rows in
+/// memory. Requests for an earlier band reopen the file.
+pub struct PngRowSource {
+    path: PathBuf,
+    reader: png::Reader<BufReader<File>>,
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+    /// Next source row the reader will return.
+    next_row: u32,
+    /// First row and height of the buffered band.
+    band: (u32, u32),
+    band_data: Vec<u8>,
+    metadata: SourceMetadata,
+}
+
+impl PngRowSource {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let reader = Self::start(&path)?;
+        let info = reader.info();
+        ensure!(
+            !info.interlaced,
+            CtiError::Unsupported("Interlaced PNG cannot be decoded row by row".into())
+        );
+        let color_type = match reader.output_color_type() {
+            (png::ColorType::Grayscale, png::BitDepth::Eight) => ColorType::L8,
+            (png::ColorType::Grayscale, png::BitDepth::Sixteen) => ColorType::L16,
+            (png::ColorType::Rgb, png::BitDepth::Eight) => ColorType::Rgb8,
+            (png::ColorType::Rgb, png::BitDepth::Sixteen) => ColorType::Rgb16,
+            (png::ColorType::Rgba, png::BitDepth::Eight) => ColorType::Rgba8,
+            (png::ColorType::Rgba, png::BitDepth::Sixteen) => ColorType::Rgba16,
+            (png::ColorType::GrayscaleAlpha, png::BitDepth::Eight) => ColorType::La8,
+            (png::ColorType::GrayscaleAlpha, png::BitDepth::Sixteen) => ColorType::La16,
+            other => bail!(CtiError::Unsupported(format!("Unsupported PNG layout {:?}", other))),
+        };
+        let (xdpi, ydpi) = match info.pixel_dims {
+            Some(dims) if dims.unit == png::Unit::Meter => {
+                (Some(dims.xppu as f32 * 0.0254), Some(dims.yppu as f32 * 0.0254))
+            }
+            _ => (None, None),
+        };
+        let metadata = SourceMetadata {
+            xdpi,
+            ydpi,
+            icc: info.icc_profile.as_ref().map(|icc| icc.to_vec()),
+            bands: None,
+            descriptive: read_descriptive_metadata(&path).unwrap_or_default(),
         };
+        Ok(Self {
+            path,
+            width: info.width,
+            height: info.height,
+            reader,
+            color_type,
+            next_row: 0,
+            band: (0, 0),
+            band_data: Vec::new(),
+            metadata,
+        })
     }
-    Ok(u16_vec_to_bytes(&out))
-}
-
-fn predictive_forward_u16(data: &[u8], channels: usize) -> Result<Vec<u8>> {
-    let samples = bytes_to_u16_vec(data)?;
-    let mut out = vec![0u16; samples.len()];
-
-    let head = (channels * 2).min(samples.len());
-    out[..head].copy_from_slice(&samples[..head]);
 
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    {
-        if std::is_x86_feature_detected!("avx2") && channels > 0 && samples.len() > channels * 2 {
-            unsafe {
-                predictive_forward_u16_avx2(&samples, channels, &mut out);
+    fn start(path: &Path) -> Result<png::Reader<BufReader<File>>> {
+        let file = File::open(path).with_context(|| format!("open {:?}", path))?;
+        let mut decoder = png::Decoder::new(BufReader::new(file));
+        decoder.set_transformations(png::Transformations::EXPAND);
+        decoder.read_info().map_err(png_error)
+    }
+
+    /// Returns the next row with 16-bit samples converted to little endian.
+    fn next_row(&mut self) -> Result<Vec<u8>> {
+        let mut row = self
+            .reader
+            .next_row()
+            .map_err(png_error)?
+            .ok_or_else(|| CtiError::Corrupt(format!("PNG ends before row {}", self.next_row)))?
+            .data()
+            .to_vec();
+        if sample_layout(self.color_type)?.1 == 2 {
+            for sample in row.chunks_exact_mut(2) {
+                sample.swap(0, 1);
             }
-            return Ok(u16_vec_to_bytes(&out));
         }
+        self.next_row += 1;
+        Ok(row)
     }
 
-    for i in channels * 2..samples.len() {
-        let prev = samples[i - channels];
-        let prev_prev = samples[i - channels * 2];
-        let predicted = prev.wrapping_add(prev.wrapping_sub(prev_prev));
-        out[i] = samples[i].wrapping_sub(predicted);
+    fn load_band(&mut self, y: u32, rows: u32) -> Result<()> {
+        if y < self.next_row {
+            self.reader = Self::start(&self.path)?;
+            self.next_row = 0;
+        }
+        while self.next_row < y {
+            self.next_row()?;
+        }
+        self.band_data.clear();
+        for _ in 0..rows {
+            let row = self.next_row()?;
+            self.band_data.extend_from_slice(&row);
+        }
+        self.band = (y, rows);
+        Ok(())
     }
-    Ok(u16_vec_to_bytes(&out))
 }
 
-fn predictive_inverse_u16(data: &[u8], channels: usize) -> Result<Vec<u8>> {
-    let residuals = bytes_to_u16_vec(data)?;
-    let mut out = vec![0u16; residuals.len()];
-    for i in 0..residuals.len() {
-        out[i] = if i < channels * 2 {
-            residuals[i]
-        } else {
-            let prev = out[i - channels];
-            let prev_prev = out[i - channels * 2];
-            let predicted = prev.wrapping_add(prev.wrapping_sub(prev_prev));
-            predicted.wrapping_add(residuals[i])
-        };
+fn png_error(err: png::DecodingError) -> CtiError {
+    match err {
+        png::DecodingError::IoError(err) => CtiError::Io(err),
+        other => CtiError::Corrupt(format!("PNG: {other}")),
     }
-    Ok(u16_vec_to_bytes(&out))
 }
 
-fn gradient_forward_u16(data: &[u8], width: usize, channels: usize) -> Result<Vec<u8>> {
-    ensure!(width > 0, "gradient predictor requires non-zero width");
-    let samples = bytes_to_u16_vec(data)?;
-    let row_stride = width * channels;
-    ensure!(row_stride > 0, "gradient predictor requires non-zero row stride");
-    ensure!(
-        samples.len() % row_stride == 0,
-        "gradient predictor tile size does not match row stride"
-    );
-
-    let mut out = vec![0u16; samples.len()];
-    for i in 0..samples.len() {
-        let row_offset = i % row_stride;
-        out[i] = if i < row_stride {
-            if row_offset < channels {
-                samples[i]
-            } else {
-                samples[i].wrapping_sub(samples[i - channels])
-            }
-        } else if row_offset < channels {
-            samples[i].wrapping_sub(samples[i - row_stride])
-        } else {
-            let left = samples[i - channels];
-            let top = samples[i - row_stride];
-            let top_left = samples[i - row_stride - channels];
-            samples[i].wrapping_sub(jpegls_predict_u16(left, top, top_left))
-        };
+impl TileSource for PngRowSource {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
     }
-    Ok(u16_vec_to_bytes(&out))
-}
 
-fn gradient_inverse_u16(data: &[u8], width: usize, channels: usize) -> Result<Vec<u8>> {
-    ensure!(width > 0, "gradient predictor requires non-zero width");
-    let residuals = bytes_to_u16_vec(data)?;
-    let row_stride = width * channels;
-    ensure!(row_stride > 0, "gradient predictor requires non-zero row stride");
-    ensure!(
-        residuals.len() % row_stride == 0,
-        "gradient predictor tile size does not match row stride"
-    );
+    fn color_type(&self) -> ColorType {
+        self.color_type
+    }
 
-    let mut out = vec![0u16; residuals.len()];
-    for i in 0..residuals.len() {
-        let row_offset = i % row_stride;
-        out[i] = if i < row_stride {
-            if row_offset < channels {
-                residuals[i]
-            } else {
-                out[i - channels].wrapping_add(residuals[i])
-            }
-        } else if row_offset < channels {
-            out[i - row_stride].wrapping_add(residuals[i])
-        } else {
-            let left = out[i - channels];
-            let top = out[i - row_stride];
-            let top_left = out[i - row_stride - channels];
-            jpegls_predict_u16(left, top, top_left).wrapping_add(residuals[i])
-        };
+    fn read_tile(&mut self, tx: u32, ty: u32, tile_dims: (u32, u32)) -> Result<Vec<u8>> {
+        let (x, y, tile_w, tile_h) = tile_rect(self.width, self.height, tile_dims, tx, ty)?;
+        if self.band != (y, tile_h) {
+            self.load_band(y, tile_h)?;
+        }
+        let bpp = bytes_per_pixel(&self.color_type)? as usize;
+        let row_bytes = self.width as usize * bpp;
+        let mut out = Vec::with_capacity(tile_w as usize * tile_h as usize * bpp);
+        for row in self.band_data.chunks_exact(row_bytes) {
+            out.extend_from_slice(&row[x as usize * bpp..(x + tile_w) as usize * bpp]);
+        }
+        Ok(out)
     }
-    Ok(u16_vec_to_bytes(&out))
-}
 
-fn jpegls_predict_u16(left: u16, top: u16, top_left: u16) -> u16 {
-    if top_left >= left.max(top) {
-        left.min(top)
-    } else if top_left <= left.min(top) {
-        left.max(top)
-    } else {
-        (left as u32 + top as u32 - top_left as u32) as u16
+    fn metadata(&self) -> SourceMetadata {
+        self.metadata.clone()
     }
 }
 
-fn shuffle_u16_bytes(data: &[u8]) -> Result<Vec<u8>> {
-    ensure!(data.len() % 2 == 0, "16-bit shuffle requires even byte length");
-    let samples = data.len() / 2;
-    let mut out = vec![0u8; data.len()];
+/// Headerless interleaved pixels in row order, 16-bit samples little endian
+/// (the layout `cti decode` writes).
+pub struct RawTileSource<R: Read + Seek = BufReader<File>> {
+    inner: R,
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+}
 
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    {
-        if std::is_x86_feature_detected!("avx2") {
-            unsafe {
-                shuffle_u16_bytes_avx2(data, &mut out);
-            }
-            return Ok(out);
-        }
+impl RawTileSource {
+    pub fn open<P: AsRef<Path>>(path: P, width: u32, height: u32, color_type: ColorType) -> Result<Self> {
+        let p = path.as_ref();
+        let file = File::open(p).with_context(|| format!("open {:?}", p))?;
+        Self::new(BufReader::new(file), width, height, color_type)
     }
+}
 
-    for i in 0..samples {
-        out[i] = data[i * 2];
-        out[samples + i] = data[i * 2 + 1];
+impl<R: Read + Seek> RawTileSource<R> {
+    pub fn new(mut inner: R, width: u32, height: u32, color_type: ColorType) -> Result<Self> {
+        let expected = width as u64 * height as u64 * bytes_per_pixel(&color_type)? as u64;
+        let len = inner.seek(SeekFrom::End(0))?;
+        ensure!(
+            len == expected,
+            CtiError::InvalidInput(format!(
+                "Raw input has {} bytes, expected {} for {}x{} {:?}",
+                len, expected, width, height, color_type
+            ))
+        );
+        Ok(Self {
+            inner,
+            width,
+            height,
+            color_type,
+        })
     }
-    Ok(out)
 }
 
-fn unshuffle_u16_bytes(data: &[u8]) -> Result<Vec<u8>> {
-    ensure!(data.len() % 2 == 0, "16-bit unshuffle requires even byte length");
-    let samples = data.len() / 2;
-    let mut out = vec![0u8; data.len()];
+impl<R: Read + Seek> TileSource for RawTileSource<R> {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
 
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    {
-        if std::is_x86_feature_detected!("avx2") {
-            unsafe {
-                unshuffle_u16_bytes_avx2(data, &mut out);
-            }
-            return Ok(out);
-        }
+    fn color_type(&self) -> ColorType {
+        self.color_type
     }
 
-    for i in 0..samples {
-        out[i * 2] = data[i];
-        out[i * 2 + 1] = data[samples + i];
+    fn read_tile(&mut self, tx: u32, ty: u32, tile_dims: (u32, u32)) -> Result<Vec<u8>> {
+        let (x, y, tile_w, tile_h) = tile_rect(self.width, self.height, tile_dims, tx, ty)?;
+        let bpp = bytes_per_pixel(&self.color_type)? as u64;
+        let row_bytes = tile_w as usize * bpp as usize;
+        let mut out = vec![0u8; row_bytes * tile_h as usize];
+        for (row, dst) in out.chunks_exact_mut(row_bytes).enumerate() {
+            let offset = ((y as u64 + row as u64) * self.width as u64 + x as u64) * bpp;
+            self.inner.seek(SeekFrom::Start(offset))?;
+            self.inner.read_exact(dst)?;
+        }
+        Ok(out)
     }
-    Ok(out)
 }
 
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-#[target_feature(enable = "avx2")]
-unsafe fn delta_forward_u16_avx2(samples: &[u16], channels: usize, out: &mut [u16]) {
-    #[cfg(target_arch = "x86")]
-    use std::arch::x86::*;
-    #[cfg(target_arch = "x86_64")]
-    use std::arch::x86_64::*;
+/// SampleFormat 3 marks IEEE floating-point samples; absent means unsigned.
+fn tiff_samples_are_float<R: Read + Seek>(dec: &mut RawTiffDecoder<R>) -> Result<bool> {
+    Ok(dec
+        .find_tag_unsigned_vec::<u16>(TiffTag::SampleFormat)?
+        .is_some_and(|formats| formats.contains(&3)))
+}
 
-    let mut i = channels;
-    while i + 16 <= samples.len() {
-        let cur = unsafe { _mm256_loadu_si256(samples.as_ptr().add(i) as *const __m256i) };
-        let prev = unsafe { _mm256_loadu_si256(samples.as_ptr().add(i - channels) as *const __m256i) };
-        let delta = _mm256_sub_epi16(cur, prev);
-        unsafe { _mm256_storeu_si256(out.as_mut_ptr().add(i) as *mut __m256i, delta) };
-        i += 16;
+/// Color type of the current TIFF image. The tiff decoder keeps at most one
+/// extra (alpha) sample on RGB and CMYK and silently drops the rest, so such
+/// files are rejected instead of being read short.
+fn tiff_decoder_color_type<R: Read + Seek>(dec: &mut RawTiffDecoder<R>) -> Result<ColorType> {
+    let float = tiff_samples_are_float(dec)?;
+    let color_type = tiff_color_type_to_image_color_type(dec.colortype()?, float)?;
+    let samples = dec.find_tag_unsigned::<u16>(TiffTag::SamplesPerPixel)?.unwrap_or(1);
+    ensure!(
+        samples as usize == sample_layout(color_type)?.0,
+        CtiError::Unsupported(format!(
+            "TIFF with {} samples per pixel read as {:?}; only gray images may carry more extra samples",
+            samples, color_type
+        ))
+    );
+    Ok(color_type)
+}
+
+fn tiff_color_type_to_image_color_type(ct: tiff::ColorType, float: bool) -> Result<ColorType> {
+    if float {
+        return Ok(match ct {
+            tiff::ColorType::Gray(32) => ColorType::L32F,
+            tiff::ColorType::RGB(32) => ColorType::Rgb32F,
+            other => bail!(CtiError::Unsupported(format!(
+                "Unsupported floating-point TIFF color type: {:?}",
+                other
+            ))),
+        });
     }
+    Ok(match ct {
+        tiff::ColorType::Gray(1) => ColorType::L1,
+        tiff::ColorType::Gray(8) => ColorType::L8,
+        tiff::ColorType::Gray(16) => ColorType::L16,
+        tiff::ColorType::RGB(8) => ColorType::Rgb8,
+        tiff::ColorType::RGBA(8) => ColorType::Rgba8,
+        tiff::ColorType::RGB(16) => ColorType::Rgb16,
+        // tiff reports gray with an extra (alpha) sample as two-band data
+        tiff::ColorType::GrayA(8) | tiff::ColorType::Multiband { bit_depth: 8, num_samples: 2 } => ColorType::La8,
+        tiff::ColorType::GrayA(16) | tiff::ColorType::Multiband { bit_depth: 16, num_samples: 2 } => ColorType::La16,
+        tiff::ColorType::RGBA(16) => ColorType::Rgba16,
+        tiff::ColorType::CMYK(8) => ColorType::Cmyk8,
+        tiff::ColorType::CMYK(16) => ColorType::Cmyk16,
+        tiff::ColorType::CMYKA(8) => ColorType::Bands8(5),
+        tiff::ColorType::CMYKA(16) => ColorType::Bands16(5),
+        tiff::ColorType::Multiband { bit_depth: 8, num_samples } if (1..=MAX_BANDS as u16).contains(&num_samples) => {
+            ColorType::Bands8(num_samples as u8)
+        }
+        tiff::ColorType::Multiband { bit_depth: 16, num_samples } if (1..=MAX_BANDS as u16).contains(&num_samples) => {
+            ColorType::Bands16(num_samples as u8)
+        }
+        other => bail!(CtiError::Unsupported(format!(
+            "Unsupported TIFF color type for streaming: {:?}",
+            other
+        ))),
+    })
+}
 
-    while i < samples.len() {
-        out[i] = samples[i].wrapping_sub(samples[i - channels]);
-        i += 1;
-    }
+fn color_type_from_id(id: u8) -> Result<ColorType> {
+    Ok(match id {
+        1 => ColorType::L8,
+        2 => ColorType::L16,
+        3 => ColorType::Rgb8,
+        4 => ColorType::Rgba8,
+        5 => ColorType::Rgb16,
+        6 => ColorType::La8,
+        7 => ColorType::La16,
+        8 => ColorType::Rgba16,
+        9 => ColorType::L32F,
+        10 => ColorType::Rgb32F,
+        11 => ColorType::Cmyk8,
+        12 => ColorType::Cmyk16,
+        13 => ColorType::L1,
+        COLOR_ID_BANDS8..COLOR_ID_BANDS16 => ColorType::Bands8(id - COLOR_ID_BANDS8 + 1),
+        COLOR_ID_BANDS16..0xC0 => ColorType::Bands16(id - COLOR_ID_BANDS16 + 1),
+        _ => bail!(CtiError::UnknownColorType(id)),
+    })
 }
 
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-#[target_feature(enable = "avx2")]
-unsafe fn predictive_forward_u16_avx2(samples: &[u16], channels: usize, out: &mut [u16]) {
-    #[cfg(target_arch = "x86")]
-    use std::arch::x86::*;
-    #[cfg(target_arch = "x86_64")]
-    use std::arch::x86_64::*;
+#[allow(clippy::too_many_arguments)]
+fn extract_tiff_tile<R: Read + Seek>(
+    dec: &mut RawTiffDecoder<R>,
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+    bpp: u32,
+    tile_dims: (u32, u32),
+    tx: u32,
+    ty: u32,
+    chunk_type: TiffChunkType,
+    chunk_w_default: u32,
+    chunk_h_default: u32,
+) -> Result<Vec<u8>> {
+    let start_x = tx * tile_dims.0;
+    let start_y = ty * tile_dims.1;
+    let end_x = (start_x + tile_dims.0).min(width);
+    let end_y = (start_y + tile_dims.1).min(height);
+    let tile_w = end_x - start_x;
+    let tile_h = end_y - start_y;
+    let mut out = vec![0u8; (tile_w * tile_h * bpp) as usize];
 
-    let mut i = channels * 2;
-    while i + 16 <= samples.len() {
-        let cur = unsafe { _mm256_loadu_si256(samples.as_ptr().add(i) as *const __m256i) };
-        let prev = unsafe { _mm256_loadu_si256(samples.as_ptr().add(i - channels) as *const __m256i) };
-        let prev_prev =
-            unsafe { _mm256_loadu_si256(samples.as_ptr().add(i - channels * 2) as *const __m256i) };
-        let diff = _mm256_sub_epi16(prev, prev_prev);
-        let predicted = _mm256_add_epi16(prev, diff);
-        let residual = _mm256_sub_epi16(cur, predicted);
-        unsafe { _mm256_storeu_si256(out.as_mut_ptr().add(i) as *mut __m256i, residual) };
-        i += 16;
+    let chunks_across = match chunk_type {
+        TiffChunkType::Strip => 1,
+        TiffChunkType::Tile => width.div_ceil(chunk_w_default),
+    };
+    let chunks_down = match chunk_type {
+        TiffChunkType::Strip => height.div_ceil(chunk_h_default),
+        TiffChunkType::Tile => height.div_ceil(chunk_h_default),
+    };
+
+    let chunk_x0 = match chunk_type {
+        TiffChunkType::Strip => 0,
+        TiffChunkType::Tile => start_x / chunk_w_default,
+    };
+    let chunk_x1 = match chunk_type {
+        TiffChunkType::Strip => 0,
+        TiffChunkType::Tile => (end_x - 1) / chunk_w_default,
+    };
+    let chunk_y0 = start_y / chunk_h_default;
+    let chunk_y1 = (end_y - 1) / chunk_h_default;
+
+    for cy in chunk_y0..=chunk_y1 {
+        for cx in chunk_x0..=chunk_x1 {
+            ensure!(cy < chunks_down, "Source TIFF chunk row out of range");
+            ensure!(cx < chunks_across, "Source TIFF chunk column out of range");
+            let chunk_index = match chunk_type {
+                TiffChunkType::Strip => cy,
+                TiffChunkType::Tile => cy * chunks_across + cx,
+            };
+
+            let chunk_x = match chunk_type {
+                TiffChunkType::Strip => 0,
+                TiffChunkType::Tile => cx * chunk_w_default,
+            };
+            let chunk_y = cy * chunk_h_default;
+            let actual_chunk_w = match chunk_type {
+                TiffChunkType::Strip => width,
+                TiffChunkType::Tile => (chunk_x + chunk_w_default).min(width) - chunk_x,
+            };
+            let actual_chunk_h = (chunk_y + chunk_h_default).min(height) - chunk_y;
+
+            let mut chunk = dec.read_chunk(chunk_index)?;
+            let mut chunk_bytes = chunk_to_le_bytes(&mut chunk, color_type)?;
+            if color_type == ColorType::L1 {
+                chunk_bytes = unpack_bilevel_rows(&chunk_bytes, actual_chunk_w, actual_chunk_h)?;
+            }
+            blit_chunk_overlap(
+                &mut out,
+                tile_w,
+                tile_h,
+                start_x,
+                start_y,
+                &chunk_bytes,
+                actual_chunk_w,
+                actual_chunk_h,
+                chunk_x,
+                chunk_y,
+                bpp,
+            )?;
+        }
     }
 
-    while i < samples.len() {
-        let prev = samples[i - channels];
-        let prev_prev = samples[i - channels * 2];
-        let predicted = prev.wrapping_add(prev.wrapping_sub(prev_prev));
-        out[i] = samples[i].wrapping_sub(predicted);
-        i += 1;
+    Ok(out)
+}
+
+fn chunk_to_le_bytes(chunk: &mut RawTiffDecodingResult, color_type: ColorType) -> Result<Vec<u8>> {
+    Ok(match sample_layout(color_type)?.1 {
+        1 => chunk.as_buffer(0).as_bytes().to_vec(),
+        2 => {
+            if cfg!(target_endian = "little") {
+                chunk.as_buffer(0).as_bytes().to_vec()
+            } else {
+                match chunk {
+                    RawTiffDecodingResult::U16(values) => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+                    _ => bail!("Unexpected TIFF chunk type for 16-bit image"),
+                }
+            }
+        }
+        _ => {
+            if cfg!(target_endian = "little") {
+                chunk.as_buffer(0).as_bytes().to_vec()
+            } else {
+                match chunk {
+                    RawTiffDecodingResult::F32(values) => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+                    _ => bail!("Unexpected TIFF chunk type for float image"),
+                }
+            }
+        }
+    })
+}
+
+/// MSB-first packed rows (each padded to a byte) to one byte per pixel.
+fn unpack_bilevel_rows(packed: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
+    let row_bytes = width.div_ceil(8) as usize;
+    ensure!(
+        packed.len() >= row_bytes * height as usize,
+        "Bilevel chunk has {} bytes, expected {}",
+        packed.len(),
+        row_bytes * height as usize
+    );
+    let mut out = Vec::with_capacity(width as usize * height as usize);
+    for row in packed.chunks_exact(row_bytes).take(height as usize) {
+        out.extend((0..width as usize).map(|x| ((row[x / 8] >> (7 - x % 8)) & 1) * 255));
     }
+    Ok(out)
 }
 
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-#[target_feature(enable = "avx2")]
-unsafe fn shuffle_u16_bytes_avx2(data: &[u8], out: &mut [u8]) {
-    #[cfg(target_arch = "x86")]
-    use std::arch::x86::*;
-    #[cfg(target_arch = "x86_64")]
-    use std::arch::x86_64::*;
+fn pack_bilevel_rows(data: &[u8], width: u32) -> Vec<u8> {
+    data.chunks_exact(width as usize)
+        .flat_map(|row| {
+            row.chunks(8).map(|byte| {
+                byte.iter()
+                    .enumerate()
+                    .fold(0u8, |acc, (i, &v)| acc | (((v > 127) as u8) << (7 - i)))
+            })
+        })
+        .collect()
+}
 
-    let samples = data.len() / 2;
-    let low_mask = _mm_setr_epi8(0, 2, 4, 6, 8, 10, 12, 14, -1, -1, -1, -1, -1, -1, -1, -1);
-    let high_mask = _mm_setr_epi8(1, 3, 5, 7, 9, 11, 13, 15, -1, -1, -1, -1, -1, -1, -1, -1);
+#[allow(clippy::too_many_arguments)]
+fn blit_chunk_overlap(
+    out: &mut [u8],
+    tile_w: u32,
+    tile_h: u32,
+    tile_x: u32,
+    tile_y: u32,
+    chunk: &[u8],
+    chunk_w: u32,
+    chunk_h: u32,
+    chunk_x: u32,
+    chunk_y: u32,
+    bpp: u32,
+) -> Result<()> {
+    let copy_x0 = tile_x.max(chunk_x);
+    let copy_y0 = tile_y.max(chunk_y);
+    let copy_x1 = (tile_x + tile_w).min(chunk_x + chunk_w);
+    let copy_y1 = (tile_y + tile_h).min(chunk_y + chunk_h);
+    if copy_x0 >= copy_x1 || copy_y0 >= copy_y1 {
+        return Ok(());
+    }
 
-    let mut sample_idx = 0usize;
-    while sample_idx + 16 <= samples {
-        let byte_idx = sample_idx * 2;
-        let lo = unsafe { _mm_loadu_si128(data.as_ptr().add(byte_idx) as *const __m128i) };
-        let hi = unsafe { _mm_loadu_si128(data.as_ptr().add(byte_idx + 16) as *const __m128i) };
-        let lo_bytes_lo = _mm_shuffle_epi8(lo, low_mask);
-        let lo_bytes_hi = _mm_shuffle_epi8(hi, low_mask);
-        let hi_bytes_lo = _mm_shuffle_epi8(lo, high_mask);
-        let hi_bytes_hi = _mm_shuffle_epi8(hi, high_mask);
-        let lows = _mm_unpacklo_epi64(lo_bytes_lo, lo_bytes_hi);
-        let highs = _mm_unpacklo_epi64(hi_bytes_lo, hi_bytes_hi);
-        unsafe { _mm_storeu_si128(out.as_mut_ptr().add(sample_idx) as *mut __m128i, lows) };
-        unsafe {
-            _mm_storeu_si128(out.as_mut_ptr().add(samples + sample_idx) as *mut __m128i, highs)
-        };
-        sample_idx += 16;
+    let copy_w = copy_x1 - copy_x0;
+    let copy_h = copy_y1 - copy_y0;
+    for row in 0..copy_h {
+        let src_x = copy_x0 - chunk_x;
+        let src_y = (copy_y0 - chunk_y) + row;
+        let dst_x = copy_x0 - tile_x;
+        let dst_y = (copy_y0 - tile_y) + row;
+        let src_off = ((src_y * chunk_w + src_x) * bpp) as usize;
+        let dst_off = ((dst_y * tile_w + dst_x) * bpp) as usize;
+        let len = (copy_w * bpp) as usize;
+        out[dst_off..dst_off + len].copy_from_slice(&chunk[src_off..src_off + len]);
     }
+    Ok(())
+}
 
-    while sample_idx < samples {
-        out[sample_idx] = data[sample_idx * 2];
-        out[samples + sample_idx] = data[sample_idx * 2 + 1];
-        sample_idx += 1;
+/// Half-resolution view of a CTI level, the source of the next pyramid
+/// level. Decoded source tiles are cached until the requested tile rows
+/// move past them.
+struct DownsampledCtiSource<'a, R> {
+    source: &'a mut R,
+    layout: LayoutInfo,
+    color_type: ColorType,
+    tile_cache: HashMap<usize, Vec<u8>>,
+    last_ty: u32,
+}
+
+impl<'a, R: Read + Seek> DownsampledCtiSource<'a, R> {
+    fn new(source: &'a mut R, layout: LayoutInfo) -> Result<Self> {
+        Ok(Self {
+            color_type: color_type_from_id(layout.header.color_type)?,
+            source,
+            layout,
+            tile_cache: HashMap::new(),
+            last_ty: 0,
+        })
     }
 }
 
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-#[target_feature(enable = "avx2")]
-unsafe fn unshuffle_u16_bytes_avx2(data: &[u8], out: &mut [u8]) {
-    #[cfg(target_arch = "x86")]
-    use std::arch::x86::*;
-    #[cfg(target_arch = "x86_64")]
-    use std::arch::x86_64::*;
+impl<R: Read + Seek> TileSource for DownsampledCtiSource<'_, R> {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.layout.header.width.div_ceil(2), self.layout.header.height.div_ceil(2))
+    }
 
-    let samples = data.len() / 2;
-    let mut sample_idx = 0usize;
-    while sample_idx + 16 <= samples {
-        let lows = unsafe { _mm_loadu_si128(data.as_ptr().add(sample_idx) as *const __m128i) };
-        let highs =
-            unsafe { _mm_loadu_si128(data.as_ptr().add(samples + sample_idx) as *const __m128i) };
-        let first = _mm_unpacklo_epi8(lows, highs);
-        let second = _mm_unpackhi_epi8(lows, highs);
-        unsafe { _mm_storeu_si128(out.as_mut_ptr().add(sample_idx * 2) as *mut __m128i, first) };
-        unsafe {
-            _mm_storeu_si128(out.as_mut_ptr().add(sample_idx * 2 + 16) as *mut __m128i, second)
-        };
-        sample_idx += 16;
+    fn color_type(&self) -> ColorType {
+        self.color_type
     }
 
-    while sample_idx < samples {
-        out[sample_idx * 2] = data[sample_idx];
-        out[sample_idx * 2 + 1] = data[samples + sample_idx];
-        sample_idx += 1;
+    fn read_tile(&mut self, tx: u32, ty: u32, tile_dims: (u32, u32)) -> Result<Vec<u8>> {
+        let src_header = &self.layout.header;
+        if ty < self.last_ty {
+            self.tile_cache.clear();
+        } else if ty > self.last_ty {
+            let min_keep_source_row = (ty * tile_dims.1 * 2) / src_header.tile_height();
+            let tiles_x = src_header.tiles_x;
+            self.tile_cache.retain(|idx, _| (*idx as u32) / tiles_x >= min_keep_source_row);
+        }
+        self.last_ty = ty;
+        downsample_cti_tile_from_layout(
+            self.source,
+            &self.layout,
+            self.color_type,
+            tile_dims,
+            tx,
+            ty,
+            &mut self.tile_cache,
+        )
     }
 }
 
-fn residual_score_u16(data: &[u8], warmup_samples: usize) -> Result<u64> {
-    let residuals = bytes_to_u16_vec(data)?;
-    let residuals = residuals.get(warmup_samples..).unwrap_or(&[]);
-    Ok(sum_abs_u16_residuals(residuals))
-}
+fn downsample_cti_tile_from_layout<R: Read + Seek>(
+    source: &mut R,
+    layout: &LayoutInfo,
+    color_type: ColorType,
+    tile_dims: (u32, u32),
+    tx: u32,
+    ty: u32,
+    tile_cache: &mut HashMap<usize, Vec<u8>>,
+) -> Result<Vec<u8>> {
+    let src_header = layout.header;
+    let dst_width = src_header.width.div_ceil(2);
+    let dst_height = src_header.height.div_ceil(2);
+    let start_x = tx * tile_dims.0;
+    let start_y = ty * tile_dims.1;
+    let end_x = (start_x + tile_dims.0).min(dst_width);
+    let end_y = (start_y + tile_dims.1).min(dst_height);
+    let dst_tile_w = end_x - start_x;
+    let dst_tile_h = end_y - start_y;
+    let (channels, sample_bytes) = sample_layout(color_type)?;
+    let dst_pixels = (dst_tile_w * dst_tile_h) as usize;
+    let mut sums = vec![0u32; dst_pixels * channels];
+    let mut float_sums = vec![0f64; if sample_bytes == 4 { dst_pixels * channels } else { 0 }];
+    let mut counts = vec![0u8; dst_pixels];
 
-fn sum_abs_u16_residuals(values: &[u16]) -> u64 {
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    {
-        if std::is_x86_feature_detected!("avx2") {
-            // AVX2 gives us a cheap residual score used by the adaptive codec selector.
-            unsafe {
-                return sum_abs_u16_residuals_avx2(values);
-            }
-        }
-    }
-    values
-        .iter()
-        .map(|&value| (value as i16 as i32).unsigned_abs() as u64)
-        .sum()
-}
+    let src_start_x = start_x * 2;
+    let src_start_y = start_y * 2;
+    let src_end_x = (end_x * 2).min(src_header.width);
+    let src_end_y = (end_y * 2).min(src_header.height);
+    let (src_tile_w, src_tile_h) = src_header.tile_dims();
+    let src_tile_x0 = src_start_x / src_tile_w;
+    let src_tile_y0 = src_start_y / src_tile_h;
+    let src_tile_x1 = (src_end_x.saturating_sub(1)) / src_tile_w;
+    let src_tile_y1 = (src_end_y.saturating_sub(1)) / src_tile_h;
 
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-#[target_feature(enable = "avx2")]
-unsafe fn sum_abs_u16_residuals_avx2(values: &[u16]) -> u64 {
-    #[cfg(target_arch = "x86")]
-    use std::arch::x86::*;
-    #[cfg(target_arch = "x86_64")]
-    use std::arch::x86_64::*;
+    for sty in src_tile_y0..=src_tile_y1 {
+        for stx in src_tile_x0..=src_tile_x1 {
+            let idx = (sty * src_header.tiles_x + stx) as usize;
+            let tile = match tile_cache.entry(idx) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    entry.insert(read_decoded_tile(source, layout, &layout.indices[idx], idx)?)
+                }
+            };
+            let (tile_w, tile_h) = tile_extent(
+                src_header.width,
+                src_header.height,
+                (src_tile_w, src_tile_h),
+                stx,
+                sty,
+            );
+            let tile_origin_x = stx * src_tile_w;
+            let tile_origin_y = sty * src_tile_h;
 
-    let mut acc = _mm256_setzero_si256();
-    let zero = _mm256_setzero_si256();
-    let mut chunks = values.chunks_exact(16);
-    for chunk in &mut chunks {
-        let vec = unsafe { _mm256_loadu_si256(chunk.as_ptr() as *const __m256i) };
-        let abs = _mm256_abs_epi16(vec);
-        let lo = _mm256_unpacklo_epi16(abs, zero);
-        let hi = _mm256_unpackhi_epi16(abs, zero);
-        acc = _mm256_add_epi32(acc, lo);
-        acc = _mm256_add_epi32(acc, hi);
-    }
+            for local_y in 0..tile_h as usize {
+                let src_y = tile_origin_y + local_y as u32;
+                if src_y < src_start_y || src_y >= src_end_y {
+                    continue;
+                }
+                let dst_y = src_y / 2;
+                if dst_y < start_y || dst_y >= end_y {
+                    continue;
+                }
 
-    let mut lanes = [0i32; 8];
-    unsafe { _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, acc) };
-    let mut sum = lanes.iter().map(|&lane| lane as u64).sum::<u64>();
-    for &value in chunks.remainder() {
-        sum += (value as i16 as i32).unsigned_abs() as u64;
-    }
-    sum
-}
+                for local_x in 0..tile_w as usize {
+                    let src_x = tile_origin_x + local_x as u32;
+                    if src_x < src_start_x || src_x >= src_end_x {
+                        continue;
+                    }
+                    let dst_x = src_x / 2;
+                    if dst_x < start_x || dst_x >= end_x {
+                        continue;
+                    }
 
-fn lz77_compress(data: &[u8]) -> Result<Vec<u8>> {
-    const WINDOW: usize = 4096;
-    const MIN_MATCH: usize = 3;
-    let mut out = Vec::with_capacity(data.len());
-    let mut i = 0usize;
-    while i < data.len() {
-        let start = i.saturating_sub(WINDOW);
-        let (mut best_len, mut best_dist) = (0usize, 0usize);
-        for j in start..i {
-            let mut l = 0usize;
-            while i + l < data.len() && j + l < i && data[j + l] == data[i + l] && l < 255 {
-                l += 1;
-            }
-            if l >= MIN_MATCH && l > best_len {
-                best_len = l;
-                best_dist = i - j;
-                if best_len == 255 {
-                    break;
+                    let dst_index = ((dst_y - start_y) * dst_tile_w + (dst_x - start_x)) as usize;
+                    counts[dst_index] = counts[dst_index].saturating_add(1);
+                    let src_base = (local_y * tile_w as usize + local_x) * channels * sample_bytes;
+                    let dst_base = dst_index * channels;
+                    if sample_bytes == 1 {
+                        for ch in 0..channels {
+                            sums[dst_base + ch] += tile[src_base + ch] as u32;
+                        }
+                    } else if sample_bytes == 2 {
+                        for ch in 0..channels {
+                            let off = src_base + ch * 2;
+                            sums[dst_base + ch] +=
+                                u16::from_le_bytes([tile[off], tile[off + 1]]) as u32;
+                        }
+                    } else {
+                        for ch in 0..channels {
+                            let off = src_base + ch * 4;
+                            float_sums[dst_base + ch] += f32_from_le_bytes(&tile[off..off + 4]) as f64;
+                        }
+                    }
                 }
             }
         }
-        if best_len >= MIN_MATCH {
-            out.push(0xFF);
-            out.push(TAG_LZ77);
-            out.push((best_dist >> 8) as u8);
-            out.push((best_dist & 0xFF) as u8);
-            out.push(best_len as u8);
-            i += best_len;
+    }
+
+    let mut out = Vec::with_capacity(dst_pixels * channels * sample_bytes);
+    for (pixel, &count) in counts.iter().enumerate() {
+        let count = count.max(1) as u32;
+        let base = pixel * channels;
+        if color_type == ColorType::L1 {
+            out.push(bilevel_from_average((sums[base] / count) as u8));
+        } else if sample_bytes == 1 {
+            for ch in 0..channels {
+                out.push((sums[base + ch] / count) as u8);
+            }
+        } else if sample_bytes == 2 {
+            for ch in 0..channels {
+                out.extend_from_slice(&((sums[base + ch] / count) as u16).to_le_bytes());
+            }
         } else {
-            let b = data[i];
-            if b == 0xFF {
-                out.push(0xFF);
-                out.push(TAG_ESCAPE_FF);
-            } else {
-                out.push(b);
+            for ch in 0..channels {
+                out.extend_from_slice(&((float_sums[base + ch] / count as f64) as f32).to_le_bytes());
             }
-            i += 1;
         }
     }
+
     Ok(out)
 }
 
-fn lz77_decompress(data: &[u8]) -> Result<Vec<u8>> {
-    let mut out = Vec::with_capacity(data.len());
-    let mut i = 0usize;
-    while i < data.len() {
-        let b = data[i];
-        i += 1;
-        if b != 0xFF {
-            out.push(b);
-            continue;
-        }
-        ensure!(i < data.len(), "LZ77: truncated after 0xFF");
-        let tag = data[i];
-        i += 1;
-        match tag {
-            TAG_ESCAPE_FF => out.push(0xFF),
-            TAG_RLE => {
-                ensure!(i + 1 < data.len(), "LZ77: RLE tuple truncated");
-                let count = data[i] as usize;
-                let val = data[i + 1];
-                i += 2;
-                out.extend(std::iter::repeat_n(val, count));
-            }
-            TAG_LZ77 => {
-                ensure!(i + 2 < data.len(), "LZ77: backref truncated");
-                let dist = ((data[i] as usize) << 8) | (data[i + 1] as usize);
-                let len = data[i + 2] as usize;
-                i += 3;
-                ensure!(dist > 0, "LZ77: distance zero");
-                ensure!(len >= 3, "LZ77: len too small");
-                ensure!(dist <= out.len(), "LZ77: distance beyond buffer");
-                let start = out.len() - dist;
-                for k in 0..len {
-                    let byte = out[start + k];
-                    out.push(byte);
+#[allow(dead_code)]
+fn downsample_packed_image(
+    src: &[u8],
+    src_w: u32,
+    src_h: u32,
+    color_type: ColorType,
+) -> Result<Vec<u8>> {
+    let dst_w = src_w.div_ceil(2);
+    let dst_h = src_h.div_ceil(2);
+    let bpp = bytes_per_pixel(&color_type)?;
+    let mut out = vec![0u8; (dst_w * dst_h * bpp) as usize];
+    let (channels, sample_bytes) = sample_layout(color_type)?;
+    for dy in 0..dst_h as usize {
+        let sy0 = dy * 2;
+        let sy1 = (sy0 + 1).min(src_h.saturating_sub(1) as usize);
+        for dx in 0..dst_w as usize {
+            let sx0 = dx * 2;
+            let sx1 = (sx0 + 1).min(src_w.saturating_sub(1) as usize);
+            for ch in 0..channels {
+                if sample_bytes == 1 {
+                    let mut sum = 0u32;
+                    let mut count = 0u32;
+                    for sy in [sy0, sy1] {
+                        for sx in [sx0, sx1] {
+                            let base = (sy * src_w as usize + sx) * channels + ch;
+                            sum += src[base] as u32;
+                            count += 1;
+                        }
+                    }
+                    out[(dy * dst_w as usize + dx) * channels + ch] = (sum / count) as u8;
+                } else {
+                    let mut sum = 0u32;
+                    let mut count = 0u32;
+                    for sy in [sy0, sy1] {
+                        for sx in [sx0, sx1] {
+                            let base = ((sy * src_w as usize + sx) * channels + ch) * 2;
+                            sum += u16::from_le_bytes([src[base], src[base + 1]]) as u32;
+                            count += 1;
+                        }
+                    }
+                    let value = (sum / count) as u16;
+                    let dst_off = ((dy * dst_w as usize + dx) * channels + ch) * 2;
+                    out[dst_off..dst_off + 2].copy_from_slice(&value.to_le_bytes());
                 }
             }
-            _ => bail!("LZ77: unknown tag {}", tag),
         }
     }
     Ok(out)
 }
 
-fn rct_forward_rgb8(buf: &mut [u8]) {
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    {
-        if std::is_x86_feature_detected!("avx2") && buf.len() >= 16 * 3 {
-            unsafe {
-                rct_forward_rgb8_avx2(buf);
-            }
-            return;
+#[derive(Debug)]
+struct LayoutInfo {
+    header: CTIHeader,
+    indices: Vec<TileIndex>,
+    sections: Vec<SectionDesc>,
+    adaptive_tile_modes: Option<Vec<u8>>,
+    max_error: Option<u16>,
+    near_lossless_tagged: bool,
+    zstd_dict: Option<ZstdDictionary>,
+    solid_tiles: Option<Vec<u8>>,
+    /// Tile index entries that point outside the data area or disagree with
+    /// the tile extent. Only these tiles fail to decode.
+    bad_tiles: BTreeMap<usize, String>,
+    /// Tile index or section TOC checksums that failed. Only recovery
+    /// decoding goes ahead and relies on the per-tile and per-section checks.
+    checksum_faults: Vec<&'static str>,
+    /// Limits the layout was scanned with; nested levels inherit them.
+    limits: DecodeLimits,
+    depth: u32,
+}
+
+impl LayoutInfo {
+    fn is_solid_tile(&self, tile_number: usize) -> bool {
+        self.solid_tiles
+            .as_ref()
+            .is_some_and(|bits| bits[tile_number / 8] & (1 << (tile_number % 8)) != 0)
+    }
+
+    fn solid_tile_count(&self) -> usize {
+        self.solid_tiles
+            .as_ref()
+            .map_or(0, |bits| bits.iter().map(|b| b.count_ones() as usize).sum())
+    }
+
+    fn check_checksums(&self) -> Result<()> {
+        match self.checksum_faults.first() {
+            Some(what) => Err(CtiError::ChecksumMismatch(what)),
+            None => Ok(()),
         }
     }
 
-    for p in buf.chunks_exact_mut(3) {
-        let (r, g, b) = (p[0] as i32, p[1] as i32, p[2] as i32);
-        let y = (r + (g << 1) + b) >> 2;
-        let cb = b - g;
-        let cr = r - g;
-        p[0] = y.clamp(0, 255) as u8;
-        p[1] = (cb as i16 as i32 & 0xFF) as u8;
-        p[2] = (cr as i16 as i32 & 0xFF) as u8;
+    fn check_tile(&self, tile_number: usize) -> Result<()> {
+        match self.bad_tiles.get(&tile_number) {
+            Some(reason) => Err(CtiError::Corrupt(reason.clone())),
+            None => Ok(()),
+        }
     }
-}
 
-fn rct_inverse_rgb8(buf: &mut [u8]) {
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    {
-        if std::is_x86_feature_detected!("avx2") && buf.len() >= 16 * 3 {
-            unsafe {
-                rct_inverse_rgb8_avx2(buf);
-            }
-            return;
-        }
+    /// Reads the `TMOD`, `NLER`, `ZDIC` and `TSOL` sections the tile codecs need.
+    fn read_codec_sections<R: Read + Seek>(&mut self, r: &mut R) -> Result<()> {
+        let (compression, total_tiles) = (self.header.compression, self.indices.len());
+        self.adaptive_tile_modes = read_adaptive_tile_modes(r, compression, total_tiles, &self.sections)?;
+        let near_lossless = read_near_lossless_bound(r, compression, &self.sections)?;
+        self.max_error = near_lossless.map(|(max_error, _)| max_error);
+        self.near_lossless_tagged = near_lossless.is_some_and(|(_, tagged)| tagged);
+        self.zstd_dict = read_zstd_dictionary(r, &self.sections)?;
+        self.solid_tiles = read_solid_tile_bitmap(r, total_tiles, &self.sections)?;
+        Ok(())
     }
 
-    for p in buf.chunks_exact_mut(3) {
-        let y = p[0] as i32;
-        let cb = (p[1] as i8) as i32;
-        let cr = (p[2] as i8) as i32;
-        let g = y - ((cb + cr) >> 2);
-        let r = cr + g;
-        let b = cb + g;
-        p[0] = r.clamp(0, 255) as u8;
-        p[1] = g.clamp(0, 255) as u8;
-        p[2] = b.clamp(0, 255) as u8;
+    fn codec_params(&self) -> TileCodecParams<'_> {
+        TileCodecParams {
+            zstd_level: 0,
+            quality: self.header.quality,
+            max_error: self.max_error.unwrap_or(0),
+            near_lossless_tagged: self.near_lossless_tagged,
+            zstd_dict: self.zstd_dict.as_ref(),
+        }
     }
 }
 
-fn rct_forward_rgb16(buf: &mut [u8]) {
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    {
-        if std::is_x86_feature_detected!("avx2") && buf.len() >= 16 * 6 {
-            unsafe {
-                rct_forward_rgb16_avx2(buf);
-            }
-            return;
+/// Parses and validates the header, tile index and section TOC of the CTI
+/// in `r`. Every size taken from the file is checked against the stream
+/// length and `limits` before anything is allocated. `depth` is 0 for the
+/// base image and grows by one per nested `PYLV` level.
+fn scan_cti_layout<R: Read + Seek>(r: &mut R, limits: &DecodeLimits, depth: u32) -> Result<LayoutInfo> {
+    let file_len = r.seek(SeekFrom::End(0))?;
+    ensure!(
+        file_len >= CTI_HEADER_SIZE as u64,
+        "File too short for a CTI header ({} bytes)",
+        file_len
+    );
+    r.seek(SeekFrom::Start(0))?;
+    let header = read_header(r)?;
+    ensure!(&header.magic == CTI_MAGIC, CtiError::BadMagic);
+    ensure!(
+        (1..=CTI_MAX_VERSION).contains(&header.version),
+        CtiError::UnsupportedVersion(header.version)
+    );
+    if header.has_checksums() {
+        ensure!(
+            header_crc(&header) == header.stored_crc(RESERVED_HEADER_CRC),
+            CtiError::ChecksumMismatch("Header")
+        );
+    }
+    let (tile_w, tile_h) = header.tile_dims();
+    ensure!(tile_w > 0 && tile_h > 0, "Invalid tile dimensions {}x{}", tile_w, tile_h);
+    ensure!(
+        header.tiles_x == header.width.div_ceil(tile_w)
+            && header.tiles_y == header.height.div_ceil(tile_h)
+            && header.tiles_x.checked_mul(tile_w).is_some()
+            && header.tiles_y.checked_mul(tile_h).is_some(),
+        "Tile grid {}x{} does not match {}x{} image with {}x{} tiles",
+        header.tiles_x,
+        header.tiles_y,
+        header.width,
+        header.height,
+        tile_w,
+        tile_h
+    );
+    limits.check_pixels(header.width, header.height)?;
+    let bpp = header.bytes_per_pixel()? as u64;
+
+    let total_tiles = header.tiles_x as u64 * header.tiles_y as u64;
+    let index_bytes = total_tiles.saturating_mul(TILE_INDEX_ONDISK_SIZE as u64);
+    limits.check_alloc(index_bytes, "tile index")?;
+    let data_start = (CTI_HEADER_SIZE as u64).saturating_add(index_bytes);
+    ensure!(
+        data_start <= file_len,
+        "Tile index needs {} bytes but the file has {}",
+        data_start,
+        file_len
+    );
+    let total_tiles = total_tiles as usize;
+    let indices = read_indices(r, total_tiles)?;
+    let mut checksum_faults = Vec::new();
+    if header.has_checksums() && tile_index_crc(&indices) != header.stored_crc(RESERVED_INDEX_CRC) {
+        checksum_faults.push("Tile index");
+    }
+    let bad_tiles = indices
+        .iter()
+        .enumerate()
+        .filter_map(|(n, idx)| {
+            check_tile_entry(&header, idx, n, bpp, data_start, file_len)
+                .err()
+                .map(|err| (n, err.to_string()))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let tile_ends = indices
+        .iter()
+        .enumerate()
+        .filter(|(n, _)| !bad_tiles.contains_key(n))
+        .map(|(_, idx)| idx.offset + idx.compressed_size as u64);
+    let index_damaged = !checksum_faults.is_empty();
+    let data_end =
+        tile_data_end(r, &header, tile_ends, index_damaged, file_len, limits.max_sections).unwrap_or(data_start);
+    let mut sections = read_section_descs(r, data_end, file_len, limits.max_sections)?;
+    check_section_descs(&sections, data_end, file_len, limits, depth)?;
+    if header.has_checksums() {
+        if section_toc_matches(&header, &sections) {
+            attach_section_crcs(r, &mut sections)?;
+        } else {
+            checksum_faults.push("Section TOC");
+            // The SCRC entry may still be intact; without it recovery keeps
+            // sections it cannot verify.
+            let _ = attach_section_crcs(r, &mut sections);
         }
     }
+    let mut layout = LayoutInfo {
+        header,
+        indices,
+        sections,
+        adaptive_tile_modes: None,
+        max_error: None,
+        near_lossless_tagged: false,
+        zstd_dict: None,
+        solid_tiles: None,
+        bad_tiles,
+        checksum_faults,
+        limits: *limits,
+        depth,
+    };
+    layout.read_codec_sections(r)?;
+    Ok(layout)
+}
 
-    for p in buf.chunks_exact_mut(6) {
-        let r = u16::from_le_bytes([p[0], p[1]]) as i32;
-        let g = u16::from_le_bytes([p[2], p[3]]) as i32;
-        let b = u16::from_le_bytes([p[4], p[5]]) as i32;
-        let y = (r + (g << 1) + b) >> 2;
-        let cb = b - g;
-        let cr = r - g;
-        p[0..2].copy_from_slice(&(y.clamp(0, 65535) as u16).to_le_bytes());
-        p[2..4].copy_from_slice(&((cb as i32 & 0xFFFF) as u16).to_le_bytes());
-        p[4..6].copy_from_slice(&((cr as i32 & 0xFFFF) as u16).to_le_bytes());
+/// End of the tile data, where the section TOC starts. When the tile index
+/// CRC failed a damaged entry may end past the real tile data, so the largest
+/// end at which a TOC matching the header's TOC CRC is found wins.
+fn tile_data_end<R: Read + Seek>(
+    r: &mut R,
+    header: &CTIHeader,
+    tile_ends: impl Iterator<Item = u64>,
+    index_damaged: bool,
+    file_len: u64,
+    max_sections: u32,
+) -> Option<u64> {
+    if !index_damaged {
+        return tile_ends.max();
+    }
+    let mut ends = tile_ends.collect::<Vec<_>>();
+    ends.sort_unstable_by(|a, b| b.cmp(a));
+    ends.dedup();
+    ends.iter()
+        .copied()
+        .find(|&end| {
+            read_section_descs(r, end, file_len, max_sections).is_ok_and(|descs| section_toc_matches(header, &descs))
+        })
+        .or(ends.first().copied())
+}
+
+/// Checks one tile index entry against the data area and the tile extent.
+fn check_tile_entry(
+    header: &CTIHeader,
+    idx: &TileIndex,
+    n: usize,
+    bpp: u64,
+    data_start: u64,
+    file_len: u64,
+) -> Result<()> {
+    ensure!(
+        idx.offset >= data_start && idx.offset.saturating_add(idx.compressed_size as u64) <= file_len,
+        "Tile {} payload {}+{} lies outside the data area",
+        n,
+        idx.offset,
+        idx.compressed_size
+    );
+    let (tx, ty) = (n as u32 % header.tiles_x, n as u32 / header.tiles_x);
+    let (w, h) = tile_extent(header.width, header.height, header.tile_dims(), tx, ty);
+    ensure!(
+        idx.original_size as u64 == w as u64 * h as u64 * bpp,
+        "Tile {} original size {} does not match its {}x{} extent",
+        n,
+        idx.original_size,
+        w,
+        h
+    );
+    Ok(())
+}
+
+fn check_section_descs(
+    descs: &[SectionDesc],
+    data_end: u64,
+    file_len: u64,
+    limits: &DecodeLimits,
+    depth: u32,
+) -> Result<()> {
+    let toc_end = data_end + 4 + descs.len() as u64 * 20;
+    for desc in descs {
+        ensure!(
+            desc.offset >= toc_end && desc.offset.checked_add(desc.size).is_some_and(|end| end <= file_len),
+            "Section '{}' {}+{} lies outside the file",
+            section_type_name(desc.ty),
+            desc.offset,
+            desc.size
+        );
+        limits.check_alloc(desc.size, "section payload")?;
     }
+    ensure!(
+        depth < limits.max_pyramid_depth || !descs.iter().any(|desc| desc.ty == SEC_TYPE_PYLV),
+        CtiError::LimitExceeded(format!(
+            "Pyramid levels nested deeper than {}",
+            limits.max_pyramid_depth
+        ))
+    );
+    Ok(())
 }
 
-fn rct_inverse_rgb16(buf: &mut [u8]) {
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    {
-        if std::is_x86_feature_detected!("avx2") && buf.len() >= 16 * 6 {
-            unsafe {
-                rct_inverse_rgb16_avx2(buf);
-            }
-            return;
-        }
+fn read_solid_tile_bitmap<R: Read + Seek>(
+    r: &mut R,
+    total_tiles: usize,
+    descs: &[SectionDesc],
+) -> Result<Option<Vec<u8>>> {
+    let Some(desc) = descs.iter().find(|desc| desc.ty == SEC_TYPE_TSOL) else {
+        return Ok(None);
+    };
+    ensure!(
+        desc.size as usize == total_tiles.div_ceil(8),
+        "TSOL size mismatch: expected {} bytes, got {}",
+        total_tiles.div_ceil(8),
+        desc.size
+    );
+    Ok(Some(read_section_payload(r, desc)?))
+}
+
+fn read_zstd_dictionary<R: Read + Seek>(r: &mut R, descs: &[SectionDesc]) -> Result<Option<ZstdDictionary>> {
+    let Some(desc) = descs.iter().find(|desc| desc.ty == SEC_TYPE_ZDIC) else {
+        return Ok(None);
+    };
+    Ok(Some(ZstdDictionary::new(read_section_payload(r, desc)?, 0)))
+}
+
+/// Max error and whether tiles carry a predictor id, from `NLER`: a u16 bound,
+/// optionally followed by a version byte.
+fn read_near_lossless_bound<R: Read + Seek>(
+    r: &mut R,
+    compression: u8,
+    descs: &[SectionDesc],
+) -> Result<Option<(u16, bool)>> {
+    if compression != CompressionType::NearLossless as u8 {
+        return Ok(None);
     }
 
-    for p in buf.chunks_exact_mut(6) {
-        let y = u16::from_le_bytes([p[0], p[1]]) as i32;
-        let cb = (u16::from_le_bytes([p[2], p[3]]) as i16) as i32;
-        let cr = (u16::from_le_bytes([p[4], p[5]]) as i16) as i32;
-        let g = y - ((cb + cr) >> 2);
-        let r = cr + g;
-        let b = cb + g;
-        p[0..2].copy_from_slice(&(r.clamp(0, 65535) as u16).to_le_bytes());
-        p[2..4].copy_from_slice(&(g.clamp(0, 65535) as u16).to_le_bytes());
-        p[4..6].copy_from_slice(&(b.clamp(0, 65535) as u16).to_le_bytes());
+    let desc = descs
+        .iter()
+        .find(|desc| desc.ty == SEC_TYPE_NLER)
+        .ok_or(CtiError::MissingSection("NLER"))?;
+    ensure!(
+        desc.size == 2 || desc.size == 3,
+        "NLER size mismatch: expected 2 or 3 bytes, got {}",
+        desc.size
+    );
+    let payload = read_section_payload(r, desc)?;
+    let tagged = match payload.get(2) {
+        None => false,
+        Some(&NLER_VERSION_TAGGED_PREDICTOR) => true,
+        Some(&version) => bail!(CtiError::Unsupported(format!("NLER version {version}"))),
+    };
+    Ok(Some((u16::from_le_bytes([payload[0], payload[1]]), tagged)))
+}
+
+fn read_adaptive_tile_modes<R: Read + Seek>(
+    r: &mut R,
+    compression: u8,
+    total_tiles: usize,
+    descs: &[SectionDesc],
+) -> Result<Option<Vec<u8>>> {
+    if compression != CompressionType::Adaptive as u8 {
+        return Ok(None);
     }
+
+    let desc = descs
+        .iter()
+        .find(|desc| desc.ty == SEC_TYPE_TMOD)
+        .ok_or(CtiError::MissingTmod)?;
+    ensure!(
+        desc.size as usize == total_tiles,
+        "TMOD size mismatch: expected {} bytes, got {}",
+        total_tiles,
+        desc.size
+    );
+    Ok(Some(read_section_payload(r, desc)?))
 }
 
-fn rgb16_delta_g_forward(buf: &mut [u8]) {
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn read_section_descs<R: Read + Seek>(
+    r: &mut R,
+    start: u64,
+    file_len: u64,
+    max_sections: u32,
+) -> Result<Vec<SectionDesc>> {
+    r.seek(SeekFrom::Start(start))?;
+    let mut count_buf = [0u8; 4];
+    match r.read_exact(&mut count_buf) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    }
+
+    let count = u32::from_le_bytes(count_buf);
+    ensure!(
+        count <= max_sections,
+        CtiError::LimitExceeded(format!(
+            "{} sections exceed the limit of {}",
+            count,
+            max_sections
+        ))
+    );
+    ensure!(
+        start + 4 + count as u64 * 20 <= file_len,
+        "Section TOC with {} entries does not fit in the file",
+        count
+    );
+    let mut descs = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        descs.push(SectionDesc {
+            ty: read_u32_le(r)?,
+            offset: read_u64_le(r)?,
+            size: read_u64_le(r)?,
+            crc32: None,
+        });
+    }
+    Ok(descs)
+}
+
+/// Checks a whole CTI file and collects every problem instead of stopping at
+/// the first one: header fields and checksums, tile payload bounds and
+/// overlaps (identical shared payloads are fine), the section TOC and payload
+/// CRCs, codec sections, every tile CRC, fixity digests and all embedded
+/// pyramid levels. Only I/O errors on the outer reader are returned as `Err`.
+pub fn verify_cti<R: Read + Seek>(r: &mut R) -> Result<VerifyReport> {
+    verify_cti_with_limits(r, &DecodeLimits::default())
+}
+
+/// Like [`verify_cti`], reporting anything beyond `limits` as an issue.
+pub fn verify_cti_with_limits<R: Read + Seek>(r: &mut R, limits: &DecodeLimits) -> Result<VerifyReport> {
+    let file_size = r.seek(SeekFrom::End(0))?;
+    let mut report = VerifyReport {
+        file_size,
+        ..VerifyReport::default()
+    };
+    verify_level(r, 0, file_size, 0, limits, &mut report);
+    Ok(report)
+}
+
+const VERIFY_MAX_DEPTH: u32 = 7;
+const VERIFY_BATCH_BYTES: usize = 64 << 20;
+
+/// Verifies the CTI stored in `start..start + len` of `outer`; pyramid levels
+/// recurse with their absolute range. Damaged index entries and sections are
+/// reported and left out, and every remaining tile is still decoded.
+fn verify_level<R: Read + Seek>(
+    outer: &mut R,
+    start: u64,
+    len: u64,
+    depth: u32,
+    limits: &DecodeLimits,
+    report: &mut VerifyReport,
+) {
+    let r = &mut SubRangeReader::new(&mut *outer, start, len);
+    let level = report.levels_checked;
+    report.levels_checked += 1;
+    let issue = |report: &mut VerifyReport, tile: Option<(u32, u32)>, section: Option<usize>, message: String| {
+        report.issues.push(VerifyIssue {
+            level,
+            tile,
+            section,
+            message,
+        })
+    };
+
+    let header = match verify_header(r, len).and_then(|header| {
+        limits.check_pixels(header.width, header.height)?;
+        Ok(header)
+    }) {
+        Ok(header) => header,
+        Err(err) => return issue(report, None, None, err.to_string()),
+    };
+    let total_tiles = header.tiles_x as u64 * header.tiles_y as u64;
+    if let Err(err) = limits.check_alloc(total_tiles.saturating_mul(TILE_INDEX_ONDISK_SIZE as u64), "tile index") {
+        return issue(report, None, None, err.to_string());
+    }
+    let data_start = (total_tiles as u128 * TILE_INDEX_ONDISK_SIZE as u128 + CTI_HEADER_SIZE as u128)
+        .min(u64::MAX as u128) as u64;
+    if data_start > len {
+        return issue(
+            report,
+            None,
+            None,
+            format!("Tile index needs {} bytes but the file has {}", data_start, len),
+        );
+    }
+    let total_tiles = total_tiles as usize;
+    let indices = match r
+        .seek(SeekFrom::Start(CTI_HEADER_SIZE as u64))
+        .map_err(CtiError::from)
+        .and_then(|_| read_indices(r, total_tiles))
     {
-        if std::is_x86_feature_detected!("avx2") && buf.len() >= 16 * 6 {
-            unsafe {
-                rgb16_delta_g_forward_avx2(buf);
+        Ok(indices) => indices,
+        Err(err) => return issue(report, None, None, format!("Tile index unreadable: {err}")),
+    };
+    let mut checksum_faults = Vec::new();
+    if header.has_checksums() && tile_index_crc(&indices) != header.stored_crc(RESERVED_INDEX_CRC) {
+        issue(report, None, None, "Tile index CRC mismatch".to_string());
+        checksum_faults.push("Tile index");
+    }
+
+    let tile_pos = |n: usize| (n as u32 % header.tiles_x, n as u32 / header.tiles_x);
+    let bpp = header.bytes_per_pixel().unwrap_or(1) as u64;
+    let mut bad_tiles = BTreeMap::new();
+    let mut ranges = Vec::with_capacity(total_tiles);
+    for (n, idx) in indices.iter().enumerate() {
+        match check_tile_entry(&header, idx, n, bpp, data_start, len) {
+            Ok(()) => ranges.push((idx.offset, idx.compressed_size as u64, n)),
+            Err(err) => {
+                issue(report, Some(tile_pos(n)), None, err.to_string());
+                bad_tiles.insert(n, err.to_string());
             }
-            return;
+        }
+    }
+    let tile_ends = ranges.iter().map(|&(offset, size, _)| offset + size).collect::<Vec<_>>();
+    ranges.sort_unstable();
+    ranges.dedup_by_key(|(offset, size, _)| (*offset, *size));
+    let mut furthest: Option<(u64, usize)> = None;
+    for &(offset, size, n) in &ranges {
+        match furthest {
+            Some((end, owner)) if offset < end => {
+                let (otx, oty) = tile_pos(owner);
+                issue(
+                    report,
+                    Some(tile_pos(n)),
+                    None,
+                    format!("Tile payload at {} overlaps the payload of tile ({}, {})", offset, otx, oty),
+                );
+                if offset + size > end {
+                    furthest = Some((offset + size, n));
+                }
+            }
+            _ => furthest = Some((offset + size, n)),
         }
     }
 
-    for p in buf.chunks_exact_mut(6) {
-        let r = u16::from_le_bytes([p[0], p[1]]);
-        let g = u16::from_le_bytes([p[2], p[3]]);
-        let b = u16::from_le_bytes([p[4], p[5]]);
-        let dr = r.wrapping_sub(g);
-        let db = b.wrapping_sub(g);
-        p[0..2].copy_from_slice(&g.to_le_bytes());
-        p[2..4].copy_from_slice(&dr.to_le_bytes());
-        p[4..6].copy_from_slice(&db.to_le_bytes());
+    let index_damaged = !checksum_faults.is_empty();
+    let data_end = tile_data_end(r, &header, tile_ends.into_iter(), index_damaged, len, limits.max_sections)
+        .unwrap_or(data_start);
+    let mut sections = verify_section_descs(r, len, data_end, limits.max_sections).unwrap_or_else(|err| {
+        issue(report, None, None, err.to_string());
+        Vec::new()
+    });
+    report.sections_checked += sections.len();
+    let toc_end = data_end + 4 + sections.len() as u64 * 20;
+    let mut usable = vec![false; sections.len()];
+    let mut section_ranges = Vec::with_capacity(sections.len());
+    for (i, desc) in sections.iter().enumerate() {
+        match desc.offset.checked_add(desc.size) {
+            Some(end) if desc.offset >= toc_end && end <= len => match limits.check_alloc(desc.size, "section payload") {
+                Ok(()) => {
+                    usable[i] = true;
+                    section_ranges.push((desc.offset, end, i));
+                }
+                Err(err) => issue(report, None, Some(i), err.to_string()),
+            },
+            _ => issue(
+                report,
+                None,
+                Some(i),
+                format!(
+                    "Section '{}' {}+{} outside the section area {}..{}",
+                    section_type_name(desc.ty),
+                    desc.offset,
+                    desc.size,
+                    toc_end,
+                    len
+                ),
+            ),
+        }
+    }
+    section_ranges.sort_unstable();
+    let mut furthest: Option<(u64, usize)> = None;
+    for &(offset, end, i) in &section_ranges {
+        match furthest {
+            Some((prev_end, owner)) if offset < prev_end => {
+                issue(report, None, Some(i), format!("Section overlaps section {}", owner));
+                if end > prev_end {
+                    furthest = Some((end, i));
+                }
+            }
+            _ => furthest = Some((end, i)),
+        }
     }
-}
 
-fn rgb16_delta_g_inverse(buf: &mut [u8]) {
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    {
-        if std::is_x86_feature_detected!("avx2") && buf.len() >= 16 * 6 {
-            unsafe {
-                rgb16_delta_g_inverse_avx2(buf);
+    if header.has_checksums() {
+        if !section_toc_matches(&header, &sections) {
+            issue(report, None, None, CtiError::ChecksumMismatch("Section TOC").to_string());
+            checksum_faults.push("Section TOC");
+        }
+        if let Err(err) = attach_section_crcs(r, &mut sections) {
+            issue(report, None, None, err.to_string());
+        }
+        for (i, desc) in sections.iter().enumerate().filter(|(i, _)| usable[*i]) {
+            if let Err(err) = read_section_payload(r, desc) {
+                issue(report, None, Some(i), err.to_string());
             }
-            return;
         }
     }
 
-    for p in buf.chunks_exact_mut(6) {
-        let g = u16::from_le_bytes([p[0], p[1]]);
-        let dr = u16::from_le_bytes([p[2], p[3]]);
-        let db = u16::from_le_bytes([p[4], p[5]]);
-        let r = g.wrapping_add(dr);
-        let b = g.wrapping_add(db);
-        p[0..2].copy_from_slice(&r.to_le_bytes());
-        p[2..4].copy_from_slice(&g.to_le_bytes());
-        p[4..6].copy_from_slice(&b.to_le_bytes());
+    let usable_sections = sections
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| usable[*i])
+        .map(|(i, desc)| (i, *desc))
+        .collect::<Vec<_>>();
+    let mut layout = LayoutInfo {
+        header,
+        indices,
+        sections: usable_sections.iter().map(|(_, desc)| *desc).collect(),
+        adaptive_tile_modes: None,
+        max_error: None,
+        near_lossless_tagged: false,
+        zstd_dict: None,
+        solid_tiles: None,
+        bad_tiles,
+        checksum_faults,
+        limits: *limits,
+        depth,
+    };
+    match layout.read_codec_sections(r) {
+        Ok(()) => verify_tiles(r, &layout, level, report),
+        // Without the codec sections no tile can be decoded.
+        Err(err) => issue(report, None, None, err.to_string()),
     }
-}
 
-const DEFAULT_RGB8_PLANAR_BLOCK_PIXELS: usize = 2048;
-const DEFAULT_RGB16_PLANAR_BLOCK_PIXELS: usize = 512;
+    let max_depth = limits.max_pyramid_depth.min(VERIFY_MAX_DEPTH);
+    for (i, desc) in usable_sections.into_iter().filter(|(_, desc)| desc.ty == SEC_TYPE_PYLV) {
+        if depth >= max_depth {
+            issue(report, None, Some(i), format!("Pyramid levels nested deeper than {}", max_depth));
+            continue;
+        }
+        verify_level(outer, start + desc.offset, desc.size, depth + 1, limits, report);
+    }
+}
 
-fn rgb_planar_block_pixels(default_pixels: usize) -> usize {
-    static RGB_PLANAR_BLOCK_PIXELS_OVERRIDE: OnceLock<Option<usize>> = OnceLock::new();
-    RGB_PLANAR_BLOCK_PIXELS_OVERRIDE
-        .get_or_init(|| {
-        std::env::var("CTI_RGB_PLANAR_BLOCK_PIXELS")
-            .ok()
-            .and_then(|value| value.parse::<usize>().ok())
-            .map(|value| value.clamp(64, 16_384))
-        })
-        .unwrap_or(default_pixels)
+fn verify_header<R: Read + Seek>(r: &mut R, len: u64) -> Result<CTIHeader> {
+    ensure!(
+        len >= CTI_HEADER_SIZE as u64,
+        "File too short for a CTI header ({} bytes)",
+        len
+    );
+    r.seek(SeekFrom::Start(0))?;
+    let header = read_header(r)?;
+    ensure!(&header.magic == CTI_MAGIC, CtiError::BadMagic);
+    ensure!(
+        (1..=CTI_MAX_VERSION).contains(&header.version),
+        CtiError::UnsupportedVersion(header.version)
+    );
+    if header.has_checksums() {
+        ensure!(
+            header_crc(&header) == header.stored_crc(RESERVED_HEADER_CRC),
+            CtiError::ChecksumMismatch("Header")
+        );
+    }
+    let (tile_w, tile_h) = header.tile_dims();
+    ensure!(tile_w > 0 && tile_h > 0, "Invalid tile dimensions {}x{}", tile_w, tile_h);
+    ensure!(
+        header.tiles_x == header.width.div_ceil(tile_w) && header.tiles_y == header.height.div_ceil(tile_h),
+        "Tile grid {}x{} does not match {}x{} image with {}x{} tiles",
+        header.tiles_x,
+        header.tiles_y,
+        header.width,
+        header.height,
+        tile_w,
+        tile_h
+    );
+    bytes_per_pixel_from_id(header.color_type)?;
+    CompressionType::from_id(header.compression)?;
+    Ok(header)
 }
 
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-#[target_feature(enable = "avx2")]
-unsafe fn rct_forward_rgb8_avx2(buf: &mut [u8]) {
-    #[cfg(target_arch = "x86")]
-    use std::arch::x86::*;
-    #[cfg(target_arch = "x86_64")]
-    use std::arch::x86_64::*;
+fn verify_section_descs<R: Read + Seek>(
+    r: &mut R,
+    len: u64,
+    data_end: u64,
+    max_sections: u32,
+) -> Result<Vec<SectionDesc>> {
+    if data_end == len {
+        return Ok(Vec::new());
+    }
+    ensure!(
+        data_end.saturating_add(4) <= len,
+        "Section TOC at {} is past the end of the file ({} bytes)",
+        data_end,
+        len
+    );
+    r.seek(SeekFrom::Start(data_end))?;
+    let count = read_u32_le(r)? as u64;
+    ensure!(
+        data_end + 4 + count * 20 <= len,
+        "Section TOC with {} entries does not fit in the file",
+        count
+    );
+    read_section_descs(r, data_end, len, max_sections)
+}
 
-    let pixels = buf.len() / 3;
-    let block_pixels = rgb_planar_block_pixels(DEFAULT_RGB8_PLANAR_BLOCK_PIXELS);
-    let mut rs = vec![0u8; block_pixels];
-    let mut gs = vec![0u8; block_pixels];
-    let mut bs = vec![0u8; block_pixels];
-    let mut ys = vec![0u8; block_pixels];
-    let mut cbs = vec![0u8; block_pixels];
-    let mut crs = vec![0u8; block_pixels];
-    let low_byte_mask = _mm256_set1_epi16(0x00FF);
-
-    let mut pixel_idx = 0usize;
-    while pixel_idx < pixels {
-        let count = block_pixels.min(pixels - pixel_idx);
-        for lane in 0..count {
-            let base = (pixel_idx + lane) * 3;
-            rs[lane] = buf[base];
-            gs[lane] = buf[base + 1];
-            bs[lane] = buf[base + 2];
+/// Reads tile payloads in batches and decodes each batch in parallel; every
+/// failed tile becomes an issue. Fixity digests are compared when present.
+fn verify_tiles<R: Read + Seek>(r: &mut R, layout: &LayoutInfo, level: u32, report: &mut VerifyReport) {
+    let header = &layout.header;
+    let fixity = layout
+        .sections
+        .iter()
+        .position(|desc| desc.ty == SEC_TYPE_FXTY)
+        .map(|i| {
+            read_section_payload(r, &layout.sections[i])
+                .and_then(|payload| FixityDigests::from_section(&payload, layout.indices.len()))
+                .map_err(|err| (i, err))
+        });
+    let stored_fixity = match fixity {
+        Some(Ok(digests)) => Some(digests),
+        Some(Err((i, err))) => {
+            report.issues.push(VerifyIssue {
+                level,
+                tile: None,
+                section: Some(i),
+                message: err.to_string(),
+            });
+            None
+        }
+        None => None,
+    };
+    let bpp = header.bytes_per_pixel().unwrap_or(1) as usize;
+    let mut hasher = stored_fixity.as_ref().map(|stored| {
+        RasterHasher::new(header.width, header.height, header.tile_dims(), bpp, !stored.tiles.is_empty())
+    });
+
+    let total_tiles = layout.indices.len();
+    let mut next = 0usize;
+    let mut all_decoded = true;
+    while next < total_tiles {
+        let mut batch = Vec::new();
+        let mut batch_bytes = 0usize;
+        while next < total_tiles && (batch.is_empty() || batch_bytes < VERIFY_BATCH_BYTES) {
+            if layout.bad_tiles.contains_key(&next) {
+                // Reported with the tile index checks.
+                all_decoded = false;
+                hasher = None;
+                next += 1;
+                continue;
+            }
+            let payload = read_tile_payload(r, &layout.indices[next]);
+            batch_bytes += payload.as_ref().map_or(0, Vec::len);
+            batch.push((next, payload));
+            next += 1;
         }
 
-        let mut lane = 0usize;
-        while lane + 16 <= count {
-            let r =
-                unsafe { _mm256_cvtepu8_epi16(_mm_loadu_si128(rs.as_ptr().add(lane) as *const __m128i)) };
-            let g =
-                unsafe { _mm256_cvtepu8_epi16(_mm_loadu_si128(gs.as_ptr().add(lane) as *const __m128i)) };
-            let b =
-                unsafe { _mm256_cvtepu8_epi16(_mm_loadu_si128(bs.as_ptr().add(lane) as *const __m128i)) };
-            let y = _mm256_srli_epi16(
-                _mm256_add_epi16(_mm256_add_epi16(r, _mm256_slli_epi16(g, 1)), b),
-                2,
-            );
-            let cb = _mm256_and_si256(_mm256_sub_epi16(b, g), low_byte_mask);
-            let cr = _mm256_and_si256(_mm256_sub_epi16(r, g), low_byte_mask);
-
-            let y_lo = _mm256_castsi256_si128(y);
-            let y_hi = _mm256_extracti128_si256(y, 1);
-            let cb_lo = _mm256_castsi256_si128(cb);
-            let cb_hi = _mm256_extracti128_si256(cb, 1);
-            let cr_lo = _mm256_castsi256_si128(cr);
-            let cr_hi = _mm256_extracti128_si256(cr, 1);
-            let y8 = _mm_packus_epi16(y_lo, y_hi);
-            let cb8 = _mm_packus_epi16(cb_lo, cb_hi);
-            let cr8 = _mm_packus_epi16(cr_lo, cr_hi);
-
-            unsafe { _mm_storeu_si128(ys.as_mut_ptr().add(lane) as *mut __m128i, y8) };
-            unsafe { _mm_storeu_si128(cbs.as_mut_ptr().add(lane) as *mut __m128i, cb8) };
-            unsafe { _mm_storeu_si128(crs.as_mut_ptr().add(lane) as *mut __m128i, cr8) };
-            lane += 16;
+        let decoded = batch
+            .into_par_iter()
+            .map(|(n, payload)| (n, payload.and_then(|comp| decode_tile_payload(layout, &layout.indices[n], n, &comp))))
+            .collect::<Vec<_>>();
+        for (n, tile) in decoded {
+            report.tiles_checked += 1;
+            let pos = (n as u32 % header.tiles_x, n as u32 / header.tiles_x);
+            match tile {
+                Ok(tile) => {
+                    if let Some(hasher) = hasher.as_mut() {
+                        hasher.push_tile(tile);
+                    }
+                }
+                Err(err) => {
+                    all_decoded = false;
+                    hasher = None;
+                    report.issues.push(VerifyIssue {
+                        level,
+                        tile: Some(pos),
+                        section: None,
+                        message: err.to_string(),
+                    });
+                }
+            }
         }
+    }
 
-        while lane < count {
-            let r = rs[lane] as i32;
-            let g = gs[lane] as i32;
-            let b = bs[lane] as i32;
-            let y = (r + (g << 1) + b) >> 2;
-            let cb = b - g;
-            let cr = r - g;
-            ys[lane] = y.clamp(0, 255) as u8;
-            cbs[lane] = (cb as i16 as i32 & 0xFF) as u8;
-            crs[lane] = (cr as i16 as i32 & 0xFF) as u8;
-            lane += 1;
+    if let (Some(stored), Some(hasher), true) = (stored_fixity, hasher, all_decoded) {
+        let computed = hasher.finish();
+        for (n, (stored, computed)) in stored.tiles.iter().zip(&computed.tiles).enumerate() {
+            if stored != computed {
+                report.issues.push(VerifyIssue {
+                    level,
+                    tile: Some((n as u32 % header.tiles_x, n as u32 / header.tiles_x)),
+                    section: None,
+                    message: "Tile fixity digest mismatch".to_string(),
+                });
+            }
+        }
+        if stored.image != computed.image {
+            report.issues.push(VerifyIssue {
+                level,
+                tile: None,
+                section: None,
+                message: "Image fixity digest mismatch".to_string(),
+            });
         }
+    }
+}
 
-        for lane in 0..count {
-            let base = (pixel_idx + lane) * 3;
-            buf[base] = ys[lane];
-            buf[base + 1] = cbs[lane];
-            buf[base + 2] = crs[lane];
+fn read_sections_from_descs<R: Read + Seek>(r: &mut R, descs: &[SectionDesc]) -> Result<Vec<CTISection>> {
+    let mut out = Vec::with_capacity(descs.len());
+    for desc in descs {
+        out.push(CTISection {
+            ty: desc.ty,
+            payload: read_section_payload(r, desc)?,
+        });
+    }
+    Ok(out)
+}
+
+fn read_section_payload<R: Read + Seek>(r: &mut R, desc: &SectionDesc) -> Result<Vec<u8>> {
+    r.seek(SeekFrom::Start(desc.offset))?;
+    let mut payload = vec![0u8; desc.size as usize];
+    r.read_exact(&mut payload)?;
+    check_section_crc(desc, &payload)?;
+    Ok(payload)
+}
+
+/// Reads every section with `read`, keeping the ones that succeed and
+/// returning the TOC indices of the rest.
+fn recover_sections(
+    descs: &[SectionDesc],
+    mut read: impl FnMut(&SectionDesc) -> Result<Vec<u8>>,
+) -> (Vec<CTISection>, Vec<usize>) {
+    let mut sections = Vec::with_capacity(descs.len());
+    let mut failed = Vec::new();
+    for (idx, desc) in descs.iter().enumerate() {
+        match read(desc) {
+            Ok(payload) => sections.push(CTISection { ty: desc.ty, payload }),
+            Err(_) => failed.push(idx),
         }
+    }
+    (sections, failed)
+}
 
-        pixel_idx += count;
+fn check_section_crc(desc: &SectionDesc, payload: &[u8]) -> Result<()> {
+    if let Some(expected) = desc.crc32 {
+        ensure!(
+            crc32(payload) == expected,
+            CtiError::SectionCrcMismatch {
+                section: section_type_name(desc.ty),
+                offset: desc.offset,
+            }
+        );
     }
+    Ok(())
 }
 
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-#[target_feature(enable = "avx2")]
-unsafe fn rct_inverse_rgb8_avx2(buf: &mut [u8]) {
-    #[cfg(target_arch = "x86")]
-    use std::arch::x86::*;
-    #[cfg(target_arch = "x86_64")]
-    use std::arch::x86_64::*;
+fn section_toc_matches(header: &CTIHeader, descs: &[SectionDesc]) -> bool {
+    crc32(&section_toc_bytes(descs)) == header.stored_crc(RESERVED_TOC_CRC)
+}
 
-    let pixels = buf.len() / 3;
-    let block_pixels = rgb_planar_block_pixels(DEFAULT_RGB8_PLANAR_BLOCK_PIXELS);
-    let mut ys = vec![0u8; block_pixels];
-    let mut cbs = vec![0u8; block_pixels];
-    let mut crs = vec![0u8; block_pixels];
-    let mut rs = vec![0u8; block_pixels];
-    let mut gs = vec![0u8; block_pixels];
-    let mut bs = vec![0u8; block_pixels];
+/// Attaches the `SCRC` payload CRCs to the section descriptors.
+fn attach_section_crcs<R: Read + Seek>(r: &mut R, descs: &mut [SectionDesc]) -> Result<()> {
+    if descs.is_empty() {
+        return Ok(());
+    }
 
-    let mut pixel_idx = 0usize;
-    while pixel_idx < pixels {
-        let count = block_pixels.min(pixels - pixel_idx);
-        for lane in 0..count {
-            let base = (pixel_idx + lane) * 3;
-            ys[lane] = buf[base];
-            cbs[lane] = buf[base + 1];
-            crs[lane] = buf[base + 2];
+    let crc_desc = *descs
+        .iter()
+        .find(|desc| desc.ty == SEC_TYPE_SCRC)
+        .ok_or(CtiError::MissingSection("SCRC"))?;
+    ensure!(
+        crc_desc.size == descs.len() as u64 * 4,
+        "SCRC size mismatch: expected {} bytes, got {}",
+        descs.len() * 4,
+        crc_desc.size
+    );
+    let payload = read_section_payload(r, &crc_desc)?;
+    for (desc, crc) in descs.iter_mut().zip(payload.chunks_exact(4)) {
+        if desc.ty != SEC_TYPE_SCRC {
+            desc.crc32 = Some(u32::from_le_bytes(crc.try_into().unwrap()));
         }
+    }
+    Ok(())
+}
 
-        let mut lane = 0usize;
-        while lane + 16 <= count {
-            let y =
-                unsafe { _mm256_cvtepu8_epi16(_mm_loadu_si128(ys.as_ptr().add(lane) as *const __m128i)) };
-            let cb = unsafe {
-                _mm256_cvtepi8_epi16(_mm_loadu_si128(cbs.as_ptr().add(lane) as *const __m128i))
-            };
-            let cr = unsafe {
-                _mm256_cvtepi8_epi16(_mm_loadu_si128(crs.as_ptr().add(lane) as *const __m128i))
-            };
-            let g = _mm256_sub_epi16(y, _mm256_srai_epi16(_mm256_add_epi16(cb, cr), 2));
-            let r = _mm256_add_epi16(cr, g);
-            let b = _mm256_add_epi16(cb, g);
+/// Source of compressed tile payloads: owned buffers read from a stream or
+/// slices borrowed straight from a memory map.
+type PayloadFetch<'f, 'a> = dyn FnMut(&TileIndex) -> Result<Cow<'a, [u8]>> + 'f;
 
-            let r_lo = _mm256_castsi256_si128(r);
-            let r_hi = _mm256_extracti128_si256(r, 1);
-            let g_lo = _mm256_castsi256_si128(g);
-            let g_hi = _mm256_extracti128_si256(g, 1);
-            let b_lo = _mm256_castsi256_si128(b);
-            let b_hi = _mm256_extracti128_si256(b, 1);
-            let r8 = _mm_packus_epi16(r_lo, r_hi);
-            let g8 = _mm_packus_epi16(g_lo, g_hi);
-            let b8 = _mm_packus_epi16(b_lo, b_hi);
+fn stream_payloads<R: Read + Seek>(r: &mut R) -> impl FnMut(&TileIndex) -> Result<Cow<'static, [u8]>> + '_ {
+    move |index| read_tile_payload(r, index).map(Cow::Owned)
+}
 
-            unsafe { _mm_storeu_si128(rs.as_mut_ptr().add(lane) as *mut __m128i, r8) };
-            unsafe { _mm_storeu_si128(gs.as_mut_ptr().add(lane) as *mut __m128i, g8) };
-            unsafe { _mm_storeu_si128(bs.as_mut_ptr().add(lane) as *mut __m128i, b8) };
-            lane += 16;
-        }
+fn decode_tile_from_layout(
+    fetch: &mut PayloadFetch<'_, '_>,
+    layout: &LayoutInfo,
+    tx: u32,
+    ty: u32,
+) -> Result<RasterRegion> {
+    ensure!(tx < layout.header.tiles_x, CtiError::OutOfRange(format!("Tile x {} out of range", tx)));
+    ensure!(ty < layout.header.tiles_y, CtiError::OutOfRange(format!("Tile y {} out of range", ty)));
+
+    layout.check_checksums()?;
+    let tile_index = (ty * layout.header.tiles_x + tx) as usize;
+    let index = &layout.indices[tile_index];
+    layout.check_tile(tile_index)?;
+    let tile = decode_tile_payload(layout, index, tile_index, &fetch(index)?)?;
+    let (tile_w, tile_h) =
+        tile_extent(layout.header.width, layout.header.height, layout.header.tile_dims(), tx, ty);
+
+    Ok(RasterRegion {
+        width: tile_w,
+        height: tile_h,
+        color_type: layout.header.color_type,
+        data: tile,
+    })
+}
 
-        while lane < count {
-            let y = ys[lane] as i32;
-            let cb = (cbs[lane] as i8) as i32;
-            let cr = (crs[lane] as i8) as i32;
-            let g = y - ((cb + cr) >> 2);
-            let r = cr + g;
-            let b = cb + g;
-            rs[lane] = r.clamp(0, 255) as u8;
-            gs[lane] = g.clamp(0, 255) as u8;
-            bs[lane] = b.clamp(0, 255) as u8;
-            lane += 1;
-        }
+fn decode_tile_into_buffer(
+    fetch: &mut PayloadFetch<'_, '_>,
+    layout: &LayoutInfo,
+    tx: u32,
+    ty: u32,
+    out: &mut [u8],
+    stride: usize,
+) -> Result<(u32, u32)> {
+    let tile = decode_tile_from_layout(fetch, layout, tx, ty)?;
+    let hdr = &layout.header;
+    let bpp = bytes_per_pixel_from_id(hdr.color_type)?;
+    check_output_buffer(out.len(), tile.width, tile.height, bpp, stride)?;
+    let (tile_w, tile_h) = hdr.tile_dims();
+    blit_tile_region(
+        out,
+        &tile.data,
+        tile.width,
+        tile.height,
+        tx * tile_w,
+        ty * tile_h,
+        stride,
+        (tile_w, tile_h),
+        tile.width,
+        tile.height,
+        bpp,
+        tx,
+        ty,
+    )?;
+    Ok((tile.width, tile.height))
+}
 
-        for lane in 0..count {
-            let base = (pixel_idx + lane) * 3;
-            buf[base] = rs[lane];
-            buf[base + 1] = gs[lane];
-            buf[base + 2] = bs[lane];
-        }
+#[allow(clippy::too_many_arguments)]
+fn extract_region_from_layout(
+    fetch: &mut PayloadFetch<'_, '_>,
+    layout: &LayoutInfo,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    pool: Option<&ThreadPool>,
+    recovery: Option<&TileRecovery>,
+) -> Result<RasterRegion> {
+    let bpp = bytes_per_pixel_from_id(layout.header.color_type)?;
+    let mut out = vec![0u8; layout.limits.raster_bytes(width, height, bpp)?];
+    extract_region_into_buffer(
+        fetch,
+        layout,
+        x,
+        y,
+        width,
+        height,
+        &mut out,
+        width as usize * bpp as usize,
+        pool,
+        recovery,
+    )?;
 
-        pixel_idx += count;
-    }
+    Ok(RasterRegion {
+        width,
+        height,
+        color_type: layout.header.color_type,
+        data: out,
+    })
 }
 
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-#[target_feature(enable = "avx2")]
-unsafe fn rct_forward_rgb16_avx2(buf: &mut [u8]) {
-    #[cfg(target_arch = "x86")]
-    use std::arch::x86::*;
-    #[cfg(target_arch = "x86_64")]
-    use std::arch::x86_64::*;
+#[allow(clippy::too_many_arguments)]
+fn extract_region_into_buffer(
+    fetch: &mut PayloadFetch<'_, '_>,
+    layout: &LayoutInfo,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    out: &mut [u8],
+    stride: usize,
+    pool: Option<&ThreadPool>,
+    recovery: Option<&TileRecovery>,
+) -> Result<()> {
+    ensure!(x < layout.header.width, CtiError::OutOfRange(format!("Region x {} out of range", x)));
+    ensure!(y < layout.header.height, CtiError::OutOfRange(format!("Region y {} out of range", y)));
+    ensure!(
+        width > 0 && height > 0,
+        CtiError::OutOfRange("Region size must be positive".into())
+    );
+    ensure!(
+        x.checked_add(width).is_some_and(|end| end <= layout.header.width),
+        CtiError::OutOfRange("Region exceeds image width".into())
+    );
+    ensure!(
+        y.checked_add(height).is_some_and(|end| end <= layout.header.height),
+        CtiError::OutOfRange("Region exceeds image height".into())
+    );
+    decode_tiles_into_region(fetch, layout, out, stride, x, y, width, height, pool, recovery)
+}
 
-    let pixels = buf.len() / 6;
-    let block_pixels = rgb_planar_block_pixels(DEFAULT_RGB16_PLANAR_BLOCK_PIXELS);
-    let mut c0 = vec![0u16; block_pixels];
-    let mut c1 = vec![0u16; block_pixels];
-    let mut c2 = vec![0u16; block_pixels];
+fn decode_all_tiles(
+    fetch: &mut PayloadFetch<'_, '_>,
+    layout: &LayoutInfo,
+    pool: Option<&ThreadPool>,
+    recovery: Option<&TileRecovery>,
+) -> Result<Vec<u8>> {
+    let hdr = &layout.header;
+    let bpp = bytes_per_pixel_from_id(hdr.color_type)?;
+    let mut out = vec![0u8; layout.limits.raster_bytes(hdr.width, hdr.height, bpp)?];
+    decode_all_tiles_into(fetch, layout, &mut out, hdr.width as usize * bpp as usize, pool, recovery)?;
+    Ok(out)
+}
 
-    let mut pixel_idx = 0usize;
-    while pixel_idx < pixels {
-        let count = block_pixels.min(pixels - pixel_idx);
-        for lane in 0..count {
-            let base = (pixel_idx + lane) * 6;
-            c0[lane] = u16::from_le_bytes([buf[base], buf[base + 1]]);
-            c1[lane] = u16::from_le_bytes([buf[base + 2], buf[base + 3]]);
-            c2[lane] = u16::from_le_bytes([buf[base + 4], buf[base + 5]]);
-        }
+fn decode_all_tiles_into(
+    fetch: &mut PayloadFetch<'_, '_>,
+    layout: &LayoutInfo,
+    out: &mut [u8],
+    stride: usize,
+    pool: Option<&ThreadPool>,
+    recovery: Option<&TileRecovery>,
+) -> Result<()> {
+    let hdr = &layout.header;
+    if hdr.width == 0 || hdr.height == 0 {
+        return Ok(());
+    }
+    decode_tiles_into_region(fetch, layout, out, stride, 0, 0, hdr.width, hdr.height, pool, recovery)
+}
 
-        let mut lane = 0usize;
-        while lane + 16 <= count {
-            let r = unsafe { _mm256_loadu_si256(c0.as_ptr().add(lane) as *const __m256i) };
-            let g = unsafe { _mm256_loadu_si256(c1.as_ptr().add(lane) as *const __m256i) };
-            let b = unsafe { _mm256_loadu_si256(c2.as_ptr().add(lane) as *const __m256i) };
-            let y = _mm256_srli_epi16(
+/// Recovery decoding state: tiles whose payload cannot be read, decoded or
+/// verified are painted with `fill_pixel` and recorded instead of failing.
+struct TileRecovery {
+    fill_pixel: Vec<u8>,
+    failed: Mutex<Vec<(u32, u32)>>,
+}
+
+impl TileRecovery {
+    /// `fill` is applied to every sample, clamped to 255 for 8-bit samples
+    /// and converted to the same value for float samples.
+    fn new(color_type: u8, fill: u16) -> Result<Self> {
+        let (channels, sample_bytes) = sample_layout(color_type_from_id(color_type)?)?;
+        let sample = match sample_bytes {
+            1 => vec![fill.min(255) as u8],
+            4 => (fill as f32).to_le_bytes().to_vec(),
+            _ => fill.to_le_bytes().to_vec(),
+        };
+        Ok(Self {
+            fill_pixel: sample.repeat(channels),
+            failed: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn into_failed_tiles(self) -> Vec<(u32, u32)> {
+        let mut failed = self.failed.into_inner().unwrap();
+        failed.sort_unstable_by_key(|&(tx, ty)| (ty, tx));
+        failed
+    }
+}
+
+/// Validates that `len` bytes hold `height` rows of `width` pixels spaced
+/// `stride` bytes apart. The last row does not need trailing padding.
+fn check_output_buffer(len: usize, width: u32, height: u32, bpp: u32, stride: usize) -> Result<()> {
+    let row_bytes = (width as usize)
+        .checked_mul(bpp as usize)
+        .ok_or_else(|| CtiError::LimitExceeded(format!("Row of {} pixels does not fit in memory", width)))?;
+    ensure!(
+        stride >= row_bytes,
+        CtiError::InvalidInput(format!("Row stride {} is smaller than row size {}", stride, row_bytes))
+    );
+    let required = match height {
+        0 => 0,
+        h => (h as usize - 1)
+            .checked_mul(stride)
+            .and_then(|rows| rows.checked_add(row_bytes))
+            .ok_or_else(|| CtiError::LimitExceeded(format!("{} rows with stride {} do not fit in memory", h, stride)))?,
+    };
+    ensure!(
+        len >= required,
+        CtiError::InvalidInput(format!("Output buffer too small: {} bytes, need {}", len, required))
+    );
+    Ok(())
+}
+
+const DECODE_BATCH_BYTES: usize = 64 * 1024 * 1024;
+
+#[allow(clippy::too_many_arguments)]
+fn decode_tiles_into_region(
+    fetch: &mut PayloadFetch<'_, '_>,
+    layout: &LayoutInfo,
+    out: &mut [u8],
+    stride: usize,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    pool: Option<&ThreadPool>,
+    recovery: Option<&TileRecovery>,
+) -> Result<()> {
+    if recovery.is_none() {
+        layout.check_checksums()?;
+    }
+    let hdr = &layout.header;
+    let bpp = bytes_per_pixel_from_id(hdr.color_type)?;
+    check_output_buffer(out.len(), width, height, bpp, stride)?;
+    let tile_dims = hdr.tile_dims();
+    let tile_x0 = x / tile_dims.0;
+    let tile_x1 = (x + width - 1) / tile_dims.0;
+    let tile_y0 = y / tile_dims.1;
+    let tile_y1 = (y + height - 1) / tile_dims.1;
+
+    // One output band per tile row, so tile rows can be blitted in parallel.
+    let mut bands = Vec::with_capacity((tile_y1 - tile_y0 + 1) as usize);
+    let mut rest = out;
+    for ty in tile_y0..=tile_y1 {
+        let band_y0 = (ty * tile_dims.1).max(y);
+        let band_y1 = ((ty + 1) * tile_dims.1).min(y + height);
+        let band_h = band_y1 - band_y0;
+        let (band, tail) = rest.split_at_mut((band_h as usize * stride).min(rest.len()));
+        bands.push((ty, band_y0, band_h, band));
+        rest = tail;
+    }
+
+    let mut next_band = 0usize;
+    while next_band < bands.len() {
+        let mut batch = Vec::new();
+        let mut batch_bytes = 0usize;
+        while next_band + batch.len() < bands.len() && (batch.is_empty() || batch_bytes < DECODE_BATCH_BYTES) {
+            let ty = bands[next_band + batch.len()].0;
+            let mut row = Vec::with_capacity((tile_x1 - tile_x0 + 1) as usize);
+            for tx in tile_x0..=tile_x1 {
+                let idx = (ty * hdr.tiles_x + tx) as usize;
+                let comp = match layout.check_tile(idx).and_then(|()| fetch(&layout.indices[idx])) {
+                    Err(err) if recovery.is_none() => return Err(err),
+                    comp => comp,
+                };
+                batch_bytes += comp.as_ref().map_or(0, |comp| comp.len());
+                row.push((tx, idx, comp));
+            }
+            batch.push(row);
+        }
+
+        let batch_len = batch.len();
+        let batch_bands = &mut bands[next_band..next_band + batch_len];
+        run_in_pool(pool, || {
+            batch_bands
+                .par_iter_mut()
+                .zip(batch.into_par_iter())
+                .try_for_each(|((ty, band_y0, band_h, band), row)| -> Result<()> {
+                    let tiles = row
+                        .into_par_iter()
+                        .map(|(tx, idx, comp)| {
+                            let tile = comp.and_then(|comp| decode_tile_payload(layout, &layout.indices[idx], idx, &comp));
+                            match (tile, recovery) {
+                                (Ok(tile), _) => Ok((tx, tile)),
+                                (Err(_), Some(recovery)) => {
+                                    recovery.failed.lock().unwrap().push((tx, *ty));
+                                    let (tile_w, tile_h) = tile_extent(hdr.width, hdr.height, tile_dims, tx, *ty);
+                                    Ok((tx, recovery.fill_pixel.repeat((tile_w * tile_h) as usize)))
+                                }
+                                (Err(err), None) => Err(err),
+                            }
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    for (tx, tile) in tiles {
+                        let (tile_w, tile_h) = tile_extent(hdr.width, hdr.height, tile_dims, tx, *ty);
+                        blit_tile_region(
+                            band, &tile, width, *band_h, x, *band_y0, stride, tile_dims, tile_w, tile_h, bpp, tx, *ty,
+                        )?;
+                    }
+                    Ok(())
+                })
+        })?;
+        next_band += batch_len;
+    }
+    Ok(())
+}
+
+fn run_in_pool<T: Send>(pool: Option<&ThreadPool>, op: impl FnOnce() -> T + Send) -> T {
+    match pool {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
+}
+
+fn read_decoded_tile<R: Read + Seek>(
+    r: &mut R,
+    layout: &LayoutInfo,
+    index: &TileIndex,
+    tile_number: usize,
+) -> Result<Vec<u8>> {
+    layout.check_checksums()?;
+    layout.check_tile(tile_number)?;
+    let comp = read_tile_payload(r, index)?;
+    decode_tile_payload(layout, index, tile_number, &comp)
+}
+
+fn read_tile_payload<R: Read + Seek>(r: &mut R, index: &TileIndex) -> Result<Vec<u8>> {
+    r.seek(SeekFrom::Start(index.offset))?;
+    let mut comp = vec![0u8; index.compressed_size as usize];
+    r.read_exact(&mut comp)?;
+    Ok(comp)
+}
+
+fn decode_tile_payload(
+    layout: &LayoutInfo,
+    index: &TileIndex,
+    tile_number: usize,
+    comp: &[u8],
+) -> Result<Vec<u8>> {
+    let hdr = &layout.header;
+    let adaptive_mode = layout
+        .adaptive_tile_modes
+        .as_ref()
+        .map(|modes| AdaptiveTileMode::from_byte(modes[tile_number]))
+        .transpose()?;
+    let tile_x = tile_number as u32 % hdr.tiles_x;
+    let tile_y = tile_number as u32 / hdr.tiles_x;
+    let mut tile_bytes = if layout.is_solid_tile(tile_number) {
+        let bpp = hdr.bytes_per_pixel()? as usize;
+        let original_size = index.original_size as usize;
+        ensure!(
+            comp.len() == bpp && original_size.is_multiple_of(bpp),
+            "Solid tile {} has a malformed payload",
+            tile_number
+        );
+        comp.repeat(original_size / bpp)
+    } else {
+        decompress_tile_with_size(
+            hdr.compression,
+            comp,
+            index.original_size as usize,
+            hdr.color_type,
+            tile_extent(hdr.width, hdr.height, hdr.tile_dims(), tile_x, tile_y),
+            adaptive_mode,
+            &layout.codec_params(),
+        )?
+    };
+    ensure!(crc32(&tile_bytes) == index.crc32, CtiError::CrcMismatch { tile: tile_number });
+
+    let color_transform = color_transform_mode_from_header(hdr.flags, hdr.color_type);
+    apply_color_transform_inverse(color_transform, hdr.color_type, &mut tile_bytes);
+    Ok(tile_bytes)
+}
+
+fn dynamic_image_into_raw(img: DynamicImage) -> (ColorType, Vec<u8>) {
+    match img {
+        DynamicImage::ImageLuma8(buf) => (ColorType::L8, buf.into_raw()),
+        DynamicImage::ImageLuma16(buf) => (
+            ColorType::L16,
+            buf.into_raw()
+                .into_iter()
+                .flat_map(|v| v.to_le_bytes())
+                .collect(),
+        ),
+        DynamicImage::ImageRgb8(buf) => (ColorType::Rgb8, buf.into_raw()),
+        DynamicImage::ImageRgba8(buf) => (ColorType::Rgba8, buf.into_raw()),
+        DynamicImage::ImageRgb16(buf) => (
+            ColorType::Rgb16,
+            buf.into_raw()
+                .into_iter()
+                .flat_map(|v| v.to_le_bytes())
+                .collect(),
+        ),
+        DynamicImage::ImageLumaA8(buf) => (ColorType::La8, buf.into_raw()),
+        DynamicImage::ImageLumaA16(buf) => (
+            ColorType::La16,
+            buf.into_raw()
+                .into_iter()
+                .flat_map(|v| v.to_le_bytes())
+                .collect(),
+        ),
+        DynamicImage::ImageRgba16(buf) => (
+            ColorType::Rgba16,
+            buf.into_raw()
+                .into_iter()
+                .flat_map(|v| v.to_le_bytes())
+                .collect(),
+        ),
+        DynamicImage::ImageRgb32F(buf) => (
+            ColorType::Rgb32F,
+            buf.into_raw()
+                .into_iter()
+                .flat_map(|v| v.to_le_bytes())
+                .collect(),
+        ),
+        other => (ColorType::Rgba8, other.into_rgba8().into_raw()),
+    }
+}
+
+fn color_type_to_id(ct: ColorType) -> Result<u8> {
+    Ok(match ct {
+        ColorType::L8 => 1,
+        ColorType::L16 => 2,
+        ColorType::Rgb8 => 3,
+        ColorType::Rgba8 => 4,
+        ColorType::Rgb16 => 5,
+        ColorType::La8 => 6,
+        ColorType::La16 => 7,
+        ColorType::Rgba16 => 8,
+        ColorType::L32F => 9,
+        ColorType::Rgb32F => 10,
+        ColorType::Cmyk8 => 11,
+        ColorType::Cmyk16 => 12,
+        ColorType::Bands8(n) => COLOR_ID_BANDS8 + band_count(n)? as u8 - 1,
+        ColorType::Bands16(n) => COLOR_ID_BANDS16 + band_count(n)? as u8 - 1,
+        ColorType::L1 => 13,
+    })
+}
+
+fn band_count(n: u8) -> Result<usize> {
+    ensure!(
+        (1..=MAX_BANDS).contains(&n),
+        CtiError::Unsupported(format!("{} bands (1..={} supported)", n, MAX_BANDS))
+    );
+    Ok(n as usize)
+}
+
+fn color_type_from_image(ct: image::ColorType) -> Result<ColorType> {
+    ColorType::try_from(ct)
+}
+
+impl TryFrom<image::ColorType> for ColorType {
+    type Error = CtiError;
+
+    fn try_from(ct: image::ColorType) -> Result<Self> {
+        Ok(match ct {
+            image::ColorType::L8 => ColorType::L8,
+            image::ColorType::L16 => ColorType::L16,
+            image::ColorType::Rgb8 => ColorType::Rgb8,
+            image::ColorType::Rgba8 => ColorType::Rgba8,
+            image::ColorType::Rgb16 => ColorType::Rgb16,
+            image::ColorType::La8 => ColorType::La8,
+            image::ColorType::La16 => ColorType::La16,
+            image::ColorType::Rgba16 => ColorType::Rgba16,
+            image::ColorType::Rgb32F => ColorType::Rgb32F,
+            other => bail!(CtiError::Unsupported(format!("Unsupported color type: {:?}", other))),
+        })
+    }
+}
+
+impl TryFrom<ColorType> for image::ColorType {
+    type Error = CtiError;
+
+    fn try_from(ct: ColorType) -> Result<Self> {
+        Ok(match ct {
+            ColorType::L8 => image::ColorType::L8,
+            ColorType::L16 => image::ColorType::L16,
+            ColorType::Rgb8 => image::ColorType::Rgb8,
+            ColorType::Rgba8 => image::ColorType::Rgba8,
+            ColorType::Rgb16 => image::ColorType::Rgb16,
+            ColorType::La8 => image::ColorType::La8,
+            ColorType::La16 => image::ColorType::La16,
+            ColorType::Rgba16 => image::ColorType::Rgba16,
+            ColorType::Rgb32F => image::ColorType::Rgb32F,
+            other => bail!(CtiError::Unsupported(format!("{:?} has no image::ColorType", other))),
+        })
+    }
+}
+
+fn is_float_color_type(ct: ColorType) -> bool {
+    matches!(ct, ColorType::L32F | ColorType::Rgb32F)
+}
+
+fn storage_color_type(color_type: ColorType, downcast_16_to_8: bool) -> ColorType {
+    if !downcast_16_to_8 {
+        return color_type;
+    }
+
+    match color_type {
+        ColorType::L16 => ColorType::L8,
+        ColorType::La16 => ColorType::La8,
+        ColorType::Rgb16 => ColorType::Rgb8,
+        ColorType::Rgba16 => ColorType::Rgba8,
+        ColorType::Cmyk16 => ColorType::Cmyk8,
+        ColorType::Bands16(n) => ColorType::Bands8(n),
+        other => other,
+    }
+}
+
+fn bytes_per_pixel(ct: &ColorType) -> Result<u32> {
+    Ok(match ct {
+        ColorType::L8 => 1,
+        ColorType::L16 => 2,
+        ColorType::Rgb8 => 3,
+        ColorType::Rgba8 => 4,
+        ColorType::Rgb16 => 6,
+        ColorType::La8 => 2,
+        ColorType::La16 => 4,
+        ColorType::Rgba16 => 8,
+        ColorType::L32F => 4,
+        ColorType::Rgb32F => 12,
+        ColorType::Cmyk8 => 4,
+        ColorType::Cmyk16 => 8,
+        ColorType::Bands8(n) => band_count(*n)? as u32,
+        ColorType::Bands16(n) => band_count(*n)? as u32 * 2,
+        ColorType::L1 => 1,
+    })
+}
+
+fn bytes_per_pixel_from_id(id: u8) -> Result<u32> {
+    bytes_per_pixel(&color_type_from_id(id)?)
+}
+
+fn sample_layout(color_type: ColorType) -> Result<(usize, usize)> {
+    Ok(match color_type {
+        ColorType::L8 => (1, 1),
+        ColorType::L16 => (1, 2),
+        ColorType::Rgb8 => (3, 1),
+        ColorType::Rgba8 => (4, 1),
+        ColorType::Rgb16 => (3, 2),
+        ColorType::La8 => (2, 1),
+        ColorType::La16 => (2, 2),
+        ColorType::Rgba16 => (4, 2),
+        ColorType::L32F => (1, 4),
+        ColorType::Rgb32F => (3, 4),
+        ColorType::Cmyk8 => (4, 1),
+        ColorType::Cmyk16 => (4, 2),
+        ColorType::Bands8(n) => (band_count(n)?, 1),
+        ColorType::Bands16(n) => (band_count(n)?, 2),
+        ColorType::L1 => (1, 1),
+    })
+}
+
+fn convert_tile_for_storage(data: &[u8], input_color_type: ColorType, output_color_type: ColorType) -> Result<Vec<u8>> {
+    if input_color_type == output_color_type {
+        return Ok(data.to_vec());
+    }
+
+    match (input_color_type, output_color_type) {
+        (ColorType::L16, ColorType::L8)
+        | (ColorType::La16, ColorType::La8)
+        | (ColorType::Rgb16, ColorType::Rgb8)
+        | (ColorType::Rgba16, ColorType::Rgba8)
+        | (ColorType::Cmyk16, ColorType::Cmyk8) => downcast_u16_bytes_to_u8(data),
+        (ColorType::Bands16(a), ColorType::Bands8(b)) if a == b => downcast_u16_bytes_to_u8(data),
+        _ => bail!(CtiError::Unsupported(format!(
+            "Unsupported storage conversion from {:?} to {:?}",
+            input_color_type, output_color_type
+        ))),
+    }
+}
+
+fn downcast_u16_bytes_to_u8(data: &[u8]) -> Result<Vec<u8>> {
+    Ok(bytes_to_u16_vec(data)?
+        .into_iter()
+        .map(|value| ((value as u32 + 128) / 257) as u8)
+        .collect())
+}
+
+fn extract_tile(img: &TiffImage, tx: u32, ty: u32, tile_dims: (u32, u32)) -> Result<Vec<u8>> {
+    let bpp = bytes_per_pixel(&img.color_type)?;
+    let start_x = tx * tile_dims.0;
+    let start_y = ty * tile_dims.1;
+    let (tile_w, tile_h) = tile_extent(img.width, img.height, tile_dims, tx, ty);
+    let end_y = start_y + tile_h;
+
+    let mut out = Vec::with_capacity((tile_w * tile_h * bpp) as usize);
+    for y in start_y..end_y {
+        let row_start = ((y * img.width + start_x) * bpp) as usize;
+        let row_end = row_start + (tile_w * bpp) as usize;
+        out.extend_from_slice(&img.data[row_start..row_end]);
+    }
+    Ok(out)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn blit_tile_region(
+    out: &mut [u8],
+    tile: &[u8],
+    region_w: u32,
+    region_h: u32,
+    region_x: u32,
+    region_y: u32,
+    stride: usize,
+    tile_dims: (u32, u32),
+    tile_w: u32,
+    tile_h: u32,
+    bpp: u32,
+    tx: u32,
+    ty: u32,
+) -> Result<()> {
+    let tile_x = tx * tile_dims.0;
+    let tile_y = ty * tile_dims.1;
+    let copy_x0 = tile_x.max(region_x);
+    let copy_y0 = tile_y.max(region_y);
+    let copy_x1 = (tile_x + tile_w).min(region_x + region_w);
+    let copy_y1 = (tile_y + tile_h).min(region_y + region_h);
+
+    if copy_x0 >= copy_x1 || copy_y0 >= copy_y1 {
+        return Ok(());
+    }
+
+    let bpp = bpp as usize;
+    let copy_w = (copy_x1 - copy_x0) as usize;
+    let copy_h = copy_y1 - copy_y0;
+    ensure!(
+        tile.len() >= tile_w as usize * tile_h as usize * bpp,
+        "Tile {},{} holds {} bytes, expected {}x{} pixels",
+        tx,
+        ty,
+        tile.len(),
+        tile_w,
+        tile_h
+    );
+    for row in 0..copy_h {
+        let src_x = (copy_x0 - tile_x) as usize;
+        let src_y = ((copy_y0 - tile_y) + row) as usize;
+        let dst_x = (copy_x0 - region_x) as usize;
+        let dst_y = ((copy_y0 - region_y) + row) as usize;
+        let src_off = (src_y * tile_w as usize + src_x) * bpp;
+        let dst_off = dst_y * stride + dst_x * bpp;
+        let len = copy_w * bpp;
+        out[dst_off..dst_off + len].copy_from_slice(&tile[src_off..src_off + len]);
+    }
+    Ok(())
+}
+
+fn tile_extent(width: u32, height: u32, tile_dims: (u32, u32), tx: u32, ty: u32) -> (u32, u32) {
+    let start_x = tx * tile_dims.0;
+    let start_y = ty * tile_dims.1;
+    (
+        (start_x + tile_dims.0).min(width) - start_x,
+        (start_y + tile_dims.1).min(height) - start_y,
+    )
+}
+
+fn color_transform_mode_for_encode(config: &CTIConfig, color_type: ColorType) -> ColorTransformMode {
+    // The reversible transforms wrap chroma modulo the sample range, which a
+    // lossy codec cannot preserve; lossy modes decorrelate color themselves.
+    if !config.color_transform || config.compression.is_lossy() {
+        return ColorTransformMode::None;
+    }
+
+    match color_type {
+        ColorType::Rgb8 => ColorTransformMode::Rct,
+        ColorType::Rgb16 | ColorType::Rgba16 => ColorTransformMode::Rgb16DeltaG,
+        _ => ColorTransformMode::None,
+    }
+}
+
+fn color_transform_mode_from_header(flags: u16, color_type: u8) -> ColorTransformMode {
+    if (flags & FLAG_COLOR_RGB16_DELTA_G) != 0 && matches!(color_type, 5 | 8) {
+        ColorTransformMode::Rgb16DeltaG
+    } else if (flags & FLAG_COLOR_RCT) != 0 && matches!(color_type, 3 | 5) {
+        ColorTransformMode::Rct
+    } else {
+        ColorTransformMode::None
+    }
+}
+
+fn color_transform_flags(mode: ColorTransformMode) -> u16 {
+    match mode {
+        ColorTransformMode::None => 0,
+        ColorTransformMode::Rct => FLAG_COLOR_RCT,
+        ColorTransformMode::Rgb16DeltaG => FLAG_COLOR_RGB16_DELTA_G,
+    }
+}
+
+fn apply_color_transform_forward(mode: ColorTransformMode, color_type: ColorType, tile: &mut [u8]) {
+    match mode {
+        ColorTransformMode::None => {}
+        ColorTransformMode::Rct => match color_type {
+            ColorType::Rgb8 => rct_forward_rgb8(tile),
+            ColorType::Rgb16 => rct_forward_rgb16(tile),
+            _ => {}
+        },
+        ColorTransformMode::Rgb16DeltaG => match color_type {
+            ColorType::Rgba16 => tile.chunks_exact_mut(8).for_each(|p| delta_g_forward_pixel(&mut p[..6])),
+            _ => rgb16_delta_g_forward(tile),
+        },
+    }
+}
+
+fn apply_color_transform_inverse(mode: ColorTransformMode, color_type: u8, tile: &mut [u8]) {
+    match mode {
+        ColorTransformMode::None => {}
+        ColorTransformMode::Rct => match color_type {
+            3 => rct_inverse_rgb8(tile),
+            5 => rct_inverse_rgb16(tile),
+            _ => {}
+        },
+        ColorTransformMode::Rgb16DeltaG => match color_type {
+            8 => tile.chunks_exact_mut(8).for_each(|p| delta_g_inverse_pixel(&mut p[..6])),
+            _ => rgb16_delta_g_inverse(tile),
+        },
+    }
+}
+
+fn downsample_half(img: &TiffImage) -> Result<TiffImage> {
+    let new_w = img.width.div_ceil(2);
+    let new_h = img.height.div_ceil(2);
+    let (channels, sample_bytes) = sample_layout(img.color_type)?;
+    let mut out = Vec::with_capacity((new_w * new_h * bytes_per_pixel(&img.color_type)?) as usize);
+
+    for y in 0..new_h {
+        for x in 0..new_w {
+            for ch in 0..channels {
+                if sample_bytes == 1 {
+                    let mut sum = 0u32;
+                    let mut count = 0u32;
+                    for sy in 0..2 {
+                        for sx in 0..2 {
+                            let src_x = x * 2 + sx;
+                            let src_y = y * 2 + sy;
+                            if src_x < img.width && src_y < img.height {
+                                let idx = ((src_y * img.width + src_x) as usize * channels) + ch;
+                                sum += img.data[idx] as u32;
+                                count += 1;
+                            }
+                        }
+                    }
+                    let average = (sum / count) as u8;
+                    out.push(if img.color_type == ColorType::L1 { bilevel_from_average(average) } else { average });
+                } else if sample_bytes == 2 {
+                    let mut sum = 0u32;
+                    let mut count = 0u32;
+                    for sy in 0..2 {
+                        for sx in 0..2 {
+                            let src_x = x * 2 + sx;
+                            let src_y = y * 2 + sy;
+                            if src_x < img.width && src_y < img.height {
+                                let pixel = (src_y * img.width + src_x) as usize;
+                                let off = pixel * channels * 2 + ch * 2;
+                                let val = u16::from_le_bytes([img.data[off], img.data[off + 1]]) as u32;
+                                sum += val;
+                                count += 1;
+                            }
+                        }
+                    }
+                    out.extend_from_slice(&((sum / count) as u16).to_le_bytes());
+                } else {
+                    let mut sum = 0f64;
+                    let mut count = 0u32;
+                    for sy in 0..2 {
+                        for sx in 0..2 {
+                            let src_x = x * 2 + sx;
+                            let src_y = y * 2 + sy;
+                            if src_x < img.width && src_y < img.height {
+                                let pixel = (src_y * img.width + src_x) as usize;
+                                let off = pixel * channels * 4 + ch * 4;
+                                sum += f32_from_le_bytes(&img.data[off..off + 4]) as f64;
+                                count += 1;
+                            }
+                        }
+                    }
+                    out.extend_from_slice(&((sum / count as f64) as f32).to_le_bytes());
+                }
+            }
+        }
+    }
+
+    Ok(TiffImage {
+        width: new_w,
+        height: new_h,
+        color_type: img.color_type,
+        data: out,
+        xdpi: img.xdpi.map(|v| v / 2.0),
+        ydpi: img.ydpi.map(|v| v / 2.0),
+        icc: img.icc.clone(),
+        bands: img.bands.clone(),
+        // XMP/EXIF/IPTC describe the original and stay at level 0 only.
+        descriptive: DescriptiveMetadata::default(),
+    })
+}
+
+fn compress_tile(
+    kind: CompressionType,
+    color_type: ColorType,
+    data: &[u8],
+    tile_extent: (u32, u32),
+    params: &TileCodecParams,
+) -> Result<TileCompressionResult> {
+    let comp = match kind {
+        CompressionType::None => TileCompressionResult {
+            comp: data.to_vec(),
+            adaptive_mode: None,
+        },
+        CompressionType::RLE => TileCompressionResult {
+            comp: rle_compress(data)?,
+            adaptive_mode: None,
+        },
+        CompressionType::Delta => TileCompressionResult {
+            comp: rle_compress(&delta_forward_for_color(data, color_type)?)?,
+            adaptive_mode: None,
+        },
+        CompressionType::Predictive => TileCompressionResult {
+            comp: rle_compress(&predictive_forward_for_color(data, color_type)?)?,
+            adaptive_mode: None,
+        },
+        CompressionType::LZ77 => TileCompressionResult {
+            comp: lz77_compress(data)?,
+            adaptive_mode: None,
+        },
+        CompressionType::Zstd => TileCompressionResult {
+            comp: params.zstd_compress(data)?,
+            adaptive_mode: None,
+        },
+        CompressionType::Lz4 => TileCompressionResult {
+            comp: lz4_flex::block::compress_prepend_size(data),
+            adaptive_mode: None,
+        },
+        CompressionType::Dct | CompressionType::NearLossless if is_float_color_type(color_type) => {
+            bail!(CtiError::Unsupported(format!("{} does not support floating-point samples", kind.label())))
+        }
+        CompressionType::Dct | CompressionType::NearLossless if color_type == ColorType::L1 => {
+            bail!(CtiError::Unsupported(format!("{} does not support bilevel images", kind.label())))
+        }
+        CompressionType::Bilevel => {
+            ensure!(
+                color_type == ColorType::L1,
+                CtiError::Unsupported(format!("bilevel compression needs L1 input, got {:?}", color_type))
+            );
+            TileCompressionResult {
+                comp: bilevel_compress(data, tile_extent)?,
+                adaptive_mode: None,
+            }
+        }
+        CompressionType::Dct => TileCompressionResult {
+            comp: dct_compress(data, color_type_to_id(color_type)?, tile_extent, params.quality, params.zstd_level)?,
+            adaptive_mode: None,
+        },
+        CompressionType::NearLossless => TileCompressionResult {
+            comp: near_lossless_compress(data, color_type_to_id(color_type)?, tile_extent, params.max_error, params.zstd_level)?,
+            adaptive_mode: None,
+        },
+        CompressionType::Adaptive => compress_tile_adaptive(color_type, data, tile_extent, params)?,
+    };
+    Ok(comp)
+}
+
+fn compress_prepared_tile(
+    kind: CompressionType,
+    color_type: ColorType,
+    tile: Vec<u8>,
+    tile_extent: (u32, u32),
+    params: &TileCodecParams,
+    detect_solid: bool,
+) -> Result<CompTile> {
+    let bpp = bytes_per_pixel(&color_type)? as usize;
+    if detect_solid && is_solid_tile(&tile, bpp) {
+        return Ok(CompTile {
+            comp: tile[..bpp].to_vec(),
+            orig_len: tile.len() as u32,
+            crc: crc32(&tile),
+            // TMOD still needs a byte for every tile; solid tiles ignore it.
+            adaptive_mode: (kind == CompressionType::Adaptive).then_some(AdaptiveTileMode::ZstdRaw),
+            solid: true,
+        });
+    }
+
+    let comp = compress_tile(kind, color_type, &tile, tile_extent, params)?;
+    let crc = if kind.is_lossy() {
+        let color_type_id = color_type_to_id(color_type)?;
+        crc32(&decompress_tile_with_size(
+            kind as u8,
+            &comp.comp,
+            tile.len(),
+            color_type_id,
+            tile_extent,
+            comp.adaptive_mode,
+            params,
+        )?)
+    } else {
+        crc32(&tile)
+    };
+    Ok(CompTile {
+        comp: comp.comp,
+        orig_len: tile.len() as u32,
+        crc,
+        adaptive_mode: comp.adaptive_mode,
+        solid: false,
+    })
+}
+
+fn is_solid_tile(tile: &[u8], bpp: usize) -> bool {
+    tile.len() > bpp && tile.chunks_exact(bpp).all(|px| px == &tile[..bpp])
+}
+
+fn streaming_batch_tiles(tile_dims: (u32, u32), bytes_per_pixel: u32) -> usize {
+    if let Some(override_tiles) = std::env::var_os("CTI_BATCH_TILES")
+        .and_then(|value| value.to_str().map(str::trim).map(str::to_owned))
+        .and_then(|value| value.parse::<usize>().ok())
+        .filter(|&value| value > 0)
+    {
+        return override_tiles;
+    }
+
+    let threads = rayon::current_num_threads().max(1);
+    let max_tile_bytes = (tile_dims.0 as usize)
+        .saturating_mul(tile_dims.1 as usize)
+        .saturating_mul(bytes_per_pixel as usize)
+        .max(1);
+    let target_batch_bytes = 64usize * 1024 * 1024;
+    let memory_limited = (target_batch_bytes / max_tile_bytes).max(1);
+    memory_limited.min(threads * 2).max(1)
+}
+
+fn compress_tile_adaptive(
+    color_type: ColorType,
+    data: &[u8],
+    tile_extent: (u32, u32),
+    params: &TileCodecParams,
+) -> Result<TileCompressionResult> {
+    let mut best_mode = AdaptiveTileMode::ZstdRaw;
+    let mut best_comp = params.zstd_compress(data)?;
+
+    let (channels, sample_bytes) = sample_layout(color_type)?;
+
+    if sample_bytes == 2 {
+
+        let shuffle = shuffle_u16_bytes(data)?;
+        let shuffle_comp = params.zstd_compress(&shuffle)?;
+        if shuffle_comp.len() < best_comp.len() {
+            best_mode = AdaptiveTileMode::ZstdShuffle16;
+            best_comp = shuffle_comp;
+        }
+
+        let delta = delta_forward_u16(data, channels)?;
+        let delta_score = residual_score_u16(&delta, channels)?;
+        let delta_comp = params.zstd_compress(&delta)?;
+        if delta_comp.len() < best_comp.len() {
+            best_mode = AdaptiveTileMode::ZstdDelta16;
+            best_comp = delta_comp;
+        }
+
+        let predict = predictive_forward_u16(data, channels)?;
+        let predict_score = residual_score_u16(&predict, channels * 2)?;
+        if predict_score <= delta_score {
+            let predict_comp = params.zstd_compress(&predict)?;
+            if predict_comp.len() < best_comp.len() {
+                best_mode = AdaptiveTileMode::ZstdPredict16;
+                best_comp = predict_comp;
+            }
+        }
+
+        let gradient = gradient_forward_u16(data, tile_extent.0 as usize, channels)?;
+        let gradient_score =
+            residual_score_u16(&gradient, tile_extent.0 as usize * channels + channels)?;
+        let best_linear_score = predict_score.min(delta_score);
+        if gradient_score * 100 <= best_linear_score * 95 {
+            let gradient_comp = params.zstd_compress(&gradient)?;
+            if gradient_comp.len() * 100 <= best_comp.len() * 97 {
+                best_mode = AdaptiveTileMode::ZstdGradient16;
+                best_comp = gradient_comp;
+            }
+        }
+
+    }
+
+    if sample_bytes == 1 {
+        let width = tile_extent.0 as usize;
+        let warmup = width * channels + channels;
+
+        let delta = delta_forward_u8(data, channels);
+        let delta_score = residual_score_u8(&delta, channels);
+        let delta_comp = params.zstd_compress(&delta)?;
+        if delta_comp.len() < best_comp.len() {
+            best_mode = AdaptiveTileMode::ZstdDelta8;
+            best_comp = delta_comp;
+        }
+
+        // Only the better-scoring 2D predictor is worth a Zstd pass.
+        let gradient = spatial_forward_u8(data, width, channels, med_predict_u8)?;
+        let paeth = spatial_forward_u8(data, width, channels, paeth_predict_u8)?;
+        let gradient_score = residual_score_u8(&gradient, warmup);
+        let paeth_score = residual_score_u8(&paeth, warmup);
+        let (spatial_mode, spatial, spatial_score) = if paeth_score < gradient_score {
+            (AdaptiveTileMode::ZstdPaeth8, paeth, paeth_score)
+        } else {
+            (AdaptiveTileMode::ZstdGradient8, gradient, gradient_score)
+        };
+        if spatial_score * 100 <= delta_score * 95 {
+            let spatial_comp = params.zstd_compress(&spatial)?;
+            if spatial_comp.len() < best_comp.len() {
+                best_mode = spatial_mode;
+                best_comp = spatial_comp;
+            }
+        }
+    }
+
+    // Samples other than 0/255 leave the bilevel candidate out.
+    if color_type == ColorType::L1
+        && let Ok(bilevel_comp) = bilevel_compress(data, tile_extent)
+        && bilevel_comp.len() < best_comp.len()
+    {
+        best_mode = AdaptiveTileMode::Bilevel;
+        best_comp = bilevel_comp;
+    }
+
+    if is_float_color_type(color_type) {
+        let shuffle_comp = params.zstd_compress(&shuffle_u32_bytes(data)?)?;
+        if shuffle_comp.len() < best_comp.len() {
+            best_mode = AdaptiveTileMode::ZstdShuffle32;
+            best_comp = shuffle_comp;
+        }
+
+        let xor_comp = params.zstd_compress(&xor_delta_forward_u32(data, channels)?)?;
+        if xor_comp.len() < best_comp.len() {
+            best_mode = AdaptiveTileMode::ZstdXorDelta32;
+            best_comp = xor_comp;
+        }
+    }
+
+    if sample_bytes <= 2 {
+        let wavelet = wavelet53_forward(data, tile_extent.0 as usize, channels, sample_bytes)?;
+        let wavelet_comp = params.zstd_compress(&wavelet)?;
+        if wavelet_comp.len() < best_comp.len() {
+            best_mode = AdaptiveTileMode::ZstdWavelet53;
+            best_comp = wavelet_comp;
+        }
+    }
+
+    let lz4_raw = lz4_flex::block::compress_prepend_size(data);
+    if lz4_raw.len() * 100 <= best_comp.len() * 105 {
+        best_mode = AdaptiveTileMode::Lz4Raw;
+        best_comp = lz4_raw;
+    }
+
+    Ok(TileCompressionResult {
+        comp: best_comp,
+        adaptive_mode: Some(best_mode),
+    })
+}
+
+/// LZ4 block with its size prepended. The stored size is only trusted up to
+/// `original_size`, which the index entry has already bounded by the tile extent.
+fn lz4_decompress(comp: &[u8], original_size: usize) -> Result<Vec<u8>> {
+    ensure!(comp.len() >= 4, "LZ4 tile is missing its size prefix");
+    let stored = u32::from_le_bytes([comp[0], comp[1], comp[2], comp[3]]) as usize;
+    ensure!(
+        stored <= original_size,
+        "LZ4 tile claims {} bytes, the index allows {}",
+        stored,
+        original_size
+    );
+    lz4_flex::block::decompress(&comp[4..], original_size).map_err(|e| CtiError::Corrupt(e.to_string()))
+}
+
+fn decompress_tile_with_size(
+    kind: u8,
+    comp: &[u8],
+    original_size: usize,
+    color_type: u8,
+    tile_extent: (u32, u32),
+    adaptive_mode: Option<AdaptiveTileMode>,
+    params: &TileCodecParams,
+) -> Result<Vec<u8>> {
+    match kind {
+        0 => Ok(comp.to_vec()),
+        1 => rle_decompress(comp),
+        2 => lz77_decompress(comp),
+        3 => {
+            let d = rle_decompress(comp)?;
+            delta_inverse_for_color(&d, color_type)
+        }
+        4 => {
+            let d = rle_decompress(comp)?;
+            predictive_inverse_for_color(&d, color_type)
+        }
+        10 => params.zstd_decompress(comp, original_size),
+        11 => lz4_decompress(comp, original_size),
+        20 => dct_decompress(comp, color_type, tile_extent, params.quality),
+        21 => near_lossless_decompress(comp, color_type, tile_extent, params.max_error, params.near_lossless_tagged),
+        30 => bilevel_decompress(comp, tile_extent),
+        250 => {
+            let mode = adaptive_mode.context("Adaptive tile is missing mode metadata")?;
+            match mode {
+                AdaptiveTileMode::ZstdRaw => params.zstd_decompress(comp, original_size),
+                AdaptiveTileMode::ZstdDelta16 => {
+                    let d = params.zstd_decompress(comp, original_size)?;
+                    delta_inverse_for_color(&d, color_type)
+                }
+                AdaptiveTileMode::ZstdPredict16 => {
+                    let d = params.zstd_decompress(comp, original_size)?;
+                    predictive_inverse_for_color(&d, color_type)
+                }
+                AdaptiveTileMode::ZstdShuffle16 => {
+                    let d = params.zstd_decompress(comp, original_size)?;
+                    unshuffle_u16_bytes(&d)
+                }
+                AdaptiveTileMode::ZstdGradient16 => {
+                    let d = params.zstd_decompress(comp, original_size)?;
+                    gradient_inverse_u16(
+                        &d,
+                        tile_extent.0 as usize,
+                        bytes_per_pixel_from_id(color_type)? as usize / 2,
+                    )
+                }
+                AdaptiveTileMode::Lz4Raw => lz4_decompress(comp, original_size),
+                AdaptiveTileMode::ZstdWavelet53 => {
+                    let d = params.zstd_decompress(comp, original_size)?;
+                    let (channels, sample_bytes) = sample_layout(color_type_from_id(color_type)?)?;
+                    wavelet53_inverse(&d, tile_extent.0 as usize, channels, sample_bytes)
+                }
+                AdaptiveTileMode::ZstdDelta8 => {
+                    let d = params.zstd_decompress(comp, original_size)?;
+                    Ok(delta_inverse_u8(&d, bytes_per_pixel_from_id(color_type)? as usize))
+                }
+                AdaptiveTileMode::ZstdShuffle32 => {
+                    let d = params.zstd_decompress(comp, original_size)?;
+                    unshuffle_u32_bytes(&d)
+                }
+                AdaptiveTileMode::ZstdXorDelta32 => {
+                    let d = params.zstd_decompress(comp, original_size)?;
+                    xor_delta_inverse_u32(&d, sample_layout(color_type_from_id(color_type)?)?.0)
+                }
+                AdaptiveTileMode::Bilevel => bilevel_decompress(comp, tile_extent),
+                AdaptiveTileMode::ZstdGradient8 | AdaptiveTileMode::ZstdPaeth8 => {
+                    let d = params.zstd_decompress(comp, original_size)?;
+                    let predict = if mode == AdaptiveTileMode::ZstdPaeth8 { paeth_predict_u8 } else { med_predict_u8 };
+                    spatial_inverse_u8(
+                        &d,
+                        tile_extent.0 as usize,
+                        bytes_per_pixel_from_id(color_type)? as usize,
+                        predict,
+                    )
+                }
+            }
+        }
+        _ => bail!(CtiError::UnknownCompression(kind)),
+    }
+}
+
+fn rle_compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0usize;
+    while i < data.len() {
+        let val = data[i];
+        let mut cnt = 1usize;
+        while i + cnt < data.len() && data[i + cnt] == val && cnt < 255 {
+            cnt += 1;
+        }
+        if cnt >= 4 {
+            out.push(0xFF);
+            out.push(TAG_RLE);
+            out.push(cnt as u8);
+            out.push(val);
+            i += cnt;
+        } else {
+            for _ in 0..cnt {
+                if val == 0xFF {
+                    out.push(0xFF);
+                    out.push(TAG_ESCAPE_FF);
+                } else {
+                    out.push(val);
+                }
+            }
+            i += cnt;
+        }
+    }
+    Ok(out)
+}
+
+fn rle_decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let b = data[i];
+        i += 1;
+        if b != 0xFF {
+            out.push(b);
+            continue;
+        }
+        ensure!(i < data.len(), "RLE: truncated after 0xFF");
+        let tag = data[i];
+        i += 1;
+        match tag {
+            TAG_ESCAPE_FF => out.push(0xFF),
+            TAG_RLE => {
+                ensure!(i + 1 < data.len(), "RLE: truncated run");
+                let count = data[i] as usize;
+                let val = data[i + 1];
+                i += 2;
+                out.extend(std::iter::repeat_n(val, count));
+            }
+            TAG_LZ77 => bail!("RLE stream contains LZ77 tag"),
+            _ => bail!("RLE unknown tag {}", tag),
+        }
+    }
+    Ok(out)
+}
+
+fn delta_forward(data: &[u8]) -> Vec<u8> {
+    if data.is_empty() {
+        return vec![];
+    }
+    let mut out = Vec::with_capacity(data.len());
+    out.push(data[0]);
+    for i in 1..data.len() {
+        out.push(data[i].wrapping_sub(data[i - 1]));
+    }
+    out
+}
+
+fn delta_forward_for_color(data: &[u8], color_type: ColorType) -> Result<Vec<u8>> {
+    match sample_layout(color_type)? {
+        (channels, 2) => delta_forward_u16(data, channels),
+        _ => Ok(delta_forward(data)),
+    }
+}
+
+fn delta_inverse(data: &[u8]) -> Vec<u8> {
+    if data.is_empty() {
+        return vec![];
+    }
+    let mut out = Vec::with_capacity(data.len());
+    let mut prev = data[0];
+    out.push(prev);
+    for &value in data.iter().skip(1) {
+        let v = prev.wrapping_add(value);
+        out.push(v);
+        prev = v;
+    }
+    out
+}
+
+fn delta_inverse_for_color(data: &[u8], color_type: u8) -> Result<Vec<u8>> {
+    match sample_layout(color_type_from_id(color_type)?)? {
+        (channels, 2) => delta_inverse_u16(data, channels),
+        _ => Ok(delta_inverse(data)),
+    }
+}
+
+fn predictive_forward(data: &[u8]) -> Vec<u8> {
+    if data.len() < 3 {
+        return data.to_vec();
+    }
+    let mut out = Vec::with_capacity(data.len());
+    out.push(data[0]);
+    out.push(data[1]);
+    for i in 2..data.len() {
+        let p = data[i - 1].wrapping_add(data[i - 1].wrapping_sub(data[i - 2]));
+        out.push(data[i].wrapping_sub(p));
+    }
+    out
+}
+
+fn predictive_forward_for_color(data: &[u8], color_type: ColorType) -> Result<Vec<u8>> {
+    match sample_layout(color_type)? {
+        (channels, 2) => predictive_forward_u16(data, channels),
+        _ => Ok(predictive_forward(data)),
+    }
+}
+
+fn predictive_inverse(data: &[u8]) -> Vec<u8> {
+    if data.len() < 3 {
+        return data.to_vec();
+    }
+    let mut out = Vec::with_capacity(data.len());
+    let (mut a0, mut a1) = (data[0], data[1]);
+    out.push(a0);
+    out.push(a1);
+    for &value in data.iter().skip(2) {
+        let p = a1.wrapping_add(a1.wrapping_sub(a0));
+        let v = p.wrapping_add(value);
+        out.push(v);
+        a0 = a1;
+        a1 = v;
+    }
+    out
+}
+
+fn predictive_inverse_for_color(data: &[u8], color_type: u8) -> Result<Vec<u8>> {
+    match sample_layout(color_type_from_id(color_type)?)? {
+        (channels, 2) => predictive_inverse_u16(data, channels),
+        _ => Ok(predictive_inverse(data)),
+    }
+}
+
+fn delta_forward_u8(data: &[u8], channels: usize) -> Vec<u8> {
+    let mut out = vec![0u8; data.len()];
+    let head = channels.min(data.len());
+    out[..head].copy_from_slice(&data[..head]);
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if std::is_x86_feature_detected!("avx2") && channels > 0 && data.len() > channels {
+            unsafe {
+                delta_forward_u8_avx2(data, channels, &mut out);
+            }
+            return out;
+        }
+    }
+
+    for i in channels..data.len() {
+        out[i] = data[i].wrapping_sub(data[i - channels]);
+    }
+    out
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn delta_forward_u8_avx2(data: &[u8], channels: usize, out: &mut [u8]) {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    let mut i = channels;
+    while i + 32 <= data.len() {
+        let cur = unsafe { _mm256_loadu_si256(data.as_ptr().add(i) as *const __m256i) };
+        let prev = unsafe { _mm256_loadu_si256(data.as_ptr().add(i - channels) as *const __m256i) };
+        let diff = _mm256_sub_epi8(cur, prev);
+        unsafe { _mm256_storeu_si256(out.as_mut_ptr().add(i) as *mut __m256i, diff) };
+        i += 32;
+    }
+    for j in i..data.len() {
+        out[j] = data[j].wrapping_sub(data[j - channels]);
+    }
+}
+
+fn delta_inverse_u8(data: &[u8], channels: usize) -> Vec<u8> {
+    let mut out = vec![0u8; data.len()];
+    for i in 0..data.len() {
+        out[i] = if i < channels {
+            data[i]
+        } else {
+            out[i - channels].wrapping_add(data[i])
+        };
+    }
+    out
+}
+
+fn med_predict_u8(left: u8, top: u8, top_left: u8) -> u8 {
+    jpegls_predict_u16(left as u16, top as u16, top_left as u16) as u8
+}
+
+fn paeth_predict_u8(left: u8, top: u8, top_left: u8) -> u8 {
+    let p = left as i16 + top as i16 - top_left as i16;
+    let pa = (p - left as i16).abs();
+    let pb = (p - top as i16).abs();
+    let pc = (p - top_left as i16).abs();
+    if pa <= pb && pa <= pc {
+        left
+    } else if pb <= pc {
+        top
+    } else {
+        top_left
+    }
+}
+
+/// Row-aware 8-bit spatial predictor: left neighbour on the first row, top
+/// neighbour in the first column, `predict(left, top, top_left)` elsewhere.
+fn spatial_forward_u8(
+    data: &[u8],
+    width: usize,
+    channels: usize,
+    predict: fn(u8, u8, u8) -> u8,
+) -> Result<Vec<u8>> {
+    let row_stride = width * channels;
+    ensure!(row_stride > 0, "spatial predictor requires non-zero row stride");
+    ensure!(
+        data.len().is_multiple_of(row_stride),
+        "spatial predictor tile size does not match row stride"
+    );
+
+    let mut out = vec![0u8; data.len()];
+    for i in 0..data.len() {
+        let row_offset = i % row_stride;
+        out[i] = if i < row_stride {
+            if row_offset < channels {
+                data[i]
+            } else {
+                data[i].wrapping_sub(data[i - channels])
+            }
+        } else if row_offset < channels {
+            data[i].wrapping_sub(data[i - row_stride])
+        } else {
+            let left = data[i - channels];
+            let top = data[i - row_stride];
+            let top_left = data[i - row_stride - channels];
+            data[i].wrapping_sub(predict(left, top, top_left))
+        };
+    }
+    Ok(out)
+}
+
+fn spatial_inverse_u8(
+    data: &[u8],
+    width: usize,
+    channels: usize,
+    predict: fn(u8, u8, u8) -> u8,
+) -> Result<Vec<u8>> {
+    let row_stride = width * channels;
+    ensure!(row_stride > 0, "spatial predictor requires non-zero row stride");
+    ensure!(
+        data.len().is_multiple_of(row_stride),
+        "spatial predictor tile size does not match row stride"
+    );
+
+    let mut out = vec![0u8; data.len()];
+    for i in 0..data.len() {
+        let row_offset = i % row_stride;
+        out[i] = if i < row_stride {
+            if row_offset < channels {
+                data[i]
+            } else {
+                out[i - channels].wrapping_add(data[i])
+            }
+        } else if row_offset < channels {
+            out[i - row_stride].wrapping_add(data[i])
+        } else {
+            let left = out[i - channels];
+            let top = out[i - row_stride];
+            let top_left = out[i - row_stride - channels];
+            predict(left, top, top_left).wrapping_add(data[i])
+        };
+    }
+    Ok(out)
+}
+
+fn delta_forward_u16(data: &[u8], channels: usize) -> Result<Vec<u8>> {
+    let samples = bytes_to_u16_vec(data)?;
+    let mut out = vec![0u16; samples.len()];
+
+    let head = channels.min(samples.len());
+    out[..head].copy_from_slice(&samples[..head]);
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if std::is_x86_feature_detected!("avx2") && channels > 0 && samples.len() > channels {
+            unsafe {
+                delta_forward_u16_avx2(&samples, channels, &mut out);
+            }
+            return Ok(u16_vec_to_bytes(&out));
+        }
+    }
+
+    for i in channels..samples.len() {
+        out[i] = samples[i].wrapping_sub(samples[i - channels]);
+    }
+    Ok(u16_vec_to_bytes(&out))
+}
+
+fn delta_inverse_u16(data: &[u8], channels: usize) -> Result<Vec<u8>> {
+    let residuals = bytes_to_u16_vec(data)?;
+    let mut out = vec![0u16; residuals.len()];
+    for i in 0..residuals.len() {
+        out[i] = if i < channels {
+            residuals[i]
+        } else {
+            out[i - channels].wrapping_add(residuals[i])
+        };
+    }
+    Ok(u16_vec_to_bytes(&out))
+}
+
+fn predictive_forward_u16(data: &[u8], channels: usize) -> Result<Vec<u8>> {
+    let samples = bytes_to_u16_vec(data)?;
+    let mut out = vec![0u16; samples.len()];
+
+    let head = (channels * 2).min(samples.len());
+    out[..head].copy_from_slice(&samples[..head]);
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if std::is_x86_feature_detected!("avx2") && channels > 0 && samples.len() > channels * 2 {
+            unsafe {
+                predictive_forward_u16_avx2(&samples, channels, &mut out);
+            }
+            return Ok(u16_vec_to_bytes(&out));
+        }
+    }
+
+    for i in channels * 2..samples.len() {
+        let prev = samples[i - channels];
+        let prev_prev = samples[i - channels * 2];
+        let predicted = prev.wrapping_add(prev.wrapping_sub(prev_prev));
+        out[i] = samples[i].wrapping_sub(predicted);
+    }
+    Ok(u16_vec_to_bytes(&out))
+}
+
+fn predictive_inverse_u16(data: &[u8], channels: usize) -> Result<Vec<u8>> {
+    let residuals = bytes_to_u16_vec(data)?;
+    let mut out = vec![0u16; residuals.len()];
+    for i in 0..residuals.len() {
+        out[i] = if i < channels * 2 {
+            residuals[i]
+        } else {
+            let prev = out[i - channels];
+            let prev_prev = out[i - channels * 2];
+            let predicted = prev.wrapping_add(prev.wrapping_sub(prev_prev));
+            predicted.wrapping_add(residuals[i])
+        };
+    }
+    Ok(u16_vec_to_bytes(&out))
+}
+
+fn gradient_forward_u16(data: &[u8], width: usize, channels: usize) -> Result<Vec<u8>> {
+    ensure!(width > 0, "gradient predictor requires non-zero width");
+    let samples = bytes_to_u16_vec(data)?;
+    let row_stride = width * channels;
+    ensure!(row_stride > 0, "gradient predictor requires non-zero row stride");
+    ensure!(
+        samples.len() % row_stride == 0,
+        "gradient predictor tile size does not match row stride"
+    );
+
+    let mut out = vec![0u16; samples.len()];
+    for i in 0..samples.len() {
+        let row_offset = i % row_stride;
+        out[i] = if i < row_stride {
+            if row_offset < channels {
+                samples[i]
+            } else {
+                samples[i].wrapping_sub(samples[i - channels])
+            }
+        } else if row_offset < channels {
+            samples[i].wrapping_sub(samples[i - row_stride])
+        } else {
+            let left = samples[i - channels];
+            let top = samples[i - row_stride];
+            let top_left = samples[i - row_stride - channels];
+            samples[i].wrapping_sub(jpegls_predict_u16(left, top, top_left))
+        };
+    }
+    Ok(u16_vec_to_bytes(&out))
+}
+
+fn gradient_inverse_u16(data: &[u8], width: usize, channels: usize) -> Result<Vec<u8>> {
+    ensure!(width > 0, "gradient predictor requires non-zero width");
+    let residuals = bytes_to_u16_vec(data)?;
+    let row_stride = width * channels;
+    ensure!(row_stride > 0, "gradient predictor requires non-zero row stride");
+    ensure!(
+        residuals.len() % row_stride == 0,
+        "gradient predictor tile size does not match row stride"
+    );
+
+    let mut out = vec![0u16; residuals.len()];
+    for i in 0..residuals.len() {
+        let row_offset = i % row_stride;
+        out[i] = if i < row_stride {
+            if row_offset < channels {
+                residuals[i]
+            } else {
+                out[i - channels].wrapping_add(residuals[i])
+            }
+        } else if row_offset < channels {
+            out[i - row_stride].wrapping_add(residuals[i])
+        } else {
+            let left = out[i - channels];
+            let top = out[i - row_stride];
+            let top_left = out[i - row_stride - channels];
+            jpegls_predict_u16(left, top, top_left).wrapping_add(residuals[i])
+        };
+    }
+    Ok(u16_vec_to_bytes(&out))
+}
+
+fn jpegls_predict_u16(left: u16, top: u16, top_left: u16) -> u16 {
+    if top_left >= left.max(top) {
+        left.min(top)
+    } else if top_left <= left.min(top) {
+        left.max(top)
+    } else {
+        (left as u32 + top as u32 - top_left as u32) as u16
+    }
+}
+
+fn shuffle_u16_bytes(data: &[u8]) -> Result<Vec<u8>> {
+    ensure!(data.len().is_multiple_of(2), "16-bit shuffle requires even byte length");
+    let samples = data.len() / 2;
+    let mut out = vec![0u8; data.len()];
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if std::is_x86_feature_detected!("avx2") {
+            unsafe {
+                shuffle_u16_bytes_avx2(data, &mut out);
+            }
+            return Ok(out);
+        }
+    }
+
+    for i in 0..samples {
+        out[i] = data[i * 2];
+        out[samples + i] = data[i * 2 + 1];
+    }
+    Ok(out)
+}
+
+fn unshuffle_u16_bytes(data: &[u8]) -> Result<Vec<u8>> {
+    ensure!(data.len().is_multiple_of(2), "16-bit unshuffle requires even byte length");
+    let samples = data.len() / 2;
+    let mut out = vec![0u8; data.len()];
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if std::is_x86_feature_detected!("avx2") {
+            unsafe {
+                unshuffle_u16_bytes_avx2(data, &mut out);
+            }
+            return Ok(out);
+        }
+    }
+
+    for i in 0..samples {
+        out[i * 2] = data[i];
+        out[i * 2 + 1] = data[samples + i];
+    }
+    Ok(out)
+}
+
+/// Byte-plane shuffle of 4-byte samples: the sign/exponent bytes of all
+/// samples end up in one plane and the noisy low mantissa bytes in another.
+fn shuffle_u32_bytes(data: &[u8]) -> Result<Vec<u8>> {
+    ensure!(data.len().is_multiple_of(4), "32-bit shuffle requires a multiple of 4 bytes");
+    let samples = data.len() / 4;
+    let mut out = vec![0u8; data.len()];
+    for (i, sample) in data.chunks_exact(4).enumerate() {
+        for (plane, &byte) in sample.iter().enumerate() {
+            out[plane * samples + i] = byte;
+        }
+    }
+    Ok(out)
+}
+
+fn unshuffle_u32_bytes(data: &[u8]) -> Result<Vec<u8>> {
+    ensure!(data.len().is_multiple_of(4), "32-bit unshuffle requires a multiple of 4 bytes");
+    let samples = data.len() / 4;
+    let mut out = vec![0u8; data.len()];
+    for (i, sample) in out.chunks_exact_mut(4).enumerate() {
+        for (plane, byte) in sample.iter_mut().enumerate() {
+            *byte = data[plane * samples + i];
+        }
+    }
+    Ok(out)
+}
+
+/// XORs every 32-bit sample with the previous sample of its channel, so the
+/// shared sign, exponent and high mantissa bits of neighbouring floats cancel
+/// to zero, then shuffles the residuals into byte planes.
+fn xor_delta_forward_u32(data: &[u8], channels: usize) -> Result<Vec<u8>> {
+    ensure!(data.len().is_multiple_of(4), "32-bit XOR delta requires a multiple of 4 bytes");
+    let samples: Vec<u32> = data
+        .chunks_exact(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect();
+    let mut residuals = Vec::with_capacity(data.len());
+    for (i, &value) in samples.iter().enumerate() {
+        let prev = if i >= channels { samples[i - channels] } else { 0 };
+        residuals.extend_from_slice(&(value ^ prev).to_le_bytes());
+    }
+    shuffle_u32_bytes(&residuals)
+}
+
+fn xor_delta_inverse_u32(data: &[u8], channels: usize) -> Result<Vec<u8>> {
+    let residuals = unshuffle_u32_bytes(data)?;
+    let mut out = vec![0u8; residuals.len()];
+    for i in 0..residuals.len() / 4 {
+        let off = i * 4;
+        let mut value = u32::from_le_bytes([residuals[off], residuals[off + 1], residuals[off + 2], residuals[off + 3]]);
+        if i >= channels {
+            let prev = off - channels * 4;
+            value ^= u32::from_le_bytes([out[prev], out[prev + 1], out[prev + 2], out[prev + 3]]);
+        }
+        out[off..off + 4].copy_from_slice(&value.to_le_bytes());
+    }
+    Ok(out)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn delta_forward_u16_avx2(samples: &[u16], channels: usize, out: &mut [u16]) {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    let mut i = channels;
+    while i + 16 <= samples.len() {
+        let cur = unsafe { _mm256_loadu_si256(samples.as_ptr().add(i) as *const __m256i) };
+        let prev = unsafe { _mm256_loadu_si256(samples.as_ptr().add(i - channels) as *const __m256i) };
+        let delta = _mm256_sub_epi16(cur, prev);
+        unsafe { _mm256_storeu_si256(out.as_mut_ptr().add(i) as *mut __m256i, delta) };
+        i += 16;
+    }
+
+    while i < samples.len() {
+        out[i] = samples[i].wrapping_sub(samples[i - channels]);
+        i += 1;
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn predictive_forward_u16_avx2(samples: &[u16], channels: usize, out: &mut [u16]) {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    let mut i = channels * 2;
+    while i + 16 <= samples.len() {
+        let cur = unsafe { _mm256_loadu_si256(samples.as_ptr().add(i) as *const __m256i) };
+        let prev = unsafe { _mm256_loadu_si256(samples.as_ptr().add(i - channels) as *const __m256i) };
+        let prev_prev =
+            unsafe { _mm256_loadu_si256(samples.as_ptr().add(i - channels * 2) as *const __m256i) };
+        let diff = _mm256_sub_epi16(prev, prev_prev);
+        let predicted = _mm256_add_epi16(prev, diff);
+        let residual = _mm256_sub_epi16(cur, predicted);
+        unsafe { _mm256_storeu_si256(out.as_mut_ptr().add(i) as *mut __m256i, residual) };
+        i += 16;
+    }
+
+    while i < samples.len() {
+        let prev = samples[i - channels];
+        let prev_prev = samples[i - channels * 2];
+        let predicted = prev.wrapping_add(prev.wrapping_sub(prev_prev));
+        out[i] = samples[i].wrapping_sub(predicted);
+        i += 1;
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn shuffle_u16_bytes_avx2(data: &[u8], out: &mut [u8]) {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    let samples = data.len() / 2;
+    let low_mask = _mm_setr_epi8(0, 2, 4, 6, 8, 10, 12, 14, -1, -1, -1, -1, -1, -1, -1, -1);
+    let high_mask = _mm_setr_epi8(1, 3, 5, 7, 9, 11, 13, 15, -1, -1, -1, -1, -1, -1, -1, -1);
+
+    let mut sample_idx = 0usize;
+    while sample_idx + 16 <= samples {
+        let byte_idx = sample_idx * 2;
+        let lo = unsafe { _mm_loadu_si128(data.as_ptr().add(byte_idx) as *const __m128i) };
+        let hi = unsafe { _mm_loadu_si128(data.as_ptr().add(byte_idx + 16) as *const __m128i) };
+        let lo_bytes_lo = _mm_shuffle_epi8(lo, low_mask);
+        let lo_bytes_hi = _mm_shuffle_epi8(hi, low_mask);
+        let hi_bytes_lo = _mm_shuffle_epi8(lo, high_mask);
+        let hi_bytes_hi = _mm_shuffle_epi8(hi, high_mask);
+        let lows = _mm_unpacklo_epi64(lo_bytes_lo, lo_bytes_hi);
+        let highs = _mm_unpacklo_epi64(hi_bytes_lo, hi_bytes_hi);
+        unsafe { _mm_storeu_si128(out.as_mut_ptr().add(sample_idx) as *mut __m128i, lows) };
+        unsafe {
+            _mm_storeu_si128(out.as_mut_ptr().add(samples + sample_idx) as *mut __m128i, highs)
+        };
+        sample_idx += 16;
+    }
+
+    while sample_idx < samples {
+        out[sample_idx] = data[sample_idx * 2];
+        out[samples + sample_idx] = data[sample_idx * 2 + 1];
+        sample_idx += 1;
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn unshuffle_u16_bytes_avx2(data: &[u8], out: &mut [u8]) {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    let samples = data.len() / 2;
+    let mut sample_idx = 0usize;
+    while sample_idx + 16 <= samples {
+        let lows = unsafe { _mm_loadu_si128(data.as_ptr().add(sample_idx) as *const __m128i) };
+        let highs =
+            unsafe { _mm_loadu_si128(data.as_ptr().add(samples + sample_idx) as *const __m128i) };
+        let first = _mm_unpacklo_epi8(lows, highs);
+        let second = _mm_unpackhi_epi8(lows, highs);
+        unsafe { _mm_storeu_si128(out.as_mut_ptr().add(sample_idx * 2) as *mut __m128i, first) };
+        unsafe {
+            _mm_storeu_si128(out.as_mut_ptr().add(sample_idx * 2 + 16) as *mut __m128i, second)
+        };
+        sample_idx += 16;
+    }
+
+    while sample_idx < samples {
+        out[sample_idx * 2] = data[sample_idx];
+        out[sample_idx * 2 + 1] = data[samples + sample_idx];
+        sample_idx += 1;
+    }
+}
+
+fn residual_score_u16(data: &[u8], warmup_samples: usize) -> Result<u64> {
+    let residuals = bytes_to_u16_vec(data)?;
+    let residuals = residuals.get(warmup_samples..).unwrap_or(&[]);
+    Ok(sum_abs_u16_residuals(residuals))
+}
+
+fn sum_abs_u16_residuals(values: &[u16]) -> u64 {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if std::is_x86_feature_detected!("avx2") {
+            // AVX2 gives us a cheap residual score used by the adaptive codec selector.
+            unsafe {
+                return sum_abs_u16_residuals_avx2(values);
+            }
+        }
+    }
+    values
+        .iter()
+        .map(|&value| (value as i16 as i32).unsigned_abs() as u64)
+        .sum()
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn sum_abs_u16_residuals_avx2(values: &[u16]) -> u64 {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    let mut acc = _mm256_setzero_si256();
+    let zero = _mm256_setzero_si256();
+    let mut chunks = values.chunks_exact(16);
+    for chunk in &mut chunks {
+        let vec = unsafe { _mm256_loadu_si256(chunk.as_ptr() as *const __m256i) };
+        let abs = _mm256_abs_epi16(vec);
+        let lo = _mm256_unpacklo_epi16(abs, zero);
+        let hi = _mm256_unpackhi_epi16(abs, zero);
+        acc = _mm256_add_epi32(acc, lo);
+        acc = _mm256_add_epi32(acc, hi);
+    }
+
+    let mut lanes = [0i32; 8];
+    unsafe { _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, acc) };
+    let mut sum = lanes.iter().map(|&lane| lane as u64).sum::<u64>();
+    for &value in chunks.remainder() {
+        sum += (value as i16 as i32).unsigned_abs() as u64;
+    }
+    sum
+}
+
+fn residual_score_u8(data: &[u8], warmup_samples: usize) -> u64 {
+    sum_abs_u8_residuals(data.get(warmup_samples..).unwrap_or(&[]))
+}
+
+fn sum_abs_u8_residuals(values: &[u8]) -> u64 {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if std::is_x86_feature_detected!("avx2") {
+            unsafe {
+                return sum_abs_u8_residuals_avx2(values);
+            }
+        }
+    }
+    values.iter().map(|&value| (value as i8).unsigned_abs() as u64).sum()
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn sum_abs_u8_residuals_avx2(values: &[u8]) -> u64 {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    // |x| as unsigned bytes, then SAD against zero folds 8 bytes per u64 lane.
+    let mut acc = _mm256_setzero_si256();
+    let zero = _mm256_setzero_si256();
+    let mut chunks = values.chunks_exact(32);
+    for chunk in &mut chunks {
+        let vec = unsafe { _mm256_loadu_si256(chunk.as_ptr() as *const __m256i) };
+        acc = _mm256_add_epi64(acc, _mm256_sad_epu8(_mm256_abs_epi8(vec), zero));
+    }
+
+    let mut lanes = [0u64; 4];
+    unsafe { _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, acc) };
+    let mut sum = lanes.iter().sum::<u64>();
+    for &value in chunks.remainder() {
+        sum += (value as i8).unsigned_abs() as u64;
+    }
+    sum
+}
+
+fn lz77_compress(data: &[u8]) -> Result<Vec<u8>> {
+    const WINDOW: usize = 4096;
+    const MIN_MATCH: usize = 3;
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0usize;
+    while i < data.len() {
+        let start = i.saturating_sub(WINDOW);
+        let (mut best_len, mut best_dist) = (0usize, 0usize);
+        for j in start..i {
+            let mut l = 0usize;
+            while i + l < data.len() && j + l < i && data[j + l] == data[i + l] && l < 255 {
+                l += 1;
+            }
+            if l >= MIN_MATCH && l > best_len {
+                best_len = l;
+                best_dist = i - j;
+                if best_len == 255 {
+                    break;
+                }
+            }
+        }
+        if best_len >= MIN_MATCH {
+            out.push(0xFF);
+            out.push(TAG_LZ77);
+            out.push((best_dist >> 8) as u8);
+            out.push((best_dist & 0xFF) as u8);
+            out.push(best_len as u8);
+            i += best_len;
+        } else {
+            let b = data[i];
+            if b == 0xFF {
+                out.push(0xFF);
+                out.push(TAG_ESCAPE_FF);
+            } else {
+                out.push(b);
+            }
+            i += 1;
+        }
+    }
+    Ok(out)
+}
+
+fn lz77_decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0usize;
+    while i < data.len() {
+        let b = data[i];
+        i += 1;
+        if b != 0xFF {
+            out.push(b);
+            continue;
+        }
+        ensure!(i < data.len(), "LZ77: truncated after 0xFF");
+        let tag = data[i];
+        i += 1;
+        match tag {
+            TAG_ESCAPE_FF => out.push(0xFF),
+            TAG_RLE => {
+                ensure!(i + 1 < data.len(), "LZ77: RLE tuple truncated");
+                let count = data[i] as usize;
+                let val = data[i + 1];
+                i += 2;
+                out.extend(std::iter::repeat_n(val, count));
+            }
+            TAG_LZ77 => {
+                ensure!(i + 2 < data.len(), "LZ77: backref truncated");
+                let dist = ((data[i] as usize) << 8) | (data[i + 1] as usize);
+                let len = data[i + 2] as usize;
+                i += 3;
+                ensure!(dist > 0, "LZ77: distance zero");
+                ensure!(len >= 3, "LZ77: len too small");
+                ensure!(dist <= out.len(), "LZ77: distance beyond buffer");
+                let start = out.len() - dist;
+                for k in 0..len {
+                    let byte = out[start + k];
+                    out.push(byte);
+                }
+            }
+            _ => bail!("LZ77: unknown tag {}", tag),
+        }
+    }
+    Ok(out)
+}
+
+const DCT_BLOCK: usize = 8;
+const DCT_FIXED_SHIFT: u32 = 24;
+/// Bound on a dequantized coefficient. Both passes of `dct_inverse_block` sum
+/// 8 products with basis values below 2^11, so the result stays below
+/// 2^(32 + 2 * 14) = 2^60 and fits i64. Real coefficients stay under 2^22.
+const DCT_MAX_DEQUANTIZED: i64 = 1 << 32;
+
+// round(4096 * a(u) * cos((2x + 1) * u * pi / 16)), orthonormal 8-point DCT-II basis.
+// Kept as integers so every platform reconstructs lossy tiles bit-exactly
+// and the stored CRC stays valid.
+const DCT_BASIS: [[i64; DCT_BLOCK]; DCT_BLOCK] = [
+    [1448, 1448, 1448, 1448, 1448, 1448, 1448, 1448],
+    [2009, 1703, 1138, 400, -400, -1138, -1703, -2009],
+    [1892, 784, -784, -1892, -1892, -784, 784, 1892],
+    [1703, -400, -2009, -1138, 1138, 2009, 400, -1703],
+    [1448, -1448, -1448, 1448, 1448, -1448, -1448, 1448],
+    [1138, -2009, 400, 1703, -1703, -400, 2009, -1138],
+    [784, -1892, 1892, -784, -784, 1892, -1892, 784],
+    [400, -1138, 1703, -2009, 2009, -1703, 1138, -400],
+];
+
+const DCT_ZIGZAG: [u8; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21,
+    28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61,
+    54, 47, 55, 62, 63,
+];
+
+// JPEG Annex K reference tables, natural (row-major) order.
+const DCT_LUMA_QUANT: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56, 14, 17, 22, 29, 51,
+    87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113, 92, 49, 64, 78, 87, 103, 121, 120, 101,
+    72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+const DCT_CHROMA_QUANT: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99, 47, 66, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99,
+];
+
+/// Lossy tile codec: YCoCg-R for RGB, 8x8 integer DCT per plane, JPEG-style
+/// quantization scaled by `quality` (1..=100), varint coefficients + Zstd.
+fn dct_compress(
+    data: &[u8],
+    color_type: u8,
+    tile_extent: (u32, u32),
+    quality: u8,
+    zstd_level: i32,
+) -> Result<Vec<u8>> {
+    let (channels, sample_bytes) = sample_layout(color_type_from_id(color_type)?)?;
+    let (w, h) = (tile_extent.0 as usize, tile_extent.1 as usize);
+    ensure!(data.len() == w * h * channels * sample_bytes, "DCT tile size mismatch");
+
+    let planes = dct_planes_forward(data, w * h, channels, sample_bytes);
+    let mut coeffs = Vec::with_capacity(data.len());
+    for (plane_idx, plane) in planes.iter().enumerate() {
+        let quant = dct_quant_table(dct_plane_is_chroma(plane_idx, channels), quality, sample_bytes);
+        for by in (0..h).step_by(DCT_BLOCK) {
+            for bx in (0..w).step_by(DCT_BLOCK) {
+                let mut block = [0i64; 64];
+                for y in 0..DCT_BLOCK {
+                    let sy = (by + y).min(h - 1);
+                    for x in 0..DCT_BLOCK {
+                        block[y * DCT_BLOCK + x] = plane[sy * w + (bx + x).min(w - 1)];
+                    }
+                }
+                let freq = dct_forward_block(&block);
+                let mut zz = [0i64; 64];
+                for (k, &pos) in DCT_ZIGZAG.iter().enumerate() {
+                    let step = (quant[pos as usize] as i64) << DCT_FIXED_SHIFT;
+                    zz[k] = div_round_i64(freq[pos as usize], step);
+                }
+                let used = zz.iter().rposition(|&c| c != 0).map_or(0, |p| p + 1);
+                write_varint(&mut coeffs, used as u64);
+                for &c in &zz[..used] {
+                    write_varint(&mut coeffs, ((c << 1) ^ (c >> 63)) as u64);
+                }
+            }
+        }
+    }
+    Ok(zstd::bulk::compress(&coeffs, zstd_level)?)
+}
+
+fn dct_decompress(comp: &[u8], color_type: u8, tile_extent: (u32, u32), quality: u8) -> Result<Vec<u8>> {
+    let (channels, sample_bytes) = sample_layout(color_type_from_id(color_type)?)?;
+    let (w, h) = (tile_extent.0 as usize, tile_extent.1 as usize);
+    // Per block: a coefficient count byte and up to 64 coefficients, whose
+    // dequantized bound keeps each zigzag varint within 5 bytes.
+    let blocks = w.div_ceil(DCT_BLOCK) * h.div_ceil(DCT_BLOCK) * channels;
+    let coeffs = zstd::bulk::decompress(comp, blocks * (1 + 64 * 5))
+        .map_err(|e| CtiError::Corrupt(format!("zstd decompress failed: {e}")))?;
+
+    let mut pos = 0usize;
+    let mut planes = vec![vec![0i64; w * h]; channels];
+    for (plane_idx, plane) in planes.iter_mut().enumerate() {
+        let quant = dct_quant_table(dct_plane_is_chroma(plane_idx, channels), quality, sample_bytes);
+        for by in (0..h).step_by(DCT_BLOCK) {
+            for bx in (0..w).step_by(DCT_BLOCK) {
+                let used = read_varint(&coeffs, &mut pos)? as usize;
+                ensure!(used <= 64, "Corrupt DCT block");
+                let mut freq = [0i64; 64];
+                for &zz_pos in &DCT_ZIGZAG[..used] {
+                    let v = read_varint(&coeffs, &mut pos)?;
+                    let c = (v >> 1) as i64 ^ -((v & 1) as i64);
+                    freq[zz_pos as usize] = c
+                        .checked_mul(quant[zz_pos as usize] as i64)
+                        .filter(|f| f.abs() < DCT_MAX_DEQUANTIZED)
+                        .ok_or_else(|| CtiError::Corrupt("Corrupt DCT coefficient".into()))?;
+                }
+                let block = dct_inverse_block(&freq);
+                for y in 0..DCT_BLOCK.min(h - by) {
+                    for x in 0..DCT_BLOCK.min(w - bx) {
+                        plane[(by + y) * w + bx + x] = block[y * DCT_BLOCK + x];
+                    }
+                }
+            }
+        }
+    }
+    ensure!(pos == coeffs.len(), "Trailing bytes in DCT tile");
+    Ok(dct_planes_inverse(&planes, w * h, channels, sample_bytes))
+}
+
+fn dct_plane_is_chroma(plane_idx: usize, channels: usize) -> bool {
+    channels >= 3 && (plane_idx == 1 || plane_idx == 2)
+}
+
+fn dct_quant_table(chroma: bool, quality: u8, sample_bytes: usize) -> [i32; 64] {
+    let base = if chroma { &DCT_CHROMA_QUANT } else { &DCT_LUMA_QUANT };
+    let q = quality.clamp(1, 100) as i32;
+    let scale = if q < 50 { 5000 / q } else { 200 - 2 * q };
+    let sample_scale = if sample_bytes == 2 { 256 } else { 1 };
+    let mut table = [0i32; 64];
+    for (dst, &b) in table.iter_mut().zip(base.iter()) {
+        *dst = ((b as i32 * scale + 50) / 100).max(1) * sample_scale;
+    }
+    table
+}
+
+/// Splits interleaved samples into level-shifted planes; RGB(A) goes through
+/// the integer YCoCg-R lifting transform first.
+fn dct_planes_forward(data: &[u8], pixels: usize, channels: usize, sample_bytes: usize) -> Vec<Vec<i64>> {
+    let mid = 1i64 << (sample_bytes * 8 - 1);
+    let sample = |i: usize| -> i64 {
+        if sample_bytes == 2 {
+            u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]) as i64
+        } else {
+            data[i] as i64
+        }
+    };
+    let mut planes = vec![Vec::with_capacity(pixels); channels];
+    for p in 0..pixels {
+        let base = p * channels;
+        let mut first = 0;
+        if channels >= 3 {
+            let (r, g, b) = (sample(base), sample(base + 1), sample(base + 2));
+            let co = r - b;
+            let t = b + (co >> 1);
+            let cg = g - t;
+            let y = t + (cg >> 1);
+            planes[0].push(y - mid);
+            planes[1].push(co);
+            planes[2].push(cg);
+            first = 3;
+        }
+        for (c, plane) in planes.iter_mut().enumerate().skip(first) {
+            plane.push(sample(base + c) - mid);
+        }
+    }
+    planes
+}
+
+fn dct_planes_inverse(planes: &[Vec<i64>], pixels: usize, channels: usize, sample_bytes: usize) -> Vec<u8> {
+    let mid = 1i64 << (sample_bytes * 8 - 1);
+    let max = (1i64 << (sample_bytes * 8)) - 1;
+    let mut out = Vec::with_capacity(pixels * channels * sample_bytes);
+    let mut push = |v: i64| {
+        let v = v.clamp(0, max);
+        if sample_bytes == 2 {
+            out.extend_from_slice(&(v as u16).to_le_bytes());
+        } else {
+            out.push(v as u8);
+        }
+    };
+    for p in 0..pixels {
+        let mut first = 0;
+        if channels >= 3 {
+            let (y, co, cg) = (planes[0][p] + mid, planes[1][p], planes[2][p]);
+            let t = y - (cg >> 1);
+            let g = cg + t;
+            let b = t - (co >> 1);
+            let r = b + co;
+            push(r);
+            push(g);
+            push(b);
+            first = 3;
+        }
+        for plane in &planes[first..] {
+            push(plane[p] + mid);
+        }
+    }
+    out
+}
+
+fn dct_forward_block(block: &[i64; 64]) -> [i64; 64] {
+    let mut rows = [0i64; 64];
+    for y in 0..DCT_BLOCK {
+        for u in 0..DCT_BLOCK {
+            rows[y * DCT_BLOCK + u] = (0..DCT_BLOCK).map(|x| DCT_BASIS[u][x] * block[y * DCT_BLOCK + x]).sum();
+        }
+    }
+    let mut out = [0i64; 64];
+    for v in 0..DCT_BLOCK {
+        for u in 0..DCT_BLOCK {
+            out[v * DCT_BLOCK + u] = (0..DCT_BLOCK).map(|y| DCT_BASIS[v][y] * rows[y * DCT_BLOCK + u]).sum();
+        }
+    }
+    out
+}
+
+fn dct_inverse_block(freq: &[i64; 64]) -> [i64; 64] {
+    let mut cols = [0i64; 64];
+    for y in 0..DCT_BLOCK {
+        for u in 0..DCT_BLOCK {
+            cols[y * DCT_BLOCK + u] = (0..DCT_BLOCK).map(|v| DCT_BASIS[v][y] * freq[v * DCT_BLOCK + u]).sum();
+        }
+    }
+    let mut out = [0i64; 64];
+    for y in 0..DCT_BLOCK {
+        for x in 0..DCT_BLOCK {
+            let acc: i64 = (0..DCT_BLOCK).map(|u| DCT_BASIS[u][x] * cols[y * DCT_BLOCK + u]).sum();
+            out[y * DCT_BLOCK + x] = div_round_i64(acc, 1 << DCT_FIXED_SHIFT);
+        }
+    }
+    out
+}
+
+fn div_round_i64(n: i64, d: i64) -> i64 {
+    if n >= 0 {
+        (n + d / 2) / d
+    } else {
+        -((-n + d / 2) / d)
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos).context("Truncated varint")?;
+        *pos += 1;
+        v |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    bail!("Varint too long")
+}
+
+/// Near-lossless tile predictors, mirroring the lossless ones. `Gradient`
+/// follows `gradient_forward_u16`: left neighbour on the first row, top on
+/// the first column and the JPEG-LS MED predictor (`jpegls_predict_u16`)
+/// elsewhere, so it also covers MED. `Predictive` extrapolates linearly from
+/// the two previous samples of the channel like `predictive_forward_u16`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NearLosslessPredictor {
+    Gradient = 0,
+    Predictive = 1,
+}
+
+impl NearLosslessPredictor {
+    const ALL: [Self; 2] = [Self::Gradient, Self::Predictive];
+
+    fn from_id(id: u8) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|p| *p as u8 == id)
+            .ok_or_else(|| CtiError::Corrupt(format!("Unknown near-lossless predictor {id}")))
+    }
+}
+
+/// `NLER` version byte: tiles start with their predictor id. Files with the
+/// 2-byte `NLER` of the first version use `Gradient` without the byte.
+const NLER_VERSION_TAGGED_PREDICTOR: u8 = 1;
+
+/// JPEG-LS style near-lossless coding: prediction from reconstructed
+/// neighbours, residuals quantized to `2 * max_error + 1` steps so every
+/// reconstructed sample is within `max_error` of the source. Each predictor is
+/// tried and the smallest tile is kept, prefixed with the predictor id.
+fn near_lossless_compress(
+    data: &[u8],
+    color_type: u8,
+    tile_extent: (u32, u32),
+    max_error: u16,
+    zstd_level: i32,
+) -> Result<Vec<u8>> {
+    let (channels, sample_bytes) = sample_layout(color_type_from_id(color_type)?)?;
+    let samples = samples_to_u32(data, sample_bytes);
+    let row_stride = tile_extent.0 as usize * channels;
+    ensure!(samples.len() == row_stride * tile_extent.1 as usize, "Near-lossless tile size mismatch");
+
+    let near = max_error as i64;
+    let step = 2 * near + 1;
+    let max = (1i64 << (sample_bytes * 8)) - 1;
+    let mut best: Option<Vec<u8>> = None;
+    for predictor in NearLosslessPredictor::ALL {
+        let mut recon = vec![0u32; samples.len()];
+        let mut residuals = Vec::with_capacity(data.len());
+        for i in 0..samples.len() {
+            let pred = near_lossless_predict(predictor, &recon, i, row_stride, channels, sample_bytes) as i64;
+            let err = samples[i] as i64 - pred;
+            let q = if err >= 0 { (err + near) / step } else { -((near - err) / step) };
+            recon[i] = (pred + q * step).clamp(0, max) as u32;
+            write_varint(&mut residuals, ((q << 1) ^ (q >> 63)) as u64);
+        }
+        let mut comp = vec![predictor as u8];
+        comp.extend_from_slice(&zstd::bulk::compress(&residuals, zstd_level)?);
+        if best.as_ref().is_none_or(|b| comp.len() < b.len()) {
+            best = Some(comp);
+        }
+    }
+    Ok(best.expect("at least one predictor"))
+}
+
+/// `tagged` is false for tiles of version-1 files, which carry no predictor id.
+fn near_lossless_decompress(
+    comp: &[u8],
+    color_type: u8,
+    tile_extent: (u32, u32),
+    max_error: u16,
+    tagged: bool,
+) -> Result<Vec<u8>> {
+    let (channels, sample_bytes) = sample_layout(color_type_from_id(color_type)?)?;
+    let (predictor, comp) = match comp.split_first() {
+        Some((&id, rest)) if tagged => (NearLosslessPredictor::from_id(id)?, rest),
+        _ => (NearLosslessPredictor::Gradient, comp),
+    };
+    let row_stride = tile_extent.0 as usize * channels;
+    let total = row_stride * tile_extent.1 as usize;
+    // Residuals are bounded by the sample range, so each zigzag varint takes at most 3 bytes.
+    let residuals = zstd::bulk::decompress(comp, total * 3)
+        .map_err(|e| CtiError::Corrupt(format!("zstd decompress failed: {e}")))?;
+
+    let step = 2 * max_error as i64 + 1;
+    let max = (1i64 << (sample_bytes * 8)) - 1;
+    let mut recon = vec![0u32; total];
+    let mut pos = 0usize;
+    for i in 0..total {
+        let pred = near_lossless_predict(predictor, &recon, i, row_stride, channels, sample_bytes) as i64;
+        let v = read_varint(&residuals, &mut pos)?;
+        let q = (v >> 1) as i64 ^ -((v & 1) as i64);
+        ensure!(q.abs() <= max, "Corrupt near-lossless residual");
+        recon[i] = (pred + q * step).clamp(0, max) as u32;
+    }
+    ensure!(pos == residuals.len(), "Trailing bytes in near-lossless tile");
+    Ok(u32_samples_to_bytes(&recon, sample_bytes))
+}
+
+fn near_lossless_predict(
+    predictor: NearLosslessPredictor,
+    recon: &[u32],
+    i: usize,
+    row_stride: usize,
+    channels: usize,
+    sample_bytes: usize,
+) -> u32 {
+    let mid = 1 << (sample_bytes * 8 - 1);
+    if predictor == NearLosslessPredictor::Predictive {
+        return if i < channels {
+            mid
+        } else if i < channels * 2 {
+            recon[i - channels]
+        } else {
+            let max = (1i64 << (sample_bytes * 8)) - 1;
+            (2 * recon[i - channels] as i64 - recon[i - channels * 2] as i64).clamp(0, max) as u32
+        };
+    }
+    let row_offset = i % row_stride;
+    if i < row_stride {
+        if row_offset < channels {
+            mid
+        } else {
+            recon[i - channels]
+        }
+    } else if row_offset < channels {
+        recon[i - row_stride]
+    } else {
+        let left = recon[i - channels] as u16;
+        let top = recon[i - row_stride] as u16;
+        let top_left = recon[i - row_stride - channels] as u16;
+        jpegls_predict_u16(left, top, top_left) as u32
+    }
+}
+
+fn samples_to_u32(data: &[u8], sample_bytes: usize) -> Vec<u32> {
+    if sample_bytes == 2 {
+        data.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]]) as u32).collect()
+    } else {
+        data.iter().map(|&b| b as u32).collect()
+    }
+}
+
+fn u32_samples_to_bytes(samples: &[u32], sample_bytes: usize) -> Vec<u8> {
+    if sample_bytes == 2 {
+        samples.iter().flat_map(|&v| (v as u16).to_le_bytes()).collect()
+    } else {
+        samples.iter().map(|&v| v as u8).collect()
+    }
+}
+
+const BILEVEL_CONTEXT_BITS: usize = 12;
+const BILEVEL_PROB_BITS: u32 = 11;
+const BILEVEL_PROB_ADAPT_SHIFT: u32 = 5;
+
+/// Bilevel tiles are coded pixel by pixel with an adaptive binary range
+/// coder, JBIG style: the probability is picked by 12 already coded
+/// neighbours (three pixels two rows up, five one row up, four to the left).
+/// Pixels outside the tile count as black, so tiles decode independently.
+fn bilevel_compress(data: &[u8], tile_extent: (u32, u32)) -> Result<Vec<u8>> {
+    let width = tile_extent.0 as usize;
+    ensure!(
+        data.len() == width * tile_extent.1 as usize,
+        "bilevel tile has {} bytes, expected {}x{}",
+        data.len(),
+        tile_extent.0,
+        tile_extent.1
+    );
+    let bits = data
+        .iter()
+        .map(|&v| match v {
+            0 => Ok(0u8),
+            255 => Ok(1u8),
+            other => Err(CtiError::InvalidInput(format!("L1 samples must be 0 or 255, got {}", other))),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut probs = vec![1u16 << (BILEVEL_PROB_BITS - 1); 1 << BILEVEL_CONTEXT_BITS];
+    let mut coder = BinaryRangeEncoder::new();
+    for (i, &bit) in bits.iter().enumerate() {
+        let ctx = bilevel_context(&bits, width, i % width, i / width);
+        coder.encode(&mut probs[ctx], bit);
+    }
+    Ok(coder.finish())
+}
+
+fn bilevel_decompress(comp: &[u8], tile_extent: (u32, u32)) -> Result<Vec<u8>> {
+    let width = tile_extent.0 as usize;
+    let mut bits = vec![0u8; width * tile_extent.1 as usize];
+    let mut probs = vec![1u16 << (BILEVEL_PROB_BITS - 1); 1 << BILEVEL_CONTEXT_BITS];
+    let mut coder = BinaryRangeDecoder::new(comp)?;
+    for i in 0..bits.len() {
+        let ctx = bilevel_context(&bits, width, i % width, i / width);
+        bits[i] = coder.decode(&mut probs[ctx]);
+    }
+    Ok(bits.into_iter().map(|bit| bit * 255).collect())
+}
+
+fn bilevel_context(bits: &[u8], width: usize, x: usize, y: usize) -> usize {
+    let pixel = |dx: isize, dy: usize| -> usize {
+        let sx = x as isize + dx;
+        if y < dy || sx < 0 || sx >= width as isize {
+            0
+        } else {
+            bits[(y - dy) * width + sx as usize] as usize
+        }
+    };
+    let mut ctx = 0;
+    for dx in -1..=1 {
+        ctx = ctx << 1 | pixel(dx, 2);
+    }
+    for dx in -2..=2 {
+        ctx = ctx << 1 | pixel(dx, 1);
+    }
+    for dx in -4..=-1 {
+        ctx = ctx << 1 | pixel(dx, 0);
+    }
+    ctx
+}
+
+/// Ties go to black, so thin strokes survive on bilevel pyramid levels.
+fn bilevel_from_average(average: u8) -> u8 {
+    if average > 127 { 255 } else { 0 }
+}
+
+/// LZMA-style binary range coder with 11-bit adaptive probabilities of a 0 bit.
+struct BinaryRangeEncoder {
+    low: u64,
+    range: u32,
+    cache: u8,
+    cache_size: u64,
+    out: Vec<u8>,
+}
+
+impl BinaryRangeEncoder {
+    fn new() -> Self {
+        Self {
+            low: 0,
+            range: u32::MAX,
+            cache: 0,
+            cache_size: 1,
+            out: Vec::new(),
+        }
+    }
+
+    fn encode(&mut self, prob: &mut u16, bit: u8) {
+        let bound = (self.range >> BILEVEL_PROB_BITS) * *prob as u32;
+        if bit == 0 {
+            self.range = bound;
+            *prob += ((1 << BILEVEL_PROB_BITS) - *prob) >> BILEVEL_PROB_ADAPT_SHIFT;
+        } else {
+            self.low += bound as u64;
+            self.range -= bound;
+            *prob -= *prob >> BILEVEL_PROB_ADAPT_SHIFT;
+        }
+        while self.range < 1 << 24 {
+            self.range <<= 8;
+            self.shift_low();
+        }
+    }
+
+    fn shift_low(&mut self) {
+        if self.low < 0xFF00_0000 || self.low > u32::MAX as u64 {
+            let carry = (self.low >> 32) as u8;
+            let mut byte = self.cache;
+            while self.cache_size > 0 {
+                self.out.push(byte.wrapping_add(carry));
+                byte = 0xFF;
+                self.cache_size -= 1;
+            }
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.cache_size += 1;
+        self.low = (self.low & 0x00FF_FFFF) << 8;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for _ in 0..5 {
+            self.shift_low();
+        }
+        self.out
+    }
+}
+
+struct BinaryRangeDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    range: u32,
+    code: u32,
+}
+
+impl<'a> BinaryRangeDecoder<'a> {
+    fn new(data: &'a [u8]) -> Result<Self> {
+        ensure!(data.len() >= 5, "bilevel payload too short");
+        let mut decoder = Self {
+            data,
+            pos: 0,
+            range: u32::MAX,
+            code: 0,
+        };
+        for _ in 0..5 {
+            decoder.code = decoder.code << 8 | decoder.next_byte() as u32;
+        }
+        Ok(decoder)
+    }
+
+    /// Past the end of a truncated payload the decoder reads zeros; the tile
+    /// CRC catches the damage.
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.data.get(self.pos).copied().unwrap_or(0);
+        self.pos += 1;
+        byte
+    }
+
+    fn decode(&mut self, prob: &mut u16) -> u8 {
+        let bound = (self.range >> BILEVEL_PROB_BITS) * *prob as u32;
+        let bit = if self.code < bound {
+            self.range = bound;
+            *prob += ((1 << BILEVEL_PROB_BITS) - *prob) >> BILEVEL_PROB_ADAPT_SHIFT;
+            0
+        } else {
+            self.code -= bound;
+            self.range -= bound;
+            *prob -= *prob >> BILEVEL_PROB_ADAPT_SHIFT;
+            1
+        };
+        while self.range < 1 << 24 {
+            self.range <<= 8;
+            self.code = self.code << 8 | self.next_byte() as u32;
+        }
+        bit
+    }
+}
+
+const WAVELET53_LEVELS: usize = 5;
+
+/// Reversible multi-level 2D 5/3 lifting transform (JPEG 2000 style) on each
+/// channel plane. Arithmetic wraps modulo the sample width and the predict
+/// step averages neighbours through their signed difference, so samples that
+/// were already wrapped by RCT / `Rgb16DeltaG` stay cheap to code and the
+/// output keeps the input size. Output is planar; detail bands are zig-zag
+/// mapped and 16-bit coefficients are byte-shuffled.
+fn wavelet53_forward(data: &[u8], width: usize, channels: usize, sample_bytes: usize) -> Result<Vec<u8>> {
+    let bits = (sample_bytes * 8) as u32;
+    let samples = samples_to_u32(data, sample_bytes);
+    ensure!(width > 0 && samples.len().is_multiple_of(width * channels), "wavelet tile size does not match width");
+    let height = samples.len() / (width * channels);
+
+    let mut planar = Vec::with_capacity(samples.len());
+    for c in 0..channels {
+        let mut plane = samples.iter().skip(c).step_by(channels).copied().collect::<Vec<_>>();
+        let levels = wavelet53_levels(width, height);
+        for &(w, h) in &levels {
+            wavelet53_pass_2d(&mut plane, width, w, h, bits, false);
+        }
+        let (ll_w, ll_h) = wavelet53_ll_extent(&levels, width, height);
+        for (i, v) in plane.iter_mut().enumerate() {
+            if i % width >= ll_w || i / width >= ll_h {
+                let s = ring_signed(*v, bits);
+                *v = ring_wrap((s << 1) ^ (s >> 63), bits);
+            }
+        }
+        planar.extend_from_slice(&plane);
+    }
+
+    let bytes = u32_samples_to_bytes(&planar, sample_bytes);
+    if sample_bytes == 2 {
+        shuffle_u16_bytes(&bytes)
+    } else {
+        Ok(bytes)
+    }
+}
+
+fn wavelet53_inverse(data: &[u8], width: usize, channels: usize, sample_bytes: usize) -> Result<Vec<u8>> {
+    let bits = (sample_bytes * 8) as u32;
+    let bytes = if sample_bytes == 2 { unshuffle_u16_bytes(data)? } else { data.to_vec() };
+    let planar = samples_to_u32(&bytes, sample_bytes);
+    ensure!(width > 0 && planar.len().is_multiple_of(width * channels), "wavelet tile size does not match width");
+    let plane_len = planar.len() / channels;
+    let height = plane_len / width;
+
+    let mut samples = vec![0u32; planar.len()];
+    for (c, plane) in planar.chunks_exact(plane_len.max(1)).enumerate() {
+        let mut plane = plane.to_vec();
+        let levels = wavelet53_levels(width, height);
+        let (ll_w, ll_h) = wavelet53_ll_extent(&levels, width, height);
+        for (i, v) in plane.iter_mut().enumerate() {
+            if i % width >= ll_w || i / width >= ll_h {
+                let z = *v as i64;
+                *v = ring_wrap((z >> 1) ^ -(z & 1), bits);
+            }
+        }
+        for &(w, h) in levels.iter().rev() {
+            wavelet53_pass_2d(&mut plane, width, w, h, bits, true);
+        }
+        for (i, v) in plane.into_iter().enumerate() {
+            samples[i * channels + c] = v;
+        }
+    }
+    Ok(u32_samples_to_bytes(&samples, sample_bytes))
+}
+
+fn wavelet53_levels(width: usize, height: usize) -> Vec<(usize, usize)> {
+    let mut levels = Vec::new();
+    let (mut w, mut h) = (width, height);
+    while levels.len() < WAVELET53_LEVELS && (w >= 2 || h >= 2) {
+        levels.push((w, h));
+        w = w.div_ceil(2);
+        h = h.div_ceil(2);
+    }
+    levels
+}
+
+fn wavelet53_ll_extent(levels: &[(usize, usize)], width: usize, height: usize) -> (usize, usize) {
+    levels
+        .last()
+        .map_or((width, height), |&(w, h)| (w.div_ceil(2), h.div_ceil(2)))
+}
+
+/// One decomposition level over the top-left `w x h` region: rows then
+/// columns forward, columns then rows inverse.
+fn wavelet53_pass_2d(plane: &mut [u32], stride: usize, w: usize, h: usize, bits: u32, inverse: bool) {
+    let mut line = Vec::with_capacity(w.max(h));
+    let mut scratch = Vec::with_capacity(w.max(h));
+    let rows = |plane: &mut [u32], line: &mut Vec<u32>, scratch: &mut Vec<u32>| {
+        for y in 0..h {
+            let row = &mut plane[y * stride..y * stride + w];
+            line.clear();
+            line.extend_from_slice(row);
+            lift53(line, scratch, bits, inverse);
+            row.copy_from_slice(line);
+        }
+    };
+    let cols = |plane: &mut [u32], line: &mut Vec<u32>, scratch: &mut Vec<u32>| {
+        for x in 0..w {
+            line.clear();
+            line.extend((0..h).map(|y| plane[y * stride + x]));
+            lift53(line, scratch, bits, inverse);
+            for (y, &v) in line.iter().enumerate() {
+                plane[y * stride + x] = v;
+            }
+        }
+    };
+    if inverse {
+        cols(plane, &mut line, &mut scratch);
+        rows(plane, &mut line, &mut scratch);
+    } else {
+        rows(plane, &mut line, &mut scratch);
+        cols(plane, &mut line, &mut scratch);
+    }
+}
+
+/// 1D 5/3 lifting with symmetric extension. Forward output is
+/// `[low band | high band]`; inverse expects the same layout.
+fn lift53(line: &mut [u32], scratch: &mut Vec<u32>, bits: u32, inverse: bool) {
+    let n = line.len();
+    if n < 2 {
+        return;
+    }
+    let half = n.div_ceil(2);
+    let predict = |x: &[u32], i: usize| {
+        let a = x[i - 1];
+        let b = if i + 1 < n { x[i + 1] } else { x[i - 1] };
+        a as i64 + (ring_signed(ring_wrap(b as i64 - a as i64, bits), bits) >> 1)
+    };
+    let update = |x: &[u32], i: usize| {
+        let d1 = if i > 0 { x[i - 1] } else { x[i + 1] };
+        let d2 = if i + 1 < n { x[i + 1] } else { x[i - 1] };
+        (ring_signed(d1, bits) + ring_signed(d2, bits) + 2) >> 2
+    };
+
+    scratch.clear();
+    if inverse {
+        scratch.resize(n, 0);
+        for i in 0..n {
+            scratch[i] = if i % 2 == 0 { line[i / 2] } else { line[half + i / 2] };
+        }
+        for i in (0..n).step_by(2) {
+            scratch[i] = ring_wrap(scratch[i] as i64 - update(scratch, i), bits);
+        }
+        for i in (1..n).step_by(2) {
+            scratch[i] = ring_wrap(scratch[i] as i64 + predict(scratch, i), bits);
+        }
+        line.copy_from_slice(scratch);
+    } else {
+        for i in (1..n).step_by(2) {
+            line[i] = ring_wrap(line[i] as i64 - predict(line, i), bits);
+        }
+        for i in (0..n).step_by(2) {
+            line[i] = ring_wrap(line[i] as i64 + update(line, i), bits);
+        }
+        scratch.extend(line.iter().step_by(2));
+        scratch.extend(line.iter().skip(1).step_by(2));
+        line.copy_from_slice(scratch);
+    }
+}
+
+fn ring_wrap(v: i64, bits: u32) -> u32 {
+    (v & ((1i64 << bits) - 1)) as u32
+}
+
+fn ring_signed(v: u32, bits: u32) -> i64 {
+    let v = v as i64;
+    if v >= 1 << (bits - 1) {
+        v - (1 << bits)
+    } else {
+        v
+    }
+}
+
+fn rct_forward_rgb8(buf: &mut [u8]) {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if std::is_x86_feature_detected!("avx2") && buf.len() >= 16 * 3 {
+            unsafe {
+                rct_forward_rgb8_avx2(buf);
+            }
+            return;
+        }
+    }
+
+    for p in buf.chunks_exact_mut(3) {
+        let (r, g, b) = (p[0] as i32, p[1] as i32, p[2] as i32);
+        let y = (r + (g << 1) + b) >> 2;
+        let cb = b - g;
+        let cr = r - g;
+        p[0] = y.clamp(0, 255) as u8;
+        p[1] = (cb as i16 as i32 & 0xFF) as u8;
+        p[2] = (cr as i16 as i32 & 0xFF) as u8;
+    }
+}
+
+fn rct_inverse_rgb8(buf: &mut [u8]) {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if std::is_x86_feature_detected!("avx2") && buf.len() >= 16 * 3 {
+            unsafe {
+                rct_inverse_rgb8_avx2(buf);
+            }
+            return;
+        }
+    }
+
+    for p in buf.chunks_exact_mut(3) {
+        let y = p[0] as i32;
+        let cb = (p[1] as i8) as i32;
+        let cr = (p[2] as i8) as i32;
+        let g = y - ((cb + cr) >> 2);
+        let r = cr + g;
+        let b = cb + g;
+        p[0] = r.clamp(0, 255) as u8;
+        p[1] = g.clamp(0, 255) as u8;
+        p[2] = b.clamp(0, 255) as u8;
+    }
+}
+
+fn rct_forward_rgb16(buf: &mut [u8]) {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if std::is_x86_feature_detected!("avx2") && buf.len() >= 16 * 6 {
+            unsafe {
+                rct_forward_rgb16_avx2(buf);
+            }
+            return;
+        }
+    }
+
+    for p in buf.chunks_exact_mut(6) {
+        let r = u16::from_le_bytes([p[0], p[1]]) as i32;
+        let g = u16::from_le_bytes([p[2], p[3]]) as i32;
+        let b = u16::from_le_bytes([p[4], p[5]]) as i32;
+        let y = (r + (g << 1) + b) >> 2;
+        let cb = b - g;
+        let cr = r - g;
+        p[0..2].copy_from_slice(&(y.clamp(0, 65535) as u16).to_le_bytes());
+        p[2..4].copy_from_slice(&((cb & 0xFFFF) as u16).to_le_bytes());
+        p[4..6].copy_from_slice(&((cr & 0xFFFF) as u16).to_le_bytes());
+    }
+}
+
+fn rct_inverse_rgb16(buf: &mut [u8]) {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if std::is_x86_feature_detected!("avx2") && buf.len() >= 16 * 6 {
+            unsafe {
+                rct_inverse_rgb16_avx2(buf);
+            }
+            return;
+        }
+    }
+
+    for p in buf.chunks_exact_mut(6) {
+        let y = u16::from_le_bytes([p[0], p[1]]) as i32;
+        let cb = (u16::from_le_bytes([p[2], p[3]]) as i16) as i32;
+        let cr = (u16::from_le_bytes([p[4], p[5]]) as i16) as i32;
+        let g = y - ((cb + cr) >> 2);
+        let r = cr + g;
+        let b = cb + g;
+        p[0..2].copy_from_slice(&(r.clamp(0, 65535) as u16).to_le_bytes());
+        p[2..4].copy_from_slice(&(g.clamp(0, 65535) as u16).to_le_bytes());
+        p[4..6].copy_from_slice(&(b.clamp(0, 65535) as u16).to_le_bytes());
+    }
+}
+
+fn rgb16_delta_g_forward(buf: &mut [u8]) {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if std::is_x86_feature_detected!("avx2") && buf.len() >= 16 * 6 {
+            unsafe {
+                rgb16_delta_g_forward_avx2(buf);
+            }
+            return;
+        }
+    }
+
+    buf.chunks_exact_mut(6).for_each(delta_g_forward_pixel);
+}
+
+fn delta_g_forward_pixel(p: &mut [u8]) {
+    let r = u16::from_le_bytes([p[0], p[1]]);
+    let g = u16::from_le_bytes([p[2], p[3]]);
+    let b = u16::from_le_bytes([p[4], p[5]]);
+    let dr = r.wrapping_sub(g);
+    let db = b.wrapping_sub(g);
+    p[0..2].copy_from_slice(&g.to_le_bytes());
+    p[2..4].copy_from_slice(&dr.to_le_bytes());
+    p[4..6].copy_from_slice(&db.to_le_bytes());
+}
+
+fn rgb16_delta_g_inverse(buf: &mut [u8]) {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if std::is_x86_feature_detected!("avx2") && buf.len() >= 16 * 6 {
+            unsafe {
+                rgb16_delta_g_inverse_avx2(buf);
+            }
+            return;
+        }
+    }
+
+    buf.chunks_exact_mut(6).for_each(delta_g_inverse_pixel);
+}
+
+fn delta_g_inverse_pixel(p: &mut [u8]) {
+    let g = u16::from_le_bytes([p[0], p[1]]);
+    let dr = u16::from_le_bytes([p[2], p[3]]);
+    let db = u16::from_le_bytes([p[4], p[5]]);
+    let r = g.wrapping_add(dr);
+    let b = g.wrapping_add(db);
+    p[0..2].copy_from_slice(&r.to_le_bytes());
+    p[2..4].copy_from_slice(&g.to_le_bytes());
+    p[4..6].copy_from_slice(&b.to_le_bytes());
+}
+
+const DEFAULT_RGB8_PLANAR_BLOCK_PIXELS: usize = 2048;
+const DEFAULT_RGB16_PLANAR_BLOCK_PIXELS: usize = 512;
+
+fn rgb_planar_block_pixels(default_pixels: usize) -> usize {
+    static RGB_PLANAR_BLOCK_PIXELS_OVERRIDE: OnceLock<Option<usize>> = OnceLock::new();
+    RGB_PLANAR_BLOCK_PIXELS_OVERRIDE
+        .get_or_init(|| {
+        std::env::var("CTI_RGB_PLANAR_BLOCK_PIXELS")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .map(|value| value.clamp(64, 16_384))
+        })
+        .unwrap_or(default_pixels)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn rct_forward_rgb8_avx2(buf: &mut [u8]) {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    let pixels = buf.len() / 3;
+    let block_pixels = rgb_planar_block_pixels(DEFAULT_RGB8_PLANAR_BLOCK_PIXELS);
+    let mut rs = vec![0u8; block_pixels];
+    let mut gs = vec![0u8; block_pixels];
+    let mut bs = vec![0u8; block_pixels];
+    let mut ys = vec![0u8; block_pixels];
+    let mut cbs = vec![0u8; block_pixels];
+    let mut crs = vec![0u8; block_pixels];
+    let low_byte_mask = _mm256_set1_epi16(0x00FF);
+
+    let mut pixel_idx = 0usize;
+    while pixel_idx < pixels {
+        let count = block_pixels.min(pixels - pixel_idx);
+        for lane in 0..count {
+            let base = (pixel_idx + lane) * 3;
+            rs[lane] = buf[base];
+            gs[lane] = buf[base + 1];
+            bs[lane] = buf[base + 2];
+        }
+
+        let mut lane = 0usize;
+        while lane + 16 <= count {
+            let r =
+                unsafe { _mm256_cvtepu8_epi16(_mm_loadu_si128(rs.as_ptr().add(lane) as *const __m128i)) };
+            let g =
+                unsafe { _mm256_cvtepu8_epi16(_mm_loadu_si128(gs.as_ptr().add(lane) as *const __m128i)) };
+            let b =
+                unsafe { _mm256_cvtepu8_epi16(_mm_loadu_si128(bs.as_ptr().add(lane) as *const __m128i)) };
+            let y = _mm256_srli_epi16(
+                _mm256_add_epi16(_mm256_add_epi16(r, _mm256_slli_epi16(g, 1)), b),
+                2,
+            );
+            let cb = _mm256_and_si256(_mm256_sub_epi16(b, g), low_byte_mask);
+            let cr = _mm256_and_si256(_mm256_sub_epi16(r, g), low_byte_mask);
+
+            let y_lo = _mm256_castsi256_si128(y);
+            let y_hi = _mm256_extracti128_si256(y, 1);
+            let cb_lo = _mm256_castsi256_si128(cb);
+            let cb_hi = _mm256_extracti128_si256(cb, 1);
+            let cr_lo = _mm256_castsi256_si128(cr);
+            let cr_hi = _mm256_extracti128_si256(cr, 1);
+            let y8 = _mm_packus_epi16(y_lo, y_hi);
+            let cb8 = _mm_packus_epi16(cb_lo, cb_hi);
+            let cr8 = _mm_packus_epi16(cr_lo, cr_hi);
+
+            unsafe { _mm_storeu_si128(ys.as_mut_ptr().add(lane) as *mut __m128i, y8) };
+            unsafe { _mm_storeu_si128(cbs.as_mut_ptr().add(lane) as *mut __m128i, cb8) };
+            unsafe { _mm_storeu_si128(crs.as_mut_ptr().add(lane) as *mut __m128i, cr8) };
+            lane += 16;
+        }
+
+        while lane < count {
+            let r = rs[lane] as i32;
+            let g = gs[lane] as i32;
+            let b = bs[lane] as i32;
+            let y = (r + (g << 1) + b) >> 2;
+            let cb = b - g;
+            let cr = r - g;
+            ys[lane] = y.clamp(0, 255) as u8;
+            cbs[lane] = (cb as i16 as i32 & 0xFF) as u8;
+            crs[lane] = (cr as i16 as i32 & 0xFF) as u8;
+            lane += 1;
+        }
+
+        for lane in 0..count {
+            let base = (pixel_idx + lane) * 3;
+            buf[base] = ys[lane];
+            buf[base + 1] = cbs[lane];
+            buf[base + 2] = crs[lane];
+        }
+
+        pixel_idx += count;
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn rct_inverse_rgb8_avx2(buf: &mut [u8]) {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    let pixels = buf.len() / 3;
+    let block_pixels = rgb_planar_block_pixels(DEFAULT_RGB8_PLANAR_BLOCK_PIXELS);
+    let mut ys = vec![0u8; block_pixels];
+    let mut cbs = vec![0u8; block_pixels];
+    let mut crs = vec![0u8; block_pixels];
+    let mut rs = vec![0u8; block_pixels];
+    let mut gs = vec![0u8; block_pixels];
+    let mut bs = vec![0u8; block_pixels];
+
+    let mut pixel_idx = 0usize;
+    while pixel_idx < pixels {
+        let count = block_pixels.min(pixels - pixel_idx);
+        for lane in 0..count {
+            let base = (pixel_idx + lane) * 3;
+            ys[lane] = buf[base];
+            cbs[lane] = buf[base + 1];
+            crs[lane] = buf[base + 2];
+        }
+
+        let mut lane = 0usize;
+        while lane + 16 <= count {
+            let y =
+                unsafe { _mm256_cvtepu8_epi16(_mm_loadu_si128(ys.as_ptr().add(lane) as *const __m128i)) };
+            let cb = unsafe {
+                _mm256_cvtepi8_epi16(_mm_loadu_si128(cbs.as_ptr().add(lane) as *const __m128i))
+            };
+            let cr = unsafe {
+                _mm256_cvtepi8_epi16(_mm_loadu_si128(crs.as_ptr().add(lane) as *const __m128i))
+            };
+            let g = _mm256_sub_epi16(y, _mm256_srai_epi16(_mm256_add_epi16(cb, cr), 2));
+            let r = _mm256_add_epi16(cr, g);
+            let b = _mm256_add_epi16(cb, g);
+
+            let r_lo = _mm256_castsi256_si128(r);
+            let r_hi = _mm256_extracti128_si256(r, 1);
+            let g_lo = _mm256_castsi256_si128(g);
+            let g_hi = _mm256_extracti128_si256(g, 1);
+            let b_lo = _mm256_castsi256_si128(b);
+            let b_hi = _mm256_extracti128_si256(b, 1);
+            let r8 = _mm_packus_epi16(r_lo, r_hi);
+            let g8 = _mm_packus_epi16(g_lo, g_hi);
+            let b8 = _mm_packus_epi16(b_lo, b_hi);
+
+            unsafe { _mm_storeu_si128(rs.as_mut_ptr().add(lane) as *mut __m128i, r8) };
+            unsafe { _mm_storeu_si128(gs.as_mut_ptr().add(lane) as *mut __m128i, g8) };
+            unsafe { _mm_storeu_si128(bs.as_mut_ptr().add(lane) as *mut __m128i, b8) };
+            lane += 16;
+        }
+
+        while lane < count {
+            let y = ys[lane] as i32;
+            let cb = (cbs[lane] as i8) as i32;
+            let cr = (crs[lane] as i8) as i32;
+            let g = y - ((cb + cr) >> 2);
+            let r = cr + g;
+            let b = cb + g;
+            rs[lane] = r.clamp(0, 255) as u8;
+            gs[lane] = g.clamp(0, 255) as u8;
+            bs[lane] = b.clamp(0, 255) as u8;
+            lane += 1;
+        }
+
+        for lane in 0..count {
+            let base = (pixel_idx + lane) * 3;
+            buf[base] = rs[lane];
+            buf[base + 1] = gs[lane];
+            buf[base + 2] = bs[lane];
+        }
+
+        pixel_idx += count;
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn rct_forward_rgb16_avx2(buf: &mut [u8]) {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    let pixels = buf.len() / 6;
+    let block_pixels = rgb_planar_block_pixels(DEFAULT_RGB16_PLANAR_BLOCK_PIXELS);
+    let mut c0 = vec![0u16; block_pixels];
+    let mut c1 = vec![0u16; block_pixels];
+    let mut c2 = vec![0u16; block_pixels];
+
+    let mut pixel_idx = 0usize;
+    while pixel_idx < pixels {
+        let count = block_pixels.min(pixels - pixel_idx);
+        for lane in 0..count {
+            let base = (pixel_idx + lane) * 6;
+            c0[lane] = u16::from_le_bytes([buf[base], buf[base + 1]]);
+            c1[lane] = u16::from_le_bytes([buf[base + 2], buf[base + 3]]);
+            c2[lane] = u16::from_le_bytes([buf[base + 4], buf[base + 5]]);
+        }
+
+        let mut lane = 0usize;
+        while lane + 16 <= count {
+            let r = unsafe { _mm256_loadu_si256(c0.as_ptr().add(lane) as *const __m256i) };
+            let g = unsafe { _mm256_loadu_si256(c1.as_ptr().add(lane) as *const __m256i) };
+            let b = unsafe { _mm256_loadu_si256(c2.as_ptr().add(lane) as *const __m256i) };
+            let y = _mm256_srli_epi16(
                 _mm256_add_epi16(_mm256_add_epi16(r, _mm256_slli_epi16(g, 1)), b),
                 2,
             );
@@ -3243,450 +8312,2153 @@ unsafe fn rct_forward_rgb16_avx2(buf: &mut [u8]) {
             lane += 16;
         }
 
-        while lane < count {
-            let r = c0[lane] as i32;
-            let g = c1[lane] as i32;
-            let b = c2[lane] as i32;
-            c0[lane] = ((r + (g << 1) + b) >> 2) as u16;
-            c1[lane] = (b - g) as u16;
-            c2[lane] = (r - g) as u16;
-            lane += 1;
+        while lane < count {
+            let r = c0[lane] as i32;
+            let g = c1[lane] as i32;
+            let b = c2[lane] as i32;
+            c0[lane] = ((r + (g << 1) + b) >> 2) as u16;
+            c1[lane] = (b - g) as u16;
+            c2[lane] = (r - g) as u16;
+            lane += 1;
+        }
+
+        for lane in 0..count {
+            let base = (pixel_idx + lane) * 6;
+            buf[base..base + 2].copy_from_slice(&c0[lane].to_le_bytes());
+            buf[base + 2..base + 4].copy_from_slice(&c1[lane].to_le_bytes());
+            buf[base + 4..base + 6].copy_from_slice(&c2[lane].to_le_bytes());
+        }
+
+        pixel_idx += count;
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn rct_inverse_rgb16_avx2(buf: &mut [u8]) {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    let pixels = buf.len() / 6;
+    let block_pixels = rgb_planar_block_pixels(DEFAULT_RGB16_PLANAR_BLOCK_PIXELS);
+    let mut c0 = vec![0u16; block_pixels];
+    let mut c1 = vec![0u16; block_pixels];
+    let mut c2 = vec![0u16; block_pixels];
+
+    let mut pixel_idx = 0usize;
+    while pixel_idx < pixels {
+        let count = block_pixels.min(pixels - pixel_idx);
+        for lane in 0..count {
+            let base = (pixel_idx + lane) * 6;
+            c0[lane] = u16::from_le_bytes([buf[base], buf[base + 1]]);
+            c1[lane] = u16::from_le_bytes([buf[base + 2], buf[base + 3]]);
+            c2[lane] = u16::from_le_bytes([buf[base + 4], buf[base + 5]]);
+        }
+
+        let mut lane = 0usize;
+        while lane + 16 <= count {
+            let y = unsafe { _mm256_loadu_si256(c0.as_ptr().add(lane) as *const __m256i) };
+            let cb_bits = unsafe { _mm256_loadu_si256(c1.as_ptr().add(lane) as *const __m256i) };
+            let cr_bits = unsafe { _mm256_loadu_si256(c2.as_ptr().add(lane) as *const __m256i) };
+            let cb = cb_bits;
+            let cr = cr_bits;
+            let g = _mm256_sub_epi16(y, _mm256_srai_epi16(_mm256_add_epi16(cb, cr), 2));
+            let r = _mm256_add_epi16(cr, g);
+            let b = _mm256_add_epi16(cb, g);
+            unsafe { _mm256_storeu_si256(c0.as_mut_ptr().add(lane) as *mut __m256i, r) };
+            unsafe { _mm256_storeu_si256(c1.as_mut_ptr().add(lane) as *mut __m256i, g) };
+            unsafe { _mm256_storeu_si256(c2.as_mut_ptr().add(lane) as *mut __m256i, b) };
+            lane += 16;
+        }
+
+        while lane < count {
+            let y = c0[lane] as i32;
+            let cb = c1[lane] as i16 as i32;
+            let cr = c2[lane] as i16 as i32;
+            let g = y - ((cb + cr) >> 2);
+            let r = cr + g;
+            let b = cb + g;
+            c0[lane] = r.clamp(0, 65535) as u16;
+            c1[lane] = g.clamp(0, 65535) as u16;
+            c2[lane] = b.clamp(0, 65535) as u16;
+            lane += 1;
+        }
+
+        for lane in 0..count {
+            let base = (pixel_idx + lane) * 6;
+            buf[base..base + 2].copy_from_slice(&c0[lane].to_le_bytes());
+            buf[base + 2..base + 4].copy_from_slice(&c1[lane].to_le_bytes());
+            buf[base + 4..base + 6].copy_from_slice(&c2[lane].to_le_bytes());
+        }
+
+        pixel_idx += count;
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn rgb16_delta_g_forward_avx2(buf: &mut [u8]) {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    let pixels = buf.len() / 6;
+    let block_pixels = rgb_planar_block_pixels(DEFAULT_RGB16_PLANAR_BLOCK_PIXELS);
+    let mut c0 = vec![0u16; block_pixels];
+    let mut c1 = vec![0u16; block_pixels];
+    let mut c2 = vec![0u16; block_pixels];
+
+    let mut pixel_idx = 0usize;
+    while pixel_idx < pixels {
+        let count = block_pixels.min(pixels - pixel_idx);
+        for lane in 0..count {
+            let base = (pixel_idx + lane) * 6;
+            c0[lane] = u16::from_le_bytes([buf[base], buf[base + 1]]);
+            c1[lane] = u16::from_le_bytes([buf[base + 2], buf[base + 3]]);
+            c2[lane] = u16::from_le_bytes([buf[base + 4], buf[base + 5]]);
+        }
+
+        let mut lane = 0usize;
+        while lane + 16 <= count {
+            let r = unsafe { _mm256_loadu_si256(c0.as_ptr().add(lane) as *const __m256i) };
+            let g = unsafe { _mm256_loadu_si256(c1.as_ptr().add(lane) as *const __m256i) };
+            let b = unsafe { _mm256_loadu_si256(c2.as_ptr().add(lane) as *const __m256i) };
+            let dr = _mm256_sub_epi16(r, g);
+            let db = _mm256_sub_epi16(b, g);
+            unsafe { _mm256_storeu_si256(c0.as_mut_ptr().add(lane) as *mut __m256i, g) };
+            unsafe { _mm256_storeu_si256(c1.as_mut_ptr().add(lane) as *mut __m256i, dr) };
+            unsafe { _mm256_storeu_si256(c2.as_mut_ptr().add(lane) as *mut __m256i, db) };
+            lane += 16;
+        }
+
+        while lane < count {
+            let r = c0[lane];
+            let g = c1[lane];
+            let b = c2[lane];
+            c0[lane] = g;
+            c1[lane] = r.wrapping_sub(g);
+            c2[lane] = b.wrapping_sub(g);
+            lane += 1;
+        }
+
+        for lane in 0..count {
+            let base = (pixel_idx + lane) * 6;
+            buf[base..base + 2].copy_from_slice(&c0[lane].to_le_bytes());
+            buf[base + 2..base + 4].copy_from_slice(&c1[lane].to_le_bytes());
+            buf[base + 4..base + 6].copy_from_slice(&c2[lane].to_le_bytes());
+        }
+
+        pixel_idx += count;
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn rgb16_delta_g_inverse_avx2(buf: &mut [u8]) {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    let pixels = buf.len() / 6;
+    let block_pixels = rgb_planar_block_pixels(DEFAULT_RGB16_PLANAR_BLOCK_PIXELS);
+    let mut c0 = vec![0u16; block_pixels];
+    let mut c1 = vec![0u16; block_pixels];
+    let mut c2 = vec![0u16; block_pixels];
+
+    let mut pixel_idx = 0usize;
+    while pixel_idx < pixels {
+        let count = block_pixels.min(pixels - pixel_idx);
+        for lane in 0..count {
+            let base = (pixel_idx + lane) * 6;
+            c0[lane] = u16::from_le_bytes([buf[base], buf[base + 1]]);
+            c1[lane] = u16::from_le_bytes([buf[base + 2], buf[base + 3]]);
+            c2[lane] = u16::from_le_bytes([buf[base + 4], buf[base + 5]]);
+        }
+
+        let mut lane = 0usize;
+        while lane + 16 <= count {
+            let g = unsafe { _mm256_loadu_si256(c0.as_ptr().add(lane) as *const __m256i) };
+            let dr = unsafe { _mm256_loadu_si256(c1.as_ptr().add(lane) as *const __m256i) };
+            let db = unsafe { _mm256_loadu_si256(c2.as_ptr().add(lane) as *const __m256i) };
+            let r = _mm256_add_epi16(g, dr);
+            let b = _mm256_add_epi16(g, db);
+            unsafe { _mm256_storeu_si256(c0.as_mut_ptr().add(lane) as *mut __m256i, r) };
+            unsafe { _mm256_storeu_si256(c1.as_mut_ptr().add(lane) as *mut __m256i, g) };
+            unsafe { _mm256_storeu_si256(c2.as_mut_ptr().add(lane) as *mut __m256i, b) };
+            lane += 16;
+        }
+
+        while lane < count {
+            let g = c0[lane];
+            let dr = c1[lane];
+            let db = c2[lane];
+            c0[lane] = g.wrapping_add(dr);
+            c1[lane] = g;
+            c2[lane] = g.wrapping_add(db);
+            lane += 1;
+        }
+
+        for lane in 0..count {
+            let base = (pixel_idx + lane) * 6;
+            buf[base..base + 2].copy_from_slice(&c0[lane].to_le_bytes());
+            buf[base + 2..base + 4].copy_from_slice(&c1[lane].to_le_bytes());
+            buf[base + 4..base + 6].copy_from_slice(&c2[lane].to_le_bytes());
+        }
+
+        pixel_idx += count;
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut h = crc32fast::Hasher::new();
+    h.update(data);
+    h.finalize()
+}
+
+type TiffSectionMetadata = (Option<f32>, Option<f32>, Option<Vec<u8>>);
+
+fn read_tiff_metadata_for_sections(path: &Path) -> Result<TiffSectionMetadata> {
+    use tiff::decoder::Decoder;
+    use tiff::tags::Tag;
+
+    let f = File::open(path)?;
+    let mut dec = Decoder::new(std::io::BufReader::new(f))?;
+
+    let unit = dec.get_tag(Tag::ResolutionUnit).ok();
+    let xr = dec.get_tag(Tag::XResolution).ok();
+    let yr = dec.get_tag(Tag::YResolution).ok();
+    let icc = dec.get_tag_u8_vec(Tag::IccProfile).ok();
+
+    let mut xdpi: Option<f32> = None;
+    let mut ydpi: Option<f32> = None;
+
+    if let (Some(unit_v), Some(xv), Some(yv)) = (unit, xr, yr) {
+        let xf = rational_to_f32(&xv);
+        let yf = rational_to_f32(&yv);
+        if let Some(code) = short_first(&unit_v) {
+            match code {
+                2 => {
+                    xdpi = Some(xf);
+                    ydpi = Some(yf);
+                }
+                3 => {
+                    xdpi = Some(xf * 2.54);
+                    ydpi = Some(yf * 2.54);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok((xdpi, ydpi, icc))
+}
+
+/// Band description of a CMYK / N-band TIFF, or of any TIFF naming its inks.
+/// Alpha of LA/RGBA needs none: the color type already says what it is.
+fn read_tiff_band_info(path: &Path, color_type: ColorType) -> Result<Option<BandInfo>> {
+    let mut dec = RawTiffDecoder::new(BufReader::new(File::open(path)?))?;
+    let photometric = dec
+        .find_tag_unsigned::<u16>(TiffTag::PhotometricInterpretation)?
+        .unwrap_or(1);
+    let ink_set = dec.find_tag_unsigned::<u16>(TiffTag::Unknown(TIFF_TAG_INK_SET))?;
+    let extra_samples = dec
+        .find_tag_unsigned_vec::<u16>(TiffTag::ExtraSamples)?
+        .unwrap_or_default();
+
+    // The tiff crate cuts ASCII values at the first NUL, so read the raw
+    // NUL-separated list.
+    let mut ifd = dec.image_ifd();
+    let names = match ifd.find_entry(TiffTag::Unknown(TIFF_TAG_INK_NAMES)) {
+        Some(entry) if entry.count() <= TIFF_INK_NAMES_MAX_BYTES => {
+            let mut raw = vec![0u8; entry.count() as usize];
+            ifd.find_tag_bytes(TiffTag::Unknown(TIFF_TAG_INK_NAMES), &mut raw, 0)?;
+            raw.split(|&b| b == 0)
+                .filter(|name| !name.is_empty())
+                .map(|name| String::from_utf8_lossy(name).into_owned())
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+
+    let banded = matches!(
+        color_type,
+        ColorType::Cmyk8 | ColorType::Cmyk16 | ColorType::Bands8(_) | ColorType::Bands16(_)
+    );
+    if !banded && ink_set.is_none() && names.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(BandInfo {
+        photometric,
+        ink_set: ink_set.unwrap_or(0),
+        extra_samples,
+        names,
+    }))
+}
+
+/// Reads XMP, EXIF and IPTC from a TIFF, PNG or JPEG file, picked by its
+/// signature. Other formats carry none.
+fn read_descriptive_metadata(path: &Path) -> Result<DescriptiveMetadata> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut signature = [0u8; 8];
+    let read = reader.read(&mut signature)?;
+    reader.seek(SeekFrom::Start(0))?;
+    match &signature[..read] {
+        [b'I', b'I', 42, 0, ..] | [b'M', b'M', 0, 42, ..] => read_tiff_descriptive_metadata(reader),
+        sig if sig == PNG_SIGNATURE => read_png_descriptive_metadata(reader),
+        [0xFF, 0xD8, ..] => read_jpeg_descriptive_metadata(reader),
+        _ => Ok(DescriptiveMetadata::default()),
+    }
+}
+
+fn read_tiff_descriptive_metadata<R: Read + Seek>(inner: R) -> Result<DescriptiveMetadata> {
+    let (mut reader, ifd0_offset) = RawIfdReader::new(inner)?;
+    let (ifd0, _) = reader.read_ifd(ifd0_offset)?;
+    let value = |tag| ifd0.iter().find(|entry| entry.tag == tag).map(|entry| entry.value.clone());
+    Ok(DescriptiveMetadata {
+        xmp: value(TIFF_TAG_XMP),
+        exif: reader.exif_directories(&ifd0).to_blob()?,
+        iptc: value(TIFF_TAG_IPTC),
+    })
+}
+
+fn read_png_descriptive_metadata<R: BufRead + Seek>(inner: R) -> Result<DescriptiveMetadata> {
+    let reader = png::Decoder::new(inner).read_info().map_err(png_error)?;
+    let info = reader.info();
+    let raw_profile = info
+        .uncompressed_latin1_text
+        .iter()
+        .filter(|chunk| chunk.keyword == PNG_IPTC_KEYWORD)
+        .map(|chunk| chunk.text.clone())
+        .chain(
+            info.compressed_latin1_text
+                .iter()
+                .filter(|chunk| chunk.keyword == PNG_IPTC_KEYWORD)
+                .filter_map(|chunk| chunk.get_text().ok()),
+        )
+        .next();
+    Ok(DescriptiveMetadata {
+        xmp: info
+            .utf8_text
+            .iter()
+            .find(|chunk| chunk.keyword == PNG_XMP_KEYWORD)
+            .and_then(|chunk| chunk.get_text().ok())
+            .map(String::into_bytes),
+        exif: info.exif_metadata.as_ref().map(|exif| exif.to_vec()),
+        iptc: raw_profile.as_deref().and_then(parse_png_raw_profile),
+    })
+}
+
+/// Walks the marker segments up to the first scan: APP1 holds EXIF and XMP,
+/// APP13 the Photoshop resources with the IPTC record.
+fn read_jpeg_descriptive_metadata<R: Read>(mut reader: R) -> Result<DescriptiveMetadata> {
+    let mut out = DescriptiveMetadata::default();
+    let mut marker = [0u8; 2];
+    reader.read_exact(&mut marker)?;
+    ensure!(marker == [0xFF, 0xD8], "JPEG SOI marker missing");
+    loop {
+        reader.read_exact(&mut marker[..1])?;
+        ensure!(marker[0] == 0xFF, "JPEG marker expected");
+        // Skip fill bytes.
+        while marker[0] == 0xFF {
+            reader.read_exact(&mut marker[..1])?;
+        }
+        match marker[0] {
+            0xD9 | 0xDA => break,
+            0x01 | 0xD0..=0xD7 => {}
+            code => {
+                let mut len = [0u8; 2];
+                reader.read_exact(&mut len)?;
+                let len = u16::from_be_bytes(len) as usize;
+                ensure!(len >= 2, "JPEG segment length {} too small", len);
+                let mut segment = vec![0u8; len - 2];
+                reader.read_exact(&mut segment)?;
+                match code {
+                    0xE1 if out.exif.is_none() && segment.starts_with(JPEG_EXIF_SIGNATURE) => {
+                        out.exif = Some(segment[JPEG_EXIF_SIGNATURE.len()..].to_vec());
+                    }
+                    0xE1 if out.xmp.is_none() && segment.starts_with(JPEG_XMP_SIGNATURE) => {
+                        out.xmp = Some(segment[JPEG_XMP_SIGNATURE.len()..].to_vec());
+                    }
+                    0xED if out.iptc.is_none() && segment.starts_with(JPEG_PHOTOSHOP_SIGNATURE) => {
+                        out.iptc = photoshop_iptc(&segment[JPEG_PHOTOSHOP_SIGNATURE.len()..]);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// IPTC-NAA record (resource 0x0404) of a Photoshop image resource block.
+fn photoshop_iptc(mut resources: &[u8]) -> Option<Vec<u8>> {
+    while resources.starts_with(b"8BIM") && resources.len() >= 12 {
+        let id = u16::from_be_bytes([resources[4], resources[5]]);
+        // Pascal string name, padded to an even length.
+        let size_pos = 6 + (1 + resources[6] as usize).next_multiple_of(2);
+        let size = u32::from_be_bytes(resources.get(size_pos..size_pos + 4)?.try_into().ok()?) as usize;
+        let data = resources.get(size_pos + 4..size_pos + 4 + size)?;
+        if id == 0x0404 {
+            return Some(data.to_vec());
+        }
+        resources = resources.get((size_pos + 4 + size.next_multiple_of(2)).min(resources.len())..)?;
+    }
+    None
+}
+
+/// Decodes an ImageMagick-style "Raw profile type" text: the profile name,
+/// its length and the bytes as hex digits, all separated by whitespace.
+fn parse_png_raw_profile(text: &str) -> Option<Vec<u8>> {
+    let mut fields = text.split_ascii_whitespace();
+    let _name = fields.next()?;
+    let len = fields.next()?.parse::<usize>().ok()?;
+    let hex = fields.flat_map(str::bytes).collect::<Vec<_>>();
+    let mut data = hex
+        .chunks_exact(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    if data.len() < len {
+        return None;
+    }
+    data.truncate(len);
+    if data.starts_with(b"8BIM") {
+        return photoshop_iptc(&data);
+    }
+    Some(data)
+}
+
+fn png_raw_profile(name: &str, data: &[u8]) -> String {
+    let mut text = format!("\n{name}\n{:8}\n", data.len());
+    for line in data.chunks(36) {
+        for byte in line {
+            text.push_str(&format!("{byte:02x}"));
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// Adds `eXIf`, XMP `iTXt` and IPTC raw profile `tEXt` chunks to the PNG at
+/// `path`, in front of its first `IDAT`. `iTXt` is UTF-8 only, so other XMP
+/// encodings are left out.
+fn insert_png_descriptive_metadata(path: &Path, descriptive: &DescriptiveMetadata) -> Result<()> {
+    let mut chunks = Vec::new();
+    if let Some(exif) = &descriptive.exif {
+        chunks.push((*b"eXIf", exif.clone()));
+    }
+    if let Some(xmp) = &descriptive.xmp
+        && std::str::from_utf8(xmp).is_ok()
+    {
+        let mut data = PNG_XMP_KEYWORD.as_bytes().to_vec();
+        // NUL, uncompressed, no language tag or translated keyword.
+        data.extend_from_slice(&[0, 0, 0, 0, 0]);
+        data.extend_from_slice(xmp);
+        chunks.push((*b"iTXt", data));
+    }
+    if let Some(iptc) = &descriptive.iptc {
+        let mut data = PNG_IPTC_KEYWORD.as_bytes().to_vec();
+        data.push(0);
+        data.extend_from_slice(png_raw_profile("iptc", iptc).as_bytes());
+        chunks.push((*b"tEXt", data));
+    }
+    if chunks.is_empty() {
+        return Ok(());
+    }
+
+    let png = std::fs::read(path)?;
+    ensure!(png.starts_with(PNG_SIGNATURE), "{:?} is not a PNG file", path);
+    let mut pos = PNG_SIGNATURE.len();
+    loop {
+        let header = png.get(pos..pos + 8).ok_or_else(|| CtiError::Corrupt("PNG has no IDAT chunk".into()))?;
+        if &header[4..] == b"IDAT" {
+            break;
+        }
+        pos += 12 + u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+    }
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(&png[..pos])?;
+    for (ty, data) in &chunks {
+        let len = u32::try_from(data.len()).map_err(|_| CtiError::LimitExceeded("PNG chunk too large".into()))?;
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(ty);
+        hasher.update(data);
+        out.write_all(&len.to_be_bytes())?;
+        out.write_all(ty)?;
+        out.write_all(data)?;
+        out.write_all(&hasher.finalize().to_be_bytes())?;
+    }
+    out.write_all(&png[pos..])?;
+    out.flush()?;
+    Ok(())
+}
+
+/// TIFF directory entry with its value in the byte order of the file it was
+/// read from (or is written to).
+#[derive(Debug, Clone)]
+struct RawIfdEntry {
+    tag: u16,
+    field_type: u16,
+    count: u32,
+    value: Vec<u8>,
+}
+
+impl RawIfdEntry {
+    fn bytes(tag: u16, field_type: u16, value: &[u8]) -> Self {
+        Self { tag, field_type, count: value.len() as u32, value: value.to_vec() }
+    }
+
+    fn long(tag: u16, value: u32, big_endian: bool) -> Self {
+        Self { tag, field_type: TIFF_TYPE_LONG, count: 1, value: tiff_u32_bytes(value, big_endian).to_vec() }
+    }
+
+    fn swap_byte_order(&mut self) {
+        if let Some((_, component)) = tiff_field_size(self.field_type) {
+            self.value.chunks_exact_mut(component).for_each(<[u8]>::reverse);
+        }
+    }
+}
+
+/// `(value size, byte-swapped unit)` of a TIFF field type; rationals swap
+/// their numerator and denominator separately.
+fn tiff_field_size(field_type: u16) -> Option<(usize, usize)> {
+    Some(match field_type {
+        1 | 2 | 6 | 7 => (1, 1),
+        3 | 8 => (2, 2),
+        4 | 9 | 11 | 13 => (4, 4),
+        5 | 10 => (8, 4),
+        12 => (8, 8),
+        _ => return None,
+    })
+}
+
+fn tiff_u32_bytes(value: u32, big_endian: bool) -> [u8; 4] {
+    if big_endian { value.to_be_bytes() } else { value.to_le_bytes() }
+}
+
+/// Minimal classic TIFF directory reader that keeps entry values as raw bytes,
+/// so they can be copied between files without knowing each tag.
+struct RawIfdReader<R> {
+    inner: R,
+    big_endian: bool,
+}
+
+impl<R: Read + Seek> RawIfdReader<R> {
+    /// Reads the header; returns the reader and the IFD0 offset.
+    fn new(mut inner: R) -> Result<(Self, u32)> {
+        let mut header = [0u8; 8];
+        inner.read_exact(&mut header)?;
+        let big_endian = match &header[..4] {
+            b"II*\0" => false,
+            b"MM\0*" => true,
+            _ => bail!(CtiError::Unsupported("Only classic TIFF directories can be copied".into())),
+        };
+        let reader = Self { inner, big_endian };
+        let ifd0_offset = reader.u32_at(&header[4..8]);
+        Ok((reader, ifd0_offset))
+    }
+
+    fn u16_at(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+    }
+
+    fn u32_at(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    }
+
+    /// Entries of the IFD at `offset` and the offset of the next IFD. Entries
+    /// of unknown field types or with oversized values are left out.
+    fn read_ifd(&mut self, offset: u32) -> Result<(Vec<RawIfdEntry>, u32)> {
+        self.inner.seek(SeekFrom::Start(offset as u64))?;
+        let mut count = [0u8; 2];
+        self.inner.read_exact(&mut count)?;
+        let mut raw = vec![0u8; self.u16_at(&count) as usize * 12 + 4];
+        self.inner.read_exact(&mut raw)?;
+        let next_ifd = self.u32_at(&raw[raw.len() - 4..]);
+
+        let mut entries = Vec::new();
+        for field in raw.chunks_exact(12) {
+            let field_type = self.u16_at(&field[2..4]);
+            let count = self.u32_at(&field[4..8]);
+            let Some((size, _)) = tiff_field_size(field_type) else {
+                continue;
+            };
+            let len = size as u64 * count as u64;
+            let value = if len <= 4 {
+                field[8..8 + len as usize].to_vec()
+            } else if len <= DESCRIPTIVE_METADATA_MAX_BYTES {
+                self.inner.seek(SeekFrom::Start(self.u32_at(&field[8..12]) as u64))?;
+                let mut value = vec![0u8; len as usize];
+                self.inner.read_exact(&mut value)?;
+                value
+            } else {
+                continue;
+            };
+            entries.push(RawIfdEntry { tag: self.u16_at(&field[0..2]), field_type, count, value });
+        }
+        Ok((entries, next_ifd))
+    }
+
+    fn sub_ifd(&mut self, ifd: &[RawIfdEntry], tag: u16) -> Option<Vec<RawIfdEntry>> {
+        let entry = ifd
+            .iter()
+            .find(|entry| entry.tag == tag && matches!(entry.field_type, TIFF_TYPE_LONG | 13))?;
+        let offset = self.u32_at(entry.value.get(..4)?);
+        self.read_ifd(offset).ok().map(|(entries, _)| entries)
+    }
+
+    /// The EXIF-relevant part of `ifd0`. Unreadable sub-IFDs are dropped
+    /// rather than failing the whole image.
+    fn exif_directories(&mut self, ifd0: &[RawIfdEntry]) -> ExifDirectories {
+        let mut exif = self.sub_ifd(ifd0, TIFF_TAG_EXIF_IFD);
+        // Interoperability IFD offsets would dangle once copied.
+        if let Some(exif) = &mut exif {
+            exif.retain(|entry| entry.tag != EXIF_TAG_INTEROP_IFD);
+        }
+        ExifDirectories {
+            big_endian: self.big_endian,
+            ifd0: ifd0.iter().filter(|entry| EXIF_IFD0_TAGS.contains(&entry.tag)).cloned().collect(),
+            exif,
+            gps: self.sub_ifd(ifd0, TIFF_TAG_GPS_IFD),
+        }
+    }
+}
+
+/// Descriptive IFD0 tags plus the Exif and GPS IFDs: what an `EXIF` section
+/// keeps of a TIFF directory.
+struct ExifDirectories {
+    big_endian: bool,
+    ifd0: Vec<RawIfdEntry>,
+    exif: Option<Vec<RawIfdEntry>>,
+    gps: Option<Vec<RawIfdEntry>>,
+}
+
+impl ExifDirectories {
+    /// Parses a TIFF-structured EXIF blob, as stored in PNG `eXIf` and JPEG
+    /// APP1 segments.
+    fn from_blob(blob: &[u8]) -> Result<Self> {
+        let (mut reader, ifd0_offset) = RawIfdReader::new(Cursor::new(blob))?;
+        let (ifd0, _) = reader.read_ifd(ifd0_offset)?;
+        Ok(reader.exif_directories(&ifd0))
+    }
+
+    fn to_blob(&self) -> Result<Option<Vec<u8>>> {
+        if self.ifd0.is_empty() && self.exif.is_none() && self.gps.is_none() {
+            return Ok(None);
+        }
+        let mut out = if self.big_endian { b"MM\0*".to_vec() } else { b"II*\0".to_vec() };
+        out.extend_from_slice(&[0; 4]);
+        let mut ifd0 = self.ifd0.clone();
+        ifd0.extend(self.write_sub_ifds(&mut out, 0, self.big_endian)?);
+        ifd0.sort_by_key(|entry| entry.tag);
+        let ifd0_offset = write_raw_ifd(&mut out, 0, &ifd0, self.big_endian, 0)?;
+        out[4..8].copy_from_slice(&tiff_u32_bytes(ifd0_offset, self.big_endian));
+        Ok(Some(out))
+    }
+
+    fn in_byte_order(&self, entries: &[RawIfdEntry], big_endian: bool) -> Vec<RawIfdEntry> {
+        let mut entries = entries.to_vec();
+        if big_endian != self.big_endian {
+            entries.iter_mut().for_each(RawIfdEntry::swap_byte_order);
+        }
+        entries.sort_by_key(|entry| entry.tag);
+        entries
+    }
+
+    fn ifd0_entries(&self, big_endian: bool) -> Vec<RawIfdEntry> {
+        self.in_byte_order(&self.ifd0, big_endian)
+    }
+
+    /// Appends the Exif and GPS IFDs to `out` (which starts at file offset
+    /// `base`) and returns the IFD0 entries pointing at them.
+    fn write_sub_ifds(&self, out: &mut Vec<u8>, base: u64, big_endian: bool) -> Result<Vec<RawIfdEntry>> {
+        let mut pointers = Vec::new();
+        for (tag, ifd) in [(TIFF_TAG_EXIF_IFD, &self.exif), (TIFF_TAG_GPS_IFD, &self.gps)] {
+            if let Some(ifd) = ifd {
+                let offset = write_raw_ifd(out, base, &self.in_byte_order(ifd, big_endian), big_endian, 0)?;
+                pointers.push(RawIfdEntry::long(tag, offset, big_endian));
+            }
+        }
+        Ok(pointers)
+    }
+}
+
+/// Appends an IFD with its out-of-line values to `out`, which starts at file
+/// offset `base`, and returns the IFD offset.
+fn write_raw_ifd(out: &mut Vec<u8>, base: u64, entries: &[RawIfdEntry], big_endian: bool, next_ifd: u32) -> Result<u32> {
+    let u16_bytes = |value: u16| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+    if (base + out.len() as u64) % 2 == 1 {
+        out.push(0);
+    }
+    let ifd_offset = base + out.len() as u64;
+    let data_offset = ifd_offset + 2 + 12 * entries.len() as u64 + 4;
+    let mut data = Vec::new();
+    out.extend_from_slice(&u16_bytes(entries.len() as u16));
+    for entry in entries {
+        out.extend_from_slice(&u16_bytes(entry.tag));
+        out.extend_from_slice(&u16_bytes(entry.field_type));
+        out.extend_from_slice(&tiff_u32_bytes(entry.count, big_endian));
+        if entry.value.len() <= 4 {
+            let mut inline = [0u8; 4];
+            inline[..entry.value.len()].copy_from_slice(&entry.value);
+            out.extend_from_slice(&inline);
+        } else {
+            out.extend_from_slice(&tiff_u32_bytes((data_offset + data.len() as u64) as u32, big_endian));
+            data.extend_from_slice(&entry.value);
+            if data.len() % 2 == 1 {
+                data.push(0);
+            }
+        }
+    }
+    out.extend_from_slice(&tiff_u32_bytes(next_ifd, big_endian));
+    out.extend_from_slice(&data);
+    ensure!(
+        base + out.len() as u64 <= u32::MAX as u64,
+        CtiError::LimitExceeded("TIFF metadata would end past 4 GiB".into())
+    );
+    Ok(ifd_offset as u32)
+}
+
+fn parse_resolution_section(payload: &[u8]) -> Option<(f32, f32)> {
+    if payload.len() != 8 {
+        return None;
+    }
+    let x = f32::from_le_bytes(payload[0..4].try_into().ok()?);
+    let y = f32::from_le_bytes(payload[4..8].try_into().ok()?);
+    Some((x, y))
+}
+
+fn rational_to_f32(v: &tiff::decoder::ifd::Value) -> f32 {
+    use tiff::decoder::ifd::Value::*;
+    match v {
+        Rational(n, d) => {
+            if *d == 0 {
+                0.0
+            } else {
+                *n as f32 / *d as f32
+            }
+        }
+        SRational(n, d) => {
+            if *d == 0 {
+                0.0
+            } else {
+                *n as f32 / *d as f32
+            }
+        }
+        Short(s) => *s as f32,
+        Float(f) => *f,
+        Double(f) => *f as f32,
+        _ => 0.0,
+    }
+}
+
+fn short_first(v: &tiff::decoder::ifd::Value) -> Option<u32> {
+    use tiff::decoder::ifd::Value::*;
+    match v {
+        Short(s) => Some(*s as u32),
+        _ => None,
+    }
+}
+
+fn f32_from_le_bytes(bytes: &[u8]) -> f32 {
+    f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn bytes_to_u16_vec(data: &[u8]) -> Result<Vec<u16>> {
+    ensure!(data.len().is_multiple_of(2), "16-bit data must have even byte length");
+    Ok(data
+        .chunks_exact(2)
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect())
+}
+
+fn u16_vec_to_bytes(data: &[u16]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() * 2);
+    for value in data {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    out
+}
+
+fn read_u8<R: Read>(r: &mut R) -> Result<u8> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+fn read_u16_le<R: Read>(r: &mut R) -> Result<u16> {
+    let mut b = [0u8; 2];
+    r.read_exact(&mut b)?;
+    Ok(u16::from_le_bytes(b))
+}
+
+fn read_u32_le<R: Read>(r: &mut R) -> Result<u32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_u64_le<R: Read>(r: &mut R) -> Result<u64> {
+    let mut b = [0u8; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_rgb8() -> TiffImage {
+        TiffImage {
+            xdpi: Some(300.0),
+            ydpi: Some(300.0),
+            icc: Some(vec![1, 2, 3, 4]),
+            ..TiffImage::new(
+                4,
+                4,
+                ColorType::Rgb8,
+                vec![
+                    10, 20, 30, 40, 50, 60, 70, 80, 90, 100, 110, 120,
+                    11, 21, 31, 41, 51, 61, 71, 81, 91, 101, 111, 121,
+                    12, 22, 32, 42, 52, 62, 72, 82, 92, 102, 112, 122,
+                    13, 23, 33, 43, 53, 63, 73, 83, 93, 103, 113, 123,
+                ],
+            )
+        }
+    }
+
+    fn sample_rgb16() -> TiffImage {
+        let mut data = Vec::new();
+        for y in 0..4u16 {
+            for x in 0..4u16 {
+                data.extend_from_slice(&(1000 + x * 17 + y * 5).to_le_bytes());
+                data.extend_from_slice(&(2000 + x * 13 + y * 7).to_le_bytes());
+                data.extend_from_slice(&(3000 + x * 11 + y * 9).to_le_bytes());
+            }
+        }
+        TiffImage::new(4, 4, ColorType::Rgb16, data)
+    }
+
+    #[test]
+    fn roundtrip_sections_and_pyramid_work() {
+        let cfg = CTIConfig {
+            tile_size: 2,
+            compression: CompressionType::Zstd,
+            color_transform: true,
+            pyramid_levels: 2,
+            ..CTIConfig::default()
+        };
+        let enc = CTIEncoder::new(cfg);
+        let img = sample_rgb8();
+        let mut cur = Cursor::new(Vec::new());
+        enc.encode_to_writer(&img, &mut cur).unwrap();
+
+        cur.set_position(0);
+        let info = CTIDecoder::info_from_reader(&mut cur).unwrap();
+        assert_eq!(info.header.width, 4);
+        assert_eq!(info.pyramid_levels.len(), 2);
+        assert_eq!(info.icc_size, Some(4));
+        assert_eq!(info.xdpi, Some(300.0));
+
+        cur.set_position(0);
+        let decoded = CTIDecoder::decode_from_reader(&mut cur, 0).unwrap();
+        assert_eq!(decoded.data, img.data);
+
+        cur.set_position(0);
+        let level1 = CTIDecoder::decode_from_reader(&mut cur, 1).unwrap();
+        assert_eq!(level1.header.width, 2);
+        assert_eq!(level1.header.height, 2);
+    }
+
+    #[test]
+    fn tile_and_region_decode_work() {
+        let img = TiffImage::new(4, 4, ColorType::L8, (0u8..16).collect());
+        let enc = CTIEncoder::new(CTIConfig {
+            tile_size: 2,
+            compression: CompressionType::Lz4,
+            ..CTIConfig::default()
+        });
+        let mut cur = Cursor::new(Vec::new());
+        enc.encode_to_writer(&img, &mut cur).unwrap();
+
+        cur.set_position(0);
+        let tile = CTIDecoder::decode_tile_from_reader(&mut cur, 1, 0, 0).unwrap();
+        assert_eq!(tile.width, 2);
+        assert_eq!(tile.height, 2);
+        assert_eq!(tile.data, vec![2, 3, 6, 7]);
+
+        cur.set_position(0);
+        let region = CTIDecoder::extract_region_from_reader(&mut cur, 1, 1, 2, 2, 0).unwrap();
+        assert_eq!(region.data, vec![5, 6, 9, 10]);
+    }
+
+    #[test]
+    fn reader_serves_repeated_requests_from_one_layout() {
+        let enc = CTIEncoder::new(CTIConfig {
+            tile_size: 2,
+            compression: CompressionType::Adaptive,
+            pyramid_levels: 1,
+            ..CTIConfig::default()
+        });
+        let img = sample_rgb8();
+        let mut cur = Cursor::new(Vec::new());
+        enc.encode_to_writer(&img, &mut cur).unwrap();
+
+        let mut reader = CTIReader::new(cur).unwrap();
+        assert_eq!(reader.header().tiles_x, 2);
+        assert_eq!(reader.pyramid_level_count(), 1);
+        for _ in 0..3 {
+            let tile = reader.decode_tile(1, 1, 0).unwrap();
+            assert_eq!(tile.data, extract_tile(&img, 1, 1, (2, 2)).unwrap());
+        }
+
+        let region = reader.extract_region(1, 1, 2, 2, 0).unwrap();
+        assert_eq!(&region.data[..3], &img.data[15..18]);
+
+        let level1 = reader.decode(1).unwrap();
+        assert_eq!((level1.header.width, level1.header.height), (2, 2));
+        let level_tile = reader.decode_tile(0, 0, 1).unwrap();
+        assert_eq!(level_tile.data, level1.data);
+        assert!(reader.decode_tile(0, 0, 2).is_err());
+
+        let decoded = reader.decode(0).unwrap();
+        assert_eq!(decoded.data, img.data);
+    }
+
+    #[test]
+    fn parallel_decode_matches_source_with_thread_cap() {
+        let (width, height) = (37u32, 29u32);
+        let data = (0..width * height)
+            .flat_map(|i| ((i * 37) as u16 ^ 0x5A5A).to_le_bytes())
+            .collect::<Vec<u8>>();
+        let img = TiffImage::new(width, height, ColorType::L16, data);
+        let enc = CTIEncoder::new(CTIConfig {
+            tile_size: 4,
+            compression: CompressionType::Adaptive,
+            ..CTIConfig::default()
+        });
+        let mut cur = Cursor::new(Vec::new());
+        enc.encode_to_writer(&img, &mut cur).unwrap();
+
+        let mut reader = CTIReader::new(cur).unwrap().with_threads(2).unwrap();
+        assert_eq!(reader.decode(0).unwrap().data, img.data);
+
+        let (x, y, w, h) = (3u32, 5u32, 30u32, 21u32);
+        let region = reader.extract_region(x, y, w, h, 0).unwrap();
+        let mut expected = Vec::new();
+        for row in y..y + h {
+            let start = ((row * width + x) * 2) as usize;
+            expected.extend_from_slice(&img.data[start..start + (w * 2) as usize]);
+        }
+        assert_eq!(region.data, expected);
+    }
+
+    #[test]
+    fn pyramid_levels_are_read_in_place() {
+        let enc = CTIEncoder::new(CTIConfig {
+            tile_size: 2,
+            compression: CompressionType::Zstd,
+            pyramid_levels: 2,
+            ..CTIConfig::default()
+        });
+        let img = sample_rgb8();
+        let mut cur = Cursor::new(Vec::new());
+        enc.encode_to_writer(&img, &mut cur).unwrap();
+        let bytes = cur.into_inner();
+
+        let mut reader = CTIReader::new(Cursor::new(bytes.clone())).unwrap();
+        let levels = reader.pyramid_levels().unwrap();
+        assert_eq!(levels.len(), 2);
+        let level2_desc = *reader
+            .section_descs()
+            .iter()
+            .filter(|desc| desc.ty == SEC_TYPE_PYLV)
+            .nth(1)
+            .unwrap();
+        let nested = &bytes[level2_desc.offset as usize..(level2_desc.offset + level2_desc.size) as usize];
+        let expected = CTIDecoder::decode_from_reader(&mut Cursor::new(nested), 0).unwrap();
+
+        let tile = reader.decode_tile(0, 0, 2).unwrap();
+        assert_eq!((tile.width, tile.height), (1, 1));
+        assert_eq!(tile.data, expected.data);
+        assert_eq!(reader.level_header(2).unwrap().width, 1);
+
+        let mut sub = SubRangeReader::new(Cursor::new(bytes), level2_desc.offset, level2_desc.size);
+        assert_eq!(sub.seek(SeekFrom::End(0)).unwrap(), level2_desc.size);
+        sub.seek(SeekFrom::Start(0)).unwrap();
+        assert_eq!(read_header(&mut sub).unwrap(), expected.header);
+    }
+
+    #[test]
+    fn mmap_reader_serves_tiles_concurrently() {
+        let enc = CTIEncoder::new(CTIConfig {
+            tile_size: 2,
+            compression: CompressionType::Adaptive,
+            pyramid_levels: 1,
+            ..CTIConfig::default()
+        });
+        let img = sample_rgb8();
+        let path = std::env::temp_dir().join(format!("cti_mmap_test_{}.cti", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        enc.encode_to_writer(&img, &mut file).unwrap();
+        drop(file);
+
+        let reader = CTIDecoder::open_mmap(&path).unwrap();
+        let streamed = CTIDecoder::decode_detailed(&path, 1).unwrap();
+        std::thread::scope(|scope| {
+            for t in 0..4u32 {
+                let reader = &reader;
+                let img = &img;
+                scope.spawn(move || {
+                    let (tx, ty) = (t % 2, t / 2);
+                    let tile = reader.decode_tile(tx, ty, 0).unwrap();
+                    assert_eq!(tile.data, extract_tile(img, tx, ty, (2, 2)).unwrap());
+                });
+            }
+        });
+        assert_eq!(reader.decode(0).unwrap().data, img.data);
+        assert_eq!(reader.extract_region(1, 1, 2, 2, 0).unwrap().data[..3], img.data[15..18]);
+        assert_eq!(reader.decode_tile(0, 0, 1).unwrap().data, streamed.data);
+        assert!(reader.decode_tile(0, 0, 2).is_err());
+        drop(reader);
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn decode_into_respects_row_stride() {
+        let (width, height) = (5u32, 3u32);
+        let img = TiffImage::new(width, height, ColorType::Rgb8, (0..(width * height * 3) as u8).collect());
+        let enc = CTIEncoder::new(CTIConfig {
+            tile_size: 2,
+            compression: CompressionType::Zstd,
+            ..CTIConfig::default()
+        });
+        let mut cur = Cursor::new(Vec::new());
+        enc.encode_to_writer(&img, &mut cur).unwrap();
+        let mut reader = CTIReader::new(cur).unwrap();
+
+        let row_bytes = (width * 3) as usize;
+        let stride = row_bytes + 7;
+        let mut out = vec![0xEEu8; stride * (height as usize - 1) + row_bytes];
+        let header = reader.decode_into(0, &mut out, stride).unwrap();
+        assert_eq!(header.bytes_per_pixel().unwrap(), 3);
+        for row in 0..height as usize {
+            assert_eq!(&out[row * stride..row * stride + row_bytes], &img.data[row * row_bytes..(row + 1) * row_bytes]);
         }
+        assert!(out[row_bytes..stride].iter().all(|&b| b == 0xEE));
+        assert!(reader.decode_into(0, &mut out[..stride * 2], stride).is_err());
+        assert!(reader.decode_into(0, &mut out, row_bytes - 1).is_err());
 
-        for lane in 0..count {
-            let base = (pixel_idx + lane) * 6;
-            buf[base..base + 2].copy_from_slice(&c0[lane].to_le_bytes());
-            buf[base + 2..base + 4].copy_from_slice(&c1[lane].to_le_bytes());
-            buf[base + 4..base + 6].copy_from_slice(&c2[lane].to_le_bytes());
-        }
+        let mut tile = vec![0u8; 16];
+        assert_eq!(reader.decode_tile_into(2, 1, 0, &mut tile, 8).unwrap(), (1, 1));
+        assert_eq!(&tile[..3], &img.data[(row_bytes * 2 + 12)..(row_bytes * 2 + 15)]);
 
-        pixel_idx += count;
+        let mut region = vec![0u8; 2 * 16];
+        reader.extract_region_into(1, 1, 3, 2, 0, &mut region, 16).unwrap();
+        let expected = reader.extract_region(1, 1, 3, 2, 0).unwrap();
+        assert_eq!(&region[..9], &expected.data[..9]);
+        assert_eq!(&region[16..25], &expected.data[9..]);
     }
-}
 
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-#[target_feature(enable = "avx2")]
-unsafe fn rct_inverse_rgb16_avx2(buf: &mut [u8]) {
-    #[cfg(target_arch = "x86")]
-    use std::arch::x86::*;
-    #[cfg(target_arch = "x86_64")]
-    use std::arch::x86_64::*;
+    #[test]
+    fn dct_lossy_roundtrip_tracks_quality() {
+        let (width, height) = (37u32, 29u32);
+        let mut data = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                data.extend_from_slice(&[(x * 6) as u8, (y * 8) as u8, (128 + x * 2 - y) as u8]);
+            }
+        }
+        let img = TiffImage::new(width, height, ColorType::Rgb8, data);
+        let encode = |quality: u8| {
+            let enc = CTIEncoder::new(CTIConfig {
+                tile_size: 16,
+                compression: CompressionType::Dct,
+                quality_level: quality,
+                color_transform: true,
+                ..CTIConfig::default()
+            });
+            let mut cur = Cursor::new(Vec::new());
+            enc.encode_to_writer(&img, &mut cur).unwrap();
+            cur.into_inner()
+        };
 
-    let pixels = buf.len() / 6;
-    let block_pixels = rgb_planar_block_pixels(DEFAULT_RGB16_PLANAR_BLOCK_PIXELS);
-    let mut c0 = vec![0u16; block_pixels];
-    let mut c1 = vec![0u16; block_pixels];
-    let mut c2 = vec![0u16; block_pixels];
+        let high = encode(95);
+        let low = encode(20);
+        assert!(low.len() < high.len());
 
-    let mut pixel_idx = 0usize;
-    while pixel_idx < pixels {
-        let count = block_pixels.min(pixels - pixel_idx);
-        for lane in 0..count {
-            let base = (pixel_idx + lane) * 6;
-            c0[lane] = u16::from_le_bytes([buf[base], buf[base + 1]]);
-            c1[lane] = u16::from_le_bytes([buf[base + 2], buf[base + 3]]);
-            c2[lane] = u16::from_le_bytes([buf[base + 4], buf[base + 5]]);
+        let decoded = CTIDecoder::decode_from_reader(&mut Cursor::new(high), 0).unwrap();
+        assert_eq!(decoded.header.compression, CompressionType::Dct as u8);
+        assert_eq!(decoded.header.quality, 95);
+        assert_eq!(decoded.header.flags & FLAG_COLOR_RCT, 0);
+        let max_err = decoded
+            .data
+            .iter()
+            .zip(&img.data)
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap();
+        assert!(max_err <= 8, "max error {}", max_err);
+
+        let samples = (0..64u16 * 64).flat_map(|i| (i * 16).to_le_bytes()).collect::<Vec<u8>>();
+        let tile = dct_compress(&samples, 2, (64, 64), 75, 3).unwrap();
+        let restored = dct_decompress(&tile, 2, (64, 64), 75).unwrap();
+        assert_eq!(restored.len(), samples.len());
+        assert_eq!(restored, dct_decompress(&tile, 2, (64, 64), 75).unwrap());
+
+        // A crafted tile with huge coefficients is corrupt, not an overflow.
+        let mut coeffs = Vec::new();
+        write_varint(&mut coeffs, 64);
+        for _ in 0..64 {
+            write_varint(&mut coeffs, ((1u64 << 24) - 1) << 1);
         }
+        let hostile = zstd::bulk::compress(&coeffs, 1).unwrap();
+        assert!(matches!(dct_decompress(&hostile, 2, (8, 8), 1), Err(CtiError::Corrupt(_))));
+    }
 
-        let mut lane = 0usize;
-        while lane + 16 <= count {
-            let y = unsafe { _mm256_loadu_si256(c0.as_ptr().add(lane) as *const __m256i) };
-            let cb_bits = unsafe { _mm256_loadu_si256(c1.as_ptr().add(lane) as *const __m256i) };
-            let cr_bits = unsafe { _mm256_loadu_si256(c2.as_ptr().add(lane) as *const __m256i) };
-            let cb = cb_bits;
-            let cr = cr_bits;
-            let g = _mm256_sub_epi16(y, _mm256_srai_epi16(_mm256_add_epi16(cb, cr), 2));
-            let r = _mm256_add_epi16(cr, g);
-            let b = _mm256_add_epi16(cb, g);
-            unsafe { _mm256_storeu_si256(c0.as_mut_ptr().add(lane) as *mut __m256i, r) };
-            unsafe { _mm256_storeu_si256(c1.as_mut_ptr().add(lane) as *mut __m256i, g) };
-            unsafe { _mm256_storeu_si256(c2.as_mut_ptr().add(lane) as *mut __m256i, b) };
-            lane += 16;
+    #[test]
+    fn near_lossless_respects_max_error() {
+        let (width, height) = (23u32, 17u32);
+        let data = (0..width * height)
+            .flat_map(|i| ((i * 7919) as u16 ^ (i * 31) as u16).to_le_bytes())
+            .collect::<Vec<u8>>();
+        let img = TiffImage::new(width, height, ColorType::L16, data);
+        for max_error in [0u16, 2, 40] {
+            let enc = CTIEncoder::new(CTIConfig {
+                tile_size: 8,
+                compression: CompressionType::NearLossless,
+                max_error,
+                pyramid_levels: 1,
+                ..CTIConfig::default()
+            });
+            let mut cur = Cursor::new(Vec::new());
+            enc.encode_to_writer(&img, &mut cur).unwrap();
+
+            cur.set_position(0);
+            let info = CTIDecoder::info_from_reader(&mut cur).unwrap();
+            assert_eq!(info.max_error, Some(max_error));
+            cur.set_position(0);
+            let decoded = CTIDecoder::decode_from_reader(&mut cur, 0).unwrap();
+            let worst = bytes_to_u16_vec(&decoded.data)
+                .unwrap()
+                .iter()
+                .zip(bytes_to_u16_vec(&img.data).unwrap())
+                .map(|(a, b)| a.abs_diff(b))
+                .max()
+                .unwrap();
+            assert!(worst <= max_error, "error {} exceeds bound {}", worst, max_error);
+            if max_error == 0 {
+                assert_eq!(decoded.data, img.data);
+            }
+            cur.set_position(0);
+            assert!(CTIDecoder::decode_from_reader(&mut cur, 1).is_ok());
         }
 
-        while lane < count {
-            let y = c0[lane] as i32;
-            let cb = c1[lane] as i16 as i32;
-            let cr = c2[lane] as i16 as i32;
-            let g = y - ((cb + cr) >> 2);
-            let r = cr + g;
-            let b = cb + g;
-            c0[lane] = r.clamp(0, 65535) as u16;
-            c1[lane] = g.clamp(0, 65535) as u16;
-            c2[lane] = b.clamp(0, 65535) as u16;
-            lane += 1;
+        let rgb = sample_rgb8();
+        let tile = near_lossless_compress(&rgb.data, 3, (4, 4), 3, 3).unwrap();
+        let restored = near_lossless_decompress(&tile, 3, (4, 4), 3, true).unwrap();
+        assert!(restored.iter().zip(&rgb.data).all(|(a, b)| a.abs_diff(*b) <= 3));
+
+        // A ramp running on across rows is exact for the linear predictor only.
+        let ramp = (0..16u16 * 4).flat_map(|i| (i * 5).to_le_bytes()).collect::<Vec<u8>>();
+        let tile = near_lossless_compress(&ramp, 2, (16, 4), 0, 3).unwrap();
+        assert_eq!(tile[0], NearLosslessPredictor::Predictive as u8);
+        assert_eq!(near_lossless_decompress(&tile, 2, (16, 4), 0, true).unwrap(), ramp);
+        // Tiles of 2-byte NLER files have no predictor id and use Gradient.
+        let flat = vec![9u8; 16 * 4 * 2];
+        let tile = near_lossless_compress(&flat, 2, (16, 4), 0, 3).unwrap();
+        assert_eq!(tile[0], NearLosslessPredictor::Gradient as u8);
+        assert_eq!(near_lossless_decompress(&tile[1..], 2, (16, 4), 0, false).unwrap(), flat);
+    }
+
+    #[test]
+    fn wavelet53_roundtrip_and_adaptive_selection() {
+        let mut seed = 0x1234_5678u32;
+        for &(w, h, channels, sample_bytes) in &[(1, 1, 1, 1), (1, 7, 3, 2), (9, 1, 1, 2), (13, 6, 3, 1), (32, 33, 4, 1)] {
+            let data = (0..w * h * channels * sample_bytes)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    (seed >> 16) as u8
+                })
+                .collect::<Vec<u8>>();
+            let coeffs = wavelet53_forward(&data, w, channels, sample_bytes).unwrap();
+            assert_eq!(coeffs.len(), data.len());
+            assert_eq!(wavelet53_inverse(&coeffs, w, channels, sample_bytes).unwrap(), data);
         }
 
-        for lane in 0..count {
-            let base = (pixel_idx + lane) * 6;
-            buf[base..base + 2].copy_from_slice(&c0[lane].to_le_bytes());
-            buf[base + 2..base + 4].copy_from_slice(&c1[lane].to_le_bytes());
-            buf[base + 4..base + 6].copy_from_slice(&c2[lane].to_le_bytes());
+        let (width, height) = (64u32, 64u32);
+        let mut data = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let v = |k: u32| (((x * k + y * 3) as f32 / 9.0).sin() * 20000.0 + 30000.0) as u16;
+                for k in [5, 7, 11] {
+                    data.extend_from_slice(&v(k).to_le_bytes());
+                }
+            }
         }
+        let img = TiffImage::new(width, height, ColorType::Rgb16, data);
+        let enc = CTIEncoder::new(CTIConfig {
+            tile_size: 32,
+            compression: CompressionType::Adaptive,
+            color_transform: true,
+            ..CTIConfig::default()
+        });
+        let mut cur = Cursor::new(Vec::new());
+        enc.encode_to_writer(&img, &mut cur).unwrap();
+        let decoded = CTIDecoder::decode_from_reader(&mut Cursor::new(cur.into_inner()), 0).unwrap();
+        assert_eq!(decoded.data, img.data);
+        let tmod = decoded.sections.iter().find(|sec| sec.ty == SEC_TYPE_TMOD).unwrap();
+        assert!(tmod.payload.contains(&(AdaptiveTileMode::ZstdWavelet53 as u8)));
 
-        pixel_idx += count;
+        let mut tile = extract_tile(&img, 0, 0, (32, 32)).unwrap();
+        rgb16_delta_g_forward(&mut tile);
+        let coeffs = wavelet53_forward(&tile, 32, 3, 2).unwrap();
+        assert_eq!(wavelet53_inverse(&coeffs, 32, 3, 2).unwrap(), tile);
+        assert_eq!(adaptive_tile_mode_name(6), Some("zstd-wavelet53"));
     }
-}
 
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-#[target_feature(enable = "avx2")]
-unsafe fn rgb16_delta_g_forward_avx2(buf: &mut [u8]) {
-    #[cfg(target_arch = "x86")]
-    use std::arch::x86::*;
-    #[cfg(target_arch = "x86_64")]
-    use std::arch::x86_64::*;
+    #[test]
+    fn adaptive_8bit_predictors_roundtrip() {
+        let (width, height, channels) = (19usize, 11usize, 3usize);
+        let data = (0..width * height * channels)
+            .map(|i| ((i * 37) % 251) as u8 ^ ((i / 57) as u8))
+            .collect::<Vec<u8>>();
+        let expected_score = data[channels..].iter().map(|&v| (v as i8).unsigned_abs() as u64).sum::<u64>();
+        assert_eq!(residual_score_u8(&data, channels), expected_score);
+
+        let extent = (width as u32, height as u32);
+        let params = TileCodecParams {
+            zstd_level: 3,
+            quality: 100,
+            max_error: 0,
+            near_lossless_tagged: false,
+            zstd_dict: None,
+        };
+        let delta = delta_forward_u8(&data, channels);
+        let gradient = spatial_forward_u8(&data, width, channels, med_predict_u8).unwrap();
+        let paeth = spatial_forward_u8(&data, width, channels, paeth_predict_u8).unwrap();
+        for (mode, residuals) in [
+            (AdaptiveTileMode::ZstdDelta8, delta),
+            (AdaptiveTileMode::ZstdGradient8, gradient),
+            (AdaptiveTileMode::ZstdPaeth8, paeth),
+        ] {
+            let comp = zstd::bulk::compress(&residuals, 3).unwrap();
+            let restored =
+                decompress_tile_with_size(250, &comp, data.len(), 3, extent, Some(mode), &params).unwrap();
+            assert_eq!(restored, data, "{:?}", mode);
+        }
 
-    let pixels = buf.len() / 6;
-    let block_pixels = rgb_planar_block_pixels(DEFAULT_RGB16_PLANAR_BLOCK_PIXELS);
-    let mut c0 = vec![0u16; block_pixels];
-    let mut c1 = vec![0u16; block_pixels];
-    let mut c2 = vec![0u16; block_pixels];
+        let img = TiffImage::new(40, 24, ColorType::L8, (0..40u32 * 24).map(|i| ((i % 40) * 3 + (i / 40) * 5) as u8).collect());
+        let enc = CTIEncoder::new(CTIConfig {
+            tile_size: 16,
+            compression: CompressionType::Adaptive,
+            ..CTIConfig::default()
+        });
+        let mut cur = Cursor::new(Vec::new());
+        enc.encode_to_writer(&img, &mut cur).unwrap();
+        let decoded = CTIDecoder::decode_from_reader(&mut Cursor::new(cur.into_inner()), 0).unwrap();
+        assert_eq!(decoded.data, img.data);
+        let tmod = decoded.sections.iter().find(|sec| sec.ty == SEC_TYPE_TMOD).unwrap();
+        assert!(tmod.payload.iter().all(|&mode| mode != AdaptiveTileMode::ZstdRaw as u8));
+    }
 
-    let mut pixel_idx = 0usize;
-    while pixel_idx < pixels {
-        let count = block_pixels.min(pixels - pixel_idx);
-        for lane in 0..count {
-            let base = (pixel_idx + lane) * 6;
-            c0[lane] = u16::from_le_bytes([buf[base], buf[base + 1]]);
-            c1[lane] = u16::from_le_bytes([buf[base + 2], buf[base + 3]]);
-            c2[lane] = u16::from_le_bytes([buf[base + 4], buf[base + 5]]);
+    #[test]
+    fn zstd_dictionary_is_stored_and_used_per_tile() {
+        let (width, height) = (256u32, 192u32);
+        let mut seed = 0x2545_f491u32;
+        let mut data = Vec::with_capacity((width * height * 3) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let glyph = ((x % 32) * 7 + (y % 32) * 13) % 97;
+                let v = if glyph < 40 { 30 } else { 220 } + (seed >> 29) as u8;
+                data.extend_from_slice(&[v, v.wrapping_add(3), v.wrapping_sub(5)]);
+            }
         }
-
-        let mut lane = 0usize;
-        while lane + 16 <= count {
-            let r = unsafe { _mm256_loadu_si256(c0.as_ptr().add(lane) as *const __m256i) };
-            let g = unsafe { _mm256_loadu_si256(c1.as_ptr().add(lane) as *const __m256i) };
-            let b = unsafe { _mm256_loadu_si256(c2.as_ptr().add(lane) as *const __m256i) };
-            let dr = _mm256_sub_epi16(r, g);
-            let db = _mm256_sub_epi16(b, g);
-            unsafe { _mm256_storeu_si256(c0.as_mut_ptr().add(lane) as *mut __m256i, g) };
-            unsafe { _mm256_storeu_si256(c1.as_mut_ptr().add(lane) as *mut __m256i, dr) };
-            unsafe { _mm256_storeu_si256(c2.as_mut_ptr().add(lane) as *mut __m256i, db) };
-            lane += 16;
+        let img = TiffImage::new(width, height, ColorType::Rgb8, data);
+
+        let encode = |zstd_dict_size| {
+            let enc = CTIEncoder::new(CTIConfig {
+                tile_size: 32,
+                compression: CompressionType::Zstd,
+                zstd_dict_size,
+                ..CTIConfig::default()
+            });
+            let mut cur = Cursor::new(Vec::new());
+            enc.encode_to_writer(&img, &mut cur).unwrap();
+            cur.into_inner()
+        };
+        let plain = encode(0);
+        let bytes = encode(4096);
+        assert!(bytes.len() < plain.len(), "{} >= {}", bytes.len(), plain.len());
+
+        let mut reader = CTIReader::new(Cursor::new(bytes)).unwrap();
+        assert!(reader.section_descs().iter().any(|desc| desc.ty == SEC_TYPE_ZDIC));
+        assert_eq!(reader.decode(0).unwrap().data, img.data);
+        for (tx, ty) in [(5, 3), (0, 0), (7, 5)] {
+            let tile = reader.decode_tile(tx, ty, 0).unwrap();
+            assert_eq!(tile.data, extract_tile(&img, tx, ty, (32, 32)).unwrap());
         }
+    }
 
-        while lane < count {
-            let r = c0[lane];
-            let g = c1[lane];
-            let b = c2[lane];
-            c0[lane] = g;
-            c1[lane] = r.wrapping_sub(g);
-            c2[lane] = b.wrapping_sub(g);
-            lane += 1;
+    #[test]
+    fn solid_and_duplicate_tiles_share_storage() {
+        let (width, height) = (72u32, 40u32);
+        let mut data = Vec::with_capacity((width * height * 3) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let px = match x / 16 {
+                    0 => [250, 248, 240],
+                    1 | 3 => [(x % 16 * 9) as u8, (y % 16 * 5) as u8, ((x ^ y) % 16 * 11) as u8],
+                    _ => [(x * 3) as u8, (y * 7) as u8, (x * y) as u8],
+                };
+                data.extend_from_slice(&px);
+            }
         }
-
-        for lane in 0..count {
-            let base = (pixel_idx + lane) * 6;
-            buf[base..base + 2].copy_from_slice(&c0[lane].to_le_bytes());
-            buf[base + 2..base + 4].copy_from_slice(&c1[lane].to_le_bytes());
-            buf[base + 4..base + 6].copy_from_slice(&c2[lane].to_le_bytes());
+        let img = TiffImage::new(width, height, ColorType::Rgb8, data);
+
+        let encode = |dedup_tiles| {
+            let enc = CTIEncoder::new(CTIConfig {
+                tile_size: 16,
+                compression: CompressionType::Adaptive,
+                dedup_tiles,
+                ..CTIConfig::default()
+            });
+            let mut cur = Cursor::new(Vec::new());
+            enc.encode_to_writer(&img, &mut cur).unwrap();
+            cur.into_inner()
+        };
+        let plain = encode(false);
+        let bytes = encode(true);
+        assert!(bytes.len() < plain.len());
+
+        let mut reader = CTIReader::new(Cursor::new(bytes)).unwrap();
+        let info = reader.info().unwrap();
+        // Column 0 is solid; full-height columns 1 and 3 repeat each other.
+        assert_eq!(info.solid_tiles, 3);
+        let offsets = |tx: usize| (0..3).map(|ty| info.indices[ty * 5 + tx].offset).collect::<Vec<_>>();
+        assert_eq!(offsets(1)[..2], offsets(3)[..2]);
+        assert_eq!(offsets(0)[0], offsets(0)[1]);
+        assert_eq!(reader.decode(0).unwrap().data, img.data);
+        for (tx, ty) in [(3, 1), (0, 2), (4, 2)] {
+            let tile = reader.decode_tile(tx, ty, 0).unwrap();
+            assert_eq!(tile.data, extract_tile(&img, tx, ty, (16, 16)).unwrap());
         }
-
-        pixel_idx += count;
     }
-}
 
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-#[target_feature(enable = "avx2")]
-unsafe fn rgb16_delta_g_inverse_avx2(buf: &mut [u8]) {
-    #[cfg(target_arch = "x86")]
-    use std::arch::x86::*;
-    #[cfg(target_arch = "x86_64")]
-    use std::arch::x86_64::*;
+    #[test]
+    fn fixity_digests_are_stored_and_recomputed() {
+        let (width, height) = (37u32, 22u32);
+        let img = TiffImage::new(width, height, ColorType::Rgb8, (0..width * height * 3).map(|i| (i * 7 + i / 111) as u8).collect());
+        let enc = CTIEncoder::new(CTIConfig {
+            tile_size: 16,
+            tile_height: Some(8),
+            compression: CompressionType::Adaptive,
+            fixity: FixityScope::Tiles,
+            ..CTIConfig::default()
+        });
+        let mut cur = Cursor::new(Vec::new());
+        enc.encode_to_writer(&img, &mut cur).unwrap();
 
-    let pixels = buf.len() / 6;
-    let block_pixels = rgb_planar_block_pixels(DEFAULT_RGB16_PLANAR_BLOCK_PIXELS);
-    let mut c0 = vec![0u16; block_pixels];
-    let mut c1 = vec![0u16; block_pixels];
-    let mut c2 = vec![0u16; block_pixels];
+        let mut reader = CTIReader::new(Cursor::new(cur.into_inner())).unwrap();
+        let stored = reader.fixity().unwrap().unwrap();
+        assert_eq!(stored.image, enc.image_fixity(&img).unwrap());
+        assert_eq!(stored.tiles.len(), 9);
+        assert_eq!(
+            stored.tiles[8],
+            <Sha256Digest>::from(Sha256::digest(extract_tile(&img, 2, 2, (16, 8)).unwrap()))
+        );
+        let report = reader.verify_fixity().unwrap().unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.computed, stored);
 
-    let mut pixel_idx = 0usize;
-    while pixel_idx < pixels {
-        let count = block_pixels.min(pixels - pixel_idx);
-        for lane in 0..count {
-            let base = (pixel_idx + lane) * 6;
-            c0[lane] = u16::from_le_bytes([buf[base], buf[base + 1]]);
-            c1[lane] = u16::from_le_bytes([buf[base + 2], buf[base + 3]]);
-            c2[lane] = u16::from_le_bytes([buf[base + 4], buf[base + 5]]);
-        }
+        let lossy = CTIEncoder::new(CTIConfig {
+            compression: CompressionType::Dct,
+            fixity: FixityScope::Image,
+            ..CTIConfig::default()
+        });
+        assert!(lossy.encode_to_writer(&img, &mut Cursor::new(Vec::new())).is_err());
+    }
 
-        let mut lane = 0usize;
-        while lane + 16 <= count {
-            let g = unsafe { _mm256_loadu_si256(c0.as_ptr().add(lane) as *const __m256i) };
-            let dr = unsafe { _mm256_loadu_si256(c1.as_ptr().add(lane) as *const __m256i) };
-            let db = unsafe { _mm256_loadu_si256(c2.as_ptr().add(lane) as *const __m256i) };
-            let r = _mm256_add_epi16(g, dr);
-            let b = _mm256_add_epi16(g, db);
-            unsafe { _mm256_storeu_si256(c0.as_mut_ptr().add(lane) as *mut __m256i, r) };
-            unsafe { _mm256_storeu_si256(c1.as_mut_ptr().add(lane) as *mut __m256i, g) };
-            unsafe { _mm256_storeu_si256(c2.as_mut_ptr().add(lane) as *mut __m256i, b) };
-            lane += 16;
+    #[test]
+    fn writer_accepts_tiles_in_any_order() {
+        let (width, height) = (37u32, 22u32);
+        let img = TiffImage::new(width, height, ColorType::Rgb16, (0..width * height * 3).flat_map(|i| ((i * 131) as u16 ^ (i / 5) as u16).to_le_bytes()).collect());
+        let config = CTIConfig {
+            tile_size: 16,
+            tile_height: Some(8),
+            compression: CompressionType::Adaptive,
+            color_transform: true,
+            fixity: FixityScope::Tiles,
+            ..CTIConfig::default()
+        };
+        let mut writer =
+            CTIWriter::new(Cursor::new(Vec::new()), width, height, ColorType::Rgb16, config.clone()).unwrap();
+        let (tiles_x, tiles_y) = (writer.header().tiles_x, writer.header().tiles_y);
+        let mut order: Vec<(u32, u32)> = (0..tiles_y).flat_map(|ty| (0..tiles_x).map(move |tx| (tx, ty))).collect();
+        order.reverse();
+        order.swap(1, 4);
+        let (last, rest) = order.split_last().unwrap();
+        for &(tx, ty) in rest {
+            writer.write_tile(tx, ty, &extract_tile(&img, tx, ty, (16, 8)).unwrap()).unwrap();
         }
+        let tile = extract_tile(&img, 0, 1, (16, 8)).unwrap();
+        assert!(matches!(writer.write_tile(0, 1, &tile), Err(CtiError::InvalidInput(_))));
+        assert!(matches!(writer.write_tile(3, 0, &tile), Err(CtiError::OutOfRange(_))));
+        assert!(writer.write_tile(last.0, last.1, &tile[1..]).is_err());
+        writer.write_tile(last.0, last.1, &extract_tile(&img, last.0, last.1, (16, 8)).unwrap()).unwrap();
+        let bytes = writer.finish(&[(SEC_TYPE_RES, vec![1, 2, 3])]).unwrap().into_inner();
+
+        assert!(verify_cti(&mut Cursor::new(bytes.clone())).unwrap().is_ok());
+        let mut reader = CTIReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(reader.decode(0).unwrap().data, img.data);
+        let sections = reader.sections().unwrap();
+        assert_eq!(sections[0].ty, SEC_TYPE_TMOD);
+        assert!(sections.iter().any(|s| s.ty == SEC_TYPE_RES && s.payload == [1, 2, 3]));
+        let report = reader.verify_fixity().unwrap().unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.stored.image, CTIEncoder::new(config.clone()).image_fixity(&img).unwrap());
+
+        let writer = CTIWriter::new(Cursor::new(Vec::new()), width, height, ColorType::Rgb16, config).unwrap();
+        assert!(matches!(writer.finish(&[]), Err(CtiError::InvalidInput(_))));
+    }
 
-        while lane < count {
-            let g = c0[lane];
-            let dr = c1[lane];
-            let db = c2[lane];
-            c0[lane] = g.wrapping_add(dr);
-            c1[lane] = g;
-            c2[lane] = g.wrapping_add(db);
-            lane += 1;
-        }
+    #[test]
+    fn tile_sources_stream_png_and_raw() {
+        let (width, height) = (37u32, 22u32);
+        let img = TiffImage::new(width, height, ColorType::Rgb16, (0..width * height * 3).flat_map(|i| ((i * 131) as u16 ^ (i / 5) as u16).to_le_bytes()).collect());
+        let enc = CTIEncoder::new(CTIConfig {
+            tile_size: 16,
+            tile_height: Some(8),
+            compression: CompressionType::Adaptive,
+            zstd_dict_size: 4096,
+            pyramid_levels: 1,
+            ..CTIConfig::default()
+        });
+        let base = std::env::temp_dir().join(format!("cti_source_test_{}", std::process::id()));
+        let (png_path, raw_path, out_path) =
+            (base.with_extension("png"), base.with_extension("raw"), base.with_extension("cti"));
+        image::save_buffer(&png_path, &img.data, width, height, image::ExtendedColorType::Rgb16).unwrap();
+        std::fs::write(&raw_path, &img.data).unwrap();
+        let half = downsample_half(&img).unwrap();
+
+        let mut png = PngRowSource::open(&png_path).unwrap();
+        assert_eq!((png.dimensions(), png.color_type()), ((width, height), ColorType::Rgb16));
+        enc.encode_source_to_cti(&mut png, &out_path).unwrap();
+        let mut reader = CTIDecoder::open(&out_path).unwrap();
+        assert_eq!(reader.decode(0).unwrap().data, img.data);
+        assert_eq!(reader.decode(1).unwrap().data, half.data);
+
+        let mut raw = RawTileSource::open(&raw_path, width, height, ColorType::Rgb16).unwrap();
+        assert_eq!(raw.read_tile(2, 2, (16, 8)).unwrap(), extract_tile(&img, 2, 2, (16, 8)).unwrap());
+        assert!(matches!(raw.read_tile(3, 0, (16, 8)), Err(CtiError::OutOfRange(_))));
+        enc.encode_source_to_cti(&mut raw, &out_path).unwrap();
+        let mut reader = CTIDecoder::open(&out_path).unwrap();
+        assert_eq!(reader.decode(0).unwrap().data, img.data);
+        assert_eq!(reader.decode(1).unwrap().data, half.data);
+        assert!(matches!(
+            RawTileSource::open(&raw_path, width, height + 1, ColorType::Rgb16),
+            Err(CtiError::InvalidInput(_))
+        ));
 
-        for lane in 0..count {
-            let base = (pixel_idx + lane) * 6;
-            buf[base..base + 2].copy_from_slice(&c0[lane].to_le_bytes());
-            buf[base + 2..base + 4].copy_from_slice(&c1[lane].to_le_bytes());
-            buf[base + 4..base + 6].copy_from_slice(&c2[lane].to_le_bytes());
+        enc.encode_path_to_cti(&png_path, &out_path).unwrap();
+        assert_eq!(CTIDecoder::open(&out_path).unwrap().decode(0).unwrap().data, img.data);
+        for path in [png_path, raw_path, out_path] {
+            remove_file(path).unwrap();
         }
+    }
+
+    #[test]
+    fn rectangular_tiles_use_v2_header() {
+        let (width, height) = (37u32, 22u32);
+        let img = TiffImage::new(width, height, ColorType::L16, (0..width * height).flat_map(|i| ((i * 37) as u16 ^ (i / 7) as u16).to_le_bytes()).collect());
+        let enc = CTIEncoder::new(CTIConfig {
+            tile_size: 16,
+            tile_height: Some(4),
+            compression: CompressionType::Adaptive,
+            pyramid_levels: 1,
+            ..CTIConfig::default()
+        });
+        let mut cur = Cursor::new(Vec::new());
+        enc.encode_to_writer(&img, &mut cur).unwrap();
+        let mut bytes = cur.into_inner();
 
-        pixel_idx += count;
+        let mut reader = CTIReader::new(Cursor::new(bytes.clone())).unwrap();
+        let hdr = reader.header();
+        assert_eq!((hdr.version, hdr.tile_dims()), (2, (16, 4)));
+        assert_eq!((hdr.tiles_x, hdr.tiles_y), (3, 6));
+        assert_eq!(reader.decode(0).unwrap().data, img.data);
+        let tile = reader.decode_tile(2, 5, 0).unwrap();
+        assert_eq!((tile.width, tile.height), (5, 2));
+        assert_eq!(tile.data, extract_tile(&img, 2, 5, (16, 4)).unwrap());
+        let region = reader.extract_region(13, 3, 20, 9, 0).unwrap();
+        let crop = (3..12usize)
+            .flat_map(|y| img.data[(y * 37 + 13) * 2..(y * 37 + 33) * 2].to_vec())
+            .collect::<Vec<u8>>();
+        assert_eq!(region.data, crop);
+        assert_eq!(reader.decode(1).unwrap().data, downsample_half(&img).unwrap().data);
+        assert_eq!(reader.level_header(1).unwrap().version, 1);
+
+        bytes[4..6].copy_from_slice(&7u16.to_le_bytes());
+        let err = CTIReader::new(Cursor::new(bytes)).err().unwrap();
+        assert!(err.to_string().contains("Unsupported CTI version 7"));
     }
-}
-
-pub fn crc32(data: &[u8]) -> u32 {
-    let mut h = crc32fast::Hasher::new();
-    h.update(data);
-    h.finalize()
-}
-
-fn read_tiff_metadata_for_sections(
-    path: &Path,
-) -> Result<(Option<f32>, Option<f32>, Option<Vec<u8>>)> {
-    use tiff::decoder::Decoder;
-    use tiff::tags::Tag;
 
-    let f = File::open(path)?;
-    let mut dec = Decoder::new(std::io::BufReader::new(f))?;
+    #[test]
+    fn corruption_is_detected() {
+        let enc = CTIEncoder::new(CTIConfig::default());
+        let img = sample_rgb8();
+        let mut cur = Cursor::new(Vec::new());
+        enc.encode_to_writer(&img, &mut cur).unwrap();
 
-    let unit = dec.get_tag(Tag::ResolutionUnit).ok();
-    let xr = dec.get_tag(Tag::XResolution).ok();
-    let yr = dec.get_tag(Tag::YResolution).ok();
-    let icc = dec.get_tag_u8_vec(Tag::IccProfile).ok();
+        let mut bytes = cur.into_inner();
+        let mut info_cur = Cursor::new(bytes.clone());
+        let info = CTIDecoder::info_from_reader(&mut info_cur).unwrap();
+        let off = info.indices[0].offset as usize;
+        bytes[off] ^= 0x5A;
 
-    let mut xdpi: Option<f32> = None;
-    let mut ydpi: Option<f32> = None;
+        let mut bad_cur = Cursor::new(bytes);
+        assert!(CTIDecoder::decode_from_reader(&mut bad_cur, 0).is_err());
+    }
 
-    if let (Some(unit_v), Some(xv), Some(yv)) = (unit, xr, yr) {
-        let xf = rational_to_f32(&xv);
-        let yf = rational_to_f32(&yv);
-        if let Some(code) = short_first(&unit_v) {
-            match code {
-                2 => {
-                    xdpi = Some(xf);
-                    ydpi = Some(yf);
-                }
-                3 => {
-                    xdpi = Some(xf * 2.54);
-                    ydpi = Some(yf * 2.54);
-                }
-                _ => {}
+    #[test]
+    fn alpha_color_types_round_trip() {
+        let (width, height) = (37u32, 22u32);
+        let dir = std::env::temp_dir();
+        for (color_type, id) in [(ColorType::La8, 6u8), (ColorType::La16, 7), (ColorType::Rgba16, 8)] {
+            let bpp = bytes_per_pixel(&color_type).unwrap();
+            let img = TiffImage::new(width, height, color_type, (0..width * height * bpp).map(|i| ((i * 37) ^ (i / 11)) as u8).collect());
+            let enc = CTIEncoder::new(CTIConfig {
+                tile_size: 16,
+                tile_height: Some(8),
+                compression: CompressionType::Adaptive,
+                color_transform: true,
+                pyramid_levels: 1,
+                ..CTIConfig::default()
+            });
+            let mut out = Cursor::new(Vec::new());
+            enc.encode_to_writer(&img, &mut out).unwrap();
+            let mut reader = CTIReader::new(Cursor::new(out.into_inner())).unwrap();
+            assert_eq!(reader.header().color_type, id);
+            assert_eq!(
+                reader.header().flags & FLAG_COLOR_RGB16_DELTA_G != 0,
+                color_type == ColorType::Rgba16
+            );
+            assert_eq!(reader.decode(0).unwrap().data, img.data);
+            assert_eq!(reader.decode(1).unwrap().data, downsample_half(&img).unwrap().data);
+
+            for ext in ["tif", "png"] {
+                let path = dir.join(format!("cti_alpha_test_{}_{}.{}", std::process::id(), id, ext));
+                save_raster(&path, width, height, id, &img.data).unwrap();
+                assert_eq!(enc.inspect_input(&path).unwrap().color_type, color_type);
+                assert_eq!(enc.load_image(&path).unwrap().data, img.data);
+                let cti_path = path.with_extension("cti");
+                enc.encode_path_to_cti(&path, &cti_path).unwrap();
+                assert_eq!(CTIDecoder::open(&cti_path).unwrap().decode(0).unwrap().data, img.data);
+                remove_file(path).unwrap();
+                remove_file(cti_path).unwrap();
             }
         }
     }
 
-    Ok((xdpi, ydpi, icc))
-}
+    #[test]
+    fn float_color_types_round_trip() {
+        let (width, height) = (37u32, 22u32);
+        let dir = std::env::temp_dir();
+        for (color_type, id) in [(ColorType::L32F, 9u8), (ColorType::Rgb32F, 10)] {
+            let channels = sample_layout(color_type).unwrap().0 as u32;
+            let img = TiffImage::new(
+                width,
+                height,
+                color_type,
+                (0..width * height * channels)
+                    .flat_map(|i| (((i % 111) as f32 * 0.37).sin() * 1.0e3 + (i / 111) as f32).to_le_bytes())
+                    .collect(),
+            );
+            let config = CTIConfig {
+                tile_size: 16,
+                tile_height: Some(8),
+                compression: CompressionType::Adaptive,
+                pyramid_levels: 1,
+                ..CTIConfig::default()
+            };
+            let mut out = Cursor::new(Vec::new());
+            CTIEncoder::new(config.clone()).encode_to_writer(&img, &mut out).unwrap();
+            let mut bytes = out.into_inner();
+            let mut reader = CTIReader::new(Cursor::new(bytes.clone())).unwrap();
+            assert_eq!(reader.header().color_type, id);
+            assert_eq!(reader.decode(0).unwrap().data, img.data);
+            assert_eq!(reader.decode(1).unwrap().data, downsample_half(&img).unwrap().data);
+
+            // Recovery fills a damaged float tile with whole f32 samples.
+            let first = reader.info().unwrap().indices[0];
+            bytes[first.offset as usize] ^= 0x5A;
+            let recovered = CTIReader::new(Cursor::new(bytes)).unwrap().decode_recover(0, 3).unwrap();
+            assert_eq!(recovered.failed_tiles, vec![(0, 0)]);
+            let pixel = 3.0f32.to_le_bytes().repeat(channels as usize);
+            let row = (width * channels * 4) as usize;
+            assert_eq!(recovered.value.data[..pixel.len()], pixel[..]);
+            assert_eq!(recovered.value.data[7 * row + 15 * pixel.len()..][..pixel.len()], pixel[..]);
+            assert_eq!(recovered.value.data[16 * pixel.len()..row], img.data[16 * pixel.len()..row]);
+
+            let lossy = CTIEncoder::new(CTIConfig { compression: CompressionType::Dct, ..config.clone() });
+            assert!(matches!(
+                lossy.encode_to_writer(&img, &mut Cursor::new(Vec::new())),
+                Err(CtiError::Unsupported(_))
+            ));
 
-fn parse_resolution_section(payload: &[u8]) -> Option<(f32, f32)> {
-    if payload.len() != 8 {
-        return None;
+            let path = dir.join(format!("cti_float_test_{}_{}.tif", std::process::id(), id));
+            save_raster(&path, width, height, id, &img.data).unwrap();
+            assert!(save_raster(path.with_extension("png"), width, height, id, &img.data).is_err());
+            let enc = CTIEncoder::new(config);
+            assert_eq!(enc.inspect_input(&path).unwrap().color_type, color_type);
+            assert_eq!(enc.load_image(&path).unwrap().data, img.data);
+            let cti_path = path.with_extension("cti");
+            enc.encode_path_to_cti(&path, &cti_path).unwrap();
+            assert_eq!(CTIDecoder::open(&cti_path).unwrap().decode(1).unwrap().data, downsample_half(&img).unwrap().data);
+            remove_file(path).unwrap();
+            remove_file(cti_path).unwrap();
+        }
     }
-    let x = f32::from_le_bytes(payload[0..4].try_into().ok()?);
-    let y = f32::from_le_bytes(payload[4..8].try_into().ok()?);
-    Some((x, y))
-}
 
-fn rational_to_f32(v: &tiff::decoder::ifd::Value) -> f32 {
-    use tiff::decoder::ifd::Value::*;
-    match v {
-        Rational(n, d) => {
-            if *d == 0 {
-                0.0
-            } else {
-                *n as f32 / *d as f32
-            }
+    #[test]
+    fn cmyk_and_band_color_types_round_trip() {
+        let (width, height) = (37u32, 22u32);
+        let dir = std::env::temp_dir();
+        let names = |prefix: &str, n: usize| (1..=n).map(|i| format!("{prefix}{i}")).collect::<Vec<_>>();
+        let cases = [
+            (ColorType::Cmyk8, 11u8, BandInfo {
+                photometric: 5,
+                ink_set: 1,
+                extra_samples: vec![],
+                names: ["Cyan", "Magenta", "Yellow", "Black"].map(String::from).to_vec(),
+            }),
+            (ColorType::Cmyk16, 12, BandInfo { photometric: 5, ink_set: 1, ..BandInfo::default() }),
+            (ColorType::Bands16(5), 0x84, BandInfo {
+                photometric: 5,
+                ink_set: 2,
+                extra_samples: vec![2],
+                names: names("Ink", 5),
+            }),
+            (ColorType::Bands8(8), 0x47, BandInfo {
+                photometric: 1,
+                ink_set: 0,
+                extra_samples: vec![0; 7],
+                names: names("B", 8),
+            }),
+        ];
+        assert!(color_type_to_id(ColorType::Bands8(0)).is_err());
+        assert!(image::ColorType::try_from(ColorType::Cmyk16).is_err());
+        for ct in [ColorType::La16, ColorType::Rgba16, ColorType::Rgb32F] {
+            assert_eq!(ColorType::try_from(image::ColorType::try_from(ct).unwrap()).unwrap(), ct);
         }
-        SRational(n, d) => {
-            if *d == 0 {
-                0.0
-            } else {
-                *n as f32 / *d as f32
-            }
+        assert!(color_type_to_id(ColorType::Bands16(MAX_BANDS + 1)).is_err());
+        for (color_type, id, bands) in cases {
+            assert_eq!(color_type_from_id(id).unwrap(), color_type);
+            let bpp = bytes_per_pixel(&color_type).unwrap();
+            let img = TiffImage {
+                bands: Some(bands.clone()),
+                ..TiffImage::new(width, height, color_type, (0..width * height * bpp).map(|i| ((i % 97) * 3 + i / 301) as u8).collect())
+            };
+            let config = CTIConfig {
+                tile_size: 16,
+                tile_height: Some(8),
+                compression: CompressionType::Adaptive,
+                pyramid_levels: 1,
+                ..CTIConfig::default()
+            };
+            let mut out = Cursor::new(Vec::new());
+            CTIEncoder::new(config.clone()).encode_to_writer(&img, &mut out).unwrap();
+            let mut reader = CTIReader::new(Cursor::new(out.into_inner())).unwrap();
+            assert_eq!(reader.header().color_type, id);
+            assert_eq!(reader.decode(0).unwrap().data, img.data);
+            assert_eq!(reader.decode(1).unwrap().data, downsample_half(&img).unwrap().data);
+            assert_eq!(reader.metadata().unwrap().bands.as_ref(), Some(&bands));
+            assert_eq!(reader.info().unwrap().bands.as_ref(), Some(&bands));
+
+            let path = dir.join(format!("cti_band_test_{}_{}.tif", std::process::id(), id));
+            let metadata = SourceMetadata { bands: Some(bands.clone()), ..SourceMetadata::default() };
+            save_raster_with_metadata(&path, width, height, id, &img.data, &metadata).unwrap();
+            assert!(save_raster(path.with_extension("png"), width, height, id, &img.data).is_err());
+            let enc = CTIEncoder::new(config);
+            assert_eq!(enc.inspect_input(&path).unwrap().color_type, color_type);
+            let loaded = enc.load_image(&path).unwrap();
+            assert_eq!((loaded.color_type, &loaded.data), (color_type, &img.data));
+            assert_eq!(loaded.bands.as_ref(), Some(&bands));
+            let cti_path = path.with_extension("cti");
+            enc.encode_path_to_cti(&path, &cti_path).unwrap();
+            let mut streamed = CTIDecoder::open(&cti_path).unwrap();
+            assert_eq!(streamed.decode(0).unwrap().data, img.data);
+            assert_eq!(streamed.metadata().unwrap().bands, Some(bands));
+            remove_file(path).unwrap();
+            remove_file(cti_path).unwrap();
         }
-        Short(s) => *s as f32,
-        Float(f) => *f,
-        Double(f) => *f as f32,
-        _ => 0.0,
-    }
-}
 
-fn short_first(v: &tiff::decoder::ifd::Value) -> Option<u32> {
-    use tiff::decoder::ifd::Value::*;
-    match v {
-        Short(s) => Some(*s as u32),
-        _ => None,
+        // The raw TIFF path used for CMYK keeps the embedded ICC profile.
+        let path = dir.join(format!("cti_cmyk_icc_test_{}.tif", std::process::id()));
+        let icc = (0..132).map(|i| i as u8).collect::<Vec<_>>();
+        let data = (0..width * height * 4).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        {
+            let mut encoder = TiffEncoder::new(BufWriter::new(File::create(&path).unwrap())).unwrap();
+            let mut image = encoder.new_image::<tiff_colortype::CMYK8>(width, height).unwrap();
+            image.encoder().write_tag(TiffTag::IccProfile, icc.as_slice()).unwrap();
+            image.write_data(&data).unwrap();
+        }
+        let loaded = CTIEncoder::new(CTIConfig::default()).load_image(&path).unwrap();
+        assert_eq!((loaded.color_type, &loaded.data), (ColorType::Cmyk8, &data));
+        assert_eq!(loaded.icc, Some(icc));
+        remove_file(path).unwrap();
     }
-}
 
-fn bytes_to_u16_vec(data: &[u8]) -> Result<Vec<u16>> {
-    ensure!(data.len() % 2 == 0, "16-bit data must have even byte length");
-    Ok(data
-        .chunks_exact(2)
-        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
-        .collect())
-}
+    #[test]
+    fn bilevel_round_trip() {
+        let (width, height) = (83u32, 45u32);
+        let data: Vec<u8> = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let ink = (y % 11 < 7 && (x / 2 + y / 3) % 5 == 0) || (y % 11 == 8 && x % 13 < 9);
+                if ink { 0 } else { 255 }
+            })
+            .collect();
+        let img = TiffImage::new(width, height, ColorType::L1, data.clone());
+        let config = CTIConfig {
+            tile_size: 32,
+            tile_height: Some(16),
+            compression: CompressionType::Bilevel,
+            pyramid_levels: 1,
+            ..CTIConfig::default()
+        };
+        for compression in [CompressionType::Bilevel, CompressionType::Adaptive] {
+            let mut out = Cursor::new(Vec::new());
+            CTIEncoder::new(CTIConfig { compression, ..config.clone() })
+                .encode_to_writer(&img, &mut out)
+                .unwrap();
+            let mut reader = CTIReader::new(Cursor::new(out.into_inner())).unwrap();
+            assert_eq!(reader.header().color_type, 13);
+            assert_eq!(reader.decode(0).unwrap().data, data);
+            let level = reader.decode(1).unwrap().data;
+            assert_eq!(level, downsample_half(&img).unwrap().data);
+            assert!(level.iter().all(|&v| v == 0 || v == 255));
+            let stored: u64 = reader.info().unwrap().indices.iter().map(|idx| idx.compressed_size as u64).sum();
+            assert!(stored * 8 < (width * height) as u64, "{compression:?}: {stored} bytes");
+        }
 
-fn u16_vec_to_bytes(data: &[u16]) -> Vec<u8> {
-    let mut out = Vec::with_capacity(data.len() * 2);
-    for value in data {
-        out.extend_from_slice(&value.to_le_bytes());
+        let mut tile = data[..32 * 16].to_vec();
+        let comp = bilevel_compress(&tile, (32, 16)).unwrap();
+        assert_eq!(bilevel_decompress(&comp, (32, 16)).unwrap(), tile);
+        tile[5] = 7;
+        assert!(matches!(bilevel_compress(&tile, (32, 16)), Err(CtiError::InvalidInput(_))));
+        let sink = || Cursor::new(Vec::new());
+        let dct = CTIEncoder::new(CTIConfig { compression: CompressionType::Dct, ..config.clone() });
+        assert!(matches!(dct.encode_to_writer(&img, &mut sink()), Err(CtiError::Unsupported(_))));
+        let gray = TiffImage { color_type: ColorType::L8, ..img.clone() };
+        let bilevel = CTIEncoder::new(config.clone());
+        assert!(matches!(bilevel.encode_to_writer(&gray, &mut sink()), Err(CtiError::Unsupported(_))));
+
+        let path = std::env::temp_dir().join(format!("cti_bilevel_test_{}.tif", std::process::id()));
+        save_raster(&path, width, height, 13, &data).unwrap();
+        assert_eq!(bilevel.inspect_input(&path).unwrap().color_type, ColorType::L1);
+        let loaded = bilevel.load_image(&path).unwrap();
+        assert_eq!((loaded.color_type, &loaded.data), (ColorType::L1, &data));
+        let cti_path = path.with_extension("cti");
+        bilevel.encode_path_to_cti(&path, &cti_path).unwrap();
+        assert_eq!(CTIDecoder::open(&cti_path).unwrap().decode(0).unwrap().data, data);
+        let png_path = path.with_extension("png");
+        save_raster(&png_path, width, height, 13, &data).unwrap();
+        assert_eq!(bilevel.load_image(&png_path).unwrap().data, data);
+        let (wide, tall) = (1001u32, 600u32);
+        let big: Vec<u8> = (0..wide * tall).map(|i| if (i / 7 + i / wide) % 3 == 0 { 0 } else { 255 }).collect();
+        let strips_path = path.with_extension("strips.tif");
+        save_raster(&strips_path, wide, tall, 13, &big).unwrap();
+        let mut dec = RawTiffDecoder::new(File::open(&strips_path).unwrap()).unwrap();
+        assert_eq!(dec.dimensions().unwrap(), (wide, tall));
+        let byte_counts = dec.get_tag_u32_vec(TiffTag::StripByteCounts).unwrap();
+        assert!(byte_counts.len() > 1);
+        assert_eq!(byte_counts.iter().sum::<u32>(), wide.div_ceil(8) * tall);
+        assert_eq!(bilevel.load_image(&strips_path).unwrap().data, big);
+
+        // Samples other than 0/255 only drop the bilevel adaptive candidate.
+        let mut gray_levels = img.clone();
+        gray_levels.data[3] = 128;
+        let adaptive = CTIEncoder::new(CTIConfig { compression: CompressionType::Adaptive, ..config.clone() });
+        let mut out = Cursor::new(Vec::new());
+        adaptive.encode_to_writer(&gray_levels, &mut out).unwrap();
+        let mut reader = CTIReader::new(Cursor::new(out.into_inner())).unwrap();
+        assert_eq!(reader.decode(0).unwrap().data, gray_levels.data);
+        for p in [path, cti_path, png_path, strips_path] {
+            remove_file(p).unwrap();
+        }
     }
-    out
-}
-
-fn read_u8<R: Read>(r: &mut R) -> Result<u8> {
-    let mut b = [0u8; 1];
-    r.read_exact(&mut b)?;
-    Ok(b[0])
-}
-
-fn read_u16_le<R: Read>(r: &mut R) -> Result<u16> {
-    let mut b = [0u8; 2];
-    r.read_exact(&mut b)?;
-    Ok(u16::from_le_bytes(b))
-}
-
-fn read_u32_le<R: Read>(r: &mut R) -> Result<u32> {
-    let mut b = [0u8; 4];
-    r.read_exact(&mut b)?;
-    Ok(u32::from_le_bytes(b))
-}
-
-fn read_u64_le<R: Read>(r: &mut R) -> Result<u64> {
-    let mut b = [0u8; 8];
-    r.read_exact(&mut b)?;
-    Ok(u64::from_le_bytes(b))
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Cursor;
-
-    fn sample_rgb8() -> TiffImage {
-        TiffImage {
-            width: 4,
-            height: 4,
-            color_type: ColorType::Rgb8,
-            data: vec![
-                10, 20, 30, 40, 50, 60, 70, 80, 90, 100, 110, 120,
-                11, 21, 31, 41, 51, 61, 71, 81, 91, 101, 111, 121,
-                12, 22, 32, 42, 52, 62, 72, 82, 92, 102, 112, 122,
-                13, 23, 33, 43, 53, 63, 73, 83, 93, 103, 113, 123,
-            ],
-            xdpi: Some(300.0),
-            ydpi: Some(300.0),
-            icc: Some(vec![1, 2, 3, 4]),
+    #[test]
+    fn descriptive_metadata_round_trip() {
+        let (width, height) = (19u32, 13u32);
+        let data: Vec<u8> = (0..width * height).map(|i| (i * 7 % 251) as u8).collect();
+        let entry = |tag, field_type, count, value: &[u8]| RawIfdEntry { tag, field_type, count, value: value.to_vec() };
+        let exif = ExifDirectories {
+            big_endian: true,
+            ifd0: vec![entry(271, 2, 5, b"Acme\0"), entry(274, 3, 1, &[0, 6])],
+            exif: Some(vec![entry(33434, 5, 1, &[0, 0, 0, 1, 0, 0, 0, 250])]),
+            gps: None,
+        };
+        let descriptive = DescriptiveMetadata {
+            xmp: Some(b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>".to_vec()),
+            exif: exif.to_blob().unwrap(),
+            iptc: Some(vec![0x1C, 2, 120, 0, 5, b'h', b'e', b'l', b'l', b'o']),
+        };
+        let img = TiffImage {
+            descriptive: descriptive.clone(),
+            ..TiffImage::new(width, height, ColorType::L8, data.clone())
+        };
+        let encoder = CTIEncoder::new(CTIConfig { tile_size: 8, pyramid_levels: 1, ..CTIConfig::default() });
+        let mut out = Cursor::new(Vec::new());
+        encoder.encode_to_writer(&img, &mut out).unwrap();
+        let bytes = out.into_inner();
+        let mut reader = CTIReader::new(Cursor::new(bytes.clone())).unwrap();
+        let metadata = reader.metadata().unwrap();
+        assert_eq!(metadata.descriptive, descriptive);
+        let pylv = *reader.section_descs().iter().find(|desc| desc.ty == SEC_TYPE_PYLV).unwrap();
+        let level = &bytes[pylv.offset as usize..(pylv.offset + pylv.size) as usize];
+        assert!(CTIReader::new(Cursor::new(level)).unwrap().metadata().unwrap().descriptive.is_empty());
+
+        // TIFF export rebases the big-endian EXIF into the little-endian file.
+        let path = std::env::temp_dir().join(format!("cti_descriptive_test_{}.tif", std::process::id()));
+        save_raster_with_metadata(&path, width, height, 1, &data, &metadata).unwrap();
+        let loaded = encoder.load_image(&path).unwrap();
+        assert_eq!(loaded.data, data);
+        assert_eq!((&loaded.descriptive.xmp, &loaded.descriptive.iptc), (&descriptive.xmp, &descriptive.iptc));
+        let exif = ExifDirectories::from_blob(loaded.descriptive.exif.as_deref().unwrap()).unwrap();
+        assert!(!exif.big_endian);
+        let values = exif.ifd0.iter().map(|e| (e.tag, e.value.clone())).collect::<Vec<_>>();
+        assert_eq!(values, [(271, b"Acme\0".to_vec()), (274, vec![6, 0])]);
+        assert_eq!(exif.exif.unwrap()[0].value, [1, 0, 0, 0, 250, 0, 0, 0]);
+        let cti_path = path.with_extension("cti");
+        encoder.encode_path_to_cti(&path, &cti_path).unwrap();
+        assert_eq!(CTIDecoder::open(&cti_path).unwrap().metadata().unwrap().descriptive, loaded.descriptive);
+        let mut bad_exif = metadata.clone();
+        bad_exif.descriptive.exif = Some(b"not a TIFF".to_vec());
+        let bad_path = path.with_extension("bad.tif");
+        assert!(save_raster_with_metadata(&bad_path, width, height, 1, &data, &bad_exif).is_err());
+        assert!(!bad_path.exists());
+
+        let png_path = path.with_extension("png");
+        save_raster_with_metadata(&png_path, width, height, 1, &data, &metadata).unwrap();
+        assert_eq!(encoder.load_image(&png_path).unwrap().data, data);
+        assert_eq!(PngRowSource::open(&png_path).unwrap().metadata().descriptive, descriptive);
+
+        let jpg_path = path.with_extension("jpg");
+        save_raster(&jpg_path, width, height, 1, &data).unwrap();
+        let jpg = std::fs::read(&jpg_path).unwrap();
+        let mut spliced = jpg[..2].to_vec();
+        let mut irb = b"8BIM\x04\x04\0\0".to_vec();
+        irb.extend_from_slice(&(descriptive.iptc.as_ref().unwrap().len() as u32).to_be_bytes());
+        irb.extend_from_slice(descriptive.iptc.as_ref().unwrap());
+        for (marker, signature, payload) in [
+            (0xE1, JPEG_EXIF_SIGNATURE, descriptive.exif.as_ref().unwrap()),
+            (0xE1, JPEG_XMP_SIGNATURE, descriptive.xmp.as_ref().unwrap()),
+            (0xED, JPEG_PHOTOSHOP_SIGNATURE, &irb),
+        ] {
+            spliced.extend_from_slice(&[0xFF, marker]);
+            spliced.extend_from_slice(&((2 + signature.len() + payload.len()) as u16).to_be_bytes());
+            spliced.extend_from_slice(signature);
+            spliced.extend_from_slice(payload);
+        }
+        spliced.extend_from_slice(&jpg[2..]);
+        std::fs::write(&jpg_path, spliced).unwrap();
+        assert_eq!(encoder.load_image(&jpg_path).unwrap().descriptive, descriptive);
+        for p in [path, cti_path, png_path, jpg_path] {
+            remove_file(p).unwrap();
         }
     }
 
-    fn sample_rgb16() -> TiffImage {
-        let mut data = Vec::new();
-        for y in 0..4u16 {
-            for x in 0..4u16 {
-                data.extend_from_slice(&(1000 + x * 17 + y * 5).to_le_bytes());
-                data.extend_from_slice(&(2000 + x * 13 + y * 7).to_le_bytes());
-                data.extend_from_slice(&(3000 + x * 11 + y * 9).to_le_bytes());
+    #[test]
+    fn errors_are_typed() {
+        let enc = CTIEncoder::new(CTIConfig {
+            tile_size: 2,
+            ..CTIConfig::default()
+        });
+        let mut cur = Cursor::new(Vec::new());
+        enc.encode_to_writer(&sample_rgb8(), &mut cur).unwrap();
+        let bytes = cur.into_inner();
+
+        let mut bad = bytes.clone();
+        bad[0] = b'X';
+        assert!(matches!(CTIReader::new(Cursor::new(bad)), Err(CtiError::BadMagic)));
+        let mut bad = bytes.clone();
+        bad[4] = 9;
+        bad[6] &= !(FLAG_CHECKSUMS as u8);
+        assert!(matches!(CTIReader::new(Cursor::new(bad)), Err(CtiError::UnsupportedVersion(9))));
+
+        let mut reader = CTIReader::new(Cursor::new(bytes.clone())).unwrap();
+        assert!(matches!(reader.decode_tile(2, 0, 0), Err(CtiError::OutOfRange(_))));
+        assert!(matches!(reader.decode(1), Err(CtiError::PyramidLevelMissing(1))));
+        let damaged = reader.info().unwrap().indices[3];
+        let mut bad = bytes;
+        bad[(damaged.offset + damaged.compressed_size as u64) as usize - 1] ^= 0x5A;
+        let err = CTIReader::new(Cursor::new(bad)).unwrap().decode(0).unwrap_err();
+        assert!(matches!(err, CtiError::CrcMismatch { tile: 3 }), "{err}");
+    }
+
+    #[test]
+    fn recovery_decode_fills_damaged_tiles() {
+        let (width, height) = (37u32, 22u32);
+        let img = TiffImage::new(width, height, ColorType::L16, (0..width * height).flat_map(|i| ((i * 37) as u16 ^ (i / 7) as u16).to_le_bytes()).collect());
+        let enc = CTIEncoder::new(CTIConfig {
+            tile_size: 16,
+            tile_height: Some(8),
+            pyramid_levels: 1,
+            ..CTIConfig::default()
+        });
+        let mut cur = Cursor::new(Vec::new());
+        enc.encode_to_writer(&img, &mut cur).unwrap();
+        let clean = cur.into_inner();
+        let info = CTIDecoder::info_from_reader(&mut Cursor::new(clean.clone())).unwrap();
+        let mut bytes = clean.clone();
+        let damaged = info.indices[4];
+        bytes[(damaged.offset + damaged.compressed_size as u64) as usize - 1] ^= 0x5A;
+
+        let mut expected = img.data.clone();
+        for y in 8..16usize {
+            for x in 16..32usize {
+                expected[(y * 37 + x) * 2..(y * 37 + x) * 2 + 2].copy_from_slice(&0xABCDu16.to_le_bytes());
             }
         }
-        TiffImage {
-            width: 4,
-            height: 4,
-            color_type: ColorType::Rgb16,
-            data,
-            xdpi: None,
-            ydpi: None,
-            icc: None,
+        let mut reader = CTIReader::new(Cursor::new(bytes.clone())).unwrap();
+        assert!(reader.decode(0).is_err());
+        let recovered = reader.decode_recover(0, 0xABCD).unwrap();
+        assert_eq!(recovered.failed_tiles, vec![(1, 1)]);
+        assert!(recovered.failed_sections.is_empty());
+        assert_eq!(recovered.value.data, expected);
+
+        let mapped = CTIMmapReader::from_bytes(bytes).unwrap();
+        let region = mapped.extract_region_recover(10, 5, 20, 10, 0, 0xABCD).unwrap();
+        assert_eq!(region.failed_tiles, vec![(1, 1)]);
+        let crop = (5..15usize)
+            .flat_map(|y| expected[(y * 37 + 10) * 2..(y * 37 + 30) * 2].to_vec())
+            .collect::<Vec<u8>>();
+        assert_eq!(region.value.data, crop);
+        let level = mapped.decode_recover(1, 0).unwrap();
+        assert!(level.failed_tiles.is_empty());
+        assert_eq!(level.value.data, downsample_half(&img).unwrap().data);
+
+        // A damaged index entry and TOC entry fail the index and TOC CRCs,
+        // which only stop strict decoding.
+        let mut bytes = clean;
+        let entry = CTI_HEADER_SIZE + 4 * TILE_INDEX_ONDISK_SIZE;
+        bytes[entry..entry + 8].copy_from_slice(&(u64::MAX - 3).to_le_bytes());
+        let toc_pos = info.sections[0].offset as usize - info.sections.len() * 20 - 4;
+        assert_ne!(info.sections[0].ty, SEC_TYPE_SCRC);
+        let size = toc_pos + 4 + 12;
+        bytes[size..size + 8].copy_from_slice(&(info.sections[0].size - 1).to_le_bytes());
+        let mut reader = CTIReader::new(Cursor::new(bytes)).unwrap();
+        let err = reader.decode(0).unwrap_err();
+        assert!(err.to_string().contains("Tile index CRC mismatch"), "{err}");
+        assert!(reader.sections().is_err());
+        let recovered = reader.decode_recover(0, 0xABCD).unwrap();
+        assert_eq!(recovered.failed_tiles, vec![(1, 1)]);
+        assert_eq!(recovered.failed_sections, vec![0]);
+        assert_eq!(recovered.value.data, expected);
+
+        // Float samples are filled with the fill value as f32.
+        let samples = (0..width * height).flat_map(|i| (i as f32 * 0.25 - 40.0).to_le_bytes()).collect::<Vec<u8>>();
+        let float = TiffImage::new(width, height, ColorType::L32F, samples);
+        let mut cur = Cursor::new(Vec::new());
+        enc.encode_to_writer(&float, &mut cur).unwrap();
+        let mut bytes = cur.into_inner();
+        let damaged = CTIDecoder::info_from_reader(&mut Cursor::new(bytes.clone())).unwrap().indices[4];
+        bytes[(damaged.offset + damaged.compressed_size as u64) as usize - 1] ^= 0x5A;
+        let mut expected = float.data.clone();
+        for y in 8..16usize {
+            for x in 16..32usize {
+                expected[(y * 37 + x) * 4..(y * 37 + x) * 4 + 4].copy_from_slice(&7.0f32.to_le_bytes());
+            }
         }
+        let recovered = CTIReader::new(Cursor::new(bytes)).unwrap().decode_recover(0, 7).unwrap();
+        assert_eq!(recovered.failed_tiles, vec![(1, 1)]);
+        assert_eq!(recovered.value.data, expected);
     }
 
     #[test]
-    fn roundtrip_sections_and_pyramid_work() {
-        let cfg = CTIConfig {
+    fn decode_limits_reject_hostile_layouts() {
+        let enc = CTIEncoder::new(CTIConfig {
             tile_size: 2,
-            compression: CompressionType::Zstd,
-            color_transform: true,
-            pyramid_levels: 2,
+            pyramid_levels: 1,
             ..CTIConfig::default()
-        };
-        let enc = CTIEncoder::new(cfg);
-        let img = sample_rgb8();
+        });
         let mut cur = Cursor::new(Vec::new());
-        enc.encode_to_writer(&img, &mut cur).unwrap();
+        enc.encode_to_writer(&sample_rgb8(), &mut cur).unwrap();
+        let mut bytes = cur.into_inner();
+        // Drop the checksum flag so the structural checks are what fails.
+        bytes[6] &= !(FLAG_CHECKSUMS as u8);
+        let info = CTIDecoder::info_from_reader(&mut Cursor::new(bytes.clone())).unwrap();
+        let toc_pos = info.sections[0].offset as usize - info.sections.len() * 20 - 4;
+        let pylv = info.sections.iter().position(|desc| desc.ty == SEC_TYPE_PYLV).unwrap();
+
+        let open = |patch: &[(usize, &[u8])], limits: DecodeLimits, expected: &str| {
+            let mut bad = bytes.clone();
+            for (pos, data) in patch {
+                bad[*pos..*pos + data.len()].copy_from_slice(data);
+            }
+            let err = match CTIReader::with_limits(Cursor::new(bad), limits) {
+                Ok(_) => panic!("expected '{expected}'"),
+                Err(err) => err,
+            };
+            assert!(err.to_string().contains(expected), "{err}");
+        };
+        let max = u32::MAX.to_le_bytes();
+        let (wide, half) = ((u32::MAX - 1).to_le_bytes(), (u32::MAX / 2).to_le_bytes());
+        let huge: &[(usize, &[u8])] = &[(8, &wide), (12, &wide), (20, &half), (24, &half)];
+        open(huge, DecodeLimits::default(), "exceeds the limit");
+        open(huge, DecodeLimits::unlimited(), "Tile index needs");
+        open(&[(20, &max)], DecodeLimits::unlimited(), "Tile grid");
+        open(&[(toc_pos, &max)], DecodeLimits::default(), "sections exceed the limit");
+        open(&[(toc_pos, &max)], DecodeLimits::unlimited(), "does not fit in the file");
+        open(&[(toc_pos + 4 + pylv * 20 + 12, &u64::MAX.to_le_bytes())], DecodeLimits::unlimited(), "outside the file");
+        // A bad tile index entry only fails its own tile.
+        let bad_tile = |patch: &[u8], at: usize, expected: &str| {
+            let mut bad = bytes.clone();
+            bad[at..at + patch.len()].copy_from_slice(patch);
+            let mut reader = CTIReader::with_limits(Cursor::new(bad), DecodeLimits::unlimited()).unwrap();
+            let err = reader.decode_tile(0, 0, 0).unwrap_err();
+            assert!(err.to_string().contains(expected), "{err}");
+            assert!(reader.decode(0).is_err());
+            assert!(reader.decode_tile(1, 1, 0).is_ok());
+            assert!(reader.extract_region(2, 2, 2, 2, 0).is_ok());
+        };
+        bad_tile(&(u64::MAX - 3).to_le_bytes(), CTI_HEADER_SIZE, "outside the data area");
+        bad_tile(&max, CTI_HEADER_SIZE + 12, "original size");
 
-        cur.set_position(0);
-        let info = CTIDecoder::info_from_reader(&mut cur).unwrap();
-        assert_eq!(info.header.width, 4);
-        assert_eq!(info.pyramid_levels.len(), 2);
-        assert_eq!(info.icc_size, Some(4));
-        assert_eq!(info.xdpi, Some(300.0));
+        // An LZ4 size prefix is bounded by the index entry, not allocated as stored.
+        let lz4 = CTIEncoder::new(CTIConfig { tile_size: 2, compression: CompressionType::Lz4, ..CTIConfig::default() });
+        let mut cur = Cursor::new(Vec::new());
+        lz4.encode_to_writer(&sample_rgb8(), &mut cur).unwrap();
+        let mut lz4_bytes = cur.into_inner();
+        lz4_bytes[6] &= !(FLAG_CHECKSUMS as u8);
+        let first = CTIDecoder::info_from_reader(&mut Cursor::new(lz4_bytes.clone())).unwrap().indices[0];
+        lz4_bytes[first.offset as usize..first.offset as usize + 4].copy_from_slice(&max);
+        let mut reader = CTIReader::new(Cursor::new(lz4_bytes)).unwrap();
+        let err = reader.decode(0).unwrap_err();
+        assert!(err.to_string().contains("LZ4 tile claims"), "{err}");
 
-        cur.set_position(0);
-        let decoded = CTIDecoder::decode_from_reader(&mut cur, 0).unwrap();
-        assert_eq!(decoded.data, img.data);
+        assert!(CTIReader::with_limits(Cursor::new(bytes.clone()), DecodeLimits::default()).is_ok());
+        open(&[], DecodeLimits { max_pixels: 10, ..DecodeLimits::default() }, "exceeds the limit");
+        open(&[], DecodeLimits { max_pyramid_depth: 0, ..DecodeLimits::default() }, "nested deeper");
+        open(&[], DecodeLimits { max_alloc: 40, ..DecodeLimits::unlimited() }, "allocation limit");
 
-        cur.set_position(0);
-        let level1 = CTIDecoder::decode_from_reader(&mut cur, 1).unwrap();
-        assert_eq!(level1.header.width, 2);
-        assert_eq!(level1.header.height, 2);
+        let limits = DecodeLimits { max_pixels: 10, ..DecodeLimits::default() };
+        let report = verify_cti_with_limits(&mut Cursor::new(bytes), &limits).unwrap();
+        assert!(report.issues[0].message.contains("exceeds the limit"), "{:?}", report.issues);
     }
 
     #[test]
-    fn tile_and_region_decode_work() {
-        let img = TiffImage {
-            width: 4,
-            height: 4,
-            color_type: ColorType::L8,
-            data: (0u8..16).collect(),
-            xdpi: None,
-            ydpi: None,
-            icc: None,
-        };
+    fn verify_reports_every_problem_across_levels() {
         let enc = CTIEncoder::new(CTIConfig {
             tile_size: 2,
-            compression: CompressionType::Lz4,
+            pyramid_levels: 1,
+            fixity: FixityScope::Tiles,
             ..CTIConfig::default()
         });
         let mut cur = Cursor::new(Vec::new());
-        enc.encode_to_writer(&img, &mut cur).unwrap();
-
-        cur.set_position(0);
-        let tile = CTIDecoder::decode_tile_from_reader(&mut cur, 1, 0, 0).unwrap();
-        assert_eq!(tile.width, 2);
-        assert_eq!(tile.height, 2);
-        assert_eq!(tile.data, vec![2, 3, 6, 7]);
-
-        cur.set_position(0);
-        let region = CTIDecoder::extract_region_from_reader(&mut cur, 1, 1, 2, 2, 0).unwrap();
-        assert_eq!(region.data, vec![5, 6, 9, 10]);
+        enc.encode_to_writer(&sample_rgb8(), &mut cur).unwrap();
+        let clean = cur.into_inner();
+        let mut bytes = clean.clone();
+
+        let report = verify_cti(&mut Cursor::new(bytes.clone())).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!((report.levels_checked, report.tiles_checked), (2, 5));
+
+        let info = CTIDecoder::info_from_reader(&mut Cursor::new(bytes.clone())).unwrap();
+        let pylv = *info.sections.iter().find(|desc| desc.ty == SEC_TYPE_PYLV).unwrap();
+        bytes[info.indices[0].offset as usize] ^= 0x5A;
+        let last = &info.indices[3];
+        bytes[(last.offset + last.compressed_size as u64) as usize - 1] ^= 0x5A;
+        bytes[pylv.offset as usize + CTI_HEADER_SIZE + TILE_INDEX_ONDISK_SIZE] ^= 0x5A;
+
+        let report = verify_cti(&mut Cursor::new(bytes)).unwrap();
+        let places = report
+            .issues
+            .iter()
+            .map(|issue| (issue.level, issue.tile, issue.section.is_some()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            places,
+            vec![
+                (0, None, true),
+                (0, Some((0, 0)), false),
+                (0, Some((1, 1)), false),
+                (1, Some((0, 0)), false),
+            ]
+        );
+        assert!(report.issues[1].message.contains("zstd decompress failed"));
+        assert!(report.issues[2].message.contains("CRC mismatch at tile 3"));
+        assert!(report.to_json().starts_with("{\"ok\":false,"));
+
+        // A broken index entry is reported and the other tiles are still checked.
+        let mut bytes = clean;
+        let entry = CTI_HEADER_SIZE + TILE_INDEX_ONDISK_SIZE;
+        bytes[entry..entry + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        bytes[(last.offset + last.compressed_size as u64) as usize - 1] ^= 0x5A;
+        let report = verify_cti(&mut Cursor::new(bytes)).unwrap();
+        let places = report
+            .issues
+            .iter()
+            .map(|issue| (issue.level, issue.tile))
+            .collect::<Vec<_>>();
+        assert_eq!(places, vec![(0, None), (0, Some((1, 0))), (0, Some((1, 1)))]);
+        assert_eq!(report.tiles_checked, 4);
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["issues"].as_array().unwrap().len(), 3);
+        assert_eq!(json["issues"][1]["tile"], serde_json::json!([1, 0]));
     }
 
     #[test]
-    fn corruption_is_detected() {
-        let enc = CTIEncoder::new(CTIConfig::default());
+    fn header_index_toc_and_section_checksums_are_verified() {
+        let enc = CTIEncoder::new(CTIConfig {
+            tile_size: 2,
+            pyramid_levels: 1,
+            ..CTIConfig::default()
+        });
         let img = sample_rgb8();
         let mut cur = Cursor::new(Vec::new());
         enc.encode_to_writer(&img, &mut cur).unwrap();
+        let bytes = cur.into_inner();
+
+        let info = CTIDecoder::info_from_reader(&mut Cursor::new(bytes.clone())).unwrap();
+        assert!(info.header.has_checksums());
+        assert_eq!(info.sections.last().unwrap().ty, SEC_TYPE_SCRC);
+        let pylv = *info.sections.iter().find(|desc| desc.ty == SEC_TYPE_PYLV).unwrap();
+        assert!(pylv.crc32.is_some());
+        let toc_pos = info.sections[0].offset as usize - info.sections.len() * 20;
+
+        let corrupt = |pos: usize, expected: &str| {
+            let mut bad = bytes.clone();
+            bad[pos] ^= 0x01;
+            let err = CTIDecoder::sections_from_reader(&mut Cursor::new(bad)).unwrap_err();
+            assert!(err.to_string().contains(expected), "{err}");
+        };
+        corrupt(8, "Header CRC mismatch");
+        corrupt(CTI_HEADER_SIZE + 1, "Tile index CRC mismatch");
+        corrupt(toc_pos + 12, "Section TOC CRC mismatch");
+        corrupt(pylv.offset as usize + 10, "CRC mismatch in section 'PYLV'");
+
+        // Files written before the checksum flag existed still decode.
+        let mut legacy = bytes.clone();
+        legacy[6] &= !(FLAG_CHECKSUMS as u8);
+        let decoded = CTIDecoder::decode_from_reader(&mut Cursor::new(legacy), 0).unwrap();
+        assert_eq!(decoded.data, img.data);
 
-        let mut bytes = cur.into_inner();
-        let mut info_cur = Cursor::new(bytes.clone());
-        let info = CTIDecoder::info_from_reader(&mut info_cur).unwrap();
-        let off = info.indices[0].offset as usize;
-        bytes[off] ^= 0x5A;
-
-        let mut bad_cur = Cursor::new(bytes);
-        assert!(CTIDecoder::decode_from_reader(&mut bad_cur, 0).is_err());
+        // The public section writer cannot stamp the header flag, so it adds no SCRC.
+        let mut plain = Cursor::new(Vec::new());
+        write_sections(&mut plain, &[(SEC_TYPE_RES, vec![0; 8])]).unwrap();
+        let plain = plain.into_inner();
+        assert_eq!(plain.len(), 4 + 20 + 8);
+        assert_eq!(&plain[..4], &1u32.to_le_bytes());
     }
 
     #[test]
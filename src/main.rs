@@ -1,12 +1,17 @@
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use clap::{Parser, Subcommand, ValueEnum};
 use cti::{
-    save_raster, section_type_name, CompressionType, CTIDecoder, CTIEncoder, CTIConfig,
-    SEC_TYPE_ICC, SEC_TYPE_PYLV, SEC_TYPE_RES, SEC_TYPE_TMOD,
+    adaptive_tile_mode_name, save_raster_with_metadata, section_type_name, sha256_hex, ColorType, CompressionType, CTIDecoder,
+    CTIEncoder, CTIConfig, FixityScope, RawTileSource, TileSource,
+    SEC_TYPE_EXIF, SEC_TYPE_ICC, SEC_TYPE_IPTC, SEC_TYPE_NLER, SEC_TYPE_PYLV, SEC_TYPE_RES, SEC_TYPE_TMOD, SEC_TYPE_XMP,
+    SEC_TYPE_ZDIC,
 };
+use rayon::ThreadPoolBuilder;
+use std::collections::HashSet;
 use std::fs::{self, File};
 use std::io::{BufWriter, Write};
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Instant;
 
 #[derive(Parser)]
@@ -37,12 +42,36 @@ enum Commands {
         /// Zstd level (1..=15), default 6
         #[arg(long, default_value_t = 6)]
         zstd_level: i32,
-        /// Tile size (default 4096 with --ndk, else 256)
+        /// Lossy quality (1..=100) for --compression dct; overrides the preset
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: Option<u8>,
+        /// Maximum per-sample error for --compression near-lossless
+        #[arg(long, default_value_t = 0)]
+        max_error: u16,
+        /// Train a Zstd dictionary of up to N bytes from sample tiles (zstd/adaptive, 0 = off)
+        #[arg(long, default_value_t = 0)]
+        zstd_dict_size: u32,
+        /// Store solid-color tiles as one pixel and share duplicate payloads (needs a TSOL-aware reader)
         #[arg(long)]
-        tile: Option<u32>,
+        dedup: bool,
+        /// Tile size N or WxH, e.g. 4096x64 to match TIFF strips (default 4096 with --ndk, else 256)
+        #[arg(long, value_parser = parse_tile_dims)]
+        tile: Option<(u32, u32)>,
+        /// Store SHA-256 fixity digests of the pixels (lossless modes only)
+        #[arg(long, value_enum, default_value_t = FixityArg::None)]
+        fixity: FixityArg,
         /// Build embedded pyramid levels (each level downsampled by 2x)
         #[arg(long, default_value_t = 0)]
         pyramid_levels: u32,
+        /// Treat the input as headerless raw pixels of size WxH (as written by `decode`)
+        #[arg(long, value_parser = parse_raw_dims)]
+        raw: Option<(u32, u32)>,
+        /// Pixel layout of --raw input
+        #[arg(long, value_enum, default_value_t = RawTypeArg::Rgb8, requires = "raw")]
+        raw_type: RawTypeArg,
+        /// Band count for --raw-type bands8/bands16
+        #[arg(long, default_value_t = 1, requires = "raw")]
+        raw_bands: u8,
     },
 
     /// Decode CTI -> raw (and optional image file by extension)
@@ -58,6 +87,15 @@ enum Commands {
         /// Decode pyramid level (0 = full resolution)
         #[arg(long, default_value_t = 0)]
         level: u32,
+        /// Decode worker threads (0 = all cores)
+        #[arg(long, default_value_t = 0)]
+        threads: usize,
+        /// Fill unreadable tiles with --fill instead of failing, and list them
+        #[arg(long)]
+        recover: bool,
+        /// Sample value for unreadable tiles with --recover (as a float for float images)
+        #[arg(long, default_value_t = 0)]
+        fill: u16,
     },
 
     /// Decode one tile only
@@ -88,6 +126,15 @@ enum Commands {
         /// Decode pyramid level (0 = full resolution)
         #[arg(long, default_value_t = 0)]
         level: u32,
+        /// Decode worker threads (0 = all cores)
+        #[arg(long, default_value_t = 0)]
+        threads: usize,
+        /// Fill unreadable tiles with --fill instead of failing, and list them
+        #[arg(long)]
+        recover: bool,
+        /// Sample value for unreadable tiles with --recover (as a float for float images)
+        #[arg(long, default_value_t = 0)]
+        fill: u16,
     },
 
     /// Print CTI header and metadata info
@@ -105,6 +152,22 @@ enum Commands {
     DumpSections {
         input: PathBuf,
     },
+
+    /// Check header, tile index, sections, every tile CRC and all pyramid levels
+    Verify {
+        input: PathBuf,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Recompute SHA-256 fixity digests of the decoded pixels and compare them
+    Fixity {
+        input: PathBuf,
+        /// Also compare the image digest with the pixels of this source image
+        #[arg(long)]
+        source: Option<PathBuf>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -126,8 +189,18 @@ enum BenchWhat {
         compression: Option<CompressionArg>,
         #[arg(long, default_value_t = 6)]
         zstd_level: i32,
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: Option<u8>,
+        #[arg(long, default_value_t = 0)]
+        max_error: u16,
+        #[arg(long, default_value_t = 0)]
+        zstd_dict_size: u32,
         #[arg(long)]
-        tile: Option<u32>,
+        dedup: bool,
+        #[arg(long, value_parser = parse_tile_dims)]
+        tile: Option<(u32, u32)>,
+        #[arg(long, value_enum, default_value_t = FixityArg::None)]
+        fixity: FixityArg,
         #[arg(long, default_value_t = 0)]
         pyramid_levels: u32,
         /// Repeat N times (default 3)
@@ -146,6 +219,9 @@ enum BenchWhat {
         /// Repeat N times (default 5)
         #[arg(long, default_value_t = 5)]
         repeat: u32,
+        /// Decode worker threads (0 = all cores)
+        #[arg(long, default_value_t = 0)]
+        threads: usize,
     },
 }
 
@@ -158,9 +234,38 @@ enum CompressionArg {
     Predictive,
     Zstd,
     Lz4,
+    Dct,
+    NearLossless,
+    Bilevel,
     Adaptive,
 }
 
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum FixityArg {
+    None,
+    Image,
+    Tiles,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum RawTypeArg {
+    L8,
+    L16,
+    Rgb8,
+    Rgba8,
+    Rgb16,
+    La8,
+    La16,
+    Rgba16,
+    L32f,
+    Rgb32f,
+    Cmyk8,
+    Cmyk16,
+    Bands8,
+    Bands16,
+    L1,
+}
+
 #[derive(Clone, Copy, Debug, ValueEnum)]
 enum PresetArg {
     Archive,
@@ -168,6 +273,38 @@ enum PresetArg {
     WebZstd,
 }
 
+impl From<FixityArg> for FixityScope {
+    fn from(value: FixityArg) -> Self {
+        match value {
+            FixityArg::None => FixityScope::None,
+            FixityArg::Image => FixityScope::Image,
+            FixityArg::Tiles => FixityScope::Tiles,
+        }
+    }
+}
+
+impl RawTypeArg {
+    fn color_type(self, bands: u8) -> ColorType {
+        match self {
+            RawTypeArg::L8 => ColorType::L8,
+            RawTypeArg::L16 => ColorType::L16,
+            RawTypeArg::Rgb8 => ColorType::Rgb8,
+            RawTypeArg::Rgba8 => ColorType::Rgba8,
+            RawTypeArg::Rgb16 => ColorType::Rgb16,
+            RawTypeArg::La8 => ColorType::La8,
+            RawTypeArg::La16 => ColorType::La16,
+            RawTypeArg::Rgba16 => ColorType::Rgba16,
+            RawTypeArg::L32f => ColorType::L32F,
+            RawTypeArg::Rgb32f => ColorType::Rgb32F,
+            RawTypeArg::Cmyk8 => ColorType::Cmyk8,
+            RawTypeArg::Cmyk16 => ColorType::Cmyk16,
+            RawTypeArg::Bands8 => ColorType::Bands8(bands),
+            RawTypeArg::Bands16 => ColorType::Bands16(bands),
+            RawTypeArg::L1 => ColorType::L1,
+        }
+    }
+}
+
 impl From<CompressionArg> for CompressionType {
     fn from(value: CompressionArg) -> Self {
         match value {
@@ -178,6 +315,9 @@ impl From<CompressionArg> for CompressionType {
             CompressionArg::Predictive => CompressionType::Predictive,
             CompressionArg::Zstd => CompressionType::Zstd,
             CompressionArg::Lz4 => CompressionType::Lz4,
+            CompressionArg::Dct => CompressionType::Dct,
+            CompressionArg::NearLossless => CompressionType::NearLossless,
+            CompressionArg::Bilevel => CompressionType::Bilevel,
             CompressionArg::Adaptive => CompressionType::Adaptive,
         }
     }
@@ -195,23 +335,60 @@ fn main() -> Result<()> {
             rct,
             compression,
             zstd_level,
+            quality,
+            max_error,
+            zstd_dict_size,
+            dedup,
             tile,
+            fixity,
             pyramid_levels,
+            raw,
+            raw_type,
+            raw_bands,
         } => {
-            let cfg = build_config(ndk, preset, rct, compression, zstd_level, tile, pyramid_levels)?;
+            let cfg = build_config(
+                ndk,
+                preset,
+                rct,
+                compression,
+                zstd_level,
+                quality,
+                max_error,
+                zstd_dict_size,
+                dedup,
+                tile,
+                fixity,
+                pyramid_levels,
+            )?;
             let enc = CTIEncoder::new(cfg.clone());
-            let info = enc.inspect_input(&input)?;
-            println!("Loaded image: {}x{}, {:?}", info.width, info.height, info.color_type);
+            let mut raw_source = match raw {
+                Some((width, height)) => Some(RawTileSource::open(&input, width, height, raw_type.color_type(raw_bands))?),
+                None => None,
+            };
+            match &raw_source {
+                Some(source) => {
+                    let (width, height) = source.dimensions();
+                    println!("Raw input: {}x{}, {:?}", width, height, source.color_type());
+                }
+                None => {
+                    let info = enc.inspect_input(&input)?;
+                    println!("Loaded image: {}x{}, {:?}", info.width, info.height, info.color_type);
+                }
+            }
             println!(
-                "Preset: tile={}, comp={:?}, RCT={}, zstd_level={}, pyramid_levels={}, downcast16to8={}",
-                cfg.tile_size,
+                "Preset: tile={}, comp={:?}, quality={}, RCT={}, zstd_level={}, pyramid_levels={}, downcast16to8={}",
+                tile_label(cfg.tile_size, cfg.tile_height.unwrap_or(cfg.tile_size)),
                 cfg.compression,
+                cfg.quality_level,
                 cfg.color_transform,
                 cfg.zstd_level,
                 cfg.pyramid_levels,
                 cfg.downcast_16_to_8
             );
-            enc.encode_path_to_cti(&input, &output)?;
+            match raw_source.as_mut() {
+                Some(source) => enc.encode_source_to_cti(source, &output)?,
+                None => enc.encode_path_to_cti(&input, &output)?,
+            }
             println!("Wrote CTI -> {}", output.display());
         }
 
@@ -221,16 +398,26 @@ fn main() -> Result<()> {
             image_out,
             png_out,
             level,
+            threads,
+            recover,
+            fill,
         } => {
             let output_image = resolve_image_out(image_out, png_out)?;
-            let decoded = CTIDecoder::decode_detailed(&input, level)?;
+            let mut reader = CTIDecoder::open(&input)?.with_threads(threads)?;
+            let decoded = if recover {
+                let recovered = reader.decode_recover(level, fill)?;
+                report_recovery(&recovered.failed_tiles, &recovered.failed_sections);
+                recovered.value
+            } else {
+                reader.decode(level)?
+            };
             println!(
                 "Decoded CTI: {}x{}, ct={}, comp={}, tile={}, level={}",
                 decoded.header.width,
                 decoded.header.height,
                 decoded.header.color_type,
                 decoded.header.compression,
-                decoded.header.tile_size,
+                tile_label(decoded.header.tile_width(), decoded.header.tile_height()),
                 level
             );
 
@@ -238,12 +425,13 @@ fn main() -> Result<()> {
             println!("Raw written -> {}", raw_out.display());
 
             if let Some(out) = output_image {
-                save_raster(
+                save_raster_with_metadata(
                     &out,
                     decoded.header.width,
                     decoded.header.height,
                     decoded.header.color_type,
                     &decoded.data,
+                    &reader.metadata()?,
                 )?;
                 println!("Image written -> {}", out.display());
             }
@@ -265,7 +453,8 @@ fn main() -> Result<()> {
             write_all(&raw_out, &tile.data)?;
             println!("Raw written -> {}", raw_out.display());
             if let Some(out) = image_out {
-                save_raster(&out, tile.width, tile.height, tile.color_type, &tile.data)?;
+                let metadata = CTIDecoder::open(&input)?.metadata()?;
+                save_raster_with_metadata(&out, tile.width, tile.height, tile.color_type, &tile.data, &metadata)?;
                 println!("Image written -> {}", out.display());
             }
         }
@@ -279,8 +468,18 @@ fn main() -> Result<()> {
             raw_out,
             image_out,
             level,
+            threads,
+            recover,
+            fill,
         } => {
-            let region = CTIDecoder::extract_region(&input, x, y, width, height, level)?;
+            let mut reader = CTIDecoder::open(&input)?.with_threads(threads)?;
+            let region = if recover {
+                let recovered = reader.extract_region_recover(x, y, width, height, level, fill)?;
+                report_recovery(&recovered.failed_tiles, &recovered.failed_sections);
+                recovered.value
+            } else {
+                reader.extract_region(x, y, width, height, level)?
+            };
             println!(
                 "Decoded region x={}, y={}, w={}, h={} at level {}: ct={}",
                 x, y, width, height, level, region.color_type
@@ -288,7 +487,14 @@ fn main() -> Result<()> {
             write_all(&raw_out, &region.data)?;
             println!("Raw written -> {}", raw_out.display());
             if let Some(out) = image_out {
-                save_raster(&out, region.width, region.height, region.color_type, &region.data)?;
+                save_raster_with_metadata(
+                    &out,
+                    region.width,
+                    region.height,
+                    region.color_type,
+                    &region.data,
+                    &reader.metadata()?,
+                )?;
                 println!("Image written -> {}", out.display());
             }
         }
@@ -299,7 +505,9 @@ fn main() -> Result<()> {
             println!("Size: {} x {}", info.header.width, info.header.height);
             println!(
                 "Tile: {} ({} x {} tiles)",
-                info.header.tile_size, info.header.tiles_x, info.header.tiles_y
+                tile_label(info.header.tile_width(), info.header.tile_height()),
+                info.header.tiles_x,
+                info.header.tiles_y
             );
             println!("ColorType ID: {}", info.header.color_type);
             println!(
@@ -308,7 +516,22 @@ fn main() -> Result<()> {
                 CompressionType::from_id(info.header.compression)?.label()
             );
             println!("Quality: {}", info.header.quality);
-            println!("Flags: 0x{:04X} (RCT:{})", info.header.flags, (info.header.flags & 1) != 0);
+            if let Some(max_error) = info.max_error {
+                println!("Max error: ±{}", max_error);
+            }
+            println!(
+                "Flags: 0x{:04X} (RCT:{}, checksums:{})",
+                info.header.flags,
+                (info.header.flags & 1) != 0,
+                info.header.has_checksums()
+            );
+            let unique_payloads = info.indices.iter().map(|idx| idx.offset).collect::<HashSet<_>>().len();
+            println!(
+                "Tiles: {} ({} solid, {} sharing another tile's payload)",
+                info.indices.len(),
+                info.solid_tiles,
+                info.indices.len() - unique_payloads
+            );
             println!("Sections: {}", info.sections.len());
             if let (Some(xdpi), Some(ydpi)) = (info.xdpi, info.ydpi) {
                 println!("DPI: {:.2} x {:.2}", xdpi, ydpi);
@@ -316,6 +539,26 @@ fn main() -> Result<()> {
             if let Some(icc_size) = info.icc_size {
                 println!("ICC profile: {} bytes", icc_size);
             }
+            if let Some(bands) = &info.bands {
+                println!(
+                    "Bands: photometric={}, ink set={}, extra samples={:?}, names={:?}",
+                    bands.photometric, bands.ink_set, bands.extra_samples, bands.names
+                );
+            }
+            for desc in info
+                .sections
+                .iter()
+                .filter(|desc| matches!(desc.ty, SEC_TYPE_XMP | SEC_TYPE_EXIF | SEC_TYPE_IPTC))
+            {
+                println!("{}: {} bytes", section_type_name(desc.ty).trim_end(), desc.size);
+            }
+            if let Some(fixity) = &info.fixity {
+                println!(
+                    "Fixity: SHA-256 {} (+{} tile digests)",
+                    sha256_hex(&fixity.image),
+                    fixity.tiles.len()
+                );
+            }
             if info.pyramid_levels.is_empty() {
                 println!("Pyramid: none");
             } else {
@@ -326,7 +569,7 @@ fn main() -> Result<()> {
                         lvl.level,
                         lvl.width,
                         lvl.height,
-                        lvl.tile_size,
+                        tile_label(lvl.tile_size, lvl.tile_height),
                         lvl.color_type,
                         lvl.compression,
                         lvl.payload_size
@@ -344,7 +587,12 @@ fn main() -> Result<()> {
                 rct,
                 compression,
                 zstd_level,
+                quality,
+                max_error,
+                zstd_dict_size,
+                dedup,
                 tile,
+                fixity,
                 pyramid_levels,
                 repeat,
             } => {
@@ -356,7 +604,12 @@ fn main() -> Result<()> {
                     rct,
                     compression,
                     zstd_level,
+                    quality,
+                    max_error,
+                    zstd_dict_size,
+                    dedup,
                     tile,
+                    fixity,
                     pyramid_levels,
                     repeat,
                 )?;
@@ -366,34 +619,104 @@ fn main() -> Result<()> {
                 out,
                 level,
                 repeat,
+                threads,
             } => {
-                bench_decode(input, out, level, repeat)?;
+                bench_decode(input, out, level, repeat, threads)?;
             }
         },
 
+        Commands::Verify { input, json } => {
+            let report = CTIDecoder::verify(&input)?;
+            if json {
+                println!("{}", report.to_json());
+            } else {
+                println!(
+                    "Checked {}: {} B, {} level(s), {} tiles, {} sections",
+                    input.display(),
+                    report.file_size,
+                    report.levels_checked,
+                    report.tiles_checked,
+                    report.sections_checked
+                );
+                for issue in &report.issues {
+                    let place = match (issue.tile, issue.section) {
+                        (Some((tx, ty)), _) => format!(" tile ({}, {})", tx, ty),
+                        (None, Some(idx)) => format!(" section [{}]", idx),
+                        (None, None) => String::new(),
+                    };
+                    println!("  L{}{}: {}", issue.level, place, issue.message);
+                }
+                if report.is_ok() {
+                    println!("OK");
+                }
+            }
+            if !report.is_ok() {
+                bail!("{} problem(s) found in {}", report.issues.len(), input.display());
+            }
+        }
+
+        Commands::Fixity { input, source } => {
+            let report = CTIDecoder::verify_fixity(&input)?
+                .ok_or_else(|| anyhow!("{} has no FXTY fixity section", input.display()))?;
+            println!(
+                "Image SHA-256: {} ({})",
+                sha256_hex(&report.computed.image),
+                if report.image_matches() { "OK" } else { "MISMATCH" }
+            );
+            if !report.stored.tiles.is_empty() {
+                println!(
+                    "Tile digests: {} checked, {} mismatched",
+                    report.stored.tiles.len(),
+                    report.mismatched_tiles.len()
+                );
+                for (tx, ty) in &report.mismatched_tiles {
+                    println!("  tile ({}, {}) MISMATCH", tx, ty);
+                }
+            }
+            let mut ok = report.is_ok();
+            if let Some(source) = source {
+                let enc = CTIEncoder::new(CTIConfig::default());
+                let source_digest = enc.image_fixity(&enc.load_image(&source)?)?;
+                let matches = source_digest == report.stored.image;
+                println!(
+                    "Source {}: {} ({})",
+                    source.display(),
+                    sha256_hex(&source_digest),
+                    if matches { "OK" } else { "MISMATCH" }
+                );
+                ok &= matches;
+            }
+            if !ok {
+                bail!("Fixity check failed for {}", input.display());
+            }
+        }
+
         Commands::DumpSections { input } => {
-            let info = CTIDecoder::info(&input)?;
-            let sections = CTIDecoder::sections(&input)?;
-            println!("Section count: {}", sections.len());
-            let mut pyramid_iter = info.pyramid_levels.iter();
-            for (idx, sec) in sections.iter().enumerate() {
+            let mut reader = CTIDecoder::open(&input)?;
+            let descs = reader.section_descs().to_vec();
+            let pyramid_levels = reader.pyramid_levels()?;
+            println!("Section count: {}", descs.len());
+            let mut pyramid_iter = pyramid_levels.iter();
+            for (idx, desc) in descs.iter().enumerate() {
                 println!(
                     "[{}] type=0x{:08X} '{}' size={} B",
                     idx,
-                    sec.ty,
-                    section_type_name(sec.ty),
-                    sec.payload.len()
+                    desc.ty,
+                    section_type_name(desc.ty),
+                    desc.size
                 );
-                match sec.ty {
-                    SEC_TYPE_RES => {
-                        if sec.payload.len() == 8 {
-                            let x = f32::from_le_bytes(sec.payload[0..4].try_into().unwrap());
-                            let y = f32::from_le_bytes(sec.payload[4..8].try_into().unwrap());
-                            println!("    DPI: {:.2} x {:.2}", x, y);
-                        }
+                if let Some(crc) = desc.crc32 {
+                    println!("    CRC32: 0x{:08X}", crc);
+                }
+                match desc.ty {
+                    SEC_TYPE_RES if desc.size == 8 => {
+                        let sec = reader.read_section(idx)?;
+                        let x = f32::from_le_bytes(sec.payload[0..4].try_into().unwrap());
+                        let y = f32::from_le_bytes(sec.payload[4..8].try_into().unwrap());
+                        println!("    DPI: {:.2} x {:.2}", x, y);
                     }
                     SEC_TYPE_ICC => {
-                        println!("    ICC bytes: {}", sec.payload.len());
+                        println!("    ICC bytes: {}", desc.size);
                     }
                     SEC_TYPE_PYLV => {
                         if let Some(level) = pyramid_iter.next() {
@@ -402,14 +725,35 @@ fn main() -> Result<()> {
                                 level.level,
                                 level.width,
                                 level.height,
-                                level.tile_size,
+                                tile_label(level.tile_size, level.tile_height),
                                 level.color_type,
                                 level.compression
                             );
                         }
                     }
                     SEC_TYPE_TMOD => {
-                        println!("    Tile modes: {}", sec.payload.len());
+                        println!("    Tile modes: {}", desc.size);
+                        let sec = reader.read_section(idx)?;
+                        let mut counts = [0usize; 256];
+                        for &mode in &sec.payload {
+                            counts[mode as usize] += 1;
+                        }
+                        for (mode, &count) in counts.iter().enumerate().filter(|(_, count)| **count > 0) {
+                            let name = adaptive_tile_mode_name(mode as u8).unwrap_or("unknown");
+                            println!("      {} ({}): {}", mode, name, count);
+                        }
+                    }
+                    SEC_TYPE_NLER if desc.size == 2 => {
+                        let sec = reader.read_section(idx)?;
+                        println!("    Max error: ±{}", u16::from_le_bytes([sec.payload[0], sec.payload[1]]));
+                    }
+                    SEC_TYPE_ZDIC if desc.size >= 8 => {
+                        let sec = reader.read_section(idx)?;
+                        let magic = u32::from_le_bytes(sec.payload[0..4].try_into().unwrap());
+                        if magic == 0xEC30_A437 {
+                            let dict_id = u32::from_le_bytes(sec.payload[4..8].try_into().unwrap());
+                            println!("    Zstd dictionary id: {}", dict_id);
+                        }
                     }
                     _ => {}
                 }
@@ -420,6 +764,19 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+fn report_recovery(failed_tiles: &[(u32, u32)], failed_sections: &[usize]) {
+    if failed_tiles.is_empty() && failed_sections.is_empty() {
+        return;
+    }
+    eprintln!("Recovered with {} unreadable tile(s) filled", failed_tiles.len());
+    for (tx, ty) in failed_tiles {
+        eprintln!("  tile ({}, {})", tx, ty);
+    }
+    for idx in failed_sections {
+        eprintln!("  section [{}] dropped", idx);
+    }
+}
+
 fn resolve_image_out(image_out: Option<PathBuf>, png_out: Option<PathBuf>) -> Result<Option<PathBuf>> {
     match (image_out, png_out) {
         (Some(_), Some(_)) => bail!("Use either --image-out or --png-out, not both"),
@@ -429,6 +786,36 @@ fn resolve_image_out(image_out: Option<PathBuf>, png_out: Option<PathBuf>) -> Re
     }
 }
 
+fn parse_tile_dims(value: &str) -> std::result::Result<(u32, u32), String> {
+    let parse = |part: &str| match part.trim().parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid tile size '{}'", value)),
+    };
+    match value.split_once(['x', 'X']) {
+        Some((w, h)) => Ok((parse(w)?, parse(h)?)),
+        None => parse(value).map(|n| (n, n)),
+    }
+}
+
+fn parse_raw_dims(value: &str) -> std::result::Result<(u32, u32), String> {
+    let parse = |part: &str| match part.trim().parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid raw size '{}', expected WxH", value)),
+    };
+    match value.split_once(['x', 'X']) {
+        Some((w, h)) => Ok((parse(w)?, parse(h)?)),
+        None => Err(format!("invalid raw size '{}', expected WxH", value)),
+    }
+}
+
+fn tile_label(width: u32, height: u32) -> String {
+    if width == height {
+        width.to_string()
+    } else {
+        format!("{}x{}", width, height)
+    }
+}
+
 fn write_all(path: &PathBuf, data: &[u8]) -> Result<()> {
     let mut bw = BufWriter::new(File::create(path)?);
     bw.write_all(data)?;
@@ -436,18 +823,25 @@ fn write_all(path: &PathBuf, data: &[u8]) -> Result<()> {
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn build_config(
     ndk: bool,
     preset: Option<PresetArg>,
     rct: bool,
     compression: Option<CompressionArg>,
     zstd_level: i32,
-    tile: Option<u32>,
+    quality: Option<u8>,
+    max_error: u16,
+    zstd_dict_size: u32,
+    dedup: bool,
+    tile: Option<(u32, u32)>,
+    fixity: FixityArg,
     pyramid_levels: u32,
 ) -> Result<CTIConfig> {
     if ndk && preset.is_some() {
         bail!("Use either --ndk or --preset, not both");
     }
+    let (tile, tile_height) = tile.unzip();
 
     let mut cfg = match preset {
         Some(PresetArg::Archive) => CTIConfig {
@@ -458,6 +852,11 @@ fn build_config(
             zstd_level,
             pyramid_levels,
             downcast_16_to_8: false,
+            max_error: 0,
+            zstd_dict_size: 0,
+            dedup_tiles: false,
+            tile_height: None,
+            fixity: FixityScope::None,
         },
         Some(PresetArg::Web) => CTIConfig {
             tile_size: tile.unwrap_or(512),
@@ -467,6 +866,11 @@ fn build_config(
             zstd_level,
             pyramid_levels: if pyramid_levels == 0 { 1 } else { pyramid_levels },
             downcast_16_to_8: true,
+            max_error: 0,
+            zstd_dict_size: 0,
+            dedup_tiles: false,
+            tile_height: None,
+            fixity: FixityScope::None,
         },
         Some(PresetArg::WebZstd) => CTIConfig {
             tile_size: tile.unwrap_or(512),
@@ -476,6 +880,11 @@ fn build_config(
             zstd_level,
             pyramid_levels: if pyramid_levels == 0 { 1 } else { pyramid_levels },
             downcast_16_to_8: true,
+            max_error: 0,
+            zstd_dict_size: 0,
+            dedup_tiles: false,
+            tile_height: None,
+            fixity: FixityScope::None,
         },
         None if ndk => CTIConfig {
             tile_size: tile.unwrap_or(4096),
@@ -485,6 +894,11 @@ fn build_config(
             zstd_level,
             pyramid_levels,
             downcast_16_to_8: false,
+            max_error: 0,
+            zstd_dict_size: 0,
+            dedup_tiles: false,
+            tile_height: None,
+            fixity: FixityScope::None,
         },
         None => CTIConfig {
             tile_size: tile.unwrap_or(256),
@@ -497,12 +911,21 @@ fn build_config(
     if let Some(kind) = compression {
         cfg.compression = kind.into();
     }
+    if let Some(quality) = quality {
+        cfg.quality_level = quality;
+    }
+    cfg.max_error = max_error;
+    cfg.zstd_dict_size = zstd_dict_size;
+    cfg.dedup_tiles = dedup;
+    cfg.fixity = fixity.into();
+    cfg.tile_height = tile_height.filter(|&h| h != cfg.tile_size);
     if rct {
         cfg.color_transform = true;
     }
     Ok(cfg)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn bench_encode(
     input_image: PathBuf,
     out_path_opt: Option<PathBuf>,
@@ -511,23 +934,42 @@ fn bench_encode(
     rct: bool,
     compression: Option<CompressionArg>,
     zstd_level: i32,
-    tile: Option<u32>,
+    quality: Option<u8>,
+    max_error: u16,
+    zstd_dict_size: u32,
+    dedup: bool,
+    tile: Option<(u32, u32)>,
+    fixity: FixityArg,
     pyramid_levels: u32,
     repeat: u32,
 ) -> Result<()> {
     let out_path = out_path_opt.unwrap_or_else(|| input_image.with_extension("cti"));
-    let cfg = build_config(ndk, preset, rct, compression, zstd_level, tile, pyramid_levels)?;
+    let cfg = build_config(
+        ndk,
+        preset,
+        rct,
+        compression,
+        zstd_level,
+        quality,
+        max_error,
+        zstd_dict_size,
+        dedup,
+        tile,
+        fixity,
+        pyramid_levels,
+    )?;
     let enc = CTIEncoder::new(cfg.clone());
     let info = enc.inspect_input(&input_image)?;
     println!(
-        "BENCH encode: {} ({}x{}, {:?}) -> {} (tile={}, comp={:?}, RCT={}, zstd_level={}, pyramid_levels={}, downcast16to8={})",
+        "BENCH encode: {} ({}x{}, {:?}) -> {} (tile={}, comp={:?}, quality={}, RCT={}, zstd_level={}, pyramid_levels={}, downcast16to8={})",
         input_image.display(),
         info.width,
         info.height,
         info.color_type,
         out_path.display(),
-        cfg.tile_size,
+        tile_label(cfg.tile_size, cfg.tile_height.unwrap_or(cfg.tile_size)),
         cfg.compression,
+        cfg.quality_level,
         cfg.color_transform,
         cfg.zstd_level,
         cfg.pyramid_levels,
@@ -536,12 +978,21 @@ fn bench_encode(
 
     let input_bytes = fs::metadata(&input_image)?.len() as f64;
     let px_bpp = match info.color_type {
-        image::ColorType::L8 => 1.0,
-        image::ColorType::L16 => 2.0,
-        image::ColorType::Rgb8 => 3.0,
-        image::ColorType::Rgba8 => 4.0,
-        image::ColorType::Rgb16 => 6.0,
-        _ => bail!("Unsupported color type for bench"),
+        ColorType::L8 => 1.0,
+        ColorType::L16 => 2.0,
+        ColorType::Rgb8 => 3.0,
+        ColorType::Rgba8 => 4.0,
+        ColorType::Rgb16 => 6.0,
+        ColorType::La8 => 2.0,
+        ColorType::La16 => 4.0,
+        ColorType::Rgba16 => 8.0,
+        ColorType::L32F => 4.0,
+        ColorType::Rgb32F => 12.0,
+        ColorType::Cmyk8 => 4.0,
+        ColorType::Cmyk16 => 8.0,
+        ColorType::Bands8(n) => n as f64,
+        ColorType::Bands16(n) => n as f64 * 2.0,
+        ColorType::L1 => 1.0,
     };
     let raw_bytes = (info.width as f64) * (info.height as f64) * px_bpp;
 
@@ -574,29 +1025,42 @@ fn bench_encode(
     Ok(())
 }
 
-fn bench_decode(input_cti: PathBuf, out_raw_opt: Option<PathBuf>, level: u32, repeat: u32) -> Result<()> {
+fn bench_decode(
+    input_cti: PathBuf,
+    out_raw_opt: Option<PathBuf>,
+    level: u32,
+    repeat: u32,
+    threads: usize,
+) -> Result<()> {
     let out_raw = out_raw_opt.unwrap_or_else(|| input_cti.with_extension("raw"));
 
-    let (hdr0, raw0) = CTIDecoder::decode_level(&input_cti, level)?;
+    let decoded0 = CTIDecoder::open(&input_cti)?.with_threads(threads)?.decode(level)?;
+    let (hdr0, raw0) = (decoded0.header, decoded0.data);
     let raw_size = raw0.len() as f64;
     write_all(&out_raw, &raw0)?;
     println!(
-        "BENCH decode: {} ({}x{}, ct={}, comp={}, tile={}, level={}) -> {}",
+        "BENCH decode: {} ({}x{}, ct={}, comp={}, tile={}, level={}, threads={}) -> {}",
         input_cti.display(),
         hdr0.width,
         hdr0.height,
         hdr0.color_type,
         hdr0.compression,
-        hdr0.tile_size,
+        tile_label(hdr0.tile_width(), hdr0.tile_height()),
         level,
+        threads,
         out_raw.display()
     );
 
+    // Build the pool once so its startup is not part of the timings.
+    let pool = match threads {
+        0 => None,
+        n => Some(Arc::new(ThreadPoolBuilder::new().num_threads(n).build()?)),
+    };
     let mut best_ms = f64::INFINITY;
     let mut sum_ms = 0.0;
     for _ in 0..repeat {
         let start = Instant::now();
-        let (_hdr, raw) = CTIDecoder::decode_level(&input_cti, level)?;
+        let raw = CTIDecoder::open(&input_cti)?.with_thread_pool(pool.clone()).decode(level)?.data;
         let dur = start.elapsed().as_secs_f64() * 1000.0;
         std::hint::black_box(&raw);
         best_ms = best_ms.min(dur);